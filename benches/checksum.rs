@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Throughput of the checksum kernels over a 64 MB hardfile worth of blocks, the work a
+// full verification of a large HDF image does. Run with cargo bench --bench checksum.
+
+use adflib::raw::{bitmap_checksum, bootblock_checksum, normal_checksum, BOOT_BLOCK_SIZE};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const BLOCK_SIZE: usize = 512;
+const BLOCKS: usize = 128 * 1024;
+const ROUNDS: usize = 5;
+
+fn report(name: &str, bytes: usize, elapsed: Duration) {
+    let mb = (bytes * ROUNDS) as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:<10} {:>10.0} MB/s ({:?} per image)",
+        name,
+        mb / elapsed.as_secs_f64(),
+        elapsed / ROUNDS as u32
+    );
+}
+
+fn bench_blocks(name: &str, image: &[u8], checksum: fn(&[u8]) -> u32) {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let sum = image
+            .chunks_exact(BLOCK_SIZE)
+            .fold(0u32, |acc, block| acc ^ checksum(black_box(block)));
+        black_box(sum);
+    }
+    report(name, image.len(), start.elapsed());
+}
+
+fn main() {
+    // A cheap generator is enough, the kernels do not branch on the data.
+    let mut state = 0x2545_f491_u32;
+    let image: Vec<u8> = (0..BLOCKS * BLOCK_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+
+    bench_blocks("normal", &image, normal_checksum);
+    bench_blocks("bitmap", &image, bitmap_checksum);
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let sum = image
+            .chunks_exact(BOOT_BLOCK_SIZE)
+            .filter_map(|block| block.try_into().ok())
+            .fold(0u32, |acc, block| {
+                acc ^ bootblock_checksum(black_box(block))
+            });
+        black_box(sum);
+    }
+    report("boot", image.len(), start.elapsed());
+}
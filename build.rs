@@ -0,0 +1,22 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+include!("src/cli.rs");
+
+fn main() -> std::io::Result<()> {
+    println!("cargo:rerun-if-changed=src/cli.rs");
+
+    let out_dir = env::var_os("OUT_DIR")
+        .map(PathBuf::from)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "OUT_DIR is not set"))?;
+    let mut page = Vec::new();
+    clap_mangen::Man::new(build_cli()).render(&mut page)?;
+    fs::write(out_dir.join("adflibtesttool.1"), page)
+}
@@ -0,0 +1,47 @@
+/* SPDX-License-Identifier: MIT OR Apache-2.0 */
+/* C interface of adflib, built with: */
+/* cargo rustc --lib --release --features cdylib --crate-type cdylib */
+
+#ifndef ADFLIB_H
+#define ADFLIB_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RC_OK 0
+#define RC_ERROR -1
+
+struct AdfDevice;
+struct AdfVolume;
+struct AdfFile;
+
+void adfEnvInitDefault(void);
+void adfEnvCleanUp(void);
+
+struct AdfDevice *adfMountDev(const char *name, int ro);
+void adfUnMountDev(struct AdfDevice *dev);
+struct AdfVolume *adfMount(struct AdfDevice *dev, int nPart, int readOnly);
+void adfUnMount(struct AdfVolume *vol);
+
+int adfChangeDir(struct AdfVolume *vol, const char *name);
+int adfParentDir(struct AdfVolume *vol);
+int adfCreateDir(struct AdfVolume *vol, const char *name);
+int adfRemoveEntry(struct AdfVolume *vol, const char *name);
+int adfRenameEntry(struct AdfVolume *vol, const char *oldName, const char *newName);
+
+struct AdfFile *adfOpenFile(struct AdfVolume *vol, const char *name, const char *mode);
+uint32_t adfReadFile(struct AdfFile *file, uint32_t n, uint8_t *buffer);
+uint32_t adfWriteFile(struct AdfFile *file, uint32_t n, const uint8_t *buffer);
+int adfEndOfFile(const struct AdfFile *file);
+int adfFileSeek(struct AdfFile *file, uint32_t pos);
+uint32_t adfFileGetSize(const struct AdfFile *file);
+int adfCloseFile(struct AdfFile *file);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
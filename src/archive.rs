@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Export of a volume straight into a zip or tar archive, with paths, dates and permissions,
+// so its contents can be served or stored without extracting them to a directory first.
+// Files are streamed block by block from the image.
+
+use crate::disk::{FileInfo, ADF};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use std::io::{Error, ErrorKind, Result, Seek, Write};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const TAR_BLOCK: usize = 512;
+
+// Amiga protection bits for read, write and execute are set when the access is denied.
+const PROTECT_READ: u32 = 1 << 3;
+const PROTECT_WRITE: u32 = 1 << 2;
+const PROTECT_EXECUTE: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<ArchiveFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "zip" => Ok(ArchiveFormat::Zip),
+            "tar" => Ok(ArchiveFormat::Tar),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown archive format '{}', use zip or tar", name),
+            )),
+        }
+    }
+}
+
+fn unix_mode(info: &FileInfo) -> u32 {
+    let mut mode = 0;
+    if info.protection & PROTECT_READ == 0 {
+        mode |= 0o444;
+    }
+    if info.protection & PROTECT_WRITE == 0 {
+        mode |= 0o200;
+    }
+    if info.is_dir || info.protection & PROTECT_EXECUTE == 0 {
+        mode |= 0o111;
+    }
+    mode
+}
+
+// Zip dates start in 1980, so older Amiga dates are clamped to its first day.
+pub(crate) fn zip_date(time: SystemTime) -> zip::DateTime {
+    let date = DateTime::<Utc>::from(time);
+    u16::try_from(date.year())
+        .ok()
+        .and_then(|year| {
+            zip::DateTime::from_date_and_time(
+                year,
+                date.month() as u8,
+                date.day() as u8,
+                date.hour() as u8,
+                date.minute() as u8,
+                date.second() as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+// Writes value as a NUL terminated octal number filling field.
+fn tar_octal(field: &mut [u8], value: u64) {
+    let end = field.len() - 1;
+    let digits = format!("{:0width$o}", value, width = end);
+    field[..end].copy_from_slice(digits.as_bytes());
+    field[end] = 0;
+}
+
+// A ustar header. Paths over 100 bytes are split at a slash into the 155 byte prefix field.
+fn tar_header(path: &str, info: &FileInfo) -> Result<[u8; TAR_BLOCK]> {
+    let (prefix, name) = if path.len() <= 100 {
+        ("", path)
+    } else {
+        path.char_indices()
+            .filter(|&(i, c)| c == '/' && i <= 155 && path.len() - i - 1 <= 100)
+            .map(|(i, _)| (&path[..i], &path[i + 1..]))
+            .next()
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Path '{}' is too long for a tar archive", path),
+                )
+            })?
+    };
+    let mut header = [0u8; TAR_BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    tar_octal(&mut header[100..108], u64::from(unix_mode(info)));
+    tar_octal(&mut header[108..116], 0);
+    tar_octal(&mut header[116..124], 0);
+    let size = if info.is_dir { 0 } else { info.size };
+    tar_octal(&mut header[124..136], u64::from(size));
+    let mtime = info
+        .creation_date
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    tar_octal(&mut header[136..148], mtime);
+    header[156] = if info.is_dir { b'5' } else { b'0' };
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    // The checksum is taken with its own field as spaces.
+    header[148..156].fill(b' ');
+    let checksum: u64 = header.iter().map(|&b| u64::from(b)).sum();
+    tar_octal(&mut header[148..155], checksum);
+    header[155] = b' ';
+    Ok(header)
+}
+
+impl ADF {
+    // Every file and directory on the volume into a zip or tar archive written to writer.
+    // Zip needs to seek back over the entries; export_tar takes any writer.
+    pub fn export_archive<W: Write + Seek>(&self, format: ArchiveFormat, writer: W) -> Result<()> {
+        match format {
+            ArchiveFormat::Zip => self.export_zip(writer),
+            ArchiveFormat::Tar => self.export_tar(writer),
+        }
+    }
+
+    pub fn export_zip<W: Write + Seek>(&self, writer: W) -> Result<()> {
+        let mut zip = ZipWriter::new(writer);
+        for (path, info) in self.walk("")? {
+            let options = SimpleFileOptions::default()
+                .compression_method(CompressionMethod::Deflated)
+                .last_modified_time(zip_date(info.creation_date))
+                .unix_permissions(unix_mode(&info));
+            if info.is_dir {
+                zip.add_directory(path, options).map_err(Error::other)?;
+            } else {
+                zip.start_file(path.as_str(), options)
+                    .map_err(Error::other)?;
+                self.read_file_into(&path, &mut zip)?;
+            }
+        }
+        zip.finish().map_err(Error::other)?;
+        Ok(())
+    }
+
+    pub fn export_tar<W: Write>(&self, mut writer: W) -> Result<()> {
+        for (path, info) in self.walk("")? {
+            let path = if info.is_dir { path + "/" } else { path };
+            writer.write_all(&tar_header(&path, &info)?)?;
+            if info.is_dir {
+                continue;
+            }
+            let written = self.read_file_into(&path, &mut writer)?;
+            if written != u64::from(info.size) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} holds {} bytes, its header says {}",
+                        path, written, info.size
+                    ),
+                ));
+            }
+            let padding = (TAR_BLOCK - written as usize % TAR_BLOCK) % TAR_BLOCK;
+            writer.write_all(&[0; TAR_BLOCK][..padding])?;
+        }
+        // Two zero blocks end the archive.
+        writer.write_all(&[0; 2 * TAR_BLOCK])?;
+        writer.flush()
+    }
+}
@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Bad block map for images read from degraded disks, and the map of blocks format reserved
+// after the boot block. A map is a text file in the root directory, one block number per
+// line, and the blocks it lists stay allocated in the bitmap, so nothing is written to them.
+// validate, repair and defragment read both maps as well.
+
+use crate::disk::ADF;
+use std::io::{Error, ErrorKind, Result};
+
+pub const BAD_BLOCKS_FILE: &str = ".bad_blocks";
+pub const RESERVED_BLOCKS_FILE: &str = ".reserved_blocks";
+
+impl ADF {
+    // The blocks in the bad block map, sorted; empty when the volume has no map.
+    pub fn bad_blocks(&self) -> Result<Vec<usize>> {
+        self.block_map(BAD_BLOCKS_FILE)
+    }
+
+    // The blocks format reserved, e.g. for trackloader data, sorted.
+    pub fn reserved_blocks(&self) -> Result<Vec<usize>> {
+        self.block_map(RESERVED_BLOCKS_FILE)
+    }
+
+    fn block_map(&self, file: &str) -> Result<Vec<usize>> {
+        let contents = match self.read_file(file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut blocks = String::from_utf8_lossy(&contents)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                line.parse::<usize>().map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Invalid block number '{}' in {}", line, file),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        blocks.sort_unstable();
+        blocks.dedup();
+        Ok(blocks)
+    }
+
+    // Adds block to the map and takes it out of allocation. A block already holding data
+    // stays with its file; validate reports it so the file can be moved.
+    pub fn mark_bad(&mut self, block: usize) -> Result<()> {
+        if block >= self.num_blocks() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Block {} is out of range (0-{})",
+                    block,
+                    self.num_blocks() - 1
+                ),
+            ));
+        }
+        if self.is_reserved_block(block) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Block {} holds file system structures and cannot be marked bad",
+                    block
+                ),
+            ));
+        }
+        let mut blocks = self.bad_blocks()?;
+        if blocks.contains(&block) {
+            return Ok(());
+        }
+        blocks.push(block);
+        blocks.sort_unstable();
+        self.write_bad_blocks(&blocks)?;
+        self.note_change(format!("Marked block {} bad", block));
+        Ok(())
+    }
+
+    // Removes block from the map. It is freed unless the file system still uses it.
+    pub fn clear_bad(&mut self, block: usize) -> Result<()> {
+        let mut blocks = self.bad_blocks()?;
+        let Some(index) = blocks.iter().position(|&bad| bad == block) else {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Block {} is not marked bad", block),
+            ));
+        };
+        blocks.remove(index);
+        self.write_bad_blocks(&blocks)?;
+        if self.referenced_blocks().is_some_and(|used| !used[block]) {
+            self.set_block_free(block);
+            self.update_bitmap_blocks()?;
+        }
+        self.note_change(format!("Cleared bad block {}", block));
+        Ok(())
+    }
+
+    // Reserves the blocks of source's maps on a freshly formatted copy of it, before any
+    // file is written there.
+    pub(crate) fn reserve_mapped_blocks(&mut self, source: &ADF) -> Result<()> {
+        for block in source
+            .bad_blocks()?
+            .into_iter()
+            .chain(source.reserved_blocks()?)
+        {
+            if block < self.num_blocks() {
+                self.set_block_used(block);
+            }
+        }
+        self.update_bitmap_blocks()
+    }
+
+    fn write_bad_blocks(&mut self, blocks: &[usize]) -> Result<()> {
+        self.write_block_map(BAD_BLOCKS_FILE, blocks)
+    }
+
+    pub(crate) fn write_reserved_blocks(&mut self, blocks: &[usize]) -> Result<()> {
+        self.write_block_map(RESERVED_BLOCKS_FILE, blocks)
+    }
+
+    fn write_block_map(&mut self, file: &str, blocks: &[usize]) -> Result<()> {
+        if self.find_entry(file).is_ok() {
+            self.delete(file)?;
+        }
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        // The old map may have lived on a block that has just gone bad.
+        for &block in blocks {
+            self.set_block_used(block);
+        }
+        let text: String = blocks.iter().map(|block| format!("{}\n", block)).collect();
+        self.write_file(file, text.as_bytes())
+    }
+}
@@ -0,0 +1,721 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use crate::bootblock::BOOT_BLOCK_SIZE;
+use crate::disk::{
+    from_amiga_date, latin1_string, read_u32_be, write_u32_be, BLOCK_CHECKSUM, BLOCK_FIRST_DATA,
+    BLOCK_HASH_TABLE, BLOCK_HEADER_KEY, BLOCK_HIGH_SEQ, BLOCK_TABLE_SIZE, BLOCK_TYPE,
+    MAX_COMMENT_LEN, MAX_NAME_LEN, OFS_DATA_LEN, OFS_NEXT_DATA, OFS_SEQ_NUM, ST_FILE, ST_ROOT,
+    ST_USERDIR, T_DATA, T_HEADER, T_LIST,
+};
+use crate::layout::Layout;
+pub use crate::raw::{bitmap_checksum, bootblock_checksum, normal_checksum, ChecksumKind};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::num::NonZeroU32;
+use std::time::SystemTime;
+
+pub(crate) const BLOCK_VOLUME_DAYS: usize = Layout::FLOPPY.volume_days();
+pub(crate) const BLOCK_CREATION_DAYS: usize = Layout::FLOPPY.creation_days();
+const BOOT_CODE_OFFSET: usize = 12;
+const OFS_DATA: usize = 24;
+const ST_SOFTLINK: u32 = 3;
+const ST_LINKDIR: u32 = 4;
+const ST_LINKFILE: u32 = -4i32 as u32;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DateStamp {
+    pub days: u32,
+    pub mins: u32,
+    pub ticks: u32,
+}
+
+impl DateStamp {
+    fn read(block: &[u8], offset: usize) -> Self {
+        DateStamp {
+            days: read_u32_be(block, offset),
+            mins: read_u32_be(block, offset + 4),
+            ticks: read_u32_be(block, offset + 8),
+        }
+    }
+
+    fn write(&self, block: &mut [u8], offset: usize) {
+        write_u32_be(block, offset, self.days);
+        write_u32_be(block, offset + 4, self.mins);
+        write_u32_be(block, offset + 8, self.ticks);
+    }
+
+    pub fn to_system_time(&self) -> SystemTime {
+        from_amiga_date(self.days, self.mins, self.ticks)
+    }
+}
+
+// A block number as stored on disk, where 0 means "no block".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct BlockPtr(pub Option<NonZeroU32>);
+
+impl BlockPtr {
+    pub const NULL: BlockPtr = BlockPtr(None);
+
+    pub fn new(block: usize) -> Self {
+        BlockPtr(NonZeroU32::new(block as u32))
+    }
+
+    pub fn from_raw(raw: u32) -> Self {
+        BlockPtr(NonZeroU32::new(raw))
+    }
+
+    pub fn raw(self) -> u32 {
+        self.0.map_or(0, NonZeroU32::get)
+    }
+
+    pub fn index(self) -> Option<usize> {
+        self.0.map(|block| block.get() as usize)
+    }
+
+    pub fn is_null(self) -> bool {
+        self.0.is_none()
+    }
+
+    fn read(block: &[u8], offset: usize) -> Self {
+        BlockPtr::from_raw(read_u32_be(block, offset))
+    }
+}
+
+impl fmt::Display for BlockPtr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.raw())
+    }
+}
+
+impl Serialize for BlockPtr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.raw())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum SecType {
+    Root,
+    UserDir,
+    SoftLink,
+    LinkDir,
+    File,
+    LinkFile,
+    #[default]
+    None,
+    Unknown(u32),
+}
+
+impl SecType {
+    pub fn from_raw(raw: u32) -> Self {
+        match raw {
+            0 => SecType::None,
+            ST_ROOT => SecType::Root,
+            ST_USERDIR => SecType::UserDir,
+            ST_SOFTLINK => SecType::SoftLink,
+            ST_LINKDIR => SecType::LinkDir,
+            ST_FILE => SecType::File,
+            ST_LINKFILE => SecType::LinkFile,
+            other => SecType::Unknown(other),
+        }
+    }
+
+    pub fn raw(self) -> u32 {
+        match self {
+            SecType::None => 0,
+            SecType::Root => ST_ROOT,
+            SecType::UserDir => ST_USERDIR,
+            SecType::SoftLink => ST_SOFTLINK,
+            SecType::LinkDir => ST_LINKDIR,
+            SecType::File => ST_FILE,
+            SecType::LinkFile => ST_LINKFILE,
+            SecType::Unknown(raw) => raw,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SecType::Root => "ST_ROOT",
+            SecType::UserDir => "ST_USERDIR",
+            SecType::SoftLink => "ST_SOFTLINK",
+            SecType::LinkDir => "ST_LINKDIR",
+            SecType::File => "ST_FILE",
+            SecType::LinkFile => "ST_LINKFILE",
+            SecType::None | SecType::Unknown(_) => "unknown",
+        }
+    }
+
+    fn read(block: &[u8], layout: &Layout) -> Self {
+        SecType::from_raw(read_u32_be(block, layout.sec_type()))
+    }
+}
+
+impl fmt::Display for SecType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.raw() as i32, self.name())
+    }
+}
+
+impl Serialize for SecType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.raw())
+    }
+}
+
+pub fn block_type_name(block_type: u32) -> &'static str {
+    match block_type {
+        T_HEADER => "T_HEADER",
+        T_DATA => "T_DATA",
+        T_LIST => "T_LIST",
+        _ => "unknown",
+    }
+}
+
+fn check_size(block: &[u8], size: usize) -> Result<()> {
+    if block.len() != size {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Block must be {} bytes, got {}", size, block.len()),
+        ));
+    }
+    Ok(())
+}
+
+fn read_bstr(block: &[u8], offset: usize, max_len: usize) -> String {
+    let len = (block[offset] as usize).min(max_len);
+    latin1_string(&block[offset + 1..offset + 1 + len])
+}
+
+// Characters outside Latin-1 are written as '?', longer strings are cut at max_len.
+fn latin1_lossy(value: &str, max_len: usize) -> Vec<u8> {
+    value
+        .chars()
+        .map(|c| u8::try_from(c as u32).unwrap_or(b'?'))
+        .take(max_len)
+        .collect()
+}
+
+fn write_bstr(block: &mut [u8], offset: usize, max_len: usize, value: &str) {
+    let bytes = latin1_lossy(value, max_len);
+    block[offset] = bytes.len() as u8;
+    block[offset + 1..offset + 1 + bytes.len()].copy_from_slice(&bytes);
+}
+
+// Soft link targets are stored as a NUL terminated string in place of the hash table.
+fn softlink_target_len(layout: &Layout) -> usize {
+    layout.block_size - 224
+}
+
+fn read_table(block: &[u8], offset: usize, count: usize) -> Vec<u32> {
+    (0..count)
+        .map(|i| read_u32_be(block, offset + i * 4))
+        .collect()
+}
+
+fn write_table(block: &mut [u8], offset: usize, count: usize, table: &[u32]) {
+    for (i, &value) in table.iter().take(count).enumerate() {
+        write_u32_be(block, offset + i * 4, value);
+    }
+}
+
+fn read_ptr_table(block: &[u8], offset: usize, count: usize) -> Vec<BlockPtr> {
+    (0..count)
+        .map(|i| BlockPtr::read(block, offset + i * 4))
+        .collect()
+}
+
+fn write_ptr_table(block: &mut [u8], offset: usize, count: usize, table: &[BlockPtr]) {
+    for (i, ptr) in table.iter().take(count).enumerate() {
+        write_u32_be(block, offset + i * 4, ptr.raw());
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct BootBlock {
+    pub dos_type: [u8; 4],
+    pub checksum: u32,
+    pub computed_checksum: u32,
+    pub root_block: BlockPtr,
+    pub code: Vec<u8>,
+}
+
+impl BootBlock {
+    pub fn from_bytes(block: &[u8]) -> Result<Self> {
+        check_size(block, BOOT_BLOCK_SIZE)?;
+        Ok(BootBlock {
+            dos_type: [block[0], block[1], block[2], block[3]],
+            checksum: read_u32_be(block, 4),
+            computed_checksum: block.first_chunk().map_or(0, bootblock_checksum),
+            root_block: BlockPtr::read(block, 8),
+            code: block[BOOT_CODE_OFFSET..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut block = vec![0u8; BOOT_BLOCK_SIZE];
+        block[..4].copy_from_slice(&self.dos_type);
+        write_u32_be(&mut block, 4, self.checksum);
+        write_u32_be(&mut block, 8, self.root_block.raw());
+        let len = self.code.len().min(BOOT_BLOCK_SIZE - BOOT_CODE_OFFSET);
+        block[BOOT_CODE_OFFSET..BOOT_CODE_OFFSET + len].copy_from_slice(&self.code[..len]);
+        block
+    }
+
+    pub fn is_dos(&self) -> bool {
+        self.dos_type[..3] == *b"DOS"
+    }
+
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum == self.computed_checksum
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct RootBlock {
+    pub block_type: u32,
+    pub header_key: BlockPtr,
+    pub high_seq: u32,
+    pub hash_table_size: u32,
+    pub first_data: BlockPtr,
+    pub checksum: u32,
+    pub computed_checksum: u32,
+    pub hash_table: Vec<BlockPtr>,
+    pub bm_flag: u32,
+    pub bm_pages: Vec<BlockPtr>,
+    pub bm_ext: BlockPtr,
+    pub modified: DateStamp,
+    pub name: String,
+    pub volume_modified: DateStamp,
+    pub created: DateStamp,
+    pub next_hash: BlockPtr,
+    pub parent: BlockPtr,
+    pub extension: BlockPtr,
+    pub sec_type: SecType,
+}
+
+impl RootBlock {
+    pub fn from_bytes(block: &[u8], layout: &Layout) -> Result<Self> {
+        check_size(block, layout.block_size)?;
+        Ok(RootBlock {
+            block_type: read_u32_be(block, BLOCK_TYPE),
+            header_key: BlockPtr::read(block, BLOCK_HEADER_KEY),
+            high_seq: read_u32_be(block, BLOCK_HIGH_SEQ),
+            hash_table_size: read_u32_be(block, BLOCK_TABLE_SIZE),
+            first_data: BlockPtr::read(block, BLOCK_FIRST_DATA),
+            checksum: read_u32_be(block, BLOCK_CHECKSUM),
+            computed_checksum: normal_checksum(block),
+            hash_table: read_ptr_table(block, BLOCK_HASH_TABLE, layout.hash_table_size()),
+            bm_flag: read_u32_be(block, layout.bm_flag()),
+            bm_pages: read_ptr_table(block, layout.bm_pages(), layout.root_bitmap_pages()),
+            bm_ext: BlockPtr::read(block, layout.bm_ext()),
+            modified: DateStamp::read(block, layout.days()),
+            name: read_bstr(block, layout.name(), MAX_NAME_LEN),
+            volume_modified: DateStamp::read(block, layout.volume_days()),
+            created: DateStamp::read(block, layout.creation_days()),
+            next_hash: BlockPtr::read(block, layout.hash_chain()),
+            parent: BlockPtr::read(block, layout.parent()),
+            extension: BlockPtr::read(block, layout.extension()),
+            sec_type: SecType::read(block, layout),
+        })
+    }
+
+    // Writes the stored checksum as is; callers that changed fields recompute it afterwards.
+    pub fn to_bytes(&self, layout: &Layout) -> Vec<u8> {
+        let mut block = vec![0u8; layout.block_size];
+        write_u32_be(&mut block, BLOCK_TYPE, self.block_type);
+        write_u32_be(&mut block, BLOCK_HEADER_KEY, self.header_key.raw());
+        write_u32_be(&mut block, BLOCK_HIGH_SEQ, self.high_seq);
+        write_u32_be(&mut block, BLOCK_TABLE_SIZE, self.hash_table_size);
+        write_u32_be(&mut block, BLOCK_FIRST_DATA, self.first_data.raw());
+        write_u32_be(&mut block, BLOCK_CHECKSUM, self.checksum);
+        write_ptr_table(
+            &mut block,
+            BLOCK_HASH_TABLE,
+            layout.hash_table_size(),
+            &self.hash_table,
+        );
+        write_u32_be(&mut block, layout.bm_flag(), self.bm_flag);
+        write_ptr_table(
+            &mut block,
+            layout.bm_pages(),
+            layout.root_bitmap_pages(),
+            &self.bm_pages,
+        );
+        write_u32_be(&mut block, layout.bm_ext(), self.bm_ext.raw());
+        self.modified.write(&mut block, layout.days());
+        write_bstr(&mut block, layout.name(), MAX_NAME_LEN, &self.name);
+        self.volume_modified.write(&mut block, layout.volume_days());
+        self.created.write(&mut block, layout.creation_days());
+        write_u32_be(&mut block, layout.hash_chain(), self.next_hash.raw());
+        write_u32_be(&mut block, layout.parent(), self.parent.raw());
+        write_u32_be(&mut block, layout.extension(), self.extension.raw());
+        write_u32_be(&mut block, layout.sec_type(), self.sec_type.raw());
+        block
+    }
+
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum == self.computed_checksum
+    }
+}
+
+// File and directory headers share one layout; for files the table lists data blocks in
+// reverse order, for directories it is the hash table.
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct HeaderBlock {
+    pub block_type: u32,
+    pub header_key: BlockPtr,
+    pub high_seq: u32,
+    pub data_size: u32,
+    pub first_data: BlockPtr,
+    pub checksum: u32,
+    pub computed_checksum: u32,
+    pub table: Vec<BlockPtr>,
+    pub protect: u32,
+    pub byte_size: u32,
+    pub comment: String,
+    pub date: DateStamp,
+    pub name: String,
+    pub real_entry: BlockPtr,
+    pub next_link: BlockPtr,
+    pub hash_chain: BlockPtr,
+    pub parent: BlockPtr,
+    pub extension: BlockPtr,
+    pub sec_type: SecType,
+}
+
+impl HeaderBlock {
+    pub fn from_bytes(block: &[u8], layout: &Layout) -> Result<Self> {
+        check_size(block, layout.block_size)?;
+        Ok(HeaderBlock {
+            block_type: read_u32_be(block, BLOCK_TYPE),
+            header_key: BlockPtr::read(block, BLOCK_HEADER_KEY),
+            high_seq: read_u32_be(block, BLOCK_HIGH_SEQ),
+            data_size: read_u32_be(block, BLOCK_TABLE_SIZE),
+            first_data: BlockPtr::read(block, BLOCK_FIRST_DATA),
+            checksum: read_u32_be(block, BLOCK_CHECKSUM),
+            computed_checksum: normal_checksum(block),
+            table: read_ptr_table(block, BLOCK_HASH_TABLE, layout.hash_table_size()),
+            protect: read_u32_be(block, layout.protect()),
+            byte_size: read_u32_be(block, layout.byte_size()),
+            comment: read_bstr(block, layout.comment(), MAX_COMMENT_LEN),
+            date: DateStamp::read(block, layout.days()),
+            name: read_bstr(block, layout.name(), MAX_NAME_LEN),
+            real_entry: BlockPtr::read(block, layout.real_entry()),
+            next_link: BlockPtr::read(block, layout.next_link()),
+            hash_chain: BlockPtr::read(block, layout.hash_chain()),
+            parent: BlockPtr::read(block, layout.parent()),
+            extension: BlockPtr::read(block, layout.extension()),
+            sec_type: SecType::read(block, layout),
+        })
+    }
+
+    pub fn to_bytes(&self, layout: &Layout) -> Vec<u8> {
+        let mut block = vec![0u8; layout.block_size];
+        write_u32_be(&mut block, BLOCK_TYPE, self.block_type);
+        write_u32_be(&mut block, BLOCK_HEADER_KEY, self.header_key.raw());
+        write_u32_be(&mut block, BLOCK_HIGH_SEQ, self.high_seq);
+        write_u32_be(&mut block, BLOCK_TABLE_SIZE, self.data_size);
+        write_u32_be(&mut block, BLOCK_FIRST_DATA, self.first_data.raw());
+        write_u32_be(&mut block, BLOCK_CHECKSUM, self.checksum);
+        write_ptr_table(
+            &mut block,
+            BLOCK_HASH_TABLE,
+            layout.hash_table_size(),
+            &self.table,
+        );
+        write_u32_be(&mut block, layout.protect(), self.protect);
+        write_u32_be(&mut block, layout.byte_size(), self.byte_size);
+        write_bstr(&mut block, layout.comment(), MAX_COMMENT_LEN, &self.comment);
+        self.date.write(&mut block, layout.days());
+        write_bstr(&mut block, layout.name(), MAX_NAME_LEN, &self.name);
+        write_u32_be(&mut block, layout.real_entry(), self.real_entry.raw());
+        write_u32_be(&mut block, layout.next_link(), self.next_link.raw());
+        write_u32_be(&mut block, layout.hash_chain(), self.hash_chain.raw());
+        write_u32_be(&mut block, layout.parent(), self.parent.raw());
+        write_u32_be(&mut block, layout.extension(), self.extension.raw());
+        write_u32_be(&mut block, layout.sec_type(), self.sec_type.raw());
+        block
+    }
+
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum == self.computed_checksum
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.sec_type == SecType::UserDir
+    }
+
+    pub fn data_blocks(&self) -> Vec<BlockPtr> {
+        table_data_blocks(&self.table, self.high_seq)
+    }
+
+    pub fn set_data_blocks(&mut self, layout: &Layout, blocks: &[usize]) {
+        self.high_seq = blocks.len() as u32;
+        self.table = data_block_table(layout, blocks);
+    }
+}
+
+// File headers and extension blocks list their data blocks from the end of the table, the
+// first data block in the last slot.
+fn table_data_blocks(table: &[BlockPtr], high_seq: u32) -> Vec<BlockPtr> {
+    table
+        .iter()
+        .rev()
+        .take((high_seq as usize).min(table.len()))
+        .copied()
+        .collect()
+}
+
+fn data_block_table(layout: &Layout, blocks: &[usize]) -> Vec<BlockPtr> {
+    let mut table = vec![BlockPtr::NULL; layout.hash_table_size()];
+    for (slot, &block) in table.iter_mut().rev().zip(blocks) {
+        *slot = BlockPtr::new(block);
+    }
+    table
+}
+
+// Holds the data block pointers of a file that do not fit in its header, chained through
+// extension like the header itself.
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FileExtBlock {
+    pub block_type: u32,
+    pub header_key: BlockPtr,
+    pub high_seq: u32,
+    pub checksum: u32,
+    pub computed_checksum: u32,
+    pub table: Vec<BlockPtr>,
+    pub parent: BlockPtr,
+    pub extension: BlockPtr,
+    pub sec_type: SecType,
+}
+
+impl FileExtBlock {
+    pub fn new(layout: &Layout, block: usize, header: usize, data_blocks: &[usize]) -> Self {
+        FileExtBlock {
+            block_type: T_LIST,
+            header_key: BlockPtr::new(block),
+            high_seq: data_blocks.len() as u32,
+            table: data_block_table(layout, data_blocks),
+            parent: BlockPtr::new(header),
+            sec_type: SecType::File,
+            ..FileExtBlock::default()
+        }
+    }
+
+    pub fn from_bytes(block: &[u8], layout: &Layout) -> Result<Self> {
+        check_size(block, layout.block_size)?;
+        Ok(FileExtBlock {
+            block_type: read_u32_be(block, BLOCK_TYPE),
+            header_key: BlockPtr::read(block, BLOCK_HEADER_KEY),
+            high_seq: read_u32_be(block, BLOCK_HIGH_SEQ),
+            checksum: read_u32_be(block, BLOCK_CHECKSUM),
+            computed_checksum: normal_checksum(block),
+            table: read_ptr_table(block, BLOCK_HASH_TABLE, layout.hash_table_size()),
+            parent: BlockPtr::read(block, layout.parent()),
+            extension: BlockPtr::read(block, layout.extension()),
+            sec_type: SecType::read(block, layout),
+        })
+    }
+
+    pub fn to_bytes(&self, layout: &Layout) -> Vec<u8> {
+        let mut block = vec![0u8; layout.block_size];
+        write_u32_be(&mut block, BLOCK_TYPE, self.block_type);
+        write_u32_be(&mut block, BLOCK_HEADER_KEY, self.header_key.raw());
+        write_u32_be(&mut block, BLOCK_HIGH_SEQ, self.high_seq);
+        write_u32_be(&mut block, BLOCK_CHECKSUM, self.checksum);
+        write_ptr_table(
+            &mut block,
+            BLOCK_HASH_TABLE,
+            layout.hash_table_size(),
+            &self.table,
+        );
+        write_u32_be(&mut block, layout.parent(), self.parent.raw());
+        write_u32_be(&mut block, layout.extension(), self.extension.raw());
+        write_u32_be(&mut block, layout.sec_type(), self.sec_type.raw());
+        block
+    }
+
+    pub fn data_blocks(&self) -> Vec<BlockPtr> {
+        table_data_blocks(&self.table, self.high_seq)
+    }
+
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum == self.computed_checksum
+    }
+}
+
+// Hard links point at the real entry, soft links carry the target path instead.
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct LinkBlock {
+    pub block_type: u32,
+    pub header_key: BlockPtr,
+    pub checksum: u32,
+    pub computed_checksum: u32,
+    pub target: String,
+    pub date: DateStamp,
+    pub name: String,
+    pub real_entry: BlockPtr,
+    pub next_link: BlockPtr,
+    pub hash_chain: BlockPtr,
+    pub parent: BlockPtr,
+    pub sec_type: SecType,
+}
+
+impl LinkBlock {
+    pub fn from_bytes(block: &[u8], layout: &Layout) -> Result<Self> {
+        check_size(block, layout.block_size)?;
+        let target = &block[BLOCK_HASH_TABLE..BLOCK_HASH_TABLE + softlink_target_len(layout)];
+        let target_len = target.iter().position(|&b| b == 0).unwrap_or(target.len());
+        Ok(LinkBlock {
+            block_type: read_u32_be(block, BLOCK_TYPE),
+            header_key: BlockPtr::read(block, BLOCK_HEADER_KEY),
+            checksum: read_u32_be(block, BLOCK_CHECKSUM),
+            computed_checksum: normal_checksum(block),
+            target: latin1_string(&target[..target_len]),
+            date: DateStamp::read(block, layout.days()),
+            name: read_bstr(block, layout.name(), MAX_NAME_LEN),
+            real_entry: BlockPtr::read(block, layout.real_entry()),
+            next_link: BlockPtr::read(block, layout.next_link()),
+            hash_chain: BlockPtr::read(block, layout.hash_chain()),
+            parent: BlockPtr::read(block, layout.parent()),
+            sec_type: SecType::read(block, layout),
+        })
+    }
+
+    pub fn to_bytes(&self, layout: &Layout) -> Vec<u8> {
+        let mut block = vec![0u8; layout.block_size];
+        write_u32_be(&mut block, BLOCK_TYPE, self.block_type);
+        write_u32_be(&mut block, BLOCK_HEADER_KEY, self.header_key.raw());
+        write_u32_be(&mut block, BLOCK_CHECKSUM, self.checksum);
+        let target = latin1_lossy(&self.target, softlink_target_len(layout) - 1);
+        block[BLOCK_HASH_TABLE..BLOCK_HASH_TABLE + target.len()].copy_from_slice(&target);
+        self.date.write(&mut block, layout.days());
+        write_bstr(&mut block, layout.name(), MAX_NAME_LEN, &self.name);
+        write_u32_be(&mut block, layout.real_entry(), self.real_entry.raw());
+        write_u32_be(&mut block, layout.next_link(), self.next_link.raw());
+        write_u32_be(&mut block, layout.hash_chain(), self.hash_chain.raw());
+        write_u32_be(&mut block, layout.parent(), self.parent.raw());
+        write_u32_be(&mut block, layout.sec_type(), self.sec_type.raw());
+        block
+    }
+
+    pub fn is_soft(&self) -> bool {
+        self.sec_type == SecType::SoftLink
+    }
+
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum == self.computed_checksum
+    }
+}
+
+// OFS data block. FFS data blocks are raw sectors without a header.
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DataBlock {
+    pub block_type: u32,
+    pub header_key: BlockPtr,
+    pub seq_num: u32,
+    pub data_size: u32,
+    pub next_data: BlockPtr,
+    pub checksum: u32,
+    pub computed_checksum: u32,
+    pub data: Vec<u8>,
+}
+
+impl DataBlock {
+    pub fn from_bytes(block: &[u8], layout: &Layout) -> Result<Self> {
+        check_size(block, layout.block_size)?;
+        Ok(DataBlock {
+            block_type: read_u32_be(block, BLOCK_TYPE),
+            header_key: BlockPtr::read(block, BLOCK_HEADER_KEY),
+            seq_num: read_u32_be(block, OFS_SEQ_NUM),
+            data_size: read_u32_be(block, OFS_DATA_LEN),
+            next_data: BlockPtr::read(block, OFS_NEXT_DATA),
+            checksum: read_u32_be(block, BLOCK_CHECKSUM),
+            computed_checksum: normal_checksum(block),
+            data: block[OFS_DATA..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self, layout: &Layout) -> Vec<u8> {
+        let mut block = vec![0u8; layout.block_size];
+        write_u32_be(&mut block, BLOCK_TYPE, self.block_type);
+        write_u32_be(&mut block, BLOCK_HEADER_KEY, self.header_key.raw());
+        write_u32_be(&mut block, OFS_SEQ_NUM, self.seq_num);
+        write_u32_be(&mut block, OFS_DATA_LEN, self.data_size);
+        write_u32_be(&mut block, OFS_NEXT_DATA, self.next_data.raw());
+        write_u32_be(&mut block, BLOCK_CHECKSUM, self.checksum);
+        let len = self.data.len().min(layout.ofs_data_size());
+        block[OFS_DATA..OFS_DATA + len].copy_from_slice(&self.data[..len]);
+        block
+    }
+
+    // The payload as declared by data_size, clamped to what fits in the block.
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..(self.data_size as usize).min(self.data.len())]
+    }
+
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum == self.computed_checksum
+    }
+}
+
+// One bitmap page; a set bit marks a free block, starting with block 2 in bit 0 of map[0].
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct BitmapBlock {
+    pub checksum: u32,
+    pub computed_checksum: u32,
+    pub map: Vec<u32>,
+}
+
+impl BitmapBlock {
+    pub fn from_bytes(block: &[u8], layout: &Layout) -> Result<Self> {
+        check_size(block, layout.block_size)?;
+        Ok(BitmapBlock {
+            checksum: read_u32_be(block, 0),
+            computed_checksum: bitmap_checksum(block),
+            map: read_table(block, 4, layout.block_size / 4 - 1),
+        })
+    }
+
+    pub fn to_bytes(&self, layout: &Layout) -> Vec<u8> {
+        let mut block = vec![0u8; layout.block_size];
+        write_u32_be(&mut block, 0, self.checksum);
+        write_table(&mut block, 4, layout.block_size / 4 - 1, &self.map);
+        block
+    }
+
+    pub fn is_free(&self, bit: usize) -> bool {
+        self.map
+            .get(bit / 32)
+            .is_some_and(|word| word & (1 << (bit % 32)) != 0)
+    }
+
+    pub fn set_free(&mut self, bit: usize, free: bool) {
+        if self.map.len() <= bit / 32 {
+            self.map.resize(bit / 32 + 1, 0);
+        }
+        if free {
+            self.map[bit / 32] |= 1 << (bit % 32);
+        } else {
+            self.map[bit / 32] &= !(1 << (bit % 32));
+        }
+    }
+
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum == self.computed_checksum
+    }
+}
@@ -0,0 +1,236 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use crate::block::bootblock_checksum;
+use crate::disk::{read_u32_be, ADF, ADF_SECTOR_SIZE, BOOT_CODE};
+use serde::Serialize;
+use std::fmt;
+
+pub use crate::raw::BOOT_BLOCK_SIZE;
+
+const EXEC_VECTORS: [(u16, &str); 5] = [
+    (0x002a, "ColdCapture"),
+    (0x002e, "CoolCapture"),
+    (0x0032, "WarmCapture"),
+    (0x0222, "KickMemPtr"),
+    (0x0226, "KickTagPtr"),
+];
+
+// Boot code written by Install from Kickstart 2.04 on; 3.0 and 3.1 write the same bytes.
+// It sets bit 6 of the expansion.library flags before finding dos.library, as the 1.3 code
+// does. Only built with the bootblocks feature.
+#[cfg(feature = "bootblocks")]
+const BOOT_CODE_20: [u8; 82] = [
+    0x43, 0xfa, 0x00, 0x3e, 0x70, 0x25, 0x4e, 0xae, 0xfd, 0xd8, 0x4a, 0x80, 0x67, 0x0c, 0x22, 0x40,
+    0x08, 0xe9, 0x00, 0x06, 0x00, 0x22, 0x4e, 0xae, 0xfe, 0x62, 0x43, 0xfa, 0x00, 0x18, 0x4e, 0xae,
+    0xff, 0xa0, 0x4a, 0x80, 0x67, 0x0a, 0x20, 0x40, 0x20, 0x68, 0x00, 0x16, 0x70, 0x00, 0x4e, 0x75,
+    0x70, 0xff, 0x4e, 0x75, 0x64, 0x6f, 0x73, 0x2e, 0x6c, 0x69, 0x62, 0x72, 0x61, 0x72, 0x79, 0x00,
+    0x65, 0x78, 0x70, 0x61, 0x6e, 0x73, 0x69, 0x6f, 0x6e, 0x2e, 0x6c, 0x69, 0x62, 0x72, 0x61, 0x72,
+    0x79, 0x00,
+];
+
+// The Kickstart whose Install command wrote a boot block. The code is the same for OFS and
+// FFS disks, only the DOS type in front of it differs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum Kickstart {
+    #[default]
+    V13,
+    V2x,
+    V31,
+}
+
+impl Kickstart {
+    pub const ALL: [Kickstart; 3] = [Kickstart::V13, Kickstart::V2x, Kickstart::V31];
+
+    // The 2.x and 3.1 code needs the bootblocks feature.
+    pub fn boot_code(self) -> std::io::Result<&'static [u8]> {
+        match self {
+            Kickstart::V13 => Ok(&BOOT_CODE),
+            #[cfg(feature = "bootblocks")]
+            Kickstart::V2x | Kickstart::V31 => Ok(&BOOT_CODE_20),
+            #[cfg(not(feature = "bootblocks"))]
+            Kickstart::V2x | Kickstart::V31 => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Kickstart 2.x and 3.1 boot blocks need the bootblocks feature",
+            )),
+        }
+    }
+
+    // The oldest Kickstart that writes code, None for unknown code.
+    pub fn identify(code: &[u8]) -> Option<Kickstart> {
+        Kickstart::ALL.into_iter().find(|kickstart| {
+            kickstart.boot_code().is_ok_and(|known| {
+                code.starts_with(known) && code[known.len()..].iter().all(|&b| b == 0)
+            })
+        })
+    }
+}
+
+impl fmt::Display for Kickstart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kickstart::V13 => write!(f, "1.3"),
+            Kickstart::V2x => write!(f, "2.x"),
+            Kickstart::V31 => write!(f, "3.1"),
+        }
+    }
+}
+
+impl std::str::FromStr for Kickstart {
+    type Err = std::io::Error;
+
+    fn from_str(version: &str) -> std::io::Result<Kickstart> {
+        match version {
+            "1.3" | "13" => Ok(Kickstart::V13),
+            "2" | "2.x" | "2.0" | "2.04" | "2.05" => Ok(Kickstart::V2x),
+            "3" | "3.x" | "3.0" | "3.1" => Ok(Kickstart::V31),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown Kickstart '{}', use 1.3, 2.x or 3.1", version),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BootCode {
+    None,
+    Standard,
+    Custom,
+}
+
+impl fmt::Display for BootCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootCode::None => write!(f, "none"),
+            BootCode::Standard => write!(f, "standard AmigaDOS boot code"),
+            BootCode::Custom => write!(f, "custom"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BootBlockInfo {
+    pub dos_type: String,
+    pub ffs: bool,
+    pub intl: bool,
+    pub dircache: bool,
+    pub checksum: u32,
+    pub computed_checksum: u32,
+    pub root_block: u32,
+    pub code: BootCode,
+    pub warnings: Vec<String>,
+}
+
+impl BootBlockInfo {
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum == self.computed_checksum
+    }
+}
+
+impl fmt::Display for BootBlockInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "DOS Type:   {}", self.dos_type)?;
+        writeln!(
+            f,
+            "Flags:      {}{}{}",
+            if self.ffs { "FFS" } else { "OFS" },
+            if self.intl { " INTL" } else { "" },
+            if self.dircache { " DIRCACHE" } else { "" }
+        )?;
+        writeln!(
+            f,
+            "Checksum:   0x{:08x} ({})",
+            self.checksum,
+            if self.checksum_valid() {
+                "valid".to_string()
+            } else {
+                format!("invalid, expected 0x{:08x}", self.computed_checksum)
+            }
+        )?;
+        writeln!(f, "Root Block: {}", self.root_block)?;
+        write!(f, "Boot Code:  {}", self.code)
+    }
+}
+
+pub fn analyze(boot_block: &[u8; BOOT_BLOCK_SIZE]) -> BootBlockInfo {
+    let flags = boot_block[3];
+    let dos_type = if &boot_block[0..3] == b"DOS" {
+        format!("DOS\\{}", flags)
+    } else {
+        boot_block[0..4]
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() {
+                    (b as char).to_string()
+                } else {
+                    format!("\\x{:02x}", b)
+                }
+            })
+            .collect()
+    };
+
+    let code_area = &boot_block[12..];
+    let code = if code_area.iter().all(|&b| b == 0) {
+        BootCode::None
+    } else if Kickstart::identify(code_area).is_some() {
+        BootCode::Standard
+    } else {
+        BootCode::Custom
+    };
+
+    BootBlockInfo {
+        dos_type,
+        ffs: flags & 1 != 0,
+        intl: flags & 2 != 0,
+        dircache: flags & 4 != 0,
+        checksum: read_u32_be(boot_block, 4),
+        computed_checksum: bootblock_checksum(boot_block),
+        root_block: read_u32_be(boot_block, 8),
+        code,
+        warnings: scan(boot_block),
+    }
+}
+
+pub fn scan(boot_block: &[u8]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let code = &boot_block[12..];
+    for offset in (0..code.len().saturating_sub(4)).step_by(2) {
+        let opcode = u16::from_be_bytes([code[offset], code[offset + 1]]);
+        // move.l <ea>,d16(a6)
+        if opcode & 0xffc0 != 0x2d40 {
+            continue;
+        }
+        let source_words = match (opcode >> 3) & 7 {
+            5 | 6 => 1,
+            7 => match opcode & 7 {
+                0 | 2 | 3 => 1,
+                1 | 4 => 2,
+                _ => continue,
+            },
+            _ => 0,
+        };
+        let displacement_offset = offset + 2 + source_words * 2;
+        if displacement_offset + 2 > code.len() {
+            continue;
+        }
+        let displacement =
+            u16::from_be_bytes([code[displacement_offset], code[displacement_offset + 1]]);
+        if let Some((_, name)) = EXEC_VECTORS.iter().find(|(d, _)| *d == displacement) {
+            warnings.push(format!(
+                "Code at offset 0x{:03x} patches ExecBase->{}, typical of boot block viruses",
+                offset + 12,
+                name
+            ));
+        }
+    }
+    warnings
+}
+
+impl ADF {
+    pub fn boot_block_info(&self) -> BootBlockInfo {
+        analyze(self.read_boot_block())
+    }
+}
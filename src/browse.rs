@@ -0,0 +1,368 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use adflib::disk::{format_creation_date, hex_dump, join_path, FileInfo, ADF};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::io::Result;
+
+const PREVIEW_LIMIT: usize = 64 * 1024;
+const HELP: &str =
+    "Enter open  ← parent  x extract  r rename  d delete  Tab hex/text  PgUp/PgDn scroll  q quit";
+
+enum Prompt {
+    None,
+    Extract(String),
+    Rename(String),
+    Delete,
+    Quit,
+}
+
+struct Browser {
+    adf: ADF,
+    dir: String,
+    // None is the ".." entry leading to the parent directory.
+    entries: Vec<Option<FileInfo>>,
+    list: ListState,
+    hex: bool,
+    scroll: u16,
+    preview: Vec<String>,
+    prompt: Prompt,
+    message: String,
+    modified: bool,
+}
+
+// Runs the browser and returns the image if the user chose to save changes.
+pub fn browse(adf: ADF, title: &str) -> Result<Option<ADF>> {
+    let mut browser = Browser {
+        adf,
+        dir: String::new(),
+        entries: Vec::new(),
+        list: ListState::default(),
+        hex: false,
+        scroll: 0,
+        preview: Vec::new(),
+        prompt: Prompt::None,
+        message: String::new(),
+        modified: false,
+    };
+    browser.load_dir(String::new(), None)?;
+
+    let mut terminal = ratatui::init();
+    let result = browser.run(&mut terminal, title);
+    ratatui::restore();
+    result
+}
+
+impl Browser {
+    fn run(mut self, terminal: &mut DefaultTerminal, title: &str) -> Result<Option<ADF>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame, title))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let prompt = std::mem::replace(&mut self.prompt, Prompt::None);
+            match prompt {
+                Prompt::None => {
+                    if !self.handle_key(key.code)? {
+                        return Ok(None);
+                    }
+                }
+                Prompt::Quit => match key.code {
+                    KeyCode::Char('y') => return Ok(Some(self.adf)),
+                    KeyCode::Char('n') => return Ok(None),
+                    _ => {}
+                },
+                Prompt::Delete => {
+                    if key.code == KeyCode::Char('y') {
+                        self.delete_selected();
+                    }
+                }
+                Prompt::Extract(_) | Prompt::Rename(_) if key.code == KeyCode::Esc => {}
+                Prompt::Extract(input) if key.code == KeyCode::Enter => {
+                    self.extract_selected(&input)
+                }
+                Prompt::Rename(input) if key.code == KeyCode::Enter => self.rename_selected(&input),
+                Prompt::Extract(mut input) => {
+                    edit(&mut input, key.code);
+                    self.prompt = Prompt::Extract(input);
+                }
+                Prompt::Rename(mut input) => {
+                    edit(&mut input, key.code);
+                    self.prompt = Prompt::Rename(input);
+                }
+            }
+        }
+    }
+
+    // Returns false when the browser should exit without saving.
+    fn handle_key(&mut self, code: KeyCode) -> Result<bool> {
+        self.message.clear();
+        let selected = self.list.selected().unwrap_or(0);
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                if !self.modified {
+                    return Ok(false);
+                }
+                self.prompt = Prompt::Quit;
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.select(selected.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.select(selected + 1),
+            KeyCode::Home => self.select(0),
+            KeyCode::End => self.select(self.entries.len().saturating_sub(1)),
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(16),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_add(16),
+            KeyCode::Tab => {
+                self.hex = !self.hex;
+                self.refresh_preview();
+            }
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => match self.selected() {
+                Some(None) => self.leave_dir()?,
+                Some(Some(info)) if info.is_dir => {
+                    let path = join_path(&self.dir, &info.name);
+                    self.load_dir(path, None)?;
+                }
+                _ => {}
+            },
+            KeyCode::Left | KeyCode::Backspace | KeyCode::Char('h') if !self.dir.is_empty() => {
+                self.leave_dir()?
+            }
+            KeyCode::Char('x') => {
+                if let Some(Some(info)) = self.selected() {
+                    self.prompt = Prompt::Extract(info.name.clone());
+                }
+            }
+            KeyCode::Char('r') => {
+                if let Some(Some(info)) = self.selected() {
+                    self.prompt = Prompt::Rename(info.name.clone());
+                }
+            }
+            KeyCode::Char('d') | KeyCode::Delete => {
+                if let Some(Some(_)) = self.selected() {
+                    self.prompt = Prompt::Delete;
+                }
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn selected(&self) -> Option<&Option<FileInfo>> {
+        self.list
+            .selected()
+            .and_then(|index| self.entries.get(index))
+    }
+
+    fn selected_path(&self) -> Option<String> {
+        match self.selected() {
+            Some(Some(info)) => Some(join_path(&self.dir, &info.name)),
+            _ => None,
+        }
+    }
+
+    fn select(&mut self, index: usize) {
+        if !self.entries.is_empty() {
+            self.list.select(Some(index.min(self.entries.len() - 1)));
+        }
+        self.scroll = 0;
+        self.refresh_preview();
+    }
+
+    fn load_dir(&mut self, dir: String, select_name: Option<&str>) -> Result<()> {
+        let block = self.adf.find_entry(&dir)?;
+        let mut entries = self
+            .adf
+            .list_directory(block)
+            .collect::<Result<Vec<FileInfo>>>()?;
+        entries.sort_by_key(|info| (!info.is_dir, info.name.to_lowercase()));
+
+        self.entries = entries.into_iter().map(Some).collect();
+        if !dir.is_empty() {
+            self.entries.insert(0, None);
+        }
+        self.dir = dir;
+        let index = select_name
+            .and_then(|name| {
+                self.entries
+                    .iter()
+                    .position(|entry| matches!(entry, Some(info) if info.name == name))
+            })
+            .unwrap_or(0);
+        self.list.select(Some(index));
+        self.scroll = 0;
+        self.refresh_preview();
+        Ok(())
+    }
+
+    fn leave_dir(&mut self) -> Result<()> {
+        let (parent, name) = match self.dir.rsplit_once('/') {
+            Some((parent, name)) => (parent.to_string(), name.to_string()),
+            None => (String::new(), self.dir.clone()),
+        };
+        self.load_dir(parent, Some(&name))
+    }
+
+    fn reload(&mut self, select_name: Option<&str>) {
+        let dir = self.dir.clone();
+        if let Err(e) = self.load_dir(dir, select_name) {
+            self.message = format!("Error: {}", e);
+        }
+    }
+
+    fn refresh_preview(&mut self) {
+        let Some(Some(info)) = self.selected() else {
+            self.preview = vec!["Parent directory".to_string()];
+            return;
+        };
+        let mut lines = vec![
+            format!("Name:       {}", info.name),
+            format!(
+                "Protection: {}",
+                self.adf.format_protection_flags(info.protection)
+            ),
+            format!("Date:       {}", format_creation_date(info.creation_date)),
+        ];
+        if !info.comment.is_empty() {
+            lines.push(format!("Comment:    {}", info.comment));
+        }
+        let path = join_path(&self.dir, &info.name);
+        if info.is_dir {
+            let count = self
+                .adf
+                .find_entry(&path)
+                .map(|block| self.adf.list_directory(block).count())
+                .unwrap_or(0);
+            lines.push(format!("Directory with {} entries", count));
+        } else {
+            lines.push(format!("Size:       {} bytes", info.size));
+            lines.push(String::new());
+            match self.adf.read_file(&path) {
+                Ok(data) => {
+                    let data = &data[..data.len().min(PREVIEW_LIMIT)];
+                    if self.hex || !is_text(data) {
+                        lines.extend(hex_dump(data, 0).lines().map(str::to_string));
+                    } else {
+                        let text: String = data.iter().map(|&b| b as char).collect();
+                        lines.extend(text.lines().map(str::to_string));
+                    }
+                }
+                Err(e) => lines.push(format!("Cannot read file: {}", e)),
+            }
+        }
+        self.preview = lines;
+    }
+
+    fn extract_selected(&mut self, target: &str) {
+        let (Some(path), Some(Some(info))) = (self.selected_path(), self.selected()) else {
+            return;
+        };
+        let result = if info.is_dir {
+            self.adf.export_dir(&path, target)
+        } else {
+            self.adf
+                .read_file(&path)
+                .and_then(|data| std::fs::write(target, data))
+        };
+        self.message = match result {
+            Ok(()) => format!("Extracted {} to {}", path, target),
+            Err(e) => format!("Error: {}", e),
+        };
+    }
+
+    fn rename_selected(&mut self, input: &str) {
+        let Some(path) = self.selected_path() else {
+            return;
+        };
+        let target = match input.strip_prefix('/') {
+            Some(absolute) => absolute.to_string(),
+            None => join_path(&self.dir, input),
+        };
+        match self.adf.rename(&path, &target) {
+            Ok(()) => {
+                self.modified = true;
+                self.message = format!("Renamed {} to {}", path, target);
+                let name = target.rsplit('/').next().unwrap_or(&target).to_string();
+                self.reload(Some(&name));
+            }
+            Err(e) => self.message = format!("Error: {}", e),
+        }
+    }
+
+    fn delete_selected(&mut self) {
+        let Some(path) = self.selected_path() else {
+            return;
+        };
+        let index = self.list.selected().unwrap_or(0);
+        match self.adf.delete(&path) {
+            Ok(()) => {
+                self.modified = true;
+                self.message = format!("Deleted {}", path);
+                self.reload(None);
+                self.select(index);
+            }
+            Err(e) => self.message = format!("Error: {}", e),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame, title: &str) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                None => ListItem::new("..").bold(),
+                Some(info) if info.is_dir => ListItem::new(format!("{}/", info.name)).bold(),
+                Some(info) => ListItem::new(format!("{:<30} {:>7}", info.name, info.size)),
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(format!(" {}:{} ", title, self.dir)))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, left, &mut self.list);
+
+        let preview: Vec<Line> = self.preview.iter().map(|l| Line::raw(l.as_str())).collect();
+        let preview = Paragraph::new(preview)
+            .block(Block::bordered().title(if self.hex { " Hex " } else { " Preview " }))
+            .scroll((self.scroll, 0));
+        frame.render_widget(preview, right);
+
+        let status_line = match &self.prompt {
+            Prompt::None if self.message.is_empty() => HELP.to_string(),
+            Prompt::None => self.message.clone(),
+            Prompt::Extract(input) => format!("Extract to host path: {}_", input),
+            Prompt::Rename(input) => format!("Rename to: {}_", input),
+            Prompt::Delete => "Delete selected entry? (y/n)".to_string(),
+            Prompt::Quit => "Save changes to the image? (y/n, Esc to cancel)".to_string(),
+        };
+        frame.render_widget(Paragraph::new(status_line).reversed(), status);
+    }
+}
+
+fn edit(input: &mut String, code: KeyCode) {
+    match code {
+        KeyCode::Char(c) => input.push(c),
+        KeyCode::Backspace => {
+            input.pop();
+        }
+        _ => {}
+    }
+}
+
+fn is_text(data: &[u8]) -> bool {
+    data.iter()
+        .all(|&b| b == b'\n' || b == b'\t' || b == b'\r' || (0x20..0x7f).contains(&b) || b >= 0xa0)
+}
@@ -0,0 +1,1376 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Shared with build.rs, which includes this file to render the man page.
+
+use clap::{Arg, ArgGroup, Command};
+
+pub fn build_cli() -> Command {
+    Command::new("adflibtesttool")
+        .bin_name("adflibtesttool")
+        .version("0.0.1")
+        .author("Volker Schwaberow <volker@schwaberow.de>")
+        .about("ADFlib test tool")
+        .arg(output_format_arg())
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .global(true)
+                .action(clap::ArgAction::SetTrue)
+                .help("Suppress progress bars and log output"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .global(true)
+                .action(clap::ArgAction::Count)
+                .conflicts_with("quiet")
+                .help("Log each operation; repeat to also log sector writes"),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .global(true)
+                .value_name("WHEN")
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto")
+                .help("When to use colored output"),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("Lists contents of an ADF file")
+                .arg(Arg::new("FILE").required(true).help("The ADF file to read"))
+                .arg(
+                    Arg::new("directory")
+                        .short('d')
+                        .long("directory")
+                        .value_name("DIR")
+                        .help("Specify a directory to list (default: root)"),
+                )
+                .arg(
+                    Arg::new("sort")
+                        .long("sort")
+                        .value_parser(["name", "size", "date", "disk"])
+                        .default_value("name")
+                        .help("Order of the entries; disk is hash table order"),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            Command::new("extract")
+                .about("Extracts files from an ADF")
+                .long_about(
+                    "Extracts files from an ADF. A single file is written to stdout or --output. \
+                     With --to, or when FILE_NAME is an AmigaDOS pattern such as \"Libs/#?\", \
+                     every matching file and directory is extracted below the target directory \
+                     (default: the current directory), keeping its path within the ADF.",
+                )
+                .arg(
+                    Arg::new("ADF_FILE")
+                        .required(true)
+                        .help("The ADF file to read from"),
+                )
+                .arg(
+                    Arg::new("FILE_NAME")
+                        .required(true)
+                        .help("The path or pattern of the files to extract within the ADF"),
+                )
+                .arg(
+                    Arg::new("OUTPUT")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Output file (default: stdout)"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("DIR")
+                        .conflicts_with("OUTPUT")
+                        .help("Extract matching files below this host directory"),
+                ),
+        )
+        .subcommand(
+            Command::new("archive")
+                .about("Writes the contents of an ADF into a zip or tar archive")
+                .arg(Arg::new("FILE").required(true).help("The ADF file to read"))
+                .arg(
+                    Arg::new("OUTPUT")
+                        .required(true)
+                        .help("The archive to write, '-' for a tar archive on stdout"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["zip", "tar"])
+                        .help("Archive format (default: from the OUTPUT extension)"),
+                ),
+        )
+        .subcommand(
+            Command::new("bitmap")
+                .about("Performs bitmap operations on an ADF file")
+                .subcommand(
+                    Command::new("info")
+                        .about("Displays information about the bitmap of an ADF file")
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The ADF file to analyze"),
+                        )
+                        .arg(
+                            Arg::new("full")
+                                .short('f')
+                                .long("full")
+                                .help("Display full block allocation map")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(output_format_arg()),
+                )
+                .subcommand(
+                    Command::new("render")
+                        .about("Renders the block allocation map of an ADF file as a PNG image")
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The ADF file to render"),
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .value_name("PNG")
+                                .required(true)
+                                .help("The PNG file to write"),
+                        )
+                        .arg(
+                            Arg::new("by-file")
+                                .long("by-file")
+                                .help("Give the blocks of every file their own colour")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Sets the status of a block")
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The ADF file to modify"),
+                        )
+                        .arg(
+                            Arg::new("BLOCK")
+                                .required(true)
+                                .help("The block number to set"),
+                        )
+                        .arg(
+                            Arg::new("STATUS")
+                                .required(true)
+                                .help("The status to set (free/used)"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("defragment")
+                        .about("Defragments the ADF file")
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The ADF file to defragment"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("format")
+                .about("Formats an ADF file")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to format"),
+                )
+                .arg(
+                    Arg::new("TYPE")
+                        .short('t')
+                        .long("type")
+                        .value_name("TYPE")
+                        .help(
+                        "Disk type (OFS, FFS) [default: OFS, or disk_type from the config file]",
+                    ),
+                )
+                .arg(
+                    Arg::new("NAME")
+                        .short('n')
+                        .long("name")
+                        .value_name("NAME")
+                        .help("Disk name")
+                        .default_value("Untitled"),
+                )
+                .arg(
+                    Arg::new("intl")
+                        .long("intl")
+                        .help("Use international (ISO 8859-1) name hashing")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("dircache")
+                        .long("dircache")
+                        .help("Refused: directory cache volumes are not supported for writing")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("bootable")
+                        .long("bootable")
+                        .help("Install the standard AmigaDOS boot code")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(kickstart_arg())
+                .arg(
+                    Arg::new("quick")
+                        .long("quick")
+                        .help("Only rewrite the boot block, root block and bitmap")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("full")
+                        .long("full")
+                        .help("Clear the whole image before formatting (default)")
+                        .conflicts_with("quick")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("fill")
+                        .long("fill")
+                        .value_name("PATTERN")
+                        .conflicts_with("quick")
+                        .help("Fill the image with these hex bytes, or \"dos\" for the DOS\\0 pattern of the AmigaDOS Format command, instead of zeroes"),
+                )
+                .arg(
+                    Arg::new("reserved")
+                        .long("reserved")
+                        .value_name("N")
+                        .value_parser(parse_number)
+                        .default_value("0")
+                        .help("Keep N blocks after the boot block allocated, e.g. for trackloader data"),
+                ),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Displays information about an ADF file")
+                .arg(
+                    Arg::new("FILE")
+                        .num_args(1..)
+                        .required_unless_present("recurse")
+                        .help("The ADF files to analyze"),
+                )
+                .arg(
+                    Arg::new("recurse")
+                        .short('r')
+                        .long("recurse")
+                        .value_name("DIR")
+                        .help("Also analyze every .adf file below this directory"),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            Command::new("identify")
+                .about("Prints fingerprints of ADF files and matches them against a DAT file")
+                .arg(
+                    Arg::new("FILE")
+                        .num_args(1..)
+                        .required_unless_present("recurse")
+                        .help("The ADF files to identify"),
+                )
+                .arg(
+                    Arg::new("recurse")
+                        .short('r')
+                        .long("recurse")
+                        .value_name("DIR")
+                        .help("Also identify every .adf file below this directory"),
+                )
+                .arg(
+                    Arg::new("dat")
+                        .long("dat")
+                        .value_name("DAT")
+                        .help("DAT file (Logiqx XML or ClrMamePro) with known dumps, e.g. TOSEC"),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            Command::new("diskset")
+                .about("Groups multi-disk titles, reports missing disks and prints a manifest")
+                .arg(
+                    Arg::new("FILE")
+                        .num_args(1..)
+                        .required(true)
+                        .help("Images named like 'Game (Disk 1 of 3).adf'"),
+                )
+                .arg(
+                    Arg::new("find")
+                        .long("find")
+                        .value_name("PATH")
+                        .help("Print which disk holds this file, e.g. 'c/run' or 'Disk2:c/run'"),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            Command::new("provenance")
+                .about("Shows or updates the provenance sidecar of an image")
+                .long_about(
+                    "Shows the provenance sidecar of an image (FILE.provenance.toml or \
+                     FILE.provenance.json) and checks its hashes, or creates or updates it when \
+                     --source, --dumped or --dumper is given. Commands that save the image \
+                     record their changes in an existing sidecar.",
+                )
+                .arg(Arg::new("FILE").required(true).help("The ADF file"))
+                .arg(
+                    Arg::new("source")
+                        .long("source")
+                        .value_name("TEXT")
+                        .help("Where the dump came from, e.g. 'Original disk, SPS 1234'"),
+                )
+                .arg(
+                    Arg::new("dumped")
+                        .long("dumped")
+                        .value_name("DATE")
+                        .help("When the dump was made"),
+                )
+                .arg(
+                    Arg::new("dumper")
+                        .long("dumper")
+                        .value_name("TEXT")
+                        .help("Who made the dump, or with what hardware"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Create the sidecar as JSON instead of TOML"),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            Command::new("metadata")
+                .about("Exports the complete metadata of ADF files as one JSON object per line")
+                .arg(
+                    Arg::new("FILE")
+                        .num_args(1..)
+                        .required_unless_present("recurse")
+                        .help("The ADF files to export"),
+                )
+                .arg(
+                    Arg::new("recurse")
+                        .short('r')
+                        .long("recurse")
+                        .value_name("DIR")
+                        .help("Also export every .adf file below this directory"),
+                ),
+        )
+        .subcommand(
+            Command::new("setdate")
+                .about("Sets the datestamp of a file or directory in an ADF file")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to modify"),
+                )
+                .arg(
+                    Arg::new("PATH")
+                        .required(true)
+                        .help("Path of the file or directory within the ADF"),
+                )
+                .arg(Arg::new("DATE").help("The date to set (YYYY-MM-DD [HH:MM[:SS]], UTC)"))
+                .arg(
+                    Arg::new("now")
+                        .long("now")
+                        .help("Use the current time")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("from-file")
+                        .long("from-file")
+                        .value_name("HOST_FILE")
+                        .help("Use the modification time of a host file"),
+                )
+                .group(
+                    ArgGroup::new("source")
+                        .args(["DATE", "now", "from-file"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("protect")
+                .about("Sets the protection bits of a file or directory in an ADF file")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to modify"),
+                )
+                .arg(
+                    Arg::new("PATH")
+                        .required(true)
+                        .help("Path of the file or directory within the ADF"),
+                )
+                .arg(
+                    Arg::new("FLAGS")
+                        .required(true)
+                        .num_args(1..)
+                        .allow_hyphen_values(true)
+                        .help("Flags to apply: +s, -w, hsparwed-style masks or hex (0x0f)"),
+                ),
+        )
+        .subcommand(
+            Command::new("comment")
+                .about("Sets or clears the comment of a file or directory in an ADF file")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to modify"),
+                )
+                .arg(
+                    Arg::new("PATH")
+                        .required(true)
+                        .help("Path of the file or directory within the ADF"),
+                )
+                .arg(Arg::new("TEXT").help("The comment to set (omit to clear)")),
+        )
+        .subcommand(
+            Command::new("fsck")
+                .about("Checks the filesystem of an ADF file for errors")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to check"),
+                )
+                .arg(
+                    Arg::new("repair")
+                        .long("repair")
+                        .help("Repair checksums and rebuild the bitmap")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Checks the overall health of an ADF file")
+                .long_about(
+                    "Checks the overall health of an ADF file: file system, boot block and \
+                     boot block viruses, block checksums, bitmap, directory structure, \
+                     fragmentation and free space. Exits with 1 when a check finds an error.",
+                )
+                .arg(Arg::new("FILE").required(true).help("The ADF file to check"))
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            Command::new("badblocks")
+                .about("Lists, marks or clears bad blocks of an ADF file")
+                .long_about(
+                    "Lists, marks or clears bad blocks of an ADF file. Bad blocks are listed in \
+                     a .bad_blocks file in the root directory and stay allocated, so nothing is \
+                     written to them; fsck and optimize honor the list.",
+                )
+                .arg(Arg::new("FILE").required(true).help("The ADF file"))
+                .arg(
+                    Arg::new("mark")
+                        .long("mark")
+                        .value_name("BLOCK")
+                        .num_args(1..)
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Blocks to mark bad"),
+                )
+                .arg(
+                    Arg::new("clear")
+                        .long("clear")
+                        .value_name("BLOCK")
+                        .num_args(1..)
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Blocks to take off the bad block list"),
+                ),
+        )
+        .subcommand(
+            Command::new("pack")
+                .about("Creates a new ADF file from the contents of a host directory")
+                .arg(
+                    Arg::new("SOURCE")
+                        .required(true)
+                        .help("The host directory to pack"),
+                )
+                .arg(
+                    Arg::new("OUTPUT")
+                        .required(true)
+                        .help("The ADF file to create, or a .zip archive to put it in"),
+                )
+                .arg(
+                    Arg::new("label")
+                        .short('l')
+                        .long("label")
+                        .value_name("NAME")
+                        .help("Disk name (default: name of the source directory)"),
+                )
+                .arg(
+                    Arg::new("ffs")
+                        .long("ffs")
+                        .help("Use the Fast File System instead of OFS")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("bootable")
+                        .long("bootable")
+                        .help("Install a standard boot block")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(kickstart_arg())
+                .arg(
+                    Arg::new("deterministic")
+                        .long("deterministic")
+                        .help("Date every entry SOURCE_DATE_EPOCH, or 1978-01-01 when unset, so the same input always gives the same image")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("unpack")
+                .about("Extracts the complete contents of an ADF file into a host directory")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to unpack"),
+                )
+                .arg(
+                    Arg::new("OUTPUT")
+                        .required(true)
+                        .help("The host directory to extract into"),
+                )
+                .arg(
+                    Arg::new("metadata")
+                        .short('m')
+                        .long("metadata")
+                        .value_name("FILE")
+                        .help("Write protection bits, dates and comments to a sidecar file, JSON if it ends in .json, tab separated values otherwise"),
+                ),
+        )
+        .subcommand(
+            Command::new("split")
+                .about("Writes every track of an ADF to a file of its own")
+                .long_about(
+                    "Writes every track of an ADF, 11 sectors, to DIR/track000.bin, \
+                     DIR/track001.bin and so on, cylinder by cylinder and head by head, \
+                     for per-track diffs, serial transfers and track-based hardware tools.",
+                )
+                .arg(Arg::new("FILE").required(true).help("The ADF file to split"))
+                .arg(
+                    Arg::new("DIR")
+                        .required(true)
+                        .help("The host directory to write the tracks to"),
+                ),
+        )
+        .subcommand(
+            Command::new("join")
+                .about("Joins track files written by split back into an ADF")
+                .arg(
+                    Arg::new("OUTPUT")
+                        .required(true)
+                        .help("The ADF file to create"),
+                )
+                .arg(
+                    Arg::new("TRACKS")
+                        .required(true)
+                        .num_args(1..)
+                        .help("The track files, in order"),
+                ),
+        )
+        .subcommand(
+            Command::new("manifest")
+                .about("Prints a hash of every file on an ADF image")
+                .long_about(
+                    "Prints a hash of every file on an ADF image. The text output has the \
+                     format of sha256sum and friends, so an extracted tree can be checked with \
+                     them.",
+                )
+                .arg(Arg::new("FILE").required(true).help("The ADF file"))
+                .arg(
+                    Arg::new("algo")
+                        .long("algo")
+                        .value_name("HASH")
+                        .value_parser(["crc32", "md5", "sha1", "sha256"])
+                        .default_value("sha256")
+                        .help("Hash to compute"),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Compares the files of an ADF image with a directory on the host")
+                .arg(Arg::new("FILE").required(true).help("The ADF file"))
+                .arg(
+                    Arg::new("DIR")
+                        .required(true)
+                        .help("The reference directory, e.g. the one packed into the image"),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            Command::new("copy")
+                .about("Copies files between ADF files and the host")
+                .long_about(
+                    "Copies files and directories between ADF files and the host. \
+                     Locations inside an ADF are written as image.adf:path, \
+                     anything else is a host path.",
+                )
+                .arg(
+                    Arg::new("SOURCE")
+                        .required(true)
+                        .help("Source location (image.adf:path or host path)"),
+                )
+                .arg(
+                    Arg::new("DEST")
+                        .required(true)
+                        .help("Destination location (image.adf:path or host path)"),
+                ),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Copies changed files between a host directory and an ADF")
+                .long_about(
+                    "Copies files from SOURCE to DEST whose size or date differ, \
+                     in either direction. One side is a host directory, the other \
+                     an ADF location written as image.adf:path. Files are never \
+                     deleted from DEST.",
+                )
+                .arg(
+                    Arg::new("SOURCE")
+                        .required(true)
+                        .help("Source directory (image.adf:path or host path)"),
+                )
+                .arg(
+                    Arg::new("DEST")
+                        .required(true)
+                        .help("Destination directory (image.adf:path or host path)"),
+                ),
+        )
+        .subcommand(
+            Command::new("mv")
+                .about("Renames or moves a file or directory within an ADF file")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to modify"),
+                )
+                .arg(
+                    Arg::new("FROM")
+                        .required(true)
+                        .help("Current path of the file or directory"),
+                )
+                .arg(
+                    Arg::new("TO")
+                        .required(true)
+                        .help("New path, or an existing directory to move into"),
+                ),
+        )
+        .subcommand(
+            Command::new("find")
+                .about("Finds files and directories matching an AmigaDOS pattern")
+                .long_about(
+                    "Finds files and directories whose name matches an AmigaDOS pattern \
+                     (#?, ?, %, (a|b), ~, [a-z]) or a simple glob (*). Patterns \
+                     containing a / are matched against the full path.",
+                )
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to search"),
+                )
+                .arg(
+                    Arg::new("PATTERN")
+                        .required(true)
+                        .help("The pattern to match, e.g. \"#?.library\""),
+                ),
+        )
+        .subcommand(
+            Command::new("du")
+                .about("Shows cumulative disk usage per directory")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to inspect"),
+                )
+                .arg(Arg::new("PATH").help("Directory to start from (defaults to the root)")),
+        )
+        .subcommand(
+            Command::new("search")
+                .about("Searches the raw image for a byte sequence or text")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to search"),
+                )
+                .arg(
+                    Arg::new("hex")
+                        .long("hex")
+                        .value_name("BYTES")
+                        .help("Hexadecimal byte sequence to search for, e.g. 444f5300"),
+                )
+                .arg(
+                    Arg::new("text")
+                        .long("text")
+                        .value_name("TEXT")
+                        .help("Text to search for"),
+                )
+                .group(ArgGroup::new("needle").args(["hex", "text"]).required(true)),
+        )
+        .subcommand(
+            Command::new("strings")
+                .about("Prints printable strings from an ADF file or a file within it")
+                .arg(Arg::new("FILE").required(true).help("The ADF file to scan"))
+                .arg(
+                    Arg::new("PATH").help("File within the ADF to scan instead of the whole image"),
+                )
+                .arg(
+                    Arg::new("min-len")
+                        .short('n')
+                        .long("min-len")
+                        .value_name("LENGTH")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("4")
+                        .help("Minimum string length"),
+                ),
+        )
+        .subcommand(
+            Command::new("dump")
+                .about("Shows a hex dump of the image, a block or a file")
+                .arg(Arg::new("FILE").required(true).help("The ADF file to dump"))
+                .arg(
+                    Arg::new("block")
+                        .short('b')
+                        .long("block")
+                        .value_name("BLOCK")
+                        .help("Block to dump: boot, root, bitmap or a block number"),
+                )
+                .arg(
+                    Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .value_name("PATH")
+                        .help("File within the ADF to dump"),
+                )
+                .arg(
+                    Arg::new("decode")
+                        .long("decode")
+                        .value_name("BLOCK")
+                        .help("Print the parsed fields of a block: root, boot or header:N"),
+                )
+                .group(ArgGroup::new("target").args(["block", "file", "decode"]))
+                .arg(
+                    Arg::new("offset")
+                        .long("offset")
+                        .value_name("OFFSET")
+                        .value_parser(parse_number)
+                        .default_value("0")
+                        .help("Start offset within the target (decimal or 0x hex)"),
+                )
+                .arg(
+                    Arg::new("length")
+                        .long("length")
+                        .value_name("LENGTH")
+                        .value_parser(parse_number)
+                        .help("Number of bytes to dump (defaults to the rest of the target)"),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            Command::new("block")
+                .about("Performs low-level block operations on an ADF file")
+                .subcommand(
+                    Command::new("write")
+                        .about("Overwrites a block with data from a file or a fill byte")
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The ADF file to modify"),
+                        )
+                        .arg(
+                            Arg::new("BLOCK")
+                                .required(true)
+                                .help("Block to write: boot, root, bitmap or a block number"),
+                        )
+                        .arg(
+                            Arg::new("from")
+                                .long("from")
+                                .value_name("FILE")
+                                .help("Host file holding the new block contents"),
+                        )
+                        .arg(
+                            Arg::new("fill")
+                                .long("fill")
+                                .value_name("BYTE")
+                                .value_parser(parse_number)
+                                .help("Fill the block with this byte value"),
+                        )
+                        .group(
+                            ArgGroup::new("source")
+                                .args(["from", "fill"])
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .long("force")
+                                .help("Write reserved blocks without asking for confirmation")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("patch")
+                .about("Patches bytes within a sector of an ADF file")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to modify"),
+                )
+                .arg(
+                    Arg::new("sector")
+                        .long("sector")
+                        .value_name("SECTOR")
+                        .required(true)
+                        .value_parser(parse_number)
+                        .help("Sector to patch"),
+                )
+                .arg(
+                    Arg::new("offset")
+                        .long("offset")
+                        .value_name("OFFSET")
+                        .required(true)
+                        .value_parser(parse_number)
+                        .help("Byte offset within the sector (decimal or 0x hex)"),
+                )
+                .arg(
+                    Arg::new("bytes")
+                        .long("bytes")
+                        .value_name("HEX")
+                        .required(true)
+                        .help("Bytes to write as hex, e.g. \"4e 44 4f 53\""),
+                )
+                .arg(
+                    Arg::new("verify-old")
+                        .long("verify-old")
+                        .value_name("HEX")
+                        .help("Abort unless the existing bytes match this hex value"),
+                )
+                .arg(
+                    Arg::new("fix-checksum")
+                        .long("fix-checksum")
+                        .help("Recalculate the block checksum after patching")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("bootblock")
+                .about("Inspects, exports, installs and checks boot blocks")
+                .subcommand(
+                    Command::new("show")
+                        .about("Shows the boot block of an ADF file")
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The ADF file to inspect"),
+                        )
+                        .arg(
+                            Arg::new("hex")
+                                .long("hex")
+                                .help("Also print a hex dump of the boot block")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(output_format_arg()),
+                )
+                .subcommand(
+                    Command::new("export")
+                        .about("Writes the boot block to a file")
+                        .arg(Arg::new("FILE").required(true).help("The ADF file to read"))
+                        .arg(
+                            Arg::new("OUTPUT")
+                                .required(true)
+                                .help("File to write the 1024 byte boot block to"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("install")
+                        .about("Installs the standard AmigaDOS boot code")
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The ADF file to modify"),
+                        )
+                        .arg(kickstart_arg()),
+                )
+                .subcommand(
+                    Command::new("scan")
+                        .about("Scans the boot block for virus-like code")
+                        .arg(Arg::new("FILE").required(true).help("The ADF file to scan")),
+                )
+                .subcommand(
+                    Command::new("checksum")
+                        .about("Verifies the boot block checksum")
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The ADF file to check"),
+                        )
+                        .arg(
+                            Arg::new("fix")
+                                .long("fix")
+                                .help("Write the correct checksum if it does not match")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("browse")
+                .about("Opens an interactive two-pane browser for an ADF file")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to browse"),
+                ),
+        )
+        .subcommand(
+            Command::new("dms")
+                .about("Creates and checks DMS archives")
+                .subcommand(
+                    Command::new("pack")
+                        .about("Packs an ADF file into a DMS archive")
+                        .arg(Arg::new("FILE").required(true).help("The ADF file to pack"))
+                        .arg(
+                            Arg::new("OUTPUT")
+                                .required(true)
+                                .help("The DMS file to write"),
+                        )
+                        .arg(
+                            Arg::new("mode")
+                                .long("mode")
+                                .value_parser(["none", "rle"])
+                                .default_value("rle")
+                                .help("Compression mode for the tracks"),
+                        )
+                        .arg(
+                            Arg::new("banner")
+                                .long("banner")
+                                .value_name("FILE")
+                                .help("Text file to store as the banner shown before unpacking"),
+                        )
+                        .arg(
+                            Arg::new("diz")
+                                .long("diz")
+                                .value_name("FILE")
+                                .help("Text file to store as the FILE_ID.DIZ of the archive"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("info")
+                        .about("Shows the header of a DMS archive")
+                        .arg(Arg::new("FILE").required(true).help("The DMS file to read"))
+                        .arg(output_format_arg()),
+                )
+                .subcommand(
+                    Command::new("verify")
+                        .about("Unpacks every track of a DMS archive and checks all CRCs")
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The DMS file to verify"),
+                        )
+                        .arg(output_format_arg()),
+                )
+                .subcommand(
+                    Command::new("convert")
+                        .about("Unpacks many DMS archives to ADF files in parallel")
+                        .arg(
+                            Arg::new("FILES")
+                                .required(true)
+                                .num_args(1..)
+                                .help("The DMS files to convert"),
+                        )
+                        .arg(
+                            Arg::new("to")
+                                .long("to")
+                                .value_name("DIR")
+                                .default_value(".")
+                                .help("Directory for the ADF files, named after the archives"),
+                        )
+                        .arg(
+                            Arg::new("overwrite")
+                                .long("overwrite")
+                                .help("Replace ADF files that already exist")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("salvage")
+                                .long("salvage")
+                                .help(
+                                    "Write images of damaged archives anyway, with the \
+                                     tracks that fail their CRC or checksum zeroed",
+                                )
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(output_format_arg()),
+                ),
+        )
+        .subcommand(
+            Command::new("hunk")
+                .about("Works with AmigaDOS executables (hunk files)")
+                .subcommand(
+                    Command::new("extract")
+                        .about("Writes the raw contents of one hunk of an executable")
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The executable, on the host or as image.adf:path"),
+                        )
+                        .arg(
+                            Arg::new("hunk")
+                                .long("hunk")
+                                .value_name("N")
+                                .required(true)
+                                .value_parser(clap::value_parser!(usize))
+                                .help("Index of the hunk to extract, starting at 0"),
+                        )
+                        .arg(
+                            Arg::new("OUTPUT")
+                                .short('o')
+                                .long("output")
+                                .value_name("FILE")
+                                .help("Output file (default: stdout)"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("patch")
+                        .about("Patches bytes in a code or data hunk of an executable")
+                        .long_about(
+                            "Patches bytes in a code or data hunk of an executable on the host, \
+                             in place or into --output. A patch touching a relocated longword is \
+                             refused, as the loader would change what was written there.",
+                        )
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The executable to patch"),
+                        )
+                        .arg(
+                            Arg::new("hunk")
+                                .long("hunk")
+                                .value_name("N")
+                                .required(true)
+                                .value_parser(clap::value_parser!(usize))
+                                .help("Index of the hunk to patch, starting at 0"),
+                        )
+                        .arg(
+                            Arg::new("offset")
+                                .long("offset")
+                                .value_name("OFFSET")
+                                .required(true)
+                                .value_parser(parse_number)
+                                .help("Byte offset within the hunk (decimal or 0x hex)"),
+                        )
+                        .arg(
+                            Arg::new("bytes")
+                                .long("bytes")
+                                .value_name("HEX")
+                                .required(true)
+                                .help("Bytes to write as hex, e.g. \"4e 71\""),
+                        )
+                        .arg(
+                            Arg::new("OUTPUT")
+                                .short('o')
+                                .long("output")
+                                .value_name("FILE")
+                                .help("Write the patched executable here (default: FILE)"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("icon")
+                .about("Shows a Workbench icon (.info file) of an ADF file")
+                .arg(Arg::new("FILE").required(true).help("The ADF file to read"))
+                .arg(
+                    Arg::new("PATH")
+                        .required(true)
+                        .help("The file or drawer whose icon to show, with or without .info"),
+                )
+                .arg(
+                    Arg::new("png")
+                        .long("png")
+                        .value_name("PNG")
+                        .help("Also render the icon image to a PNG file"),
+                )
+                .arg(
+                    Arg::new("selected")
+                        .long("selected")
+                        .requires("png")
+                        .help("Render the selected image instead of the normal one")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(output_format_arg()),
+        )
+        .subcommand(
+            Command::new("preview")
+                .about("Renders an IFF ILBM picture stored in an ADF file as a PNG image")
+                .arg(Arg::new("FILE").required(true).help("The ADF file to read"))
+                .arg(
+                    Arg::new("PATH")
+                        .required(true)
+                        .help("The ILBM picture within the ADF"),
+                )
+                .arg(
+                    Arg::new("OUTPUT")
+                        .required(true)
+                        .help("The PNG file to write"),
+                ),
+        )
+        .subcommand(
+            Command::new("optimize")
+                .about("Defragments an ADF file, sorts its directories and scrubs free space")
+                .long_about(
+                    "Defragments an ADF file, sorts its directories and scrubs free space, then \
+                     prints a before/after fragmentation report. Without options --defrag and \
+                     --sort-dirs are applied.",
+                )
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to optimize"),
+                )
+                .arg(
+                    Arg::new("defrag")
+                        .long("defrag")
+                        .help("Rewrite the volume so every file occupies contiguous blocks")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("sort-dirs")
+                        .long("sort-dirs")
+                        .help("Order directory hash chains by name")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("scrub-free")
+                        .long("scrub-free")
+                        .help("Zero all free blocks, destroying deleted file remains")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("scrub-pattern")
+                        .long("scrub-pattern")
+                        .value_name("PATTERN")
+                        .help("Scrub free blocks with these hex bytes, or \"dos\" for the DOS\\0 fill, instead of zeroes (implies --scrub-free)"),
+                ),
+        )
+        .subcommand(
+            Command::new("template")
+                .about("Lists disk templates and creates images from them")
+                .long_about(
+                    "Lists disk templates and creates images from them. User templates are \
+                     read from templates/*.toml next to the config file and replace built-in \
+                     templates of the same name.",
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("Lists built-in and user templates")
+                        .arg(output_format_arg()),
+                )
+                .subcommand(
+                    Command::new("apply")
+                        .about("Creates a new ADF file from a template")
+                        .arg(
+                            Arg::new("TEMPLATE")
+                                .required(true)
+                                .help("The template name"),
+                        )
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The ADF file to create"),
+                        )
+                        .arg(
+                            Arg::new("NAME")
+                                .short('n')
+                                .long("name")
+                                .value_name("NAME")
+                                .help("Volume name [default: from the template]"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("create")
+                .about("Creates a new empty ADF file")
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to create"),
+                )
+                .arg(
+                    Arg::new("size")
+                        .long("size")
+                        .value_name("SIZE")
+                        .value_parser(parse_disk_size)
+                        .help("Image size: dd (880K), hd (1760K) or a size in bytes [default: dd]"),
+                )
+                .arg(
+                    Arg::new("type")
+                        .long("type")
+                        .value_name("TYPE")
+                        .value_parser(["adf", "hdf"])
+                        .default_value("adf")
+                        .help("Image type: adf (floppy) or hdf (hardfile, requires --size)"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("NAME")
+                        .help("Format the new image with this volume name"),
+                ),
+        )
+        .subcommand(
+            Command::new("mount")
+                .about("Mounts an ADF file as a read-only host filesystem")
+                .long_about(
+                    "Mounts an ADF file as a read-only host filesystem using FUSE and waits \
+                     until it is unmounted, e.g. with fusermount -u. Only available when built \
+                     with the fuse feature.",
+                )
+                .arg(
+                    Arg::new("FILE")
+                        .required(true)
+                        .help("The ADF file to mount"),
+                )
+                .arg(
+                    Arg::new("MOUNTPOINT")
+                        .required(true)
+                        .help("The directory to mount the volume on"),
+                )
+                .arg(
+                    Arg::new("allow-other")
+                        .long("allow-other")
+                        .help("Let other users access the mounted volume")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("auto-unmount")
+                        .long("auto-unmount")
+                        .help("Unmount automatically when the process exits")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("gw")
+                .about("Reads and writes real floppy disks with a Greaseweazle")
+                .long_about(
+                    "Reads and writes real floppy disks with a Greaseweazle connected over USB. \
+                     Only available when built with the hardware feature.",
+                )
+                .arg(
+                    Arg::new("port")
+                        .long("port")
+                        .value_name("PORT")
+                        .global(true)
+                        .help("Serial port of the Greaseweazle (default: /dev/ttyACM0, COM3 on Windows)"),
+                )
+                .arg(
+                    Arg::new("drive")
+                        .long("drive")
+                        .value_name("UNIT")
+                        .global(true)
+                        .value_parser(clap::value_parser!(u8).range(0..=2))
+                        .default_value("0")
+                        .help("Drive unit on the Shugart bus"),
+                )
+                .subcommand(Command::new("info").about("Prints the firmware and hardware version"))
+                .subcommand(
+                    Command::new("read")
+                        .about("Reads the disk in the drive into an ADF file")
+                        .arg(
+                            Arg::new("OUTPUT")
+                                .required(true)
+                                .help("The ADF file to write"),
+                        )
+                        .arg(
+                            Arg::new("hd")
+                                .long("hd")
+                                .help("Read a high density disk")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("revs")
+                                .long("revs")
+                                .value_name("N")
+                                .value_parser(clap::value_parser!(u16).range(1..=20))
+                                .default_value("2")
+                                .help("Revolutions to read per track"),
+                        )
+                        .arg(
+                            Arg::new("retries")
+                                .long("retries")
+                                .value_name("N")
+                                .value_parser(clap::value_parser!(usize))
+                                .default_value("5")
+                                .help("Rereads of a track with unreadable sectors"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("write")
+                        .about("Writes an ADF file to the disk in the drive")
+                        .arg(
+                            Arg::new("FILE")
+                                .required(true)
+                                .help("The ADF file to write"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Prints a shell completion script")
+                .arg(
+                    Arg::new("SHELL")
+                        .required(true)
+                        .value_parser(["bash", "zsh", "fish", "elvish", "powershell"])
+                        .help("The shell to generate completions for"),
+                ),
+        )
+        .subcommand(Command::new("man").about("Prints the man page in roff format"))
+}
+
+pub fn kickstart_arg() -> Arg {
+    Arg::new("kickstart")
+        .long("kickstart")
+        .value_name("VERSION")
+        .value_parser(["1.3", "2.x", "3.1"])
+        .default_value("1.3")
+        .help("Install the boot code of this Kickstart (2.x and 3.1 need the bootblocks feature)")
+}
+
+pub fn output_format_arg() -> Arg {
+    Arg::new("output")
+        .long("output")
+        .value_name("FORMAT")
+        .value_parser(["text", "json", "csv"])
+        .help("Output format: text, json or csv")
+}
+
+pub fn parse_number(value: &str) -> Result<usize, String> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix('$')) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| format!("Invalid number '{}'", value))
+}
+
+// Returns the image size in bytes; build.rs cannot see the library constants.
+pub fn parse_disk_size(value: &str) -> Result<usize, String> {
+    let bytes = match value.to_ascii_lowercase().as_str() {
+        "dd" => 1760 * 512,
+        "hd" => 3520 * 512,
+        _ => parse_number(value)?,
+    };
+    if bytes == 0 || !bytes.is_multiple_of(512) {
+        return Err("Size must be a non-zero multiple of 512 bytes".to_string());
+    }
+    Ok(bytes)
+}
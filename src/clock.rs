@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// The time source for every date the library writes: root and entry dates, DMS headers and
+// provenance events. Images use the system clock unless another one is set, so tests and
+// reproducible pipelines can control the dates.
+
+use crate::disk::ADF;
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+// Always the same time, for images that must come out identical on every run.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub SystemTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
+
+// The clock set on an image, None for the system clock. Unlike observers, a clone keeps it.
+#[derive(Clone, Default)]
+pub(crate) struct ClockRef(pub(crate) Option<Arc<dyn Clock>>);
+
+impl fmt::Debug for ClockRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("ClockRef(custom)"),
+            None => f.write_str("ClockRef(system)"),
+        }
+    }
+}
+
+impl ClockRef {
+    pub(crate) fn now(&self) -> SystemTime {
+        match &self.0 {
+            Some(clock) => clock.now(),
+            None => SystemTime::now(),
+        }
+    }
+}
+
+impl ADF {
+    // With a clock set, every date written to the image, in the root block and in the
+    // entries created or imported, comes from it instead of the current or host time.
+    // Allocation is already first fit in name order, so importing the same tree twice with a
+    // FixedClock gives identical images, for releases pinned by hash.
+    pub fn set_clock(&mut self, clock: Option<Arc<dyn Clock>>) {
+        self.tracking.clock = ClockRef(clock);
+    }
+
+    pub fn clock(&self) -> Option<Arc<dyn Clock>> {
+        self.tracking.clock.0.clone()
+    }
+
+    pub fn set_fixed_time(&mut self, time: Option<SystemTime>) {
+        self.set_clock(time.map(|time| Arc::new(FixedClock(time)) as Arc<dyn Clock>));
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.tracking.clock.now()
+    }
+}
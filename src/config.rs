@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use serde::Deserialize;
+use std::env;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub output: Option<String>,
+    pub disk_type: Option<String>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("ADFLIB_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join("adflib").join("config.toml"))
+    }
+
+    pub fn load() -> Result<Config> {
+        match Config::path() {
+            Some(path) if path.is_file() => {
+                let text = std::fs::read_to_string(&path)?;
+                let config: Config = toml::from_str(&text).map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Invalid config file {}: {}", path.display(), e.message()),
+                    )
+                })?;
+                config.check()?;
+                Ok(config)
+            }
+            _ => Ok(Config::default()),
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if let Some(output) = &self.output {
+            if !["text", "json", "csv"].contains(&output.as_str()) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid output format '{}' in config file", output),
+                ));
+            }
+        }
+        if let Some(disk_type) = &self.disk_type {
+            if !["OFS", "FFS"].contains(&disk_type.to_ascii_uppercase().as_str()) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid disk type '{}' in config file", disk_type),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use md5::Md5;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Fingerprint {
+    pub size: u64,
+    pub crc32: String,
+    pub md5: String,
+    pub sha1: String,
+}
+
+impl Fingerprint {
+    pub fn of(data: &[u8]) -> Self {
+        Fingerprint {
+            size: data.len() as u64,
+            crc32: format!("{:08x}", crc32fast::hash(data)),
+            md5: to_hex(&Md5::digest(data)),
+            sha1: to_hex(&Sha1::digest(data)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DatEntry {
+    pub title: String,
+    pub rom: String,
+    pub size: Option<u64>,
+    pub crc32: Option<String>,
+    pub md5: Option<String>,
+    pub sha1: Option<String>,
+}
+
+impl DatEntry {
+    pub fn matches(&self, fingerprint: &Fingerprint) -> bool {
+        if let Some(sha1) = &self.sha1 {
+            return *sha1 == fingerprint.sha1;
+        }
+        if let Some(md5) = &self.md5 {
+            return *md5 == fingerprint.md5;
+        }
+        match &self.crc32 {
+            Some(crc32) => {
+                *crc32 == fingerprint.crc32 && self.size.is_none_or(|s| s == fingerprint.size)
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Dat {
+    pub entries: Vec<DatEntry>,
+}
+
+impl Dat {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Dat> {
+        Dat::parse(&std::fs::read_to_string(path)?)
+    }
+
+    // Accepts Logiqx XML and ClrMamePro text DATs, as used by TOSEC and No-Intro.
+    pub fn parse(text: &str) -> Result<Dat> {
+        if text.trim_start().starts_with('<') {
+            parse_xml(text)
+        } else {
+            parse_clrmamepro(text)
+        }
+    }
+
+    pub fn identify(&self, fingerprint: &Fingerprint) -> Option<&DatEntry> {
+        self.entries.iter().find(|entry| entry.matches(fingerprint))
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn invalid(reason: String) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid DAT file: {}", reason),
+    )
+}
+
+fn set_field(entry: &mut DatEntry, key: &str, value: String) {
+    match key {
+        "name" => entry.rom = value,
+        "size" => entry.size = value.parse().ok(),
+        "crc" => entry.crc32 = Some(value.to_ascii_lowercase()),
+        "md5" => entry.md5 = Some(value.to_ascii_lowercase()),
+        "sha1" => entry.sha1 = Some(value.to_ascii_lowercase()),
+        _ => {}
+    }
+}
+
+fn parse_xml(text: &str) -> Result<Dat> {
+    let mut reader = Reader::from_str(text);
+    let mut dat = Dat::default();
+    let mut title = String::new();
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(tag)) | Ok(Event::Empty(tag)) => match tag.name().as_ref() {
+                "game" | "machine" => {
+                    title = xml_attributes(&tag)?
+                        .into_iter()
+                        .find(|(key, _)| key == "name")
+                        .map(|(_, value)| value)
+                        .unwrap_or_default();
+                }
+                "rom" => {
+                    let mut entry = DatEntry {
+                        title: title.clone(),
+                        ..DatEntry::default()
+                    };
+                    for (key, value) in xml_attributes(&tag)? {
+                        set_field(&mut entry, &key, value);
+                    }
+                    dat.entries.push(entry);
+                }
+                _ => {}
+            },
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(e) => return Err(invalid(e.to_string())),
+        }
+    }
+    Ok(dat)
+}
+
+fn xml_attributes(tag: &BytesStart) -> Result<Vec<(String, String)>> {
+    tag.attributes()
+        .map(|attribute| {
+            let attribute = attribute.map_err(|e| invalid(e.to_string()))?;
+            let key = attribute.key.as_ref().to_string();
+            let value = attribute
+                .normalized_value(XmlVersion::Implicit1_0)
+                .map_err(|e| invalid(e.to_string()))?
+                .into_owned();
+            Ok((key, value))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+enum Token {
+    Open,
+    Close,
+    Word(String),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(invalid("unterminated string".to_string())),
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || next == '(' || next == ')' {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_clrmamepro(text: &str) -> Result<Dat> {
+    let tokens = tokenize(text)?;
+    let mut dat = Dat::default();
+    let mut pos = 0;
+    let mut depth = 0;
+    let mut title = String::new();
+    let mut rom: Option<DatEntry> = None;
+    while pos < tokens.len() {
+        match (&tokens[pos], tokens.get(pos + 1)) {
+            (Token::Open, _) => depth += 1,
+            (Token::Close, _) => {
+                if depth == 0 {
+                    return Err(invalid("unbalanced ')'".to_string()));
+                }
+                depth -= 1;
+                if let Some(entry) = rom.take().filter(|_| depth == 1) {
+                    dat.entries.push(entry);
+                }
+            }
+            (Token::Word(key), Some(Token::Open)) if depth == 1 && key == "rom" => {
+                rom = Some(DatEntry {
+                    title: title.clone(),
+                    ..DatEntry::default()
+                });
+            }
+            (Token::Word(key), Some(Token::Word(value))) => {
+                match rom.as_mut() {
+                    Some(entry) if depth == 2 => set_field(entry, key, value.clone()),
+                    _ if depth == 1 && key == "name" => title = value.clone(),
+                    _ => {}
+                }
+                pos += 1;
+            }
+            _ => {}
+        }
+        pos += 1;
+    }
+    if depth != 0 {
+        return Err(invalid("missing ')'".to_string()));
+    }
+    Ok(dat)
+}
@@ -0,0 +1,604 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use crate::block::RootBlock;
+use crate::disk::{
+    read_u32_be, FileInfo, ADF, ADF_HD_NUM_SECTORS, ADF_NUM_SECTORS, ADF_NUM_TRACKS,
+    ADF_SECTOR_SIZE, MAX_VOLUME_BLOCKS, ST_ROOT,
+};
+use crate::layout::Layout;
+use crate::rdb::read_partitions;
+use log::debug;
+use serde::Serialize;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const DEVICETYPE_FLOPDD: u8 = 1;
+pub const DEVICETYPE_FLOPHD: u8 = 2;
+pub const DEVICETYPE_HARDDISK: u8 = 3;
+pub const DEVICETYPE_HARDFILE: u8 = 4;
+
+// Heads and sectors tried in order for hardfiles; the first that divides the image evenly
+// wins. One head with 32 sectors is what WinUAE uses for new hardfiles.
+const HARDFILE_LAYOUTS: [(u32, u32); 4] = [(1, 32), (16, 63), (16, 32), (4, 32)];
+
+pub fn device_type_name(device_type: u8) -> &'static str {
+    match device_type {
+        DEVICETYPE_FLOPDD => "DD floppy",
+        DEVICETYPE_FLOPHD => "HD floppy",
+        DEVICETYPE_HARDDISK => "Hard disk",
+        DEVICETYPE_HARDFILE => "Hardfile",
+        _ => "unknown",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Geometry {
+    pub cylinders: u32,
+    pub heads: u32,
+    pub sectors: u32,
+    pub device_type: u8,
+}
+
+impl Geometry {
+    pub fn blocks(&self) -> usize {
+        self.cylinders as usize * self.heads as usize * self.sectors as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VolumeInfo {
+    // Partition name from the RDB, such as DH0; None for unpartitioned images.
+    pub device_name: Option<String>,
+    pub first_block: usize,
+    pub blocks: usize,
+    pub dos_type: u32,
+    // Volume name from the root block; empty if the volume is not formatted.
+    pub label: String,
+}
+
+impl VolumeInfo {
+    pub fn size(&self) -> u64 {
+        (self.blocks * ADF_SECTOR_SIZE) as u64
+    }
+
+    // None for a volume without blocks.
+    pub fn last_block(&self) -> Option<usize> {
+        self.blocks.checked_sub(1).map(|n| self.first_block + n)
+    }
+
+    fn overlaps(&self, other: &VolumeInfo) -> bool {
+        match (self.last_block(), other.last_block()) {
+            (Some(last), Some(other_last)) => {
+                self.first_block <= other_last && other.first_block <= last
+            }
+            _ => false,
+        }
+    }
+}
+
+// Block access through a host file descriptor. This works the same for image files and for
+// raw block devices such as a USB floppy drive or a CF card, whose size is only available by
+// seeking to the end.
+#[derive(Debug)]
+pub struct NativeDevice {
+    file: File,
+    size: u64,
+    writable: bool,
+}
+
+impl NativeDevice {
+    pub fn init_device<P: AsRef<Path>>(path: P, writable: bool) -> Result<NativeDevice> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .open(path.as_ref())?;
+        let size = file.seek(SeekFrom::End(0))?;
+        debug!(
+            "Opened native device {} ({} bytes, {})",
+            path.as_ref().display(),
+            size,
+            if writable { "read-write" } else { "read-only" }
+        );
+        Ok(NativeDevice {
+            file,
+            size,
+            writable,
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn blocks(&self) -> usize {
+        (self.size / ADF_SECTOR_SIZE as u64) as usize
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    fn seek_block(&mut self, block: usize, count: usize) -> Result<()> {
+        if block + count > self.blocks() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "Blocks {}..{} are beyond the end of the device ({} blocks)",
+                    block,
+                    block + count,
+                    self.blocks()
+                ),
+            ));
+        }
+        self.file
+            .seek(SeekFrom::Start((block * ADF_SECTOR_SIZE) as u64))?;
+        Ok(())
+    }
+
+    pub fn read_blocks(&mut self, block: usize, count: usize) -> Result<Vec<u8>> {
+        self.seek_block(block, count)?;
+        let mut data = vec![0u8; count * ADF_SECTOR_SIZE];
+        self.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    pub fn read_block(&mut self, block: usize) -> Result<Vec<u8>> {
+        self.read_blocks(block, 1)
+    }
+
+    pub fn write_blocks(&mut self, block: usize, data: &[u8]) -> Result<()> {
+        if !self.writable {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "Device was opened read-only",
+            ));
+        }
+        if !data.len().is_multiple_of(ADF_SECTOR_SIZE) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Write of {} bytes is not a whole number of blocks",
+                    data.len()
+                ),
+            ));
+        }
+        self.seek_block(block, data.len() / ADF_SECTOR_SIZE)?;
+        self.file.write_all(data)
+    }
+
+    pub fn write_block(&mut self, block: usize, data: &[u8]) -> Result<()> {
+        self.write_blocks(block, data)
+    }
+
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.sync_all()
+    }
+
+    #[cfg(unix)]
+    pub fn is_block_device(&self) -> bool {
+        use std::os::unix::fs::FileTypeExt;
+        self.file
+            .metadata()
+            .is_ok_and(|meta| meta.file_type().is_block_device())
+    }
+
+    #[cfg(not(unix))]
+    pub fn is_block_device(&self) -> bool {
+        false
+    }
+}
+
+// Returned, wrapped in an io::Error of kind ReadOnlyFilesystem, by every mutation of a volume
+// that was mounted read-only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadOnlyVolume {
+    pub label: String,
+}
+
+impl ReadOnlyVolume {
+    pub fn is(error: &Error) -> bool {
+        error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<ReadOnlyVolume>())
+    }
+}
+
+impl fmt::Display for ReadOnlyVolume {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Volume '{}' is mounted read-only", self.label)
+    }
+}
+
+impl std::error::Error for ReadOnlyVolume {}
+
+fn read_label(device: &mut NativeDevice, first_block: usize, blocks: usize) -> Result<String> {
+    if blocks < 4 {
+        return Ok(String::new());
+    }
+    let layout = Layout::new(ADF_SECTOR_SIZE, blocks);
+    let root = RootBlock::from_bytes(
+        &device.read_block(first_block + layout.root_block())?,
+        &layout,
+    )?;
+    Ok(if root.checksum_valid() && root.sec_type.raw() == ST_ROOT {
+        root.name
+    } else {
+        String::new()
+    })
+}
+
+// A disk image on the host. Hard disk images list their volumes in a rigid disk block;
+// floppies and hardfiles without one hold a single volume spanning the whole image.
+#[derive(Debug, Clone)]
+pub struct Device {
+    path: PathBuf,
+    size: u64,
+    geometry: Geometry,
+    volumes: Vec<VolumeInfo>,
+}
+
+impl Device {
+    pub fn detect_geometry(size: u64) -> Geometry {
+        let blocks = (size / ADF_SECTOR_SIZE as u64) as usize;
+        let floppy = |sectors: u32, device_type| Geometry {
+            cylinders: (ADF_NUM_TRACKS / 2) as u32,
+            heads: 2,
+            sectors,
+            device_type,
+        };
+        if blocks == ADF_NUM_SECTORS {
+            return floppy((ADF_NUM_SECTORS / ADF_NUM_TRACKS) as u32, DEVICETYPE_FLOPDD);
+        }
+        if blocks == ADF_HD_NUM_SECTORS {
+            return floppy(
+                (ADF_HD_NUM_SECTORS / ADF_NUM_TRACKS) as u32,
+                DEVICETYPE_FLOPHD,
+            );
+        }
+        let (heads, sectors) = HARDFILE_LAYOUTS
+            .into_iter()
+            .find(|&(heads, sectors)| {
+                let cylinder = (heads * sectors) as usize;
+                blocks.is_multiple_of(cylinder) && blocks / cylinder <= u16::MAX as usize
+            })
+            .unwrap_or((1, 1));
+        Geometry {
+            cylinders: (blocks / (heads * sectors) as usize) as u32,
+            heads,
+            sectors,
+            device_type: DEVICETYPE_HARDFILE,
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Device> {
+        let path = path.as_ref().to_path_buf();
+        let mut device = NativeDevice::init_device(&path, false)?;
+        let size = device.size();
+        if size == 0 || size % ADF_SECTOR_SIZE as u64 != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} is not a multiple of {} bytes",
+                    path.display(),
+                    ADF_SECTOR_SIZE
+                ),
+            ));
+        }
+        let blocks = device.blocks();
+        let mut geometry = Device::detect_geometry(size);
+        let volumes = match read_partitions(blocks, |block| device.read_block(block))? {
+            Some((rigid_disk, partitions)) => {
+                geometry = Geometry {
+                    cylinders: rigid_disk.cylinders,
+                    heads: rigid_disk.heads,
+                    sectors: rigid_disk.sectors,
+                    device_type: DEVICETYPE_HARDDISK,
+                };
+                partitions
+                    .iter()
+                    .map(|partition| {
+                        Ok(VolumeInfo {
+                            device_name: Some(partition.drive_name.clone()),
+                            first_block: partition.first_block(),
+                            blocks: partition.blocks(),
+                            dos_type: partition.dos_type,
+                            label: read_label(
+                                &mut device,
+                                partition.first_block(),
+                                partition.blocks(),
+                            )?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            }
+            None => vec![VolumeInfo {
+                device_name: None,
+                first_block: 0,
+                blocks,
+                dos_type: read_u32_be(&device.read_block(0)?, 0),
+                label: read_label(&mut device, 0, blocks)?,
+            }],
+        };
+        for (i, volume) in volumes.iter().enumerate() {
+            if volumes[..i].iter().any(|other| other.overlaps(volume)) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Volume {} overlaps another volume on {}", i, path.display()),
+                ));
+            }
+        }
+        debug!("Opened {} with {} volume(s)", path.display(), volumes.len());
+        Ok(Device {
+            path,
+            size,
+            geometry,
+            volumes,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    pub fn volumes(&self) -> &[VolumeInfo] {
+        &self.volumes
+    }
+
+    pub fn mount(&self, index: usize) -> Result<Volume> {
+        self.mount_with_mode(index, false)
+    }
+
+    pub fn mount_read_only(&self, index: usize) -> Result<Volume> {
+        self.mount_with_mode(index, true)
+    }
+
+    fn mount_with_mode(&self, index: usize, read_only: bool) -> Result<Volume> {
+        let info = self.volumes.get(index).cloned().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "Volume {} not found, {} has {}",
+                    index,
+                    self.path.display(),
+                    self.volumes.len()
+                ),
+            )
+        })?;
+        // Volumes are held in memory as an ADF, which caps their size.
+        if info.blocks > MAX_VOLUME_BLOCKS {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Partition {} is too large to mount: {} blocks ({} MB), at most {} blocks \
+                     ({} MB) are supported",
+                    info.device_name.as_deref().unwrap_or("volume"),
+                    info.blocks,
+                    info.size() >> 20,
+                    MAX_VOLUME_BLOCKS,
+                    (MAX_VOLUME_BLOCKS * ADF_SECTOR_SIZE) >> 20
+                ),
+            ));
+        }
+        let data = NativeDevice::init_device(&self.path, false)?
+            .read_blocks(info.first_block, info.blocks)?;
+        Ok(Volume {
+            adf: ADF::from_bytes(&data)?,
+            path: Some(self.path.clone()),
+            info,
+            current_dir: String::new(),
+            read_only,
+        })
+    }
+}
+
+// A mounted volume. Changes stay in memory until flush writes the volume's block range back
+// to the device, so volumes of the same device can be mounted side by side. Relative paths
+// are resolved against the current directory, using AmigaDOS rules: a leading ':' starts at
+// the root and every extra '/' goes up one level.
+#[derive(Debug, Clone)]
+pub struct Volume {
+    adf: ADF,
+    path: Option<PathBuf>,
+    info: VolumeInfo,
+    current_dir: String,
+    read_only: bool,
+}
+
+impl Volume {
+    // An in-memory volume that is not backed by a device.
+    pub fn new(adf: ADF) -> Volume {
+        let info = VolumeInfo {
+            device_name: None,
+            first_block: 0,
+            blocks: adf.num_blocks(),
+            dos_type: read_u32_be(&adf.data, 0),
+            label: adf.entry_name(adf.root_block()),
+        };
+        Volume {
+            adf,
+            path: None,
+            info,
+            current_dir: String::new(),
+            read_only: false,
+        }
+    }
+
+    pub fn info(&self) -> &VolumeInfo {
+        &self.info
+    }
+
+    pub fn adf(&self) -> &ADF {
+        &self.adf
+    }
+
+    pub fn adf_mut(&mut self) -> Result<&mut ADF> {
+        self.check_writable()?;
+        Ok(&mut self.adf)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    // There is deliberately no way back, so code holding a read-only volume cannot unlock it.
+    pub fn make_read_only(&mut self) {
+        self.read_only = true;
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::new(
+                ErrorKind::ReadOnlyFilesystem,
+                ReadOnlyVolume {
+                    label: self.info.label.clone(),
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn into_adf(self) -> ADF {
+        self.adf
+    }
+
+    pub fn resolve(&self, path: &str) -> Result<String> {
+        let (mut parts, relative): (Vec<&str>, &str) = match path.split_once(':') {
+            Some((_, rest)) => (Vec::new(), rest),
+            None => (
+                self.current_dir
+                    .split('/')
+                    .filter(|p| !p.is_empty())
+                    .collect(),
+                path,
+            ),
+        };
+        let components: Vec<&str> = relative.split('/').collect();
+        for (i, &component) in components.iter().enumerate() {
+            match component {
+                "" if i + 1 == components.len() => {}
+                "" | ".." => {
+                    if parts.pop().is_none() {
+                        return Err(Error::new(
+                            ErrorKind::NotFound,
+                            format!("'{}' goes above the root directory", path),
+                        ));
+                    }
+                }
+                "." => {}
+                name => parts.push(name),
+            }
+        }
+        Ok(parts.join("/"))
+    }
+
+    pub fn pwd(&self) -> String {
+        format!(
+            "{}:{}",
+            self.adf.entry_name(self.adf.root_block()),
+            self.current_dir
+        )
+    }
+
+    pub fn current_dir(&self) -> &str {
+        &self.current_dir
+    }
+
+    pub fn cd(&mut self, path: &str) -> Result<()> {
+        let dir = self.resolve(path)?;
+        self.adf.find_directory(&dir)?;
+        debug!("Changed directory to '{}'", dir);
+        self.current_dir = dir;
+        Ok(())
+    }
+
+    pub fn ls(&self) -> Result<Vec<FileInfo>> {
+        self.list("")
+    }
+
+    pub fn list(&self, dir: &str) -> Result<Vec<FileInfo>> {
+        let block = self.adf.find_directory(&self.resolve(dir)?)?;
+        self.adf.list_directory(block).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Result<Vec<u8>> {
+        self.read_file(name)
+    }
+
+    // Stores a file, replacing an existing file of the same name.
+    pub fn put(&mut self, name: &str, contents: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        let path = self.resolve(name)?;
+        if self.adf.find_entry(&path).is_ok() {
+            if self.adf.is_dir(&path) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("'{}' is a directory", path),
+                ));
+            }
+            self.adf.delete(&path)?;
+        }
+        self.adf.write_file(&path, contents)
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        self.adf.read_file(&self.resolve(path)?)
+    }
+
+    pub fn write_file(&mut self, path: &str, contents: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        let path = self.resolve(path)?;
+        self.adf.write_file(&path, contents)
+    }
+
+    pub fn create_dir(&mut self, path: &str) -> Result<()> {
+        self.check_writable()?;
+        let path = self.resolve(path)?;
+        self.adf.create_dir(&path)
+    }
+
+    pub fn delete(&mut self, path: &str) -> Result<()> {
+        self.check_writable()?;
+        let path = self.resolve(path)?;
+        self.adf.delete(&path)
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        self.check_writable()?;
+        let (from, to) = (self.resolve(from)?, self.resolve(to)?);
+        self.adf.rename(&from, &to)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.check_writable()?;
+        let Some(path) = &self.path else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Volume is not backed by a device",
+            ));
+        };
+        if self.adf.data.len() != self.info.blocks * ADF_SECTOR_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Volume size changed since it was mounted",
+            ));
+        }
+        let mut device = NativeDevice::init_device(path, true)?;
+        device.write_blocks(self.info.first_block, &self.adf.data)?;
+        device.sync()?;
+        debug!("Flushed {} blocks to {}", self.info.blocks, path.display());
+        Ok(())
+    }
+}
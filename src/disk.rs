@@ -3,54 +3,348 @@
 // Copyright (c) 2023
 // - Volker Schwaberow <volker@schwaberow.de>
 
+use crate::archive::zip_date;
+use crate::block::{
+    bitmap_checksum, bootblock_checksum, normal_checksum, BitmapBlock, BlockPtr, BootBlock,
+    ChecksumKind, DataBlock, DateStamp, FileExtBlock, HeaderBlock, RootBlock, SecType,
+};
+use crate::bootblock::{Kickstart, BOOT_BLOCK_SIZE};
+use crate::cancel::CancelToken;
+use crate::clock::ClockRef;
+use crate::device::{device_type_name, Device};
+use crate::events::Observers;
+use crate::iff;
+use crate::layout::Layout;
+use crate::pattern::Pattern;
+use crate::provenance::Provenance;
+pub(crate) use crate::raw::{latin1_string, read_u32_be, write_u32_be};
+use crate::raw::{Entry, Image};
+use crate::sansio::{decode_slice, Decoded, Decoder};
+use log::{debug, trace};
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
 use std::fmt::Debug;
-use std::fs::File;
-use std::io::{self, Error, ErrorKind, Read, Result, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
 use std::time::{SystemTime, UNIX_EPOCH};
-use zip::ZipArchive;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
 
 pub const ADF_TRACK_SIZE: usize = 11 * ADF_SECTOR_SIZE;
 pub const ADF_NUM_TRACKS: usize = 80 * 2;
 pub const ROOT_BLOCK: usize = 880;
 pub const ADF_SECTOR_SIZE: usize = 512;
 pub const ADF_NUM_SECTORS: usize = 1760;
+pub const ADF_HD_NUM_SECTORS: usize = 2 * ADF_NUM_SECTORS;
+
+pub const AMIGA_EPOCH_OFFSET: u64 = 252_460_800;
+
+// "DOS\0", written by the AmigaDOS Format command over every block it does not use.
+pub const AMIGADOS_FILL: [u8; 4] = *b"DOS\0";
+
+pub(crate) const BOOT_CODE: [u8; 38] = [
+    0x43, 0xfa, 0x00, 0x18, 0x4e, 0xae, 0xff, 0xa0, 0x4a, 0x80, 0x67, 0x0a, 0x20, 0x40, 0x20, 0x68,
+    0x00, 0x16, 0x70, 0x00, 0x4e, 0x75, 0x70, 0xff, 0x60, 0xfa, 0x64, 0x6f, 0x73, 0x2e, 0x6c, 0x69,
+    0x62, 0x72, 0x61, 0x72, 0x79, 0x00,
+];
+const TICKS_PER_SECOND: u64 = 50;
+// Dirty extents closer than this are saved with a single write.
+const SAVE_COALESCE_GAP: usize = 8 * ADF_SECTOR_SIZE;
+// Images larger than an HD floppy are written sparse, runs of zero blocks left as holes.
+const SPARSE_MIN_SIZE: usize = ADF_HD_NUM_SECTORS * ADF_SECTOR_SIZE;
+
+pub(crate) use crate::layout::{
+    BITS_PER_BITMAP_PAGE, BLOCK_BM_FLAG, BLOCK_BM_PAGES, BLOCK_BYTE_SIZE, BLOCK_CHECKSUM,
+    BLOCK_COMMENT, BLOCK_DAYS, BLOCK_EXTENSION, BLOCK_FIRST_DATA, BLOCK_HASH_CHAIN,
+    BLOCK_HASH_TABLE, BLOCK_HEADER_KEY, BLOCK_HIGH_SEQ, BLOCK_MINS, BLOCK_NAME, BLOCK_PARENT,
+    BLOCK_PROTECT, BLOCK_SEC_TYPE, BLOCK_TABLE_SIZE, BLOCK_TICKS, BLOCK_TYPE, BM_PAGES, HT_SIZE,
+    OFS_DATA_LEN, OFS_DATA_SIZE, OFS_NEXT_DATA, OFS_SEQ_NUM, ST_FILE, ST_ROOT, ST_USERDIR, T_DATA,
+    T_HEADER, T_LIST,
+};
+pub use crate::layout::{MAX_COMMENT_LEN, MAX_NAME_LEN, MAX_VOLUME_BLOCKS};
 
 #[derive(Debug, Clone)]
 pub struct ADF {
     pub data: Vec<u8>,
     pub bitmap: Vec<bool>,
+    pub(crate) tracking: Tracking,
 }
 
-#[derive(Debug, Clone, Copy)]
+// State derived from data, updated by every write through the ADF methods.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Tracking {
+    // Block counts of the bitmap pages, dropped on every write.
+    pub(crate) bitmap_counts: OnceLock<BitmapCounts>,
+    // Byte ranges written since the image was loaded from or last saved to saved_to.
+    pub(crate) dirty: DirtyExtents,
+    pub(crate) saved_to: Option<PathBuf>,
+    pub(crate) dirs: DirCache,
+    // Repairs and other notable changes since the last save, for the provenance sidecar.
+    pub(crate) changes: Vec<String>,
+    pub(crate) observers: Observers,
+    // Source of every date written, see set_clock.
+    pub(crate) clock: ClockRef,
+}
+
+// Entry blocks and names of the directories listed so far. A listing is dropped when a
+// block it was read from, the directory block or one of its entries, is written.
+#[derive(Debug, Default)]
+pub(crate) struct DirCache(Mutex<DirListings>);
+
+#[derive(Debug, Default)]
+pub(crate) struct DirListings {
+    dirs: HashMap<usize, Arc<[(usize, String)]>>,
+    // For each block, the directories whose listing read it.
+    readers: HashMap<usize, Vec<usize>>,
+}
+
+// Writes of more blocks than this drop every listing instead of looking up each block.
+const DIR_CACHE_SCAN_LIMIT: usize = 16;
+
+impl Clone for DirCache {
+    fn clone(&self) -> Self {
+        DirCache::default()
+    }
+}
+
+impl DirCache {
+    fn get_or_load(
+        &self,
+        dir: usize,
+        load: impl FnOnce() -> Vec<(usize, String)>,
+    ) -> Arc<[(usize, String)]> {
+        let lock = || self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(entries) = lock().dirs.get(&dir) {
+            return entries.clone();
+        }
+        let entries: Arc<[(usize, String)]> = load().into();
+        let mut listings = lock();
+        for block in std::iter::once(dir).chain(entries.iter().map(|&(block, _)| block)) {
+            listings.readers.entry(block).or_default().push(dir);
+        }
+        listings.dirs.insert(dir, entries.clone());
+        entries
+    }
+
+    fn invalidate(&mut self, blocks: Range<usize>) {
+        let listings = self.0.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if listings.dirs.is_empty() {
+            return;
+        }
+        if blocks.len() > DIR_CACHE_SCAN_LIMIT {
+            self.clear();
+            return;
+        }
+        for block in blocks {
+            for dir in listings.readers.remove(&block).unwrap_or_default() {
+                listings.dirs.remove(&dir);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        let listings = self.0.get_mut().unwrap_or_else(PoisonError::into_inner);
+        listings.dirs.clear();
+        listings.readers.clear();
+    }
+}
+
+// Sorted byte ranges that neither overlap nor touch.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DirtyExtents(Vec<Range<usize>>);
+
+impl DirtyExtents {
+    pub(crate) fn mark(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        // Extents from first to last overlap or touch the new range and merge with it.
+        let first = self.0.partition_point(|r| r.end < range.start);
+        let last = self.0.partition_point(|r| r.start <= range.end);
+        let merged = if first < last {
+            self.0[first].start.min(range.start)..self.0[last - 1].end.max(range.end)
+        } else {
+            range
+        };
+        self.0.splice(first..last, [merged]);
+    }
+
+    // Joins extents less than gap bytes apart, rewriting a few unchanged bytes is cheaper
+    // than another seek and write.
+    pub(crate) fn coalesced(&self, gap: usize) -> Vec<Range<usize>> {
+        let mut extents: Vec<Range<usize>> = Vec::with_capacity(self.0.len());
+        for range in &self.0 {
+            match extents.last_mut() {
+                Some(last) if range.start - last.end < gap => last.end = range.end,
+                _ => extents.push(range.clone()),
+            }
+        }
+        extents
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
 pub enum DiskType {
     OFS,
     FFS,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
+pub struct FormatOptions {
+    pub intl: bool,
+    // Refused: a DOS\4 or DOS\5 volume needs directory cache blocks, which are not written
+    // or kept up to date.
+    pub dircache: bool,
+    pub bootable: bool,
+    // Whose boot code a bootable disk gets.
+    pub kickstart: Kickstart,
+    // Only rewrite the boot block, root and bitmap instead of clearing the image, as
+    // AmigaDOS Format QUICK does; the old data stays recoverable.
+    pub quick: bool,
+    // Repeated over the whole image by a full format, zeroes when empty. AMIGADOS_FILL is
+    // what the Format command leaves in unused blocks.
+    pub fill: Vec<u8>,
+    // Blocks after the boot block kept allocated, e.g. for trackloader data, and listed in
+    // RESERVED_BLOCKS_FILE so validate, repair and defragment keep them.
+    pub reserved: usize,
+}
+
+// Size of an image file and how much of it the host has allocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DiskUsage {
+    pub apparent: u64,
+    // Equal to apparent on hosts that do not report allocation.
+    pub allocated: u64,
+}
+
+impl DiskUsage {
+    pub fn of<P: AsRef<Path>>(path: P) -> Result<DiskUsage> {
+        let metadata = fs::metadata(path)?;
+        #[cfg(unix)]
+        let allocated = {
+            use std::os::unix::fs::MetadataExt;
+            metadata.blocks() * 512
+        };
+        #[cfg(not(unix))]
+        let allocated = metadata.len();
+        Ok(DiskUsage {
+            apparent: metadata.len(),
+            allocated,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct FileInfo {
     pub name: String,
     pub size: u32,
     pub is_dir: bool,
     pub protection: u32,
+    #[serde(serialize_with = "serialize_unix_time")]
     pub creation_date: SystemTime,
+    pub comment: String,
+    // The IFF FORM type, such as ILBM, 8SVX or FTXT, for IFF files.
+    pub file_type: Option<String>,
 }
 
-#[derive(Debug)]
+// Order of a directory listing. Disk is hash table order, which changes as entries come
+// and go; the others are stable, ties going by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    Disk,
+    #[default]
+    Name,
+    Size,
+    Date,
+}
+
+impl FromStr for SortOrder {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<SortOrder> {
+        match name.to_ascii_lowercase().as_str() {
+            "disk" => Ok(SortOrder::Disk),
+            "name" => Ok(SortOrder::Name),
+            "size" => Ok(SortOrder::Size),
+            "date" => Ok(SortOrder::Date),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Unknown sort order '{}', use name, size, date or disk",
+                    name
+                ),
+            )),
+        }
+    }
+}
+
+impl SortOrder {
+    pub fn sort(self, files: &mut [FileInfo]) {
+        let by_name = |a: &FileInfo, b: &FileInfo| {
+            a.name
+                .to_lowercase()
+                .cmp(&b.name.to_lowercase())
+                .then_with(|| a.name.cmp(&b.name))
+        };
+        match self {
+            SortOrder::Disk => {}
+            SortOrder::Name => files.sort_by(by_name),
+            SortOrder::Size => {
+                files.sort_by(|a, b| a.size.cmp(&b.size).then_with(|| by_name(a, b)))
+            }
+            SortOrder::Date => files.sort_by(|a, b| {
+                a.creation_date
+                    .cmp(&b.creation_date)
+                    .then_with(|| by_name(a, b))
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
 pub struct DiskInfo {
     pub filesystem: String,
     pub disk_name: String,
     pub creation_date: u32,
     pub disk_size: u32,
-    pub heads: u8,
-    pub tracks: u8,
-    pub sectors_per_track: u8,
+    pub device_type: u8,
+    pub heads: u32,
+    pub tracks: u32,
+    pub sectors_per_track: u32,
     pub bytes_per_sector: u16,
     pub hash_table_size: u32,
     pub first_reserved_block: u32,
     pub last_reserved_block: u32,
 }
 
+impl Debug for DiskInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Filesystem: {}", self.filesystem)?;
+        writeln!(f, "Disk Name: {}", self.disk_name)?;
+        writeln!(f, "Creation Date: {}", self.creation_date)?;
+        writeln!(f, "Disk Size: {} bytes", self.disk_size)?;
+        writeln!(f, "Device Type: {}", device_type_name(self.device_type))?;
+        writeln!(f, "Heads: {}", self.heads)?;
+        writeln!(f, "Tracks: {}", self.tracks)?;
+        writeln!(f, "Sectors per Track: {}", self.sectors_per_track)?;
+        writeln!(f, "Bytes per Sector: {}", self.bytes_per_sector)?;
+        writeln!(f, "Hash Table Size: {}", self.hash_table_size)?;
+        write!(
+            f,
+            "Reserved Blocks: {} - {}",
+            self.first_reserved_block, self.last_reserved_block
+        )
+    }
+}
+
 impl DiskInfo {
     pub fn as_string(&self) -> String {
         format!("{:?}", self)
@@ -72,15 +366,74 @@ pub struct ExtractedFile {
     contents: Vec<u8>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BitmapCounts {
+    pub total_blocks: u32,
+    pub free_blocks: u32,
+    pub used_blocks: u32,
+}
+
+// Walks the bitmap pages in place, yielding true for every block in use. The boot blocks
+// and blocks no bitmap page covers count as used.
+pub struct AllocationMap<'a> {
+    adf: &'a ADF,
+    pages: Vec<usize>,
+    bits: usize,
+    block: usize,
+}
+
+impl Iterator for AllocationMap<'_> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        let block = self.block;
+        if block >= self.adf.num_blocks() {
+            return None;
+        }
+        self.block += 1;
+        let Some(index) = block.checked_sub(2) else {
+            return Some(true);
+        };
+        let Some(&page) = self.pages.get(index / self.bits) else {
+            return Some(true);
+        };
+        let bit = index % self.bits;
+        let word = read_u32_be(self.adf.read_sector(page), 4 + bit / 32 * 4);
+        Some(word & (1 << (bit % 32)) == 0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.adf.num_blocks().saturating_sub(self.block);
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for AllocationMap<'_> {}
+
+#[derive(Debug, Serialize)]
 pub struct BitmapInfo {
     pub total_blocks: u32,
     pub free_blocks: u32,
     pub used_blocks: u32,
     pub disk_usage_percentage: f32,
+    #[serde(serialize_with = "serialize_allocation_map")]
     pub block_allocation_map: Vec<bool>,
 }
 
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncReport {
+    pub copied: Vec<String>,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DirectoryUsage {
+    pub path: String,
+    pub files: usize,
+    pub bytes: u64,
+    pub blocks: usize,
+}
+
 impl std::fmt::Display for BitmapInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
@@ -111,15 +464,249 @@ pub fn format_creation_date(time: SystemTime) -> String {
         .unwrap_or_else(|_| "Invalid date".to_string())
 }
 
+pub fn to_amiga_date(time: SystemTime) -> Result<(u32, u32, u32)> {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|d| d.as_secs().checked_sub(AMIGA_EPOCH_OFFSET))
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Date is before 1978-01-01"))?;
+    let subsec_ticks = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_millis() as u64 * TICKS_PER_SECOND / 1000)
+        .unwrap_or(0);
+    let days = secs / 86400;
+    let mins = (secs % 86400) / 60;
+    let ticks = (secs % 60) * TICKS_PER_SECOND + subsec_ticks;
+    Ok((days as u32, mins as u32, ticks as u32))
+}
+
+pub fn from_amiga_date(days: u32, mins: u32, ticks: u32) -> SystemTime {
+    let secs = AMIGA_EPOCH_OFFSET
+        + days as u64 * 86400
+        + mins as u64 * 60
+        + ticks as u64 / TICKS_PER_SECOND;
+    let millis = (ticks as u64 % TICKS_PER_SECOND) * 1000 / TICKS_PER_SECOND;
+    UNIX_EPOCH + std::time::Duration::from_millis(secs * 1000 + millis)
+}
+
+const PROTECTION_LETTERS: [char; 8] = ['h', 's', 'p', 'a', 'r', 'w', 'e', 'd'];
+
+// The r, w, e and d bits are active low, h, s, p and a are active high.
+fn protection_enabled(flags: u32, bit: u32) -> bool {
+    let set = flags & (1 << bit) != 0;
+    if bit < 4 {
+        !set
+    } else {
+        set
+    }
+}
+
+fn set_protection_enabled(flags: u32, bit: u32, enabled: bool) -> u32 {
+    if enabled == (bit >= 4) {
+        flags | (1 << bit)
+    } else {
+        flags & !(1 << bit)
+    }
+}
+
+pub fn parse_protection_flags(spec: &str, current: u32) -> Result<u32> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid protection flags '{}'", spec),
+        )
+    };
+
+    if let Some(hex) = spec
+        .strip_prefix("0x")
+        .or_else(|| spec.strip_prefix("0X"))
+        .or_else(|| spec.strip_prefix('$'))
+    {
+        return u32::from_str_radix(hex, 16).map_err(|_| invalid());
+    }
+
+    let is_mask = spec.len() == PROTECTION_LETTERS.len()
+        && spec
+            .chars()
+            .zip(PROTECTION_LETTERS)
+            .all(|(c, l)| c == '-' || c.to_ascii_lowercase() == l);
+
+    let (mode, letters) = match spec.chars().next() {
+        _ if is_mask => (None, spec.trim_matches('-')),
+        Some('+') => (Some(true), &spec[1..]),
+        Some('-') => (Some(false), &spec[1..]),
+        _ => (None, spec),
+    };
+
+    let mut bits = Vec::new();
+    for c in letters.chars().filter(|&c| !(is_mask && c == '-')) {
+        let index = PROTECTION_LETTERS
+            .iter()
+            .position(|&l| l == c.to_ascii_lowercase())
+            .ok_or_else(invalid)?;
+        bits.push(7 - index as u32);
+    }
+    if bits.is_empty() && mode.is_some() {
+        return Err(invalid());
+    }
+
+    Ok(match mode {
+        Some(enabled) => bits.into_iter().fold(current, |flags, bit| {
+            set_protection_enabled(flags, bit, enabled)
+        }),
+        None => (0..8).fold(current & !0xff, |flags, bit| {
+            set_protection_enabled(flags, bit, bits.contains(&bit))
+        }),
+    })
+}
+
+fn latin1_bytes(name: &str) -> Result<Vec<u8>> {
+    name.chars()
+        .map(|c| u8::try_from(c as u32))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("'{}' contains characters outside Latin-1", name),
+            )
+        })
+}
+
+fn amiga_toupper(c: u8, intl: bool) -> u8 {
+    if c.is_ascii_lowercase() || (intl && (224..=254).contains(&c) && c != 247) {
+        c - 32
+    } else {
+        c
+    }
+}
+
+// Hash table slot of name in a 512-byte block; see hash_name_for.
+pub fn hash_name(name: &[u8], intl: bool) -> usize {
+    hash_name_for(name, intl, HT_SIZE)
+}
+
+// Hash table slot of name in a table of table_size entries, Layout::hash_table_size of the
+// volume.
+pub fn hash_name_for(name: &[u8], intl: bool, table_size: usize) -> usize {
+    let hash = name.iter().fold(name.len() as u32, |hash, &c| {
+        (hash * 13 + amiga_toupper(c, intl) as u32) & 0x7ff
+    });
+    hash as usize % table_size
+}
+
+fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rsplit_once('/') {
+        Some((parent, name)) => (parent, name),
+        None => ("", path),
+    }
+}
+
+fn entry_name_bytes(name: &str) -> Result<Vec<u8>> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN || name.contains(':') {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid name '{}'", name),
+        ));
+    }
+    latin1_bytes(name)
+}
+
+pub(crate) fn serialize_unix_time<S: Serializer>(
+    time: &SystemTime,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    serializer.serialize_u64(seconds)
+}
+
+fn serialize_allocation_map<S: Serializer>(
+    map: &[bool],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    let map: String = map
+        .iter()
+        .map(|&used| if used { '#' } else { '.' })
+        .collect();
+    serializer.serialize_str(&map)
+}
+
+fn count_host_entries(dir: &Path) -> Result<usize> {
+    let mut count = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            count += 1 + count_host_entries(&entry.path())?;
+        } else if file_type.is_file() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+pub fn find_strings(data: &[u8], min_len: usize) -> Vec<(usize, String)> {
+    let mut strings = Vec::new();
+    let mut start = 0;
+    for (i, &byte) in data.iter().chain(std::iter::once(&0)).enumerate() {
+        if byte == b'\t' || (0x20..0x7f).contains(&byte) {
+            continue;
+        }
+        if i - start >= min_len.max(1) {
+            strings.push((start, latin1_string(&data[start..i])));
+        }
+        start = i + 1;
+    }
+    strings
+}
+
+pub fn hex_dump(data: &[u8], base: usize) -> String {
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if (0x20..0x7f).contains(&b) {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        out.push_str(&format!(
+            "{:08x}  {:<47}  |{}|\n",
+            base + i * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    out
+}
+
+fn same_second(a: SystemTime, b: SystemTime) -> bool {
+    let secs = |time: SystemTime| time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).ok();
+    secs(a) == secs(b)
+}
+
+pub fn join_path(dir: &str, name: &str) -> String {
+    let dir = dir.trim_matches('/');
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
 pub fn load_adf_from_zip(zip_data: &[u8], adf_filename: &str) -> io::Result<ADF> {
     let reader = std::io::Cursor::new(zip_data);
-    let mut archive =
-        ZipArchive::new(reader).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let mut archive = ZipArchive::new(reader).map_err(io::Error::other)?;
 
     for i in 0..archive.len() {
-        let mut file = archive
-            .by_index(i)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let mut file = archive.by_index(i).map_err(io::Error::other)?;
         if file.name() == adf_filename {
             let mut adf_data = Vec::new();
             file.read_to_end(&mut adf_data)?;
@@ -133,19 +720,158 @@ pub fn load_adf_from_zip(zip_data: &[u8], adf_filename: &str) -> io::Result<ADF>
     ))
 }
 
+// Lists a directory by walking its hash chains, asking for one block at a time. Pointers
+// outside the volume end a chain, and the walk stops after as many entries as the volume
+// has blocks, so a looping chain cannot hang the caller.
+pub struct DirectoryDecoder {
+    layout: Layout,
+    dir: usize,
+    // Chain heads from the hash table still to walk, last slot first.
+    heads: Option<Vec<usize>>,
+    next: Option<usize>,
+    positioned: bool,
+    count: usize,
+}
+
+impl DirectoryDecoder {
+    pub fn new(layout: Layout, dir: usize) -> Self {
+        DirectoryDecoder {
+            layout,
+            dir,
+            heads: None,
+            next: None,
+            positioned: false,
+            count: 0,
+        }
+    }
+
+    fn pointer(&self, sector: &[u8], offset: usize) -> Option<usize> {
+        Some(read_u32_be(sector, offset) as usize).filter(|&p| p != 0 && p < self.layout.blocks)
+    }
+
+    // Asks for block, first moving there.
+    fn read(&mut self, block: usize, input: &[u8]) -> Option<Decoded<Entry>> {
+        let block_size = self.layout.block_size;
+        if !self.positioned {
+            self.positioned = true;
+            return Some(Decoded::Seek((block * block_size) as u64));
+        }
+        (input.len() < block_size).then_some(Decoded::Need(block_size))
+    }
+}
+
+impl Decoder for DirectoryDecoder {
+    type Item = Entry;
+
+    fn decode(&mut self, input: &[u8], _eof: bool) -> Result<Decoded<Entry>> {
+        let block_size = self.layout.block_size;
+        if self.heads.is_none() {
+            if let Some(step) = self.read(self.dir, input) {
+                return Ok(step);
+            }
+            let mut heads: Vec<usize> = (0..self.layout.hash_table_size())
+                .filter_map(|slot| self.pointer(input, BLOCK_HASH_TABLE + slot * 4))
+                .collect();
+            heads.reverse();
+            self.heads = Some(heads);
+            self.positioned = false;
+            return Ok(Decoded::Consumed(block_size, None));
+        }
+        if self.next.is_none() {
+            self.next = self.heads.as_mut().and_then(Vec::pop);
+        }
+        let Some(block) = self.next.filter(|_| self.count < self.layout.blocks) else {
+            return Ok(Decoded::Done);
+        };
+        if let Some(step) = self.read(block, input) {
+            return Ok(step);
+        }
+        let sector = &input[..block_size];
+        self.next = self.pointer(sector, self.layout.hash_chain());
+        self.positioned = false;
+        self.count += 1;
+        Ok(Decoded::Consumed(
+            block_size,
+            Some(Entry::from_sector(&self.layout, block, sector)),
+        ))
+    }
+}
+
 impl ADF {
     pub fn new(size: usize, block_size: usize) -> Self {
         ADF {
             data: vec![0; size * block_size],
             bitmap: vec![true; size],
+            tracking: Tracking::default(),
         }
     }
 
     pub fn format(&mut self, disk_type: DiskType, disk_name: &str) -> Result<()> {
-        self.data.fill(0);
+        self.format_with_options(disk_type, disk_name, &FormatOptions::default())
+    }
+
+    pub fn format_with_options(
+        &mut self,
+        disk_type: DiskType,
+        disk_name: &str,
+        options: &FormatOptions,
+    ) -> Result<()> {
+        let layout = self.layout();
+        if !(4..=layout.max_blocks()).contains(&layout.blocks) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Cannot format {} blocks, volumes must have 4 to {} blocks",
+                    layout.blocks,
+                    layout.max_blocks()
+                ),
+            ));
+        }
+        if options.dircache {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Directory cache volumes cannot be created, their cache blocks are not maintained",
+            ));
+        }
+        if 2 + options.reserved >= self.root_block() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Cannot reserve {} blocks, at most {} fit before the root block",
+                    options.reserved,
+                    self.root_block() - 3
+                ),
+            ));
+        }
+        debug!(
+            "Formatting as {:?} with name '{}' ({:?})",
+            disk_type, disk_name, options
+        );
+        if !options.quick {
+            let len = self.data.len();
+            let data = self.data_mut(0..len);
+            if options.fill.is_empty() {
+                data.fill(0);
+            } else {
+                for chunk in data.chunks_mut(options.fill.len()) {
+                    chunk.copy_from_slice(&options.fill[..chunk.len()]);
+                }
+            }
+        }
         self.write_boot_block(disk_type)?;
+        if options.intl {
+            self.data[3] |= 2;
+        }
         self.write_root_block(disk_type, disk_name)?;
         self.write_bitmap_blocks()?;
+        self.update_bitmap_blocks()?;
+        if options.reserved > 0 {
+            let reserved: Vec<usize> = (2..2 + options.reserved).collect();
+            self.write_reserved_blocks(&reserved)?;
+        }
+        if options.bootable {
+            self.install_bootblock_for(options.kickstart)?;
+        }
         Ok(())
     }
     pub fn extract_file(&self, file_name: &str) -> io::Result<ExtractedFile> {
@@ -160,7 +886,8 @@ impl ADF {
                     ));
                 }
 
-                let file_header_block = self.find_file_header_block(ROOT_BLOCK, file_name)?;
+                let file_header_block =
+                    self.find_file_header_block(self.root_block(), file_name)?;
                 let contents = self.read_file_contents(file_header_block)?;
                 let is_ascii = contents
                     .iter()
@@ -168,7 +895,7 @@ impl ADF {
 
                 return Ok(ExtractedFile {
                     name: file_name.to_string(),
-                    size: file_info.size as u32,
+                    size: file_info.size,
                     header_block: file_header_block as u32,
                     is_ascii,
                     contents,
@@ -183,21 +910,8 @@ impl ADF {
     }
 
     fn find_file_header_block(&self, dir_block: usize, file_name: &str) -> io::Result<usize> {
-        let block_data = self.read_sector(dir_block);
-
-        for i in (24..=51).rev() {
-            let sector = u32::from_be_bytes([
-                block_data[i * 4],
-                block_data[i * 4 + 1],
-                block_data[i * 4 + 2],
-                block_data[i * 4 + 3],
-            ]);
-            if sector != 0 {
-                let file_info = self.read_file_header(sector as usize)?;
-                if file_info.name == file_name {
-                    return Ok(sector as usize);
-                }
-            }
+        if let Some(block) = self.lookup_entry(dir_block, file_name)? {
+            return Ok(block);
         }
 
         Err(io::Error::new(
@@ -207,27 +921,59 @@ impl ADF {
     }
 
     pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
-        if data.len() != ADF_TRACK_SIZE * ADF_NUM_TRACKS {
+        let floppy = data.len() == ADF_NUM_SECTORS * ADF_SECTOR_SIZE
+            || data.len() == ADF_HD_NUM_SECTORS * ADF_SECTOR_SIZE;
+        let hardfile = data.len().is_multiple_of(ADF_SECTOR_SIZE)
+            && (4 * ADF_SECTOR_SIZE..=MAX_VOLUME_BLOCKS * ADF_SECTOR_SIZE).contains(&data.len())
+            && data.starts_with(b"DOS");
+        if !floppy && !hardfile {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!(
-                    "Invalid ADF size: expected {} bytes, got {} bytes",
-                    ADF_TRACK_SIZE * ADF_NUM_TRACKS,
+                    "Invalid ADF size: expected {} (DD) or {} (HD) bytes or a DOS formatted \
+                     hardfile, got {} bytes",
+                    ADF_NUM_SECTORS * ADF_SECTOR_SIZE,
+                    ADF_HD_NUM_SECTORS * ADF_SECTOR_SIZE,
                     data.len()
                 ),
             ));
         }
-        Ok(ADF {
+        let mut adf = ADF {
             data: data.to_vec(),
-            bitmap: vec![true; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
-        })
+            bitmap: Vec::new(),
+            tracking: Tracking::default(),
+        };
+        adf.bitmap = adf.read_allocation_map();
+        Ok(adf)
     }
 
     pub fn from_file(path: &str) -> Result<ADF> {
-        let mut file = File::open(path)?;
-        let mut data = vec![0; ADF_SECTOR_SIZE * ADF_NUM_SECTORS];
-        file.read_exact(&mut data)?;
-        ADF::from_bytes(&data)
+        let mut adf = ADF::from_bytes(&fs::read(path)?)?;
+        adf.tracking.saved_to = Some(PathBuf::from(path));
+        Ok(adf)
+    }
+
+    // Accepts a plain image, a DMS archive or a ZIP archive holding an .adf or .hdf file,
+    // telling them apart by their contents.
+    pub fn open_any(data: &[u8]) -> Result<ADF> {
+        if data.starts_with(b"DMS!") {
+            return ADF::from_dms(data);
+        }
+        if data.starts_with(b"PK\x03\x04") {
+            let archive = ZipArchive::new(std::io::Cursor::new(data)).map_err(io::Error::other)?;
+            let name = archive
+                .file_names()
+                .find(|name| {
+                    let name = name.to_lowercase();
+                    name.ends_with(".adf") || name.ends_with(".hdf")
+                })
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "No disk image in ZIP archive")
+                })?
+                .to_string();
+            return load_adf_from_zip(data, &name);
+        }
+        ADF::from_bytes(data)
     }
 
     pub fn get_bitmap(&self) -> &[bool] {
@@ -235,35 +981,64 @@ impl ADF {
     }
 
     pub fn get_bitmap_info(&self) -> BitmapInfo {
-        let bitmap_block = self.read_sector(ROOT_BLOCK + 1);
-        let mut free_blocks = 0;
-        let mut used_blocks = 0;
-        let mut block_allocation_map = Vec::with_capacity(ADF_NUM_SECTORS);
-
-        for (i, &byte) in bitmap_block.iter().enumerate() {
-            if i < 220 {
-                for bit in 0..8 {
-                    if i * 8 + bit < ADF_NUM_SECTORS {
-                        let is_free = byte & (1 << (7 - bit)) != 0;
-                        if is_free {
-                            free_blocks += 1;
-                        } else {
-                            used_blocks += 1;
-                        }
-                        block_allocation_map.push(!is_free);
-                    }
-                }
-            }
+        let counts = self.bitmap_counts();
+        let disk_usage_percentage =
+            (counts.used_blocks as f64 / counts.total_blocks as f64) * 100.0;
+
+        BitmapInfo {
+            total_blocks: counts.total_blocks,
+            free_blocks: counts.free_blocks,
+            used_blocks: counts.used_blocks,
+            disk_usage_percentage: disk_usage_percentage as f32,
+            block_allocation_map: self.allocation_map().collect(),
         }
+    }
 
-        let disk_usage_percentage = (used_blocks as f64 / ADF_NUM_SECTORS as f64) * 100.0;
+    pub fn allocation_map(&self) -> AllocationMap<'_> {
+        AllocationMap {
+            adf: self,
+            pages: self.bitmap_pages(),
+            bits: self.layout().bits_per_bitmap_page(),
+            block: 0,
+        }
+    }
 
-        BitmapInfo {
-            total_blocks: ADF_NUM_SECTORS as u32,
+    // Counts the free bits of each bitmap page a word at a time; the result is kept until
+    // the image is written to.
+    pub fn bitmap_counts(&self) -> BitmapCounts {
+        *self
+            .tracking
+            .bitmap_counts
+            .get_or_init(|| self.count_bitmap())
+    }
+
+    fn count_bitmap(&self) -> BitmapCounts {
+        let blocks = self.num_blocks();
+        let bits = self.layout().bits_per_bitmap_page();
+        let free_blocks: u32 = self
+            .bitmap_pages()
+            .into_iter()
+            .enumerate()
+            .map(|(page_index, page)| {
+                let covered = blocks.saturating_sub(2 + page_index * bits).min(bits);
+                self.read_sector(page)[4..]
+                    .chunks_exact(4)
+                    .take(covered.div_ceil(32))
+                    .enumerate()
+                    .map(|(word_index, word)| {
+                        let word = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+                        match covered - word_index * 32 {
+                            32.. => word.count_ones(),
+                            valid => (word & ((1 << valid) - 1)).count_ones(),
+                        }
+                    })
+                    .sum::<u32>()
+            })
+            .sum();
+        BitmapCounts {
+            total_blocks: blocks as u32,
             free_blocks,
-            used_blocks,
-            disk_usage_percentage: disk_usage_percentage as f32,
-            block_allocation_map,
+            used_blocks: blocks as u32 - free_blocks,
         }
     }
 
@@ -287,166 +1062,1199 @@ impl ADF {
         }
     }
 
-    pub fn defragment(&mut self) -> Result<()> {
-        let mut free_blocks = Vec::new();
-        for (i, &is_free) in self.bitmap.iter().enumerate() {
-            if is_free {
-                free_blocks.push(i);
-            }
-        }
-        Ok(())
-    }
-
     pub fn get_fragmentation_score(&self) -> usize {
         self.bitmap.iter().filter(|&&b| !b).count()
     }
 
     pub fn write_to_file(&self, path: &str) -> Result<()> {
+        if self.data.len() > SPARSE_MIN_SIZE {
+            return self.write_sparse(path);
+        }
         let mut file = File::create(path)?;
         file.write_all(&self.data)?;
         Ok(())
     }
 
-    pub fn find_contiguous_free_blocks(&self, count: usize) -> Option<usize> {
-        let mut free_blocks = Vec::new();
-        for (i, &is_free) in self.bitmap.iter().enumerate() {
-            if is_free {
-                free_blocks.push(i);
+    // Writes the image seeking over all-zero blocks, so a blank hardfile takes little space
+    // on hosts whose file systems support holes. DiskUsage::of tells how much it took.
+    // The image is in memory, so this is bounded by MAX_VOLUME_BLOCKS like every ADF.
+    pub fn write_sparse(&self, path: &str) -> Result<()> {
+        let mut file = File::create(path)?;
+        let len = self.data.len();
+        let is_zero = |offset: usize| {
+            self.data[offset..(offset + ADF_SECTOR_SIZE).min(len)]
+                .iter()
+                .all(|&b| b == 0)
+        };
+        let mut start = 0;
+        while start < len {
+            let zero = is_zero(start);
+            let mut end = (start + ADF_SECTOR_SIZE).min(len);
+            while end < len && is_zero(end) == zero {
+                end = (end + ADF_SECTOR_SIZE).min(len);
+            }
+            if zero {
+                file.seek(SeekFrom::Start(end as u64))?;
+            } else {
+                file.write_all(&self.data[start..end])?;
             }
+            start = end;
         }
+        file.set_len(len as u64)?;
+        Ok(())
+    }
 
-        for i in 0..free_blocks.len() - count {
-            if free_blocks[i + count] - free_blocks[i] == count {
-                return Some(free_blocks[i]);
+    // Writes the image as the single file inner_name of a new zip archive, the counterpart
+    // of load_adf_from_zip. The entry is dated with the image's clock.
+    pub fn save_to_zip<W: Write + Seek>(
+        &self,
+        writer: W,
+        inner_name: &str,
+        compression: CompressionMethod,
+    ) -> Result<()> {
+        let mut zip = ZipWriter::new(writer);
+        let options = SimpleFileOptions::default()
+            .compression_method(compression)
+            .last_modified_time(zip_date(self.now()))
+            .large_file(self.data.len() as u64 >= u64::from(u32::MAX));
+        zip.start_file(inner_name, options).map_err(Error::other)?;
+        zip.write_all(&self.data)?;
+        zip.finish().map_err(Error::other)?;
+        Ok(())
+    }
+
+    // Like write_to_file, but when path is the file the image was loaded from or last saved
+    // to and it still has the image's size, only the ranges changed since then are written.
+    // A provenance sidecar next to path gets the changes made since the last save.
+    pub fn save(&mut self, path: &str) -> Result<()> {
+        let in_place = self.tracking.saved_to.as_deref() == Some(Path::new(path))
+            && fs::metadata(path).is_ok_and(|m| m.len() == self.data.len() as u64);
+        if in_place {
+            let extents = self.tracking.dirty.coalesced(SAVE_COALESCE_GAP);
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            for range in &extents {
+                file.seek(SeekFrom::Start(range.start as u64))?;
+                file.write_all(&self.data[range.clone()])?;
             }
+            debug!("Saved {} changed ranges to {}", extents.len(), path);
+        } else {
+            self.write_to_file(path)?;
         }
+        self.tracking.dirty.clear();
+        self.tracking.saved_to = Some(PathBuf::from(path));
+        let changes = std::mem::take(&mut self.tracking.changes);
+        Provenance::update_sidecar(Path::new(path), &self.data, &changes, self.now())?;
+        Ok(())
+    }
 
-        None
+    pub(crate) fn note_change(&mut self, action: String) {
+        self.tracking.changes.push(action);
     }
 
-    pub fn read_sector(&self, sector: usize) -> &[u8] {
-        let offset = sector * ADF_SECTOR_SIZE;
-        &self.data[offset..offset + ADF_SECTOR_SIZE]
+    // Byte ranges written since the image was loaded or last saved.
+    pub fn dirty_extents(&self) -> &[Range<usize>] {
+        &self.tracking.dirty.0
     }
 
-    pub fn write_sector(&mut self, sector: usize, data: &[u8]) -> Result<()> {
-        if data.len() != ADF_SECTOR_SIZE {
+    // Records a write to data made without the ADF methods, so save picks it up.
+    pub fn mark_dirty(&mut self, range: Range<usize>) {
+        let blocks = range.start / ADF_SECTOR_SIZE..range.end.div_ceil(ADF_SECTOR_SIZE);
+        self.tracking.bitmap_counts.take();
+        self.tracking.dirs.invalidate(blocks.clone());
+        self.tracking.dirty.mark(range);
+        self.tracking.observers.block_written(blocks);
+    }
+
+    // Drops everything derived from data and reads the allocation map again, for when data
+    // was changed without the ADF methods or mark_dirty.
+    pub fn refresh(&mut self) {
+        self.tracking.bitmap_counts.take();
+        self.tracking.dirs.clear();
+        self.bitmap = self.read_allocation_map();
+    }
+
+    pub(crate) fn data_mut(&mut self, range: Range<usize>) -> &mut [u8] {
+        self.mark_dirty(range.clone());
+        &mut self.data[range]
+    }
+
+    pub fn find_contiguous_free_blocks(&self, count: usize) -> Option<usize> {
+        let mut free_blocks = Vec::new();
+        for (i, &is_free) in self.bitmap.iter().enumerate() {
+            if is_free {
+                free_blocks.push(i);
+            }
+        }
+
+        for i in 0..free_blocks.len() - count {
+            if free_blocks[i + count] - free_blocks[i] == count {
+                return Some(free_blocks[i]);
+            }
+        }
+
+        None
+    }
+
+    pub fn read_sector(&self, sector: usize) -> &[u8] {
+        let offset = sector * ADF_SECTOR_SIZE;
+        &self.data[offset..offset + ADF_SECTOR_SIZE]
+    }
+
+    pub fn write_sector(&mut self, sector: usize, data: &[u8]) -> Result<()> {
+        if data.len() != ADF_SECTOR_SIZE {
             return Err(Error::new(
                 ErrorKind::InvalidInput,
                 "Invalid sector data size",
             ));
         }
         let offset = sector * ADF_SECTOR_SIZE;
-        self.data[offset..offset + ADF_SECTOR_SIZE].copy_from_slice(data);
+        self.data_mut(offset..offset + ADF_SECTOR_SIZE)
+            .copy_from_slice(data);
+        trace!("Wrote sector {}", sector);
         Ok(())
     }
 
-    pub fn read_boot_block(&self) -> &[u8] {
-        &self.data[0..2 * ADF_SECTOR_SIZE]
+    // Converts a cylinder (track), head and sector address into a linear sector number using
+    // the geometry detected from the image size.
+    pub fn ts_to_sector(&self, track: usize, head: usize, sector: usize) -> Result<usize> {
+        let geometry = Device::detect_geometry(self.data.len() as u64);
+        let (tracks, heads, sectors) = (
+            geometry.cylinders as usize,
+            geometry.heads as usize,
+            geometry.sectors as usize,
+        );
+        if track >= tracks || head >= heads || sector >= sectors {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Track {} head {} sector {} is outside the {}/{}/{} geometry",
+                    track, head, sector, tracks, heads, sectors
+                ),
+            ));
+        }
+        Ok((track * heads + head) * sectors + sector)
+    }
+
+    pub fn sector_to_ts(&self, sector: usize) -> Result<(usize, usize, usize)> {
+        if sector >= self.num_blocks() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Sector {} is beyond the end of the image", sector),
+            ));
+        }
+        let geometry = Device::detect_geometry(self.data.len() as u64);
+        let (heads, sectors) = (geometry.heads as usize, geometry.sectors as usize);
+        Ok((
+            sector / (heads * sectors),
+            sector / sectors % heads,
+            sector % sectors,
+        ))
+    }
+
+    pub fn read_ts(&self, track: usize, head: usize, sector: usize) -> Result<&[u8]> {
+        Ok(self.read_sector(self.ts_to_sector(track, head, sector)?))
+    }
+
+    pub fn write_ts(
+        &mut self,
+        track: usize,
+        head: usize,
+        sector: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        let sector = self.ts_to_sector(track, head, sector)?;
+        self.write_sector(sector, data)
+    }
+
+    // The image in tracks of a DD floppy, 11 sectors, cylinder by cylinder and head by head;
+    // an HD track comes as two of them. Images of other sizes fail with InvalidInput.
+    pub fn split_tracks(&self) -> Result<Vec<[u8; ADF_TRACK_SIZE]>> {
+        if !self.data.len().is_multiple_of(ADF_TRACK_SIZE) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Image size {} is not a whole number of {} byte tracks",
+                    self.data.len(),
+                    ADF_TRACK_SIZE
+                ),
+            ));
+        }
+        Ok(self
+            .data
+            .chunks_exact(ADF_TRACK_SIZE)
+            .map(|track| track.try_into().unwrap())
+            .collect())
+    }
+
+    // Joins tracks as split_tracks gives them back into an image.
+    pub fn from_tracks<I: IntoIterator<Item = [u8; ADF_TRACK_SIZE]>>(tracks: I) -> Result<ADF> {
+        let data: Vec<u8> = tracks.into_iter().flatten().collect();
+        ADF::from_bytes(&data)
+    }
+
+    pub fn read_boot_block(&self) -> &[u8; BOOT_BLOCK_SIZE] {
+        self.data
+            .first_chunk()
+            .expect("image is smaller than a boot block")
     }
 
     pub fn read_root_block(&self) -> &[u8] {
-        self.read_sector(ROOT_BLOCK)
+        self.read_sector(self.root_block())
     }
 
-    pub fn list_root_directory(&self) -> Result<Vec<FileInfo>> {
-        self.list_directory(ROOT_BLOCK).collect()
+    pub fn find_entry(&self, path: &str) -> Result<usize> {
+        let mut block = self.root_block();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if !self.is_directory_block(block) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("'{}' is not a directory", path),
+                ));
+            }
+            block = self
+                .lookup_entry(block, component)?
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("'{}' not found", path)))?;
+        }
+        Ok(block)
     }
 
-    pub fn list_directory(&self, block: usize) -> impl Iterator<Item = Result<FileInfo>> + '_ {
-        let block_data = self.read_sector(block);
-        (24..=51).rev().filter_map(move |i| {
-            let sector = u32::from_be_bytes([
-                block_data[i * 4],
-                block_data[i * 4 + 1],
-                block_data[i * 4 + 2],
-                block_data[i * 4 + 3],
-            ]);
-            if sector != 0 {
-                Some(self.read_file_header(sector as usize))
-            } else {
-                None
+    // Finds name in a directory the way AmigaDOS does: hash it to its table slot and follow
+    // that slot's chain, comparing names without regard to case.
+    pub fn lookup_entry(&self, dir_block: usize, name: &str) -> Result<Option<usize>> {
+        let intl = self.is_intl();
+        let name = latin1_bytes(name)?;
+        let upper =
+            |bytes: &[u8]| -> Vec<u8> { bytes.iter().map(|&c| amiga_toupper(c, intl)).collect() };
+        let wanted = upper(&name);
+        let layout = self.layout();
+        let slot = hash_name_for(&name, intl, layout.hash_table_size());
+        let mut next = read_u32_be(self.read_sector(dir_block), BLOCK_HASH_TABLE + slot * 4);
+        let mut visited = 0;
+        while next != 0 {
+            let block = next as usize;
+            visited += 1;
+            if block >= self.num_blocks() || visited > self.num_blocks() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Broken hash chain in directory block {}", dir_block),
+                ));
             }
+            let sector = self.read_sector(block);
+            let len = (sector[BLOCK_NAME] as usize).min(MAX_NAME_LEN);
+            if upper(&sector[BLOCK_NAME + 1..BLOCK_NAME + 1 + len]) == wanted {
+                return Ok(Some(block));
+            }
+            next = read_u32_be(sector, layout.hash_chain());
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn directory_entries(&self, dir_block: usize) -> Vec<usize> {
+        self.cached_entries(dir_block)
+            .iter()
+            .map(|&(entry, _)| entry)
+            .collect()
+    }
+
+    // Entry blocks and names of a directory, decoded once and kept until one of their
+    // blocks is written.
+    fn cached_entries(&self, dir_block: usize) -> Arc<[(usize, String)]> {
+        self.tracking.dirs.get_or_load(dir_block, || {
+            decode_slice(DirectoryDecoder::new(self.layout(), dir_block), &self.data)
+                .map_while(|entry| entry.ok())
+                .map(|entry| (entry.block, entry.name))
+                .collect()
         })
     }
 
-    fn read_file_header(&self, block: usize) -> Result<FileInfo> {
+    pub(crate) fn entry_name(&self, block: usize) -> String {
         let block_data = self.read_sector(block);
+        let name_len = std::cmp::min(block_data[BLOCK_NAME] as usize, MAX_NAME_LEN);
+        latin1_string(&block_data[BLOCK_NAME + 1..BLOCK_NAME + 1 + name_len])
+    }
 
-        let name_len = block_data[432] as usize;
-        let name = String::from_utf8_lossy(&block_data[433..433 + name_len]).to_string();
-
-        let size = u32::from_be_bytes([block_data[4], block_data[5], block_data[6], block_data[7]]);
-        let is_dir = block_data[0] == 2;
-        let protection = u32::from_be_bytes([
-            block_data[436],
-            block_data[437],
-            block_data[438],
-            block_data[439],
-        ]);
-
-        let days = u32::from_be_bytes([
-            block_data[440],
-            block_data[441],
-            block_data[442],
-            block_data[443],
-        ]);
-        let mins = u32::from_be_bytes([
-            block_data[444],
-            block_data[445],
-            block_data[446],
-            block_data[447],
-        ]);
-        let ticks = u32::from_be_bytes([
-            block_data[448],
-            block_data[449],
-            block_data[450],
-            block_data[451],
-        ]);
-
-        let creation_date = match days
-            .checked_mul(86400)
-            .and_then(|d| d.checked_add(mins.checked_mul(60).unwrap_or(0)))
-            .and_then(|t| t.checked_add(ticks.checked_div(50).unwrap_or(0)))
-        {
-            Some(secs) => SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs as u64),
-            None => SystemTime::UNIX_EPOCH,
+    pub fn is_dir(&self, path: &str) -> bool {
+        self.find_entry(path)
+            .map(|block| self.is_directory_block(block))
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn is_directory_block(&self, block: usize) -> bool {
+        matches!(
+            read_u32_be(self.read_sector(block), BLOCK_SEC_TYPE),
+            ST_ROOT | ST_USERDIR
+        )
+    }
+
+    pub(crate) fn update_block_checksum(&mut self, block: usize) {
+        let offset = block * ADF_SECTOR_SIZE;
+        let checksum = normal_checksum(&self.data[offset..offset + ADF_SECTOR_SIZE]);
+        write_u32_be(
+            self.data_mut(offset..offset + ADF_SECTOR_SIZE),
+            BLOCK_CHECKSUM,
+            checksum,
+        );
+    }
+
+    pub(crate) fn file_blocks(&self, header: usize) -> Result<(Vec<usize>, Vec<usize>)> {
+        let mut data_blocks = Vec::new();
+        let mut extension_blocks = Vec::new();
+        let file_header = HeaderBlock::from_bytes(self.read_sector(header), &self.layout())?;
+        let mut table = file_header.data_blocks();
+        let mut next = file_header.extension;
+        let mut block = header;
+        loop {
+            for data_block in table {
+                match data_block.index() {
+                    Some(data_block) if data_block < self.num_blocks() => {
+                        data_blocks.push(data_block)
+                    }
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "Invalid data block pointer {} in block {}",
+                                data_block, block
+                            ),
+                        ))
+                    }
+                }
+            }
+
+            let Some(extension) = next.index() else {
+                break;
+            };
+            block = extension;
+            if block >= self.num_blocks() || extension_blocks.len() >= self.num_blocks() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid extension block pointer {}", block),
+                ));
+            }
+            extension_blocks.push(block);
+            let extension_block =
+                FileExtBlock::from_bytes(self.read_sector(block), &self.layout())?;
+            table = extension_block.data_blocks();
+            next = extension_block.extension;
+        }
+        Ok((data_blocks, extension_blocks))
+    }
+
+    pub(crate) fn bitmap_pages(&self) -> Vec<usize> {
+        let layout = self.layout();
+        let root_block = self.read_sector(layout.root_block());
+        (0..layout.root_bitmap_pages())
+            .map(|i| read_u32_be(root_block, layout.bm_pages() + i * 4) as usize)
+            .take_while(|&page| page != 0)
+            .filter(|&page| page < self.num_blocks())
+            .collect()
+    }
+
+    pub fn patch_sector(
+        &mut self,
+        sector: usize,
+        offset: usize,
+        bytes: &[u8],
+        expected: Option<&[u8]>,
+    ) -> Result<Vec<u8>> {
+        if sector >= self.data.len() / ADF_SECTOR_SIZE || offset + bytes.len() > ADF_SECTOR_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Patch of {} bytes at sector {} offset {} is out of range",
+                    bytes.len(),
+                    sector,
+                    offset
+                ),
+            ));
+        }
+        let start = sector * ADF_SECTOR_SIZE + offset;
+        let old = self.data[start..start + bytes.len()].to_vec();
+        if let Some(expected) = expected {
+            if expected != old.as_slice() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Existing bytes at sector {} offset {} do not match the expected value",
+                        sector, offset
+                    ),
+                ));
+            }
+        }
+        self.data_mut(start..start + bytes.len())
+            .copy_from_slice(bytes);
+        Ok(old)
+    }
+
+    // How block is checksummed, None for FFS data blocks and blocks of other types.
+    pub fn checksum_kind(&self, block: usize) -> Option<ChecksumKind> {
+        if block < 2 {
+            return Some(ChecksumKind::Boot);
+        }
+        if self.bitmap_pages().contains(&block) {
+            return Some(ChecksumKind::Bitmap);
+        }
+        match read_u32_be(self.read_sector(block), BLOCK_TYPE) {
+            T_HEADER | T_LIST => Some(ChecksumKind::Normal),
+            T_DATA if !self.is_ffs() => Some(ChecksumKind::Normal),
+            _ => None,
+        }
+    }
+
+    pub fn fix_checksum(&mut self, block: usize) -> Result<()> {
+        let Some(kind) = self.checksum_kind(block) else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Block {} has no checksum (type {})",
+                    block,
+                    read_u32_be(self.read_sector(block), BLOCK_TYPE)
+                ),
+            ));
+        };
+        let range = match kind {
+            ChecksumKind::Boot => 0..BOOT_BLOCK_SIZE,
+            _ => block * ADF_SECTOR_SIZE..(block + 1) * ADF_SECTOR_SIZE,
         };
+        let checksum = kind.checksum(&self.data[range.clone()]).unwrap_or_default();
+        write_u32_be(self.data_mut(range), kind.offset(), checksum);
+        self.note_change(format!("Fixed checksum of block {}", block));
+        Ok(())
+    }
+
+    pub fn is_reserved_block(&self, block: usize) -> bool {
+        block < 2 || block == self.root_block() || self.bitmap_pages().contains(&block)
+    }
+
+    pub fn block_range(&self, spec: &str) -> Result<(usize, usize)> {
+        let (block, count) = match spec.to_ascii_lowercase().as_str() {
+            "boot" => (0, 2),
+            "root" => (self.root_block(), 1),
+            "bitmap" => match self.bitmap_pages().first() {
+                Some(&page) => (page, 1),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        "Root block has no bitmap pages",
+                    ))
+                }
+            },
+            number => {
+                let block = match number.strip_prefix("0x") {
+                    Some(hex) => usize::from_str_radix(hex, 16),
+                    None => number.parse(),
+                }
+                .map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid block '{}', expected boot, root, bitmap or a number",
+                            spec
+                        ),
+                    )
+                })?;
+                (block, 1)
+            }
+        };
+        if (block + count) * ADF_SECTOR_SIZE > self.data.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Block {} is out of range", block),
+            ));
+        }
+        Ok((block * ADF_SECTOR_SIZE, count * ADF_SECTOR_SIZE))
+    }
+
+    pub fn read_allocation_map(&self) -> Vec<bool> {
+        let mut free = vec![false; self.num_blocks()];
+        let bits = self.layout().bits_per_bitmap_page();
+        for (page_index, page) in self.bitmap_pages().into_iter().enumerate() {
+            let bitmap_block = self.read_sector(page);
+            for bit in 0..bits {
+                let block_index = 2 + page_index * bits + bit;
+                if block_index >= self.num_blocks() {
+                    break;
+                }
+                free[block_index] =
+                    read_u32_be(bitmap_block, 4 + bit / 32 * 4) & (1 << (bit % 32)) != 0;
+            }
+        }
+        free
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.data.len() / ADF_SECTOR_SIZE
+    }
+
+    pub fn image(&self) -> Result<Image<'_>> {
+        Ok(Image::new(&self.data)?)
+    }
+
+    pub fn layout(&self) -> Layout {
+        Layout::new(ADF_SECTOR_SIZE, self.num_blocks())
+    }
+
+    pub fn root_block(&self) -> usize {
+        self.layout().root_block()
+    }
+
+    pub fn is_ffs(&self) -> bool {
+        self.data[3] & 1 == 1
+    }
+
+    pub fn is_intl(&self) -> bool {
+        self.data[3] & 6 != 0
+    }
+
+    pub(crate) fn free_block_count(&self) -> usize {
+        self.bitmap.iter().skip(2).filter(|&&free| free).count()
+    }
+
+    fn sector_mut(&mut self, block: usize) -> &mut [u8] {
+        trace!("Wrote sector {}", block);
+        let offset = block * ADF_SECTOR_SIZE;
+        self.data_mut(offset..offset + ADF_SECTOR_SIZE)
+    }
+
+    fn create_entry(&mut self, path: &str, sec_type: SecType) -> Result<usize> {
+        let (parent_path, name) = split_path(path);
+        let name_bytes = entry_name_bytes(name)?;
+        let parent = self.find_directory(parent_path)?;
+        if self.find_entry(path).is_ok() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("'{}' already exists", path),
+            ));
+        }
+
+        let block = self.allocate_block()?;
+        let (days, mins, ticks) = to_amiga_date(self.now())?;
+        let header = HeaderBlock {
+            block_type: T_HEADER,
+            header_key: BlockPtr::new(block),
+            date: DateStamp { days, mins, ticks },
+            name: latin1_string(&name_bytes),
+            parent: BlockPtr::new(parent),
+            sec_type,
+            ..HeaderBlock::default()
+        };
+        let bytes = header.to_bytes(&self.layout());
+        self.sector_mut(block).copy_from_slice(&bytes);
+
+        self.link_entry(parent, block);
+        debug!("Created header block {} for {}", block, path);
+        Ok(block)
+    }
+
+    pub(crate) fn find_directory(&self, path: &str) -> Result<usize> {
+        let block = self.find_entry(path)?;
+        if !self.is_directory_block(block) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("'{}' is not a directory", path),
+            ));
+        }
+        Ok(block)
+    }
+
+    fn hash_slot(&self, block: usize) -> usize {
+        let header = self.read_sector(block);
+        let len = (header[BLOCK_NAME] as usize).min(MAX_NAME_LEN);
+        let name = &header[BLOCK_NAME + 1..BLOCK_NAME + 1 + len];
+        BLOCK_HASH_TABLE + hash_name_for(name, self.is_intl(), self.layout().hash_table_size()) * 4
+    }
+
+    fn link_entry(&mut self, parent: usize, block: usize) {
+        let mut link_block = parent;
+        let mut link_offset = self.hash_slot(block);
+        loop {
+            let next = read_u32_be(self.read_sector(link_block), link_offset) as usize;
+            if next == 0 {
+                break;
+            }
+            link_block = next;
+            link_offset = BLOCK_HASH_CHAIN;
+        }
+        write_u32_be(self.sector_mut(link_block), link_offset, block as u32);
+        self.update_block_checksum(link_block);
+    }
 
+    fn unlink_entry(&mut self, parent: usize, block: usize) -> Result<()> {
+        let mut link_block = parent;
+        let mut link_offset = self.hash_slot(block);
+        let mut visited = 0;
+        loop {
+            let next = read_u32_be(self.read_sector(link_block), link_offset) as usize;
+            if next == block {
+                break;
+            }
+            visited += 1;
+            if next == 0 || next >= self.data.len() / ADF_SECTOR_SIZE || visited > self.num_blocks()
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Block {} is not linked into its parent directory", block),
+                ));
+            }
+            link_block = next;
+            link_offset = BLOCK_HASH_CHAIN;
+        }
+        let chain = read_u32_be(self.read_sector(block), BLOCK_HASH_CHAIN);
+        write_u32_be(self.sector_mut(link_block), link_offset, chain);
+        self.update_block_checksum(link_block);
+        Ok(())
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let block = self.find_entry(from)?;
+        if block == self.root_block() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Cannot rename the root directory",
+            ));
+        }
+
+        let target = match self.find_entry(to) {
+            Ok(existing) if existing != block && self.is_directory_block(existing) => {
+                join_path(to, &self.entry_name(block))
+            }
+            _ => to.to_string(),
+        };
+        let (parent_path, name) = split_path(&target);
+        let name_bytes = entry_name_bytes(name)?;
+        let new_parent = self.find_directory(parent_path)?;
+        if matches!(self.find_entry(&target), Ok(existing) if existing != block) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("'{}' already exists", target),
+            ));
+        }
+
+        let mut ancestor = new_parent;
+        while ancestor != self.root_block() {
+            if ancestor == block {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Cannot move '{}' into itself", from),
+                ));
+            }
+            ancestor = read_u32_be(self.read_sector(ancestor), BLOCK_PARENT) as usize;
+        }
+
+        let old_parent = read_u32_be(self.read_sector(block), BLOCK_PARENT) as usize;
+        self.unlink_entry(old_parent, block)?;
+
+        let header = self.sector_mut(block);
+        header[BLOCK_NAME..BLOCK_NAME + 1 + MAX_NAME_LEN].fill(0);
+        header[BLOCK_NAME] = name_bytes.len() as u8;
+        header[BLOCK_NAME + 1..BLOCK_NAME + 1 + name_bytes.len()].copy_from_slice(&name_bytes);
+        write_u32_be(header, BLOCK_HASH_CHAIN, 0);
+        write_u32_be(header, BLOCK_PARENT, new_parent as u32);
+        self.update_block_checksum(block);
+
+        self.link_entry(new_parent, block);
+        self.tracking.observers.entry_removed(from, block);
+        self.tracking.observers.entry_created(&target, block);
+        Ok(())
+    }
+
+    pub fn delete(&mut self, path: &str) -> Result<()> {
+        let block = self.find_entry(path)?;
+        if block == self.root_block() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Cannot delete the root directory",
+            ));
+        }
+        let freed = if self.is_directory_block(block) {
+            if !self.directory_entries(block).is_empty() {
+                return Err(Error::new(
+                    ErrorKind::DirectoryNotEmpty,
+                    format!("Directory '{}' is not empty", path),
+                ));
+            }
+            Vec::new()
+        } else {
+            let (data_blocks, extension_blocks) = self.file_blocks(block)?;
+            [data_blocks, extension_blocks].concat()
+        };
+
+        let parent = read_u32_be(self.read_sector(block), BLOCK_PARENT) as usize;
+        self.unlink_entry(parent, block)?;
+        for freed_block in freed.into_iter().chain([block]) {
+            self.set_block_free(freed_block);
+        }
+        debug!("Deleted {}", path);
+        self.update_bitmap_blocks()?;
+        self.tracking.observers.entry_removed(path, block);
+        Ok(())
+    }
+
+    // Like delete, but refuses directories, for callers that only mean to remove files.
+    pub fn delete_file(&mut self, path: &str) -> Result<()> {
+        if self.is_directory_block(self.find_entry(path)?) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("'{}' is a directory", path),
+            ));
+        }
+        self.delete(path)
+    }
+
+    pub fn create_dir(&mut self, path: &str) -> Result<()> {
+        let block = self.create_entry(path, SecType::UserDir)?;
+        self.update_block_checksum(block);
+        self.update_bitmap_blocks()?;
+        self.tracking.observers.entry_created(path, block);
+        Ok(())
+    }
+
+    pub fn write_file(&mut self, path: &str, contents: &[u8]) -> Result<()> {
+        debug!("Writing {} bytes to {}", contents.len(), path);
+        let ffs = self.is_ffs();
+        let layout = self.layout();
+        let block_size = if ffs {
+            layout.block_size
+        } else {
+            layout.ofs_data_size()
+        };
+        let data_count = contents.len().div_ceil(block_size);
+        let extension_count = data_count.saturating_sub(1) / layout.hash_table_size();
+        if self.free_block_count() < 1 + data_count + extension_count {
+            return Err(Error::new(ErrorKind::StorageFull, "Disk full"));
+        }
+
+        let header = self.create_entry(path, SecType::File)?;
+        let data_blocks = (0..data_count)
+            .map(|_| self.allocate_block())
+            .collect::<Result<Vec<_>>>()?;
+
+        for (i, (&block, chunk)) in data_blocks
+            .iter()
+            .zip(contents.chunks(block_size))
+            .enumerate()
+        {
+            let next = data_blocks.get(i + 1).copied().unwrap_or(0);
+            if ffs {
+                let sector = self.sector_mut(block);
+                sector.fill(0);
+                sector[..chunk.len()].copy_from_slice(chunk);
+            } else {
+                let data_block = DataBlock {
+                    block_type: T_DATA,
+                    header_key: BlockPtr::new(header),
+                    seq_num: i as u32 + 1,
+                    data_size: chunk.len() as u32,
+                    next_data: BlockPtr::new(next),
+                    data: chunk.to_vec(),
+                    ..DataBlock::default()
+                };
+                self.sector_mut(block)
+                    .copy_from_slice(&data_block.to_bytes(&layout));
+                self.update_block_checksum(block);
+            }
+        }
+
+        let mut tables = data_blocks.chunks(layout.hash_table_size());
+        let mut file_header = HeaderBlock::from_bytes(self.read_sector(header), &layout)?;
+        file_header.set_data_blocks(&layout, tables.next().unwrap_or_default());
+        file_header.first_data = BlockPtr::new(data_blocks.first().copied().unwrap_or(0));
+        file_header.byte_size = contents.len() as u32;
+
+        // Extension blocks are written back to front so each one knows its successor.
+        let extensions = (0..tables.len())
+            .map(|_| self.allocate_block())
+            .collect::<Result<Vec<_>>>()?;
+        let mut next = BlockPtr::NULL;
+        for (&extension, table) in extensions.iter().zip(tables).rev() {
+            let mut extension_block = FileExtBlock::new(&layout, extension, header, table);
+            extension_block.extension = next;
+            self.sector_mut(extension)
+                .copy_from_slice(&extension_block.to_bytes(&layout));
+            self.update_block_checksum(extension);
+            next = BlockPtr::new(extension);
+        }
+        file_header.extension = next;
+        self.sector_mut(header)
+            .copy_from_slice(&file_header.to_bytes(&layout));
+        self.update_block_checksum(header);
+        self.update_bitmap_blocks()?;
+        self.tracking.observers.entry_created(path, header);
+        Ok(())
+    }
+
+    pub fn import_dir<P: AsRef<Path>>(&mut self, host_dir: P, dest: &str) -> Result<()> {
+        self.import_dir_with_progress(host_dir, dest, &mut |_, _| {})
+    }
+
+    pub fn import_dir_with_progress<P: AsRef<Path>>(
+        &mut self,
+        host_dir: P,
+        dest: &str,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        self.import_dir_cancellable(host_dir, dest, progress, &CancelToken::default())
+    }
+
+    // Stops before the next host entry once cancel is cancelled, returning Interrupted; the
+    // entries imported until then are complete.
+    pub fn import_dir_cancellable<P: AsRef<Path>>(
+        &mut self,
+        host_dir: P,
+        dest: &str,
+        progress: &mut dyn FnMut(usize, usize),
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        let total = count_host_entries(host_dir.as_ref())?;
+        let mut done = 0;
+        self.import_entries(host_dir.as_ref(), dest, &mut done, total, progress, cancel)
+    }
+
+    fn import_entries(
+        &mut self,
+        host_dir: &Path,
+        dest: &str,
+        done: &mut usize,
+        total: usize,
+        progress: &mut dyn FnMut(usize, usize),
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        let mut entries = fs::read_dir(host_dir)?.collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            cancel.check()?;
+            let file_name = entry.file_name();
+            let name = file_name.to_str().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid file name {:?}", file_name),
+                )
+            })?;
+            let path = join_path(dest, name);
+            let metadata = entry.metadata()?;
+
+            if metadata.is_dir() {
+                self.create_dir(&path)?;
+                self.import_entries(&entry.path(), &path, done, total, progress, cancel)?;
+            } else if metadata.is_file() {
+                self.write_file(&path, &fs::read(entry.path())?)?;
+            } else {
+                continue;
+            }
+
+            if let (None, Ok(modified)) = (&self.tracking.clock.0, metadata.modified()) {
+                if to_amiga_date(modified).is_ok() {
+                    self.set_date(&path, modified)?;
+                }
+            }
+            *done += 1;
+            progress(*done, total);
+        }
+        Ok(())
+    }
+
+    pub fn copy_from(&mut self, source: &ADF, from: &str, to: &str) -> Result<()> {
+        let block = source.find_entry(from)?;
+        if block == self.root_block() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Cannot copy the root directory",
+            ));
+        }
+        let info = source.read_file_header(block)?;
+
+        if info.is_dir {
+            self.create_dir(to)?;
+            for entry in source.directory_entries(block) {
+                let name = source.entry_name(entry);
+                self.copy_from(source, &join_path(from, &name), &join_path(to, &name))?;
+            }
+        } else {
+            self.write_file(to, &source.read_file_contents(block)?)?;
+        }
+
+        self.set_protection(to, info.protection)?;
+        self.set_comment(to, &info.comment)?;
+        self.set_date(to, info.creation_date)
+    }
+
+    pub fn sync_from_host<P: AsRef<Path>>(
+        &mut self,
+        host_dir: P,
+        dest: &str,
+    ) -> Result<SyncReport> {
+        let dest = dest.trim_matches('/');
+        let mut partial = String::new();
+        for component in dest.split('/').filter(|c| !c.is_empty()) {
+            partial = join_path(&partial, component);
+            if self.find_entry(&partial).is_err() {
+                self.create_dir(&partial)?;
+            }
+        }
+        self.find_directory(dest)?;
+
+        let mut report = SyncReport::default();
+        self.sync_entries_from_host(host_dir.as_ref(), dest, &mut report)?;
+        Ok(report)
+    }
+
+    fn sync_entries_from_host(
+        &mut self,
+        host_dir: &Path,
+        dest: &str,
+        report: &mut SyncReport,
+    ) -> Result<()> {
+        let mut entries = fs::read_dir(host_dir)?.collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let file_name = entry.file_name();
+            let name = file_name.to_str().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid file name {:?}", file_name),
+                )
+            })?;
+            let path = join_path(dest, name);
+            let metadata = entry.metadata()?;
+            let existing = self
+                .find_entry(&path)
+                .ok()
+                .map(|block| self.read_file_header(block))
+                .transpose()?;
+
+            if metadata.is_dir() {
+                match existing {
+                    Some(info) if !info.is_dir => {
+                        return Err(Error::new(
+                            ErrorKind::AlreadyExists,
+                            format!("'{}' exists as a file", path),
+                        ))
+                    }
+                    Some(_) => {}
+                    None => self.create_dir(&path)?,
+                }
+                self.sync_entries_from_host(&entry.path(), &path, report)?;
+                continue;
+            } else if !metadata.is_file() {
+                continue;
+            }
+
+            let modified = metadata.modified().ok();
+            if let Some(info) = &existing {
+                if info.is_dir {
+                    return Err(Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!("'{}' exists as a directory", path),
+                    ));
+                }
+                if info.size as u64 == metadata.len()
+                    && modified.is_some_and(|time| same_second(time, info.creation_date))
+                {
+                    report.unchanged += 1;
+                    continue;
+                }
+                self.delete(&path)?;
+            }
+
+            self.write_file(&path, &fs::read(entry.path())?)?;
+            if let Some(info) = &existing {
+                self.set_protection(&path, info.protection)?;
+                self.set_comment(&path, &info.comment)?;
+            }
+            if let Some(modified) = modified.filter(|time| to_amiga_date(*time).is_ok()) {
+                self.set_date(&path, modified)?;
+            }
+            report.copied.push(path);
+        }
+        Ok(())
+    }
+
+    pub fn sync_to_host<P: AsRef<Path>>(&self, dir: &str, host_dir: P) -> Result<SyncReport> {
+        let host_dir = host_dir.as_ref();
+        fs::create_dir_all(host_dir)?;
+
+        let mut report = SyncReport::default();
+        for (path, info) in self.walk(dir)? {
+            let relative = path[dir.trim_matches('/').len()..].trim_start_matches('/');
+            if relative.split('/').any(|c| c == "." || c == "..") {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Refusing to extract unsafe path '{}'", path),
+                ));
+            }
+            let host_path = host_dir.join(relative);
+            if info.is_dir {
+                fs::create_dir_all(&host_path)?;
+                continue;
+            }
+            if let Ok(metadata) = fs::metadata(&host_path) {
+                if metadata.len() == info.size as u64
+                    && metadata
+                        .modified()
+                        .is_ok_and(|time| same_second(time, info.creation_date))
+                {
+                    report.unchanged += 1;
+                    continue;
+                }
+            }
+            let mut file = File::create(&host_path)?;
+            self.read_file_into(&path, &mut file)?;
+            file.set_modified(info.creation_date)?;
+            report.copied.push(path);
+        }
+        Ok(report)
+    }
+
+    pub fn install_bootblock(&mut self) -> Result<()> {
+        self.install_bootblock_for(Kickstart::V13)
+    }
+
+    pub fn install_bootblock_for(&mut self, kickstart: Kickstart) -> Result<()> {
+        let code = kickstart.boot_code()?;
+        if &self.data[0..3] != b"DOS" {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a DOS disk"));
+        }
+        let root = self.root_block() as u32;
+        self.mark_dirty(0..2 * ADF_SECTOR_SIZE);
+        self.data[4..2 * ADF_SECTOR_SIZE].fill(0);
+        write_u32_be(&mut self.data, 8, root);
+        self.data[12..12 + code.len()].copy_from_slice(code);
+        let checksum = bootblock_checksum(self.read_boot_block());
+        write_u32_be(&mut self.data, 4, checksum);
+        self.note_change(format!("Installed Kickstart {} boot block", kickstart));
+        Ok(())
+    }
+
+    pub fn set_date(&mut self, path: &str, time: SystemTime) -> Result<()> {
+        let block = self.find_entry(path)?;
+        let (days, mins, ticks) = to_amiga_date(time)?;
+        let offset = block * ADF_SECTOR_SIZE;
+        let block_data = self.data_mut(offset..offset + ADF_SECTOR_SIZE);
+        write_u32_be(block_data, BLOCK_DAYS, days);
+        write_u32_be(block_data, BLOCK_MINS, mins);
+        write_u32_be(block_data, BLOCK_TICKS, ticks);
+        self.update_block_checksum(block);
+        Ok(())
+    }
+
+    pub fn list_root_directory(&self) -> Result<Vec<FileInfo>> {
+        self.list_directory(self.root_block()).collect()
+    }
+
+    pub fn list_directory(&self, block: usize) -> impl Iterator<Item = Result<FileInfo>> + '_ {
+        self.directory_entries(block)
+            .into_iter()
+            .map(move |entry| self.read_file_header(entry))
+    }
+
+    pub fn list_directory_sorted(&self, block: usize, order: SortOrder) -> Result<Vec<FileInfo>> {
+        let mut files = self.list_directory(block).collect::<Result<Vec<_>>>()?;
+        order.sort(&mut files);
+        Ok(files)
+    }
+
+    pub(crate) fn read_file_header(&self, block: usize) -> Result<FileInfo> {
+        let header = HeaderBlock::from_bytes(self.read_sector(block), &self.layout())?;
         Ok(FileInfo {
-            name,
-            size,
-            is_dir,
-            protection,
-            creation_date,
+            file_type: self.file_type(&header),
+            is_dir: header.is_dir(),
+            size: header.byte_size,
+            protection: header.protect,
+            creation_date: header.date.to_system_time(),
+            comment: header.comment,
+            name: header.name,
         })
     }
 
+    // Only looks at the first data block, so listing a directory stays cheap.
+    fn file_type(&self, header: &HeaderBlock) -> Option<String> {
+        if header.is_dir() {
+            return None;
+        }
+        let first = header.data_blocks().first()?.index()?;
+        if first >= self.num_blocks() {
+            return None;
+        }
+        let data = self.read_sector(first);
+        let ofs_block;
+        let payload = if self.is_ffs() {
+            data
+        } else {
+            ofs_block = DataBlock::from_bytes(data, &self.layout()).ok()?;
+            ofs_block.payload()
+        };
+        iff::form_type(payload)
+    }
+
     pub fn format_protection_flags(&self, flags: u32) -> String {
-        let mut result = String::with_capacity(8);
-        result.push(if flags & 0x80 == 0 { 'h' } else { '-' }); // hidden
-        result.push(if flags & 0x40 == 0 { 's' } else { '-' }); // script
-        result.push(if flags & 0x20 == 0 { 'p' } else { '-' }); // pure
-        result.push(if flags & 0x10 == 0 { 'a' } else { '-' }); // archive
-        result.push(if flags & 0x08 == 0 { 'r' } else { '-' }); // read
-        result.push(if flags & 0x04 == 0 { 'w' } else { '-' }); // write
-        result.push(if flags & 0x02 == 0 { 'e' } else { '-' }); // execute
-        result.push(if flags & 0x01 == 0 { 'd' } else { '-' }); // delete
-        result
+        PROTECTION_LETTERS
+            .iter()
+            .zip((0..8).rev())
+            .map(|(&letter, bit)| {
+                if protection_enabled(flags, bit) {
+                    letter
+                } else {
+                    '-'
+                }
+            })
+            .collect()
     }
 
-    pub fn calculate_checksum(&self, data: &[u8]) -> u32 {
-        let mut checksum = 0u32;
-        for chunk in data.chunks(4) {
-            let word = u32::from_be_bytes([
-                chunk[0],
-                chunk.get(1).copied().unwrap_or(0),
-                chunk.get(2).copied().unwrap_or(0),
-                chunk.get(3).copied().unwrap_or(0),
-            ]);
-            checksum = checksum.wrapping_add(word);
+    pub fn set_protection(&mut self, path: &str, flags: u32) -> Result<()> {
+        let block = self.find_entry(path)?;
+        if block == self.root_block() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The root directory has no protection bits",
+            ));
+        }
+        write_u32_be(self.sector_mut(block), BLOCK_PROTECT, flags);
+        self.update_block_checksum(block);
+        Ok(())
+    }
+
+    pub fn set_comment(&mut self, path: &str, comment: &str) -> Result<()> {
+        let block = self.find_entry(path)?;
+        if block == self.root_block() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The root directory cannot have a comment",
+            ));
+        }
+        // Comments are Latin-1 like names, one byte per character.
+        let bytes = latin1_bytes(comment)?;
+        if bytes.len() > MAX_COMMENT_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Comment exceeds {} characters", MAX_COMMENT_LEN),
+            ));
         }
-        !checksum
+        let offset = block * ADF_SECTOR_SIZE + BLOCK_COMMENT;
+        let field = self.data_mut(offset..offset + 1 + MAX_COMMENT_LEN);
+        field.fill(0);
+        field[0] = bytes.len() as u8;
+        field[1..1 + bytes.len()].copy_from_slice(&bytes);
+        self.update_block_checksum(block);
+        Ok(())
+    }
+
+    pub fn get_protection(&self, path: &str) -> Result<u32> {
+        let block = self.find_entry(path)?;
+        Ok(read_u32_be(self.read_sector(block), BLOCK_PROTECT))
+    }
+
+    // Checksum of a boot block or of a header, list or OFS data block. The stored checksum
+    // field is skipped, so the result can be compared with it directly. Bitmap pages need
+    // ChecksumKind::Bitmap, see checksum_kind.
+    pub fn calculate_checksum(&self, data: &[u8]) -> u32 {
+        let kind = if data.len() == BOOT_BLOCK_SIZE {
+            ChecksumKind::Boot
+        } else {
+            ChecksumKind::Normal
+        };
+        kind.checksum(data).unwrap_or_default()
     }
 
     pub fn set_block_used(&mut self, block_index: usize) {
@@ -457,52 +2265,45 @@ impl ADF {
 
     pub fn set_block_free(&mut self, block_index: usize) {
         if block_index < self.bitmap.len() {
+            debug!("Freed block {}", block_index);
             self.bitmap[block_index] = true;
         }
     }
 
     pub fn update_bitmap_blocks(&mut self) -> Result<()> {
-        let bitmap_block_index = ROOT_BLOCK + 1;
-        let mut bitmap_block = vec![0u8; ADF_SECTOR_SIZE];
-        for block_index in 2..ADF_NUM_SECTORS {
-            let byte_index = block_index / 8;
-            let bit_index = block_index % 8;
-            if self.bitmap[block_index] {
-                bitmap_block[byte_index] |= 1 << (7 - bit_index);
-            } else {
-                bitmap_block[byte_index] &= !(1 << (7 - bit_index));
+        let bits = self.layout().bits_per_bitmap_page();
+        for (page_index, page) in self.bitmap_pages().into_iter().enumerate() {
+            let mut bitmap_page = BitmapBlock::default();
+            for bit in 0..bits {
+                let block_index = 2 + page_index * bits + bit;
+                if block_index >= self.num_blocks() {
+                    break;
+                }
+                if self.bitmap.get(block_index).copied().unwrap_or(false) {
+                    bitmap_page.set_free(bit, true);
+                }
             }
+            let mut bitmap_block = bitmap_page.to_bytes(&self.layout());
+            let checksum = bitmap_checksum(&bitmap_block);
+            write_u32_be(&mut bitmap_block, 0, checksum);
+            self.write_sector(page, &bitmap_block)?;
         }
-        let checksum_offset = 0;
-        let checksum = self.calculate_checksum(&bitmap_block[checksum_offset..]);
-        bitmap_block[checksum_offset..checksum_offset + 4].copy_from_slice(&checksum.to_be_bytes());
-        self.write_sector(bitmap_block_index, &bitmap_block)?;
         Ok(())
     }
 
     pub fn initialize_bitmap(&mut self) -> Result<()> {
-        let bitmap_block_index = ROOT_BLOCK + 1;
-        let mut bitmap_block = vec![0u8; ADF_SECTOR_SIZE];
-        bitmap_block[0] = 0; // bm_flag
-        bitmap_block[1] = 0;
-        let checksum_offset = 20;
-        let checksum = self.calculate_checksum(&bitmap_block[checksum_offset..]);
-        bitmap_block[4..8].copy_from_slice(&checksum.to_be_bytes());
-        self.write_sector(bitmap_block_index, &bitmap_block)?;
+        let bitmap_block_index = self.root_block() + 1;
         self.set_block_used(bitmap_block_index);
-        self.update_bitmap_blocks()?;
-        Ok(())
+        self.update_bitmap_blocks()
     }
 
     pub fn allocate_block(&mut self) -> Result<usize> {
         if let Some(block_index) = self.find_free_block() {
             self.set_block_used(block_index);
+            debug!("Allocated block {}", block_index);
             Ok(block_index)
         } else {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                "No free blocks available",
-            ))
+            Err(io::Error::other("No free blocks available"))
         }
     }
 
@@ -515,193 +2316,394 @@ impl ADF {
             .map(|(index, _)| index)
     }
 
-    pub fn read_file_contents(&self, block: usize) -> io::Result<Vec<u8>> {
+    // Passes the contents of the file with header block to chunk a data block at a time,
+    // until chunk returns false.
+    fn file_chunks(
+        &self,
+        block: usize,
+        mut chunk: impl FnMut(&[u8]) -> Result<bool>,
+    ) -> Result<()> {
         let block_data = self.read_sector(block);
+        if read_u32_be(block_data, BLOCK_TYPE) != T_HEADER
+            || read_u32_be(block_data, BLOCK_SEC_TYPE) != ST_FILE
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Block {} is not a file header", block),
+            ));
+        }
 
-        match block_data[0] {
-            2 => {
-                let file_size = u32::from_be_bytes([
-                    block_data[4],
-                    block_data[5],
-                    block_data[6],
-                    block_data[7],
-                ]) as usize;
-                let mut contents = Vec::with_capacity(file_size);
-
-                let mut current_block = u32::from_be_bytes([
-                    block_data[16],
-                    block_data[17],
-                    block_data[18],
-                    block_data[19],
-                ]) as usize;
-
-                while current_block != 0 && contents.len() < file_size {
-                    let data_block = self.read_sector(current_block);
-                    let data_size = std::cmp::min(512 - 24, file_size - contents.len());
-                    contents.extend_from_slice(&data_block[24..24 + data_size]);
-                    current_block = u32::from_be_bytes([
-                        data_block[0],
-                        data_block[1],
-                        data_block[2],
-                        data_block[3],
-                    ]) as usize;
-                }
-
-                if contents.len() != file_size {
-                    return Err(io::Error::new(
-                        io::ErrorKind::UnexpectedEof,
-                        format!(
-                            "File size mismatch. Expected: {}, Read: {}",
-                            file_size,
-                            contents.len()
-                        ),
-                    ));
-                }
-
-                Ok(contents)
-            }
-            0 => {
-                let file_size = u32::from_be_bytes([
-                    block_data[4],
-                    block_data[5],
-                    block_data[6],
-                    block_data[7],
-                ]) as usize;
-                let mut contents = Vec::with_capacity(file_size);
-                contents.extend_from_slice(&block_data[24..]);
-
-                let mut current_block = u32::from_be_bytes([
-                    block_data[16],
-                    block_data[17],
-                    block_data[18],
-                    block_data[19],
-                ]) as usize;
-                while current_block != 0 && contents.len() < file_size {
-                    let data_block = self.read_sector(current_block);
-                    let data_size = std::cmp::min(512, file_size - contents.len());
-                    contents.extend_from_slice(&data_block[..data_size]);
-                    current_block = u32::from_be_bytes([
-                        data_block[0],
-                        data_block[1],
-                        data_block[2],
-                        data_block[3],
-                    ]) as usize;
-                }
+        let file_size = read_u32_be(block_data, BLOCK_BYTE_SIZE) as usize;
+        let (data_blocks, _) = self.file_blocks(block)?;
+        let ffs = self.is_ffs();
+        let mut read = 0;
 
-                Ok(contents)
+        for data_block in data_blocks {
+            if read >= file_size {
+                break;
             }
-            _ => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Unexpected block type: {}", block_data[0]),
-            )),
+            let sector = self.read_sector(data_block);
+            let data = if ffs {
+                sector
+            } else {
+                let len = (read_u32_be(sector, OFS_DATA_LEN) as usize).min(OFS_DATA_SIZE);
+                &sector[ADF_SECTOR_SIZE - OFS_DATA_SIZE..][..len]
+            };
+            let data = &data[..data.len().min(file_size - read)];
+            read += data.len();
+            if !chunk(data)? {
+                return Ok(());
+            }
+        }
+
+        if read != file_size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "File size mismatch. Expected: {}, Read: {}",
+                    file_size, read
+                ),
+            ));
         }
+        Ok(())
     }
 
-    fn write_boot_block(&mut self, disk_type: DiskType) -> Result<()> {
-        let mut boot_block = [0u8; ADF_SECTOR_SIZE * 2];
+    pub fn read_file_contents(&self, block: usize) -> io::Result<Vec<u8>> {
+        let size = read_u32_be(self.read_sector(block), BLOCK_BYTE_SIZE) as usize;
+        let mut contents = Vec::with_capacity(size.min(self.data.len()));
+        self.file_chunks(block, |chunk| {
+            contents.extend_from_slice(chunk);
+            Ok(true)
+        })?;
+        Ok(contents)
+    }
 
-        boot_block[..4].copy_from_slice(b"DOS\0");
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        self.read_file_contents(self.find_entry(path)?)
+    }
 
-        boot_block[3] = match disk_type {
-            DiskType::OFS => 0,
-            DiskType::FFS => 1,
-        };
+    // Streams a file to out straight from the image, returning the number of bytes written.
+    pub fn read_file_into<W: Write + ?Sized>(&self, path: &str, out: &mut W) -> Result<u64> {
+        let mut written = 0u64;
+        self.file_chunks(self.find_entry(path)?, |chunk| {
+            out.write_all(chunk)?;
+            written += chunk.len() as u64;
+            Ok(true)
+        })?;
+        Ok(written)
+    }
 
-        self.data[..ADF_SECTOR_SIZE * 2].copy_from_slice(&boot_block);
-        Ok(())
+    // Reads up to len bytes starting offset bytes into the file, returning how many were
+    // read; fewer than len means the file ends early.
+    pub fn read_file_range(
+        &self,
+        path: &str,
+        offset: u64,
+        len: usize,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        if len > buf.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Buffer of {} bytes cannot hold {} bytes", buf.len(), len),
+            ));
+        }
+        self.read_contents_range(self.find_entry(path)?, offset, &mut buf[..len])
     }
 
-    fn write_root_block(&mut self, disk_type: DiskType, disk_name: &str) -> Result<()> {
-        let mut root_block = [0u8; ADF_SECTOR_SIZE];
+    pub(crate) fn read_contents_range(
+        &self,
+        block: usize,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let end = offset.saturating_add(buf.len() as u64);
+        let mut pos = 0u64;
+        let mut read = 0;
+        self.file_chunks(block, |chunk| {
+            let chunk_end = pos + chunk.len() as u64;
+            if chunk_end > offset {
+                let from = offset.saturating_sub(pos) as usize;
+                let take = (chunk.len() - from).min(buf.len() - read);
+                buf[read..read + take].copy_from_slice(&chunk[from..from + take]);
+                read += take;
+            }
+            pos = chunk_end;
+            Ok(pos < end)
+        })?;
+        Ok(read)
+    }
 
-        root_block[0] = 2;
+    pub fn data_blocks(&self, path: &str) -> Result<Vec<usize>> {
+        let block = self.find_entry(path)?;
+        if self.is_directory_block(block) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("'{}' is a directory", path),
+            ));
+        }
+        Ok(self.file_blocks(block)?.0)
+    }
 
-        root_block[ADF_SECTOR_SIZE - 4] = match disk_type {
-            DiskType::OFS => 0,
-            DiskType::FFS => 1,
-        };
+    pub fn walk(&self, dir: &str) -> Result<Vec<(String, FileInfo)>> {
+        let mut entries = Vec::new();
+        let block = self.find_entry(dir)?;
+        for entry in self.directory_entries(block) {
+            let info = self.read_file_header(entry)?;
+            let path = join_path(dir, &info.name);
+            let is_dir = info.is_dir;
+            entries.push((path.clone(), info));
+            if is_dir {
+                entries.extend(self.walk(&path)?);
+            }
+        }
+        Ok(entries)
+    }
 
-        root_block[12..14].copy_from_slice(&72u16.to_be_bytes());
+    pub fn block_owners(&self) -> Result<HashMap<usize, String>> {
+        let mut owners = HashMap::new();
+        owners.insert(0, "<bootblock>".to_string());
+        owners.insert(1, "<bootblock>".to_string());
+        owners.insert(self.root_block(), "<root>".to_string());
+        for page in self.bitmap_pages() {
+            owners.insert(page, "<bitmap>".to_string());
+        }
+        self.collect_owners("", self.root_block(), &mut owners)?;
+        Ok(owners)
+    }
 
-        if matches!(disk_type, DiskType::FFS) {
-            root_block[ADF_SECTOR_SIZE - 200] = 0xFF;
-            for i in 0..25 {
-                let block_num = u32::to_be_bytes(ROOT_BLOCK as u32 + 1 + i as u32);
-                root_block[ADF_SECTOR_SIZE - 196 + i * 4..ADF_SECTOR_SIZE - 192 + i * 4]
-                    .copy_from_slice(&block_num);
+    fn collect_owners(
+        &self,
+        dir: &str,
+        block: usize,
+        owners: &mut HashMap<usize, String>,
+    ) -> Result<()> {
+        for entry in self.directory_entries(block) {
+            let path = join_path(dir, &self.entry_name(entry));
+            owners.insert(entry, path.clone());
+            if self.is_directory_block(entry) {
+                self.collect_owners(&path, entry, owners)?;
+            } else {
+                let (data_blocks, extension_blocks) = self.file_blocks(entry)?;
+                for owned in data_blocks.into_iter().chain(extension_blocks) {
+                    owners.insert(owned, path.clone());
+                }
             }
         }
+        Ok(())
+    }
 
-        let name_bytes = disk_name.as_bytes();
-        let name_len = std::cmp::min(name_bytes.len(), 30);
-        root_block[ADF_SECTOR_SIZE - 80] = name_len as u8;
-        root_block[ADF_SECTOR_SIZE - 79..ADF_SECTOR_SIZE - 79 + name_len]
-            .copy_from_slice(&name_bytes[..name_len]);
+    pub fn search(&self, needle: &[u8]) -> Vec<usize> {
+        if needle.is_empty() {
+            return Vec::new();
+        }
+        self.data
+            .windows(needle.len())
+            .enumerate()
+            .filter(|(_, window)| *window == needle)
+            .map(|(offset, _)| offset)
+            .collect()
+    }
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default();
-        let days = u32::to_be_bytes((now.as_secs() / 86400) as u32);
-        let mins = u32::to_be_bytes(((now.as_secs() % 86400) / 60) as u32);
-        let ticks = u32::to_be_bytes(((now.as_secs() % 60) * 50) as u32);
+    pub fn disk_usage(&self, dir: &str) -> Result<Vec<DirectoryUsage>> {
+        let block = self.find_entry(dir)?;
+        if !self.is_directory_block(block) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("'{}' is not a directory", dir),
+            ));
+        }
+        let mut usage = Vec::new();
+        self.collect_usage(dir, block, &mut usage)?;
+        Ok(usage)
+    }
 
-        root_block[ADF_SECTOR_SIZE - 92..ADF_SECTOR_SIZE - 88].copy_from_slice(&days);
-        root_block[ADF_SECTOR_SIZE - 88..ADF_SECTOR_SIZE - 84].copy_from_slice(&mins);
-        root_block[ADF_SECTOR_SIZE - 84..ADF_SECTOR_SIZE - 80].copy_from_slice(&ticks);
+    fn collect_usage(
+        &self,
+        dir: &str,
+        block: usize,
+        usage: &mut Vec<DirectoryUsage>,
+    ) -> Result<DirectoryUsage> {
+        let index = usage.len();
+        let mut total = DirectoryUsage {
+            path: dir.trim_matches('/').to_string(),
+            files: 0,
+            bytes: 0,
+            blocks: 1,
+        };
+        usage.push(total.clone());
+
+        for entry in self.directory_entries(block) {
+            let info = self.read_file_header(entry)?;
+            if info.is_dir {
+                let child = self.collect_usage(&join_path(dir, &info.name), entry, usage)?;
+                total.files += child.files;
+                total.bytes += child.bytes;
+                total.blocks += child.blocks;
+            } else {
+                let (data_blocks, extension_blocks) = self.file_blocks(entry)?;
+                total.files += 1;
+                total.bytes += u64::from(info.size);
+                total.blocks += 1 + data_blocks.len() + extension_blocks.len();
+            }
+        }
 
-        self.write_sector(ROOT_BLOCK, &root_block)
+        usage[index] = total.clone();
+        Ok(total)
     }
 
-    fn write_bitmap_blocks(&mut self) -> Result<()> {
-        let mut bitmap_block = [0xFFu8; ADF_SECTOR_SIZE];
+    pub fn export_dir<P: AsRef<Path>>(&self, dir: &str, host_dir: P) -> Result<()> {
+        self.export_dir_with_progress(dir, host_dir, &mut |_, _| {})
+    }
 
-        bitmap_block[0] = 0xF8;
-        bitmap_block[1] = 0xFF;
-        bitmap_block[2] = 0xFF;
+    pub fn export_dir_with_progress<P: AsRef<Path>>(
+        &self,
+        dir: &str,
+        host_dir: P,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        let host_dir = host_dir.as_ref();
+        fs::create_dir_all(host_dir)?;
+
+        let entries = self.walk(dir)?;
+        for (index, (path, info)) in entries.iter().enumerate() {
+            let relative = path[dir.trim_matches('/').len()..].trim_start_matches('/');
+            if relative.split('/').any(|c| c == "." || c == "..") {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Refusing to extract unsafe path '{}'", path),
+                ));
+            }
+            let host_path = host_dir.join(relative);
+            if info.is_dir {
+                fs::create_dir_all(&host_path)?;
+            } else {
+                let mut file = File::create(&host_path)?;
+                self.read_file_into(path, &mut file)?;
+                file.set_modified(info.creation_date)?;
+            }
+            progress(index + 1, entries.len());
+        }
 
-        self.write_sector(ROOT_BLOCK + 1, &bitmap_block)?;
-        self.write_sector(ROOT_BLOCK + 2, &[0xFFu8; ADF_SECTOR_SIZE])?;
+        for (path, info) in entries.iter().rev().filter(|(_, info)| info.is_dir) {
+            let relative = path[dir.trim_matches('/').len()..].trim_start_matches('/');
+            if let Ok(host) = File::open(host_dir.join(relative)) {
+                let _ = host.set_modified(info.creation_date);
+            }
+        }
+        Ok(())
+    }
 
+    // Extracts every entry whose full path matches the pattern below host_dir, keeping its path
+    // relative to the root. Matched directories are extracted with their contents.
+    pub fn extract_matching<P: AsRef<Path>>(
+        &self,
+        pattern: &Pattern,
+        host_dir: P,
+    ) -> Result<Vec<String>> {
+        let host_dir = host_dir.as_ref();
+        let mut extracted: Vec<String> = Vec::new();
+        for (path, info) in self.walk("")? {
+            if !pattern.is_match(&path)
+                || extracted
+                    .iter()
+                    .any(|done| path.starts_with(&format!("{}/", done)))
+            {
+                continue;
+            }
+            if path.split('/').any(|c| c == "." || c == "..") {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Refusing to extract unsafe path '{}'", path),
+                ));
+            }
+            let host_path = host_dir.join(&path);
+            if info.is_dir {
+                self.export_dir(&path, &host_path)?;
+            } else {
+                if let Some(parent) = host_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut file = File::create(&host_path)?;
+                self.read_file_into(&path, &mut file)?;
+                file.set_modified(info.creation_date)?;
+            }
+            debug!("Extracted {} to {}", path, host_path.display());
+            extracted.push(path);
+        }
+        Ok(extracted)
+    }
+
+    fn write_boot_block(&mut self, disk_type: DiskType) -> Result<()> {
+        let flags = match disk_type {
+            DiskType::OFS => 0,
+            DiskType::FFS => 1,
+        };
+        let boot_block = BootBlock {
+            dos_type: [b'D', b'O', b'S', flags],
+            ..BootBlock::default()
+        };
+        self.data_mut(0..ADF_SECTOR_SIZE * 2)
+            .copy_from_slice(&boot_block.to_bytes());
+        Ok(())
+    }
+
+    fn write_root_block(&mut self, disk_type: DiskType, disk_name: &str) -> Result<()> {
+        let root = self.root_block();
+        let (days, mins, ticks) = to_amiga_date(self.now())?;
+        let root_block = RootBlock {
+            block_type: T_HEADER,
+            hash_table_size: self.layout().hash_table_size() as u32,
+            bm_flag: u32::MAX,
+            bm_pages: (0..self.layout().bitmap_pages())
+                .map(|page| BlockPtr::new(root + 1 + page))
+                .collect(),
+            modified: DateStamp { days, mins, ticks },
+            name: disk_name.to_string(),
+            volume_modified: DateStamp { days, mins, ticks },
+            created: DateStamp { days, mins, ticks },
+            sec_type: SecType::Root,
+            ..RootBlock::default()
+        };
+        self.write_sector(root, &root_block.to_bytes(&self.layout()))?;
+        self.update_block_checksum(root);
         Ok(())
     }
+
+    fn write_bitmap_blocks(&mut self) -> Result<()> {
+        self.bitmap = vec![true; self.num_blocks()];
+        self.bitmap[0] = false;
+        self.bitmap[1] = false;
+        let root = self.root_block();
+        for block in root..=root + self.layout().bitmap_pages() {
+            self.bitmap[block] = false;
+        }
+        self.update_bitmap_blocks()
+    }
+
     pub fn information(&self) -> io::Result<DiskInfo> {
-        let root_block = self.read_sector(ROOT_BLOCK);
+        let layout = self.layout();
+        let root = RootBlock::from_bytes(self.read_root_block(), &layout)?;
+        let geometry = Device::detect_geometry(self.data.len() as u64);
+        let created = root
+            .created
+            .to_system_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as u32);
         Ok(DiskInfo {
-            filesystem: if root_block[3] & 1 == 1 {
-                "FFS".to_string()
-            } else {
-                "OFS".to_string()
-            },
-            disk_name: self.read_disk_name()?,
-            creation_date: u32::from_be_bytes([
-                root_block[16],
-                root_block[17],
-                root_block[18],
-                root_block[19],
-            ]) as u32,
-            disk_size: (ADF_TRACK_SIZE * ADF_NUM_TRACKS) as u32,
-            heads: 2,
-            tracks: (ADF_NUM_TRACKS / 2) as u8,
-            sectors_per_track: 11,
-            bytes_per_sector: 512,
-            hash_table_size: u32::from_be_bytes([
-                root_block[12],
-                root_block[13],
-                root_block[14],
-                root_block[15],
-            ]),
-            first_reserved_block: u32::from_be_bytes([
-                root_block[128],
-                root_block[129],
-                root_block[130],
-                root_block[131],
-            ]),
-            last_reserved_block: u32::from_be_bytes([
-                root_block[132],
-                root_block[133],
-                root_block[134],
-                root_block[135],
-            ]),
+            filesystem: if self.is_ffs() { "FFS" } else { "OFS" }.to_string(),
+            disk_name: root.name,
+            creation_date: created,
+            disk_size: self.data.len() as u32,
+            device_type: geometry.device_type,
+            heads: geometry.heads,
+            tracks: geometry.cylinders,
+            sectors_per_track: geometry.sectors,
+            bytes_per_sector: layout.block_size as u16,
+            hash_table_size: root.hash_table_size,
+            // The boot blocks, which the file system never allocates.
+            first_reserved_block: 0,
+            last_reserved_block: 1,
         })
     }
 
@@ -716,14 +2718,4 @@ impl ADF {
 
         Ok(output)
     }
-
-    fn read_disk_name(&self) -> io::Result<String> {
-        let root_block = self.read_sector(ROOT_BLOCK);
-        let name_len = root_block[ADF_SECTOR_SIZE - 80] as usize;
-        let name = String::from_utf8_lossy(
-            &root_block[ADF_SECTOR_SIZE - 79..ADF_SECTOR_SIZE - 79 + name_len],
-        )
-        .to_string();
-        Ok(name)
-    }
 }
@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Titles that came on several disks. Members are recognized by the disk number in their file
+// name, "Game (Disk 1 of 3).adf" in TOSEC style or a plain "game_disk1.adf".
+
+use crate::dat::Fingerprint;
+use crate::disk::ADF;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskName {
+    pub title: String,
+    pub number: usize,
+    pub total: Option<usize>,
+}
+
+fn leading_number(text: &str) -> Option<(usize, &str)> {
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    Some((text[..digits].parse().ok()?, &text[digits..]))
+}
+
+// Splits a file name into title and disk number, None when it has no disk number.
+pub fn parse_disk_name(file_name: &str) -> Option<DiskName> {
+    let stem = Path::new(file_name).file_stem()?.to_str()?;
+    let lower = stem.to_ascii_lowercase();
+    lower.rmatch_indices("disk").find_map(|(at, _)| {
+        let rest = lower[at + 4..].trim_start_matches([' ', '_', '-']);
+        let (number, rest) = leading_number(rest)?;
+        let total = rest
+            .strip_prefix(" of ")
+            .and_then(leading_number)
+            .map(|(total, _)| total);
+        let title = stem[..at].trim_end_matches([' ', '_', '-', '(', '[']);
+        (!title.is_empty() && number > 0).then(|| DiskName {
+            title: title.to_string(),
+            number,
+            total,
+        })
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskSetMember {
+    pub number: usize,
+    pub total: Option<usize>,
+    pub path: PathBuf,
+    pub adf: ADF,
+}
+
+impl DiskSetMember {
+    pub fn label(&self) -> String {
+        self.adf.entry_name(self.adf.root_block())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiskSet {
+    pub title: String,
+    // Sorted by disk number.
+    pub members: Vec<DiskSetMember>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskSetManifest {
+    pub title: String,
+    pub total: usize,
+    pub complete: bool,
+    pub missing: Vec<usize>,
+    pub disks: Vec<ManifestDisk>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestDisk {
+    pub title: String,
+    pub number: usize,
+    pub file: String,
+    pub label: String,
+    pub files: usize,
+    pub size: u64,
+    pub crc32: String,
+    pub md5: String,
+    pub sha1: String,
+}
+
+impl DiskSet {
+    // Loads the images and groups them by title, in title order. Files without a disk
+    // number are an error, so a typo does not silently leave a disk out.
+    pub fn group<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<DiskSet>> {
+        let mut sets: BTreeMap<String, DiskSet> = BTreeMap::new();
+        for path in paths {
+            let path = path.as_ref();
+            let name = parse_disk_name(&path.to_string_lossy()).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("'{}' has no disk number in its name", path.display()),
+                )
+            })?;
+            let set = sets
+                .entry(name.title.to_lowercase())
+                .or_insert_with(|| DiskSet {
+                    title: name.title.clone(),
+                    members: Vec::new(),
+                });
+            set.members.push(DiskSetMember {
+                number: name.number,
+                total: name.total,
+                path: path.to_path_buf(),
+                adf: ADF::from_file(&path.to_string_lossy())?,
+            });
+        }
+        let mut sets: Vec<DiskSet> = sets.into_values().collect();
+        for set in &mut sets {
+            set.members.sort_by_key(|member| member.number);
+        }
+        Ok(sets)
+    }
+
+    // The images must all belong to the same title.
+    pub fn open<P: AsRef<Path>>(paths: &[P]) -> Result<DiskSet> {
+        let mut sets = DiskSet::group(paths)?;
+        match sets.len() {
+            1 => Ok(sets.remove(0)),
+            0 => Err(Error::new(ErrorKind::InvalidInput, "No disk images given")),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Images belong to different titles: {}",
+                    sets.iter()
+                        .map(|set| set.title.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            )),
+        }
+    }
+
+    // The largest "of N" in the member names, or the highest disk number without one.
+    pub fn total(&self) -> usize {
+        self.members
+            .iter()
+            .map(|member| member.total.unwrap_or(member.number).max(member.number))
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn disk(&self, number: usize) -> Option<&DiskSetMember> {
+        self.members.iter().find(|member| member.number == number)
+    }
+
+    pub fn missing(&self) -> Vec<usize> {
+        (1..=self.total())
+            .filter(|&number| self.disk(number).is_none())
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for pair in self.members.windows(2) {
+            if pair[0].number == pair[1].number {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} has two images for disk {}: {} and {}",
+                        self.title,
+                        pair[0].number,
+                        pair[0].path.display(),
+                        pair[1].path.display()
+                    ),
+                ));
+            }
+        }
+        let totals: Vec<usize> = self.members.iter().filter_map(|m| m.total).collect();
+        if totals.windows(2).any(|pair| pair[0] != pair[1]) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} has members with different disk counts", self.title),
+            ));
+        }
+        let missing = self.missing();
+        if !missing.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "{} is missing disk {} of {}",
+                    self.title,
+                    missing
+                        .iter()
+                        .map(|n| n.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    self.total()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    // Finds a file on whichever disk holds it. A volume name prefix, as in "Disk2:c/run",
+    // picks the disk by label; otherwise the disks are searched in order.
+    pub fn resolve(&self, path: &str) -> Result<(&DiskSetMember, usize)> {
+        let (label, path) = match path.split_once(':') {
+            Some((label, rest)) => (Some(label), rest),
+            None => (None, path),
+        };
+        self.members
+            .iter()
+            .filter(|member| label.is_none_or(|label| member.label().eq_ignore_ascii_case(label)))
+            .find_map(|member| Some((member, member.adf.find_entry(path).ok()?)))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("'{}' is on none of the disks of {}", path, self.title),
+                )
+            })
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let (member, block) = self.resolve(path)?;
+        member.adf.read_file_contents(block)
+    }
+
+    pub fn manifest(&self) -> Result<DiskSetManifest> {
+        let disks = self
+            .members
+            .iter()
+            .map(|member| {
+                let fingerprint = Fingerprint::of(&member.adf.data);
+                Ok(ManifestDisk {
+                    title: self.title.clone(),
+                    number: member.number,
+                    file: member.path.to_string_lossy().into_owned(),
+                    label: member.label(),
+                    files: member
+                        .adf
+                        .walk("")?
+                        .into_iter()
+                        .filter(|(_, info)| !info.is_dir)
+                        .count(),
+                    size: fingerprint.size,
+                    crc32: fingerprint.crc32,
+                    md5: fingerprint.md5,
+                    sha1: fingerprint.sha1,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let missing = self.missing();
+        Ok(DiskSetManifest {
+            title: self.title.clone(),
+            total: self.total(),
+            complete: self.validate().is_ok(),
+            missing,
+            disks,
+        })
+    }
+}
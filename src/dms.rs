@@ -0,0 +1,1365 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use crate::cancel::CancelToken;
+use crate::disk::{ADF, ADF_NUM_SECTORS, ADF_SECTOR_SIZE, ADF_TRACK_SIZE};
+pub use crate::raw::crc16;
+pub use crate::raw::TrackUnpacker;
+use crate::raw::{self, latin1_string, RawError, RLE_MARKER};
+use crate::sansio::{decode_slice, Decoded, Decoder};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const DMS_HEADER_SIZE: usize = 56;
+pub const DMS_TRACK_HEADER_SIZE: usize = 20;
+pub const DMS_TRACK_SIZE: usize = 2 * ADF_TRACK_SIZE;
+pub const DMS_CYLINDERS: usize = 80;
+
+// Archive format version written to the header, matching DMS 1.11.
+pub const DMS_VERSION: u16 = 111;
+
+const INFO_BANNER: u32 = 0x08;
+const INFO_HIGH_DENSITY: u32 = 0x10;
+const INFO_FILE_ID_DIZ: u32 = 0x100;
+
+// Track numbers of the text pseudo-tracks DMS shows before and after unpacking.
+const BANNER_TRACK: u16 = 0xffff;
+const FILE_ID_DIZ_TRACK: u16 = DMS_CYLINDERS as u16;
+// Tracks this short hold no cylinder, like the 1024 byte track 0 some packers add.
+const FAKE_TRACK_MAX: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmsMode {
+    None,
+    Rle,
+}
+
+impl DmsMode {
+    pub fn id(self) -> u8 {
+        match self {
+            DmsMode::None => 0,
+            DmsMode::Rle => 1,
+        }
+    }
+}
+
+pub fn track_checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16))
+}
+
+pub fn pack_rle(data: &[u8]) -> Vec<u8> {
+    let mut packed = Vec::with_capacity(data.len());
+    let mut pos = 0;
+    while pos < data.len() {
+        let byte = data[pos];
+        let run = data[pos..]
+            .iter()
+            .take(u16::MAX as usize)
+            .take_while(|&&b| b == byte)
+            .count();
+        if run > 3 || (byte == RLE_MARKER && run > 1) {
+            packed.push(RLE_MARKER);
+            if run < 0xff {
+                packed.push(run as u8);
+                packed.push(byte);
+            } else {
+                packed.extend_from_slice(&[0xff, byte]);
+                packed.extend_from_slice(&(run as u16).to_be_bytes());
+            }
+            pos += run;
+        } else {
+            if byte == RLE_MARKER {
+                packed.extend_from_slice(&[RLE_MARKER, 0]);
+            } else {
+                packed.push(byte);
+            }
+            pos += 1;
+        }
+    }
+    packed
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+pub fn pack_dms(image: &[u8], mode: DmsMode, date: SystemTime) -> Result<Vec<u8>> {
+    pack_dms_cancellable(image, mode, date, &CancelToken::default())
+}
+
+// Like pack_dms, checking cancel before each track.
+pub fn pack_dms_cancellable(
+    image: &[u8],
+    mode: DmsMode,
+    date: SystemTime,
+    cancel: &CancelToken,
+) -> Result<Vec<u8>> {
+    pack_dms_with_extras(image, mode, date, &DmsExtras::default(), cancel)
+}
+
+// Text stored in an archive next to the disk, as release groups added to theirs: a banner
+// shown before unpacking and a FILE_ID.DIZ description. Unpacking skips both.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DmsExtras {
+    pub banner: Option<Vec<u8>>,
+    pub file_id_diz: Option<Vec<u8>>,
+}
+
+// Amiga text is Latin-1; packers pad it with NULs and some end lines with CR LF.
+fn extra_text(text: &[u8]) -> String {
+    let end = text.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    latin1_string(&text[..end]).replace("\r\n", "\n")
+}
+
+impl DmsExtras {
+    pub fn banner_text(&self) -> Option<String> {
+        self.banner.as_deref().map(extra_text)
+    }
+
+    pub fn file_id_diz_text(&self) -> Option<String> {
+        self.file_id_diz.as_deref().map(extra_text)
+    }
+}
+
+fn pack_track(number: u16, track: &[u8], mode: DmsMode) -> Vec<u8> {
+    let rle = match mode {
+        DmsMode::Rle => Some(pack_rle(track)).filter(|p| p.len() < track.len()),
+        DmsMode::None => None,
+    };
+    let (track_mode, payload) = match rle {
+        Some(packed) => (DmsMode::Rle, packed),
+        None => (DmsMode::None, track.to_vec()),
+    };
+    let header = DmsTrackHeader {
+        number,
+        packed_len: payload.len() as u16,
+        raw_len: track.len() as u16,
+        unpacked_len: track.len() as u16,
+        flags: 0,
+        mode: track_mode.id(),
+        checksum: track_checksum(track),
+        data_crc: crc16(&payload),
+        ..DmsTrackHeader::default()
+    }
+    .with_header_crc();
+    let mut packed = header.to_bytes().to_vec();
+    packed.extend_from_slice(&payload);
+    packed
+}
+
+fn text_track(name: &str, number: u16, text: &[u8], mode: DmsMode) -> Result<Vec<u8>> {
+    if text.len() > u16::MAX as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("The {} is {} bytes, at most 65535 fit", name, text.len()),
+        ));
+    }
+    Ok(pack_track(number, text, mode))
+}
+
+// Like pack_dms_cancellable, adding the banner before the disk tracks and FILE_ID.DIZ
+// after them, with their info bits set in the header.
+pub fn pack_dms_with_extras(
+    image: &[u8],
+    mode: DmsMode,
+    date: SystemTime,
+    extras: &DmsExtras,
+    cancel: &CancelToken,
+) -> Result<Vec<u8>> {
+    let track_size = match image.len() {
+        n if n == ADF_NUM_SECTORS * ADF_SECTOR_SIZE => DMS_TRACK_SIZE,
+        n if n == 2 * ADF_NUM_SECTORS * ADF_SECTOR_SIZE => 2 * DMS_TRACK_SIZE,
+        n => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Cannot pack a {} byte image as DMS", n),
+            ))
+        }
+    };
+    let timestamp = date
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Date before 1970"))?
+        .as_secs() as u32;
+
+    let mut packed_tracks = Vec::new();
+    let mut info_flags = 0;
+    if let Some(banner) = &extras.banner {
+        packed_tracks.push(text_track("banner", BANNER_TRACK, banner, mode)?);
+        info_flags |= INFO_BANNER;
+    }
+    for (number, track) in image.chunks(track_size).enumerate() {
+        cancel.check()?;
+        packed_tracks.push(pack_track(number as u16, track, mode));
+    }
+    if let Some(diz) = &extras.file_id_diz {
+        packed_tracks.push(text_track("FILE_ID.DIZ", FILE_ID_DIZ_TRACK, diz, mode)?);
+        info_flags |= INFO_FILE_ID_DIZ;
+    }
+    let tracks = packed_tracks.concat();
+    let packed_size = (tracks.len() - packed_tracks.len() * DMS_TRACK_HEADER_SIZE) as u32;
+
+    let ffs = image[0..3] == *b"DOS" && image[3] & 1 != 0;
+    let mut header = [0u8; DMS_HEADER_SIZE];
+    header[0..4].copy_from_slice(b"DMS!");
+    if track_size != DMS_TRACK_SIZE {
+        info_flags |= INFO_HIGH_DENSITY;
+    }
+    put_u32(&mut header, 8, info_flags);
+    put_u32(&mut header, 12, timestamp);
+    put_u16(&mut header, 16, 0);
+    put_u16(&mut header, 18, (DMS_CYLINDERS - 1) as u16);
+    put_u32(&mut header, 20, packed_size);
+    put_u32(&mut header, 24, image.len() as u32);
+    put_u16(&mut header, 46, DMS_VERSION);
+    put_u16(&mut header, 48, DMS_VERSION);
+    put_u16(&mut header, 50, if ffs { 2 } else { 1 });
+    put_u16(&mut header, 52, mode.id() as u16);
+    let header_crc = crc16(&header[4..54]);
+    put_u16(&mut header, 54, header_crc);
+
+    let mut dms = header.to_vec();
+    dms.extend_from_slice(&tracks);
+    Ok(dms)
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DmsHeader {
+    pub info_flags: u32,
+    // Seconds since 1970 as stored, and decoded into created.
+    pub date: u32,
+    #[serde(serialize_with = "serialize_rfc3339")]
+    #[cfg_attr(feature = "arbitrary", arbitrary(value = UNIX_EPOCH))]
+    pub created: SystemTime,
+    pub low_track: u16,
+    pub high_track: u16,
+    pub packed_size: u32,
+    pub unpacked_size: u32,
+    pub creator_version: u16,
+    pub extract_version: u16,
+    pub disk_type: u16,
+    pub mode: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackStatus {
+    Ok,
+    Corrupt,
+    Unsupported,
+}
+
+impl fmt::Display for TrackStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackStatus::Ok => write!(f, "OK"),
+            TrackStatus::Corrupt => write!(f, "corrupt"),
+            TrackStatus::Unsupported => write!(f, "unsupported mode"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackReport {
+    pub number: u16,
+    pub mode: String,
+    pub packed_len: usize,
+    pub unpacked_len: usize,
+    pub status: TrackStatus,
+    pub detail: String,
+}
+
+pub fn mode_name(mode: u8) -> &'static str {
+    match mode {
+        0 => "NOCOMP",
+        1 => "SIMPLE",
+        2 => "QUICK",
+        3 => "MEDIUM",
+        4 => "DEEP",
+        5 => "HEAVY1",
+        6 => "HEAVY2",
+        _ => "UNKNOWN",
+    }
+}
+
+fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn serialize_rfc3339<S: Serializer>(
+    time: &SystemTime,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    let date = DateTime::<Utc>::from(*time);
+    serializer.serialize_str(&date.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+// Names of the info flag bits, lowest first. NOZERO only records that the packer left out
+// empty blocks; it does not change how the tracks unpack.
+const INFO_FLAG_NAMES: [&str; 9] = [
+    "NOZERO",
+    "ENCRYPT",
+    "APPENDS",
+    "BANNER",
+    "HD",
+    "MS-DOS",
+    "DEVICE_FIX",
+    "",
+    "FILE_ID.DIZ",
+];
+
+pub fn disk_type_name(disk_type: u16) -> &'static str {
+    match disk_type {
+        1 => "OFS",
+        2 => "FFS",
+        3 => "OFS International",
+        4 => "FFS International",
+        5 => "OFS Dircache",
+        6 => "FFS Dircache",
+        7 => "FMS",
+        _ => "unknown",
+    }
+}
+
+impl DmsHeader {
+    pub fn flag_names(&self) -> Vec<&'static str> {
+        INFO_FLAG_NAMES
+            .iter()
+            .enumerate()
+            .filter(|&(bit, name)| !name.is_empty() && self.info_flags & (1 << bit) != 0)
+            .map(|(_, &name)| name)
+            .collect()
+    }
+}
+
+impl fmt::Display for DmsHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let created = DateTime::<Utc>::from(self.created);
+        writeln!(
+            f,
+            "Created:     {}",
+            created.format("%Y-%m-%d %H:%M:%S UTC")
+        )?;
+        writeln!(
+            f,
+            "Disk type:   {} ({})",
+            disk_type_name(self.disk_type),
+            self.disk_type
+        )?;
+        writeln!(f, "Tracks:      {}-{}", self.low_track, self.high_track)?;
+        writeln!(
+            f,
+            "Size:        {} packed, {} unpacked",
+            self.packed_size, self.unpacked_size
+        )?;
+        writeln!(f, "Mode:        {}", mode_name(self.mode as u8))?;
+        let flags = self.flag_names();
+        writeln!(
+            f,
+            "Flags:       {}",
+            if flags.is_empty() {
+                "none".to_string()
+            } else {
+                flags.join(", ")
+            }
+        )?;
+        write!(
+            f,
+            "Versions:    created by {}, needs {}",
+            self.creator_version, self.extract_version
+        )
+    }
+}
+
+pub fn parse_header(dms: &[u8]) -> Result<DmsHeader> {
+    if dms.len() < DMS_HEADER_SIZE || &dms[0..4] != b"DMS!" {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a DMS archive"));
+    }
+    if crc16(&dms[4..54]) != get_u16(dms, 54) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "DMS header CRC mismatch",
+        ));
+    }
+    Ok(DmsHeader {
+        info_flags: get_u32(dms, 8),
+        date: get_u32(dms, 12),
+        created: UNIX_EPOCH + Duration::from_secs(u64::from(get_u32(dms, 12))),
+        low_track: get_u16(dms, 16),
+        high_track: get_u16(dms, 18),
+        packed_size: get_u32(dms, 20),
+        unpacked_size: get_u32(dms, 24),
+        creator_version: get_u16(dms, 46),
+        extract_version: get_u16(dms, 48),
+        disk_type: get_u16(dms, 50),
+        mode: get_u16(dms, 52),
+    })
+}
+
+// The header in front of every track of an archive, starting with "TR".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DmsTrackHeader {
+    pub number: u16,
+    pub packed_len: u16,
+    // Length after the first compression stage, only differs from unpacked_len for
+    // the two stage modes.
+    pub raw_len: u16,
+    pub unpacked_len: u16,
+    pub flags: u8,
+    pub mode: u8,
+    // Byte sum of the unpacked track.
+    pub checksum: u16,
+    pub data_crc: u16,
+    pub header_crc: u16,
+    pub computed_header_crc: u16,
+}
+
+impl DmsTrackHeader {
+    pub fn from_bytes(header: &[u8]) -> Result<Self> {
+        if header.len() < DMS_TRACK_HEADER_SIZE || &header[0..2] != b"TR" {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a DMS track header"));
+        }
+        Ok(DmsTrackHeader {
+            number: get_u16(header, 2),
+            packed_len: get_u16(header, 6),
+            raw_len: get_u16(header, 8),
+            unpacked_len: get_u16(header, 10),
+            flags: header[12],
+            mode: header[13],
+            checksum: get_u16(header, 14),
+            data_crc: get_u16(header, 16),
+            header_crc: get_u16(header, 18),
+            computed_header_crc: crc16(&header[..18]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; DMS_TRACK_HEADER_SIZE] {
+        let mut header = [0u8; DMS_TRACK_HEADER_SIZE];
+        header[0..2].copy_from_slice(b"TR");
+        put_u16(&mut header, 2, self.number);
+        put_u16(&mut header, 6, self.packed_len);
+        put_u16(&mut header, 8, self.raw_len);
+        put_u16(&mut header, 10, self.unpacked_len);
+        header[12] = self.flags;
+        header[13] = self.mode;
+        put_u16(&mut header, 14, self.checksum);
+        put_u16(&mut header, 16, self.data_crc);
+        put_u16(&mut header, 18, self.header_crc);
+        header
+    }
+
+    // The same header with header_crc set to match the other fields.
+    pub fn with_header_crc(mut self) -> Self {
+        self.header_crc = crc16(&self.to_bytes()[..18]);
+        self.computed_header_crc = self.header_crc;
+        self
+    }
+
+    pub fn header_crc_valid(&self) -> bool {
+        self.header_crc == self.computed_header_crc
+    }
+}
+
+impl fmt::Display for DmsTrackHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Track {}: {}, {} of {} bytes",
+            self.number,
+            mode_name(self.mode),
+            self.packed_len,
+            self.unpacked_len
+        )?;
+        if !self.header_crc_valid() {
+            write!(f, ", header CRC mismatch")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn unpack_rle(packed: &[u8], unpacked_len: usize) -> Result<Vec<u8>> {
+    raw::unpack_rle(packed, unpacked_len).map_err(|e| match e {
+        RawError::Truncated => Error::new(ErrorKind::InvalidData, "RLE data ends early"),
+        _ => Error::new(ErrorKind::InvalidData, "RLE run overflows the track"),
+    })
+}
+
+fn track_error(mode: u8, error: RawError) -> Error {
+    match error {
+        RawError::Truncated if mode == 0 => Error::new(
+            ErrorKind::InvalidData,
+            "Stored track is shorter than its unpacked length",
+        ),
+        RawError::Truncated => Error::new(ErrorKind::InvalidData, "RLE data ends early"),
+        RawError::Overflow => Error::new(ErrorKind::InvalidData, "RLE run overflows the track"),
+        RawError::UnsupportedMode(_) => Error::new(
+            ErrorKind::Unsupported,
+            format!("Compression mode {} is not supported", mode_name(mode)),
+        ),
+        error => error.into(),
+    }
+}
+
+impl TrackUnpacker {
+    // Unpacks the track described by header, keeping the LZ window for the next track of
+    // the archive when the header asks for it.
+    pub fn unpack_track(&mut self, header: &DmsTrackHeader, packed: &[u8]) -> Result<Vec<u8>> {
+        self.unpack(
+            header.mode,
+            header.flags,
+            packed,
+            header.raw_len as usize,
+            header.unpacked_len as usize,
+        )
+        .map_err(|e| track_error(header.mode, e))
+    }
+}
+
+// A track on its own, unpacked as if it were the first of its archive.
+pub fn unpack_track(header: &DmsTrackHeader, packed: &[u8]) -> Result<Vec<u8>> {
+    TrackUnpacker::new().unpack_track(header, packed)
+}
+
+#[derive(Debug, Clone)]
+pub enum DmsRecord {
+    Header(DmsHeader),
+    TrackHeader(DmsTrackHeader),
+    // The packed data of the track whose header came last.
+    TrackData(Vec<u8>),
+}
+
+enum DmsState {
+    Header,
+    TrackHeader,
+    TrackData(DmsTrackHeader),
+    Done,
+}
+
+// Splits an archive into its header, track headers and packed track data. The tracks end
+// at the end of the input, at anything that is not a track header, or after a track header
+// with a bad CRC, whose lengths cannot be trusted; that header is still passed on.
+pub struct DmsDecoder {
+    state: DmsState,
+}
+
+impl Default for DmsDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DmsDecoder {
+    pub fn new() -> Self {
+        DmsDecoder {
+            state: DmsState::Header,
+        }
+    }
+}
+
+impl Decoder for DmsDecoder {
+    type Item = DmsRecord;
+
+    fn decode(&mut self, input: &[u8], eof: bool) -> Result<Decoded<DmsRecord>> {
+        match &self.state {
+            DmsState::Header if input.len() < DMS_HEADER_SIZE && !eof => {
+                Ok(Decoded::Need(DMS_HEADER_SIZE))
+            }
+            DmsState::Header => {
+                let header = parse_header(input)?;
+                self.state = DmsState::TrackHeader;
+                Ok(Decoded::Consumed(
+                    DMS_HEADER_SIZE,
+                    Some(DmsRecord::Header(header)),
+                ))
+            }
+            DmsState::TrackHeader if input.len() < DMS_TRACK_HEADER_SIZE && !eof => {
+                Ok(Decoded::Need(DMS_TRACK_HEADER_SIZE))
+            }
+            DmsState::TrackHeader => match DmsTrackHeader::from_bytes(input) {
+                Ok(header) => {
+                    self.state = match header.header_crc_valid() {
+                        true => DmsState::TrackData(header.clone()),
+                        false => DmsState::Done,
+                    };
+                    Ok(Decoded::Consumed(
+                        DMS_TRACK_HEADER_SIZE,
+                        Some(DmsRecord::TrackHeader(header)),
+                    ))
+                }
+                Err(_) => {
+                    self.state = DmsState::Done;
+                    Ok(Decoded::Done)
+                }
+            },
+            DmsState::TrackData(header) => {
+                let packed_len = header.packed_len as usize;
+                match input.get(..packed_len) {
+                    Some(packed) => {
+                        let packed = packed.to_vec();
+                        self.state = DmsState::TrackHeader;
+                        Ok(Decoded::Consumed(
+                            packed_len,
+                            Some(DmsRecord::TrackData(packed)),
+                        ))
+                    }
+                    None if eof => Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        format!("DMS track {} is truncated", header.number),
+                    )),
+                    None => Ok(Decoded::Need(packed_len)),
+                }
+            }
+            DmsState::Done => Ok(Decoded::Done),
+        }
+    }
+}
+
+fn check_track(
+    unpacker: &mut TrackUnpacker,
+    lost: &mut LostState,
+    header: &DmsTrackHeader,
+    packed: &[u8],
+) -> (TrackStatus, String) {
+    if is_text_track(header) {
+        return check_packed(&mut TrackUnpacker::new(), header, packed);
+    }
+    if !is_disk_track(header) {
+        return match crc16(packed) == header.data_crc {
+            true => (TrackStatus::Ok, String::new()),
+            false => (TrackStatus::Corrupt, "packed data CRC mismatch".to_string()),
+        };
+    }
+    let (status, detail) = match lost.needed_by(header) {
+        Some(error) => (TrackStatus::Corrupt, error.to_string()),
+        None => check_packed(unpacker, header, packed),
+    };
+    lost.record(unpacker, header, status == TrackStatus::Ok);
+    (status, detail)
+}
+
+fn check_packed(
+    unpacker: &mut TrackUnpacker,
+    header: &DmsTrackHeader,
+    packed: &[u8],
+) -> (TrackStatus, String) {
+    if crc16(packed) != header.data_crc {
+        return (TrackStatus::Corrupt, "packed data CRC mismatch".to_string());
+    }
+    match unpacker.unpack_track(header, packed) {
+        Ok(track) if track_checksum(&track) != header.checksum => (
+            TrackStatus::Corrupt,
+            "unpacked data checksum mismatch".to_string(),
+        ),
+        Ok(_) => (TrackStatus::Ok, String::new()),
+        Err(e) if e.kind() == ErrorKind::Unsupported => (TrackStatus::Unsupported, e.to_string()),
+        Err(e) => (TrackStatus::Corrupt, e.to_string()),
+    }
+}
+
+// Which damaged track, if any, took the window and the HEAVY tables with it. A damaged track
+// leaves nothing for the tracks that go on from it, so those are reported as damaged in turn
+// rather than unpacked against the state of some earlier track.
+#[derive(Debug, Default)]
+struct LostState {
+    window: Option<u16>,
+    tables: Option<u16>,
+}
+
+impl LostState {
+    // The error for a track that goes on from the state of a damaged one.
+    fn needed_by(&self, header: &DmsTrackHeader) -> Option<Error> {
+        let lost = match header.mode {
+            0 | 1 => None,
+            _ if heavy_without_tables(header) && self.tables.is_some() => self.tables,
+            _ => self.window,
+        }?;
+        Some(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "DMS track {} goes on from damaged track {}",
+                header.number, lost
+            ),
+        ))
+    }
+
+    // Notes how a track went. The unpacker starts afresh after a damaged one.
+    fn record(&mut self, unpacker: &mut TrackUnpacker, header: &DmsTrackHeader, ok: bool) {
+        let keeps_window = header.flags & 1 != 0;
+        let heavy = matches!(header.mode, 5 | 6);
+        if ok {
+            // SIMPLE and NOCOMP tracks leave the window as it was.
+            if !keeps_window || header.mode > 1 {
+                self.window = None;
+            }
+            if heavy && !heavy_without_tables(header) {
+                self.tables = None;
+            }
+        } else {
+            unpacker.reset();
+            self.window = keeps_window.then_some(header.number);
+            if heavy {
+                self.tables = Some(header.number);
+            }
+        }
+    }
+}
+
+pub fn verify_dms(dms: &[u8]) -> Result<Vec<TrackReport>> {
+    let mut reports = Vec::new();
+    let mut track: Option<(DmsTrackHeader, TrackReport)> = None;
+    // The disk tracks share the LZ window; the banner and FILE_ID.DIZ unpack on their own.
+    let mut unpacker = TrackUnpacker::new();
+    let mut lost = LostState::default();
+    for record in decode_slice(DmsDecoder::new(), dms) {
+        match record {
+            Ok(DmsRecord::Header(_)) => {}
+            Ok(DmsRecord::TrackHeader(header)) => {
+                let mut report = TrackReport {
+                    number: header.number,
+                    mode: mode_name(header.mode).to_string(),
+                    packed_len: header.packed_len as usize,
+                    unpacked_len: header.unpacked_len as usize,
+                    status: TrackStatus::Ok,
+                    detail: String::new(),
+                };
+                if header.header_crc_valid() {
+                    track = Some((header, report));
+                } else {
+                    report.status = TrackStatus::Corrupt;
+                    report.detail = "track header CRC mismatch".to_string();
+                    reports.push(report);
+                }
+            }
+            Ok(DmsRecord::TrackData(packed)) => {
+                if let Some((header, mut report)) = track.take() {
+                    (report.status, report.detail) =
+                        check_track(&mut unpacker, &mut lost, &header, &packed);
+                    reports.push(report);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && track.is_some() => {
+                if let Some((_, mut report)) = track.take() {
+                    report.status = TrackStatus::Corrupt;
+                    report.detail = "track data is truncated".to_string();
+                    reports.push(report);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(reports)
+}
+
+// Rebuilds the disk image from the tracks of an archive, each at the offset its number gives.
+// Tracks outside the disk, such as the banner text some packers add, are skipped; cylinders
+// missing from the archive stay zeroed. Tracks outside the header's range, or repeated, fail.
+pub fn unpack_dms(dms: &[u8]) -> Result<Vec<u8>> {
+    unpack_dms_cancellable(dms, &CancelToken::default())
+}
+
+// Like unpack_dms, checking cancel before each track.
+pub fn unpack_dms_cancellable(dms: &[u8], cancel: &CancelToken) -> Result<Vec<u8>> {
+    Ok(unpack_tracks(dms, cancel, false)?.0)
+}
+
+// Unpacks what can be unpacked from a damaged archive. Tracks whose CRC or checksum does not
+// match, or that cannot be unpacked, are left zeroed and reported, as are the tracks that go on
+// from their window; a track header with a bad CRC or a truncated track ends the archive. The
+// archive header must still be intact.
+pub fn salvage_dms(dms: &[u8]) -> Result<(Vec<u8>, Vec<TrackReport>)> {
+    unpack_tracks(dms, &CancelToken::default(), true)
+}
+
+fn damaged(header: &DmsTrackHeader, status: TrackStatus, detail: String) -> TrackReport {
+    TrackReport {
+        number: header.number,
+        mode: mode_name(header.mode).to_string(),
+        packed_len: header.packed_len as usize,
+        unpacked_len: header.unpacked_len as usize,
+        status,
+        detail,
+    }
+}
+
+fn unpack_tracks(
+    dms: &[u8],
+    cancel: &CancelToken,
+    salvage: bool,
+) -> Result<(Vec<u8>, Vec<TrackReport>)> {
+    let mut image = Vec::new();
+    let mut track_size = DMS_TRACK_SIZE;
+    let mut tracks = 0..=DMS_CYLINDERS - 1;
+    let mut placed = [false; DMS_CYLINDERS];
+    let mut track_header = None;
+    let mut unpacker = TrackUnpacker::new();
+    let mut lost = LostState::default();
+    let mut reports = Vec::new();
+    for record in decode_slice(DmsDecoder::new(), dms) {
+        let record = match record {
+            Err(e) if salvage && e.kind() == ErrorKind::UnexpectedEof => {
+                if let Some(header) = track_header.take() {
+                    reports.push(damaged(&header, TrackStatus::Corrupt, e.to_string()));
+                }
+                break;
+            }
+            record => record?,
+        };
+        match record {
+            DmsRecord::Header(header) => {
+                if header.info_flags & INFO_HIGH_DENSITY != 0 {
+                    track_size = 2 * DMS_TRACK_SIZE;
+                }
+                tracks = disk_tracks(&header, track_size)?;
+                image = vec![0u8; DMS_CYLINDERS * track_size];
+            }
+            DmsRecord::TrackHeader(header) if !header.header_crc_valid() => {
+                let detail = "DMS track header CRC mismatch";
+                if !salvage {
+                    return Err(Error::new(ErrorKind::InvalidData, detail));
+                }
+                reports.push(damaged(&header, TrackStatus::Corrupt, detail.to_string()));
+            }
+            DmsRecord::TrackHeader(header) => track_header = Some(header),
+            DmsRecord::TrackData(packed) => {
+                cancel.check()?;
+                let Some(header) = track_header.take() else {
+                    continue;
+                };
+                let number = header.number as usize;
+                if is_text_track(&header) {
+                    match unpack_text_track(&header, &packed) {
+                        Ok(_) => continue,
+                        Err(e) if !salvage => return Err(e),
+                        Err(e) => {
+                            reports.push(damaged(&header, TrackStatus::Corrupt, e.to_string()));
+                            continue;
+                        }
+                    }
+                }
+                if !is_disk_track(&header) {
+                    continue;
+                }
+                let track = match lost.needed_by(&header) {
+                    Some(e) => Err(e),
+                    None => unpack_disk_track(&mut unpacker, &header, &packed, track_size),
+                };
+                lost.record(&mut unpacker, &header, track.is_ok());
+                let error = match track {
+                    Ok(track) => match placement_error(number, &tracks, &placed) {
+                        None => {
+                            image[number * track_size..(number + 1) * track_size]
+                                .copy_from_slice(&track);
+                            placed[number] = true;
+                            continue;
+                        }
+                        Some(e) => e,
+                    },
+                    Err(e) => e,
+                };
+                if !salvage {
+                    return Err(error);
+                }
+                let status = match error.kind() {
+                    ErrorKind::Unsupported => TrackStatus::Unsupported,
+                    _ => TrackStatus::Corrupt,
+                };
+                reports.push(damaged(&header, status, error.to_string()));
+            }
+        }
+    }
+    if !salvage && !placed.contains(&true) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "DMS archive holds no disk tracks",
+        ));
+    }
+    Ok((image, reports))
+}
+
+// The cylinders the header says the archive holds, checked against a disk with track_size
+// bytes per cylinder. Cylinders outside them stay zeroed in the image.
+fn disk_tracks(header: &DmsHeader, track_size: usize) -> Result<RangeInclusive<usize>> {
+    let (low, high) = (header.low_track as usize, header.high_track as usize);
+    if low > high || high >= DMS_CYLINDERS {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "DMS header gives tracks {}-{}, a disk has 0-{}",
+                low,
+                high,
+                DMS_CYLINDERS - 1
+            ),
+        ));
+    }
+    let disk_size = DMS_CYLINDERS * track_size;
+    if header.unpacked_size as usize > disk_size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "DMS header gives {} unpacked bytes, the disk holds {}",
+                header.unpacked_size, disk_size
+            ),
+        ));
+    }
+    Ok(low..=high)
+}
+
+fn placement_error(
+    number: usize,
+    tracks: &RangeInclusive<usize>,
+    placed: &[bool],
+) -> Option<Error> {
+    let detail = if !tracks.contains(&number) {
+        format!(
+            "DMS track {} is outside tracks {}-{} of the header",
+            number,
+            tracks.start(),
+            tracks.end()
+        )
+    } else if placed[number] {
+        format!("DMS track {} appears twice", number)
+    } else {
+        return None;
+    };
+    Some(Error::new(ErrorKind::InvalidData, detail))
+}
+
+fn unpack_disk_track(
+    unpacker: &mut TrackUnpacker,
+    header: &DmsTrackHeader,
+    packed: &[u8],
+    track_size: usize,
+) -> Result<Vec<u8>> {
+    let unpacked_len = header.unpacked_len as usize;
+    if unpacked_len != track_size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("DMS track {} has {} bytes", header.number, unpacked_len),
+        ));
+    }
+    unpack_checked(unpacker, header, packed)
+}
+
+// Unpacks a track whose data CRC and checksum match its header.
+fn unpack_checked(
+    unpacker: &mut TrackUnpacker,
+    header: &DmsTrackHeader,
+    packed: &[u8],
+) -> Result<Vec<u8>> {
+    let number = header.number;
+    if crc16(packed) != header.data_crc {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("DMS track {} data CRC mismatch", number),
+        ));
+    }
+    let track = unpacker.unpack_track(header, packed)?;
+    if track_checksum(&track) != header.checksum {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("DMS track {} checksum mismatch", number),
+        ));
+    }
+    Ok(track)
+}
+
+// The banner and FILE_ID.DIZ of an archive, when its header says it has them.
+pub fn read_dms_extras(dms: &[u8]) -> Result<DmsExtras> {
+    let mut extras = DmsExtras::default();
+    let mut wanted = 0;
+    let mut track_header = None;
+    for record in decode_slice(DmsDecoder::new(), dms) {
+        match record? {
+            DmsRecord::Header(header) => {
+                wanted = header.info_flags & (INFO_BANNER | INFO_FILE_ID_DIZ)
+            }
+            DmsRecord::TrackHeader(header) => track_header = Some(header),
+            DmsRecord::TrackData(packed) => {
+                if wanted == 0 {
+                    break;
+                }
+                let Some(header) = track_header.take() else {
+                    continue;
+                };
+                let (slot, flag) = match header.number {
+                    BANNER_TRACK if wanted & INFO_BANNER != 0 => (&mut extras.banner, INFO_BANNER),
+                    FILE_ID_DIZ_TRACK if wanted & INFO_FILE_ID_DIZ != 0 => {
+                        (&mut extras.file_id_diz, INFO_FILE_ID_DIZ)
+                    }
+                    _ => continue,
+                };
+                *slot = Some(unpack_text_track(&header, &packed)?);
+                wanted &= !flag;
+            }
+        }
+    }
+    Ok(extras)
+}
+
+// Tracks past the last cylinder hold the banner, FILE_ID.DIZ or nothing of the disk, and short
+// ones are the fake tracks some packers put first. Like xDMS, unpacking skips them all and
+// leaves the window of the disk tracks alone.
+fn is_disk_track(header: &DmsTrackHeader) -> bool {
+    (header.number as usize) < DMS_CYLINDERS && header.unpacked_len as usize > FAKE_TRACK_MAX
+}
+
+// The banner and FILE_ID.DIZ, which DMS unpacks apart from the disk tracks.
+fn is_text_track(header: &DmsTrackHeader) -> bool {
+    matches!(header.number, BANNER_TRACK | FILE_ID_DIZ_TRACK)
+}
+
+// A banner or FILE_ID.DIZ track, unpacked from a fresh state so the window of the disk tracks
+// stays as it was.
+fn unpack_text_track(header: &DmsTrackHeader, packed: &[u8]) -> Result<Vec<u8>> {
+    unpack_checked(&mut TrackUnpacker::new(), header, packed)
+}
+
+// Heavy tracks without new tables reuse those of the heavy track before them.
+fn heavy_without_tables(header: &DmsTrackHeader) -> bool {
+    matches!(header.mode, 5 | 6) && header.flags & 2 == 0
+}
+
+// Reads sectors of the disk in an archive in any order. Opening reads only the track headers,
+// seeking past the data; each track is unpacked the first time one of its sectors is read and
+// kept. Since a track may go on from the window of the one before, a read unpacks from the
+// nearest earlier track that starts afresh, or goes on from the last track unpacked.
+pub struct DmsReader<R> {
+    reader: R,
+    header: DmsHeader,
+    track_size: usize,
+    // Every track in archive order, with the offset of its data.
+    tracks: Vec<(DmsTrackHeader, u64)>,
+    // Index into tracks of each cylinder of the disk.
+    cylinders: [Option<usize>; DMS_CYLINDERS],
+    cache: HashMap<usize, Vec<u8>>,
+    // The next track to unpack in archive order and the window it goes on from.
+    next: Option<(usize, TrackUnpacker)>,
+}
+
+impl<R: Read + Seek> DmsReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut bytes = [0u8; DMS_HEADER_SIZE];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut bytes)?;
+        let header = parse_header(&bytes)?;
+        let track_size = match header.info_flags & INFO_HIGH_DENSITY {
+            0 => DMS_TRACK_SIZE,
+            _ => 2 * DMS_TRACK_SIZE,
+        };
+        let range = disk_tracks(&header, track_size)?;
+        let mut tracks = Vec::new();
+        let mut placed = [false; DMS_CYLINDERS];
+        let mut cylinders = [None; DMS_CYLINDERS];
+        let mut offset = DMS_HEADER_SIZE as u64;
+        loop {
+            let mut bytes = [0u8; DMS_TRACK_HEADER_SIZE];
+            match reader.read_exact(&mut bytes) {
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                result => result?,
+            }
+            let track = DmsTrackHeader::from_bytes(&bytes)?;
+            if !track.header_crc_valid() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("DMS track {} header CRC mismatch", track.number),
+                ));
+            }
+            offset += DMS_TRACK_HEADER_SIZE as u64;
+            let number = track.number as usize;
+            if is_disk_track(&track) {
+                if let Some(e) = placement_error(number, &range, &placed) {
+                    return Err(e);
+                }
+                placed[number] = true;
+                cylinders[number] = Some(tracks.len());
+            }
+            let packed_len = u64::from(track.packed_len);
+            tracks.push((track, offset));
+            offset += packed_len;
+            reader.seek(SeekFrom::Start(offset))?;
+        }
+        Ok(DmsReader {
+            reader,
+            header,
+            track_size,
+            tracks,
+            cylinders,
+            cache: HashMap::new(),
+            next: None,
+        })
+    }
+
+    pub fn header(&self) -> &DmsHeader {
+        &self.header
+    }
+
+    // The banner text, when the archive has one.
+    pub fn banner(&mut self) -> Result<Option<String>> {
+        self.text_track(BANNER_TRACK, INFO_BANNER)
+    }
+
+    // The FILE_ID.DIZ text, when the archive has one.
+    pub fn file_id_diz(&mut self) -> Result<Option<String>> {
+        self.text_track(FILE_ID_DIZ_TRACK, INFO_FILE_ID_DIZ)
+    }
+
+    fn text_track(&mut self, number: u16, flag: u32) -> Result<Option<String>> {
+        if self.header.info_flags & flag == 0 {
+            return Ok(None);
+        }
+        let Some((track, offset)) = self.tracks.iter().find(|(t, _)| t.number == number) else {
+            return Ok(None);
+        };
+        let mut packed = vec![0u8; track.packed_len as usize];
+        self.reader.seek(SeekFrom::Start(*offset))?;
+        self.reader.read_exact(&mut packed)?;
+        Ok(Some(extra_text(&unpack_text_track(track, &packed)?)))
+    }
+
+    pub fn num_sectors(&self) -> usize {
+        DMS_CYLINDERS * self.track_size / ADF_SECTOR_SIZE
+    }
+
+    pub fn read_sector(&mut self, sector: usize) -> Result<&[u8]> {
+        if sector >= self.num_sectors() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Sector {} is past the {} sectors of the disk",
+                    sector,
+                    self.num_sectors()
+                ),
+            ));
+        }
+        let offset = sector * ADF_SECTOR_SIZE % self.track_size;
+        let track = self.read_track(sector * ADF_SECTOR_SIZE / self.track_size)?;
+        Ok(&track[offset..offset + ADF_SECTOR_SIZE])
+    }
+
+    // One cylinder of the disk, zeroed when the archive does not hold it.
+    pub fn read_track(&mut self, cylinder: usize) -> Result<&[u8]> {
+        if cylinder >= DMS_CYLINDERS {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Track {} is past the end of the disk", cylinder),
+            ));
+        }
+        if !self.cache.contains_key(&cylinder) {
+            match self.cylinders[cylinder] {
+                Some(index) => self.unpack_through(index)?,
+                None => {
+                    self.cache.insert(cylinder, vec![0u8; self.track_size]);
+                }
+            }
+        }
+        Ok(&self.cache[&cylinder])
+    }
+
+    // Unpacks the disk tracks up to index in archive order, from the nearest one that needs
+    // nothing unpacked before it, or from where the last read stopped if that is nearer. The
+    // banner, FILE_ID.DIZ and fake tracks are left out, as unpack_dms leaves them out.
+    fn unpack_through(&mut self, index: usize) -> Result<()> {
+        let disk: Vec<usize> = (0..=index)
+            .filter(|&i| is_disk_track(&self.tracks[i].0))
+            .collect();
+        let mut start = 0;
+        let mut needs_tables = false;
+        for (k, &i) in disk.iter().enumerate().rev() {
+            let (track, _) = &self.tracks[i];
+            if matches!(track.mode, 5 | 6) {
+                needs_tables = heavy_without_tables(track);
+            }
+            if !needs_tables && (k == 0 || self.tracks[disk[k - 1]].0.flags & 1 == 0) {
+                start = i;
+                break;
+            }
+        }
+        let (from, mut unpacker) = match self.next.take() {
+            Some((next, unpacker)) if (start..=index).contains(&next) => (next, unpacker),
+            _ => (start, TrackUnpacker::new()),
+        };
+        for &i in disk.iter().filter(|&&i| i >= from) {
+            let (track, offset) = self.tracks[i].clone();
+            let mut packed = vec![0u8; track.packed_len as usize];
+            self.reader.seek(SeekFrom::Start(offset))?;
+            self.reader.read_exact(&mut packed)?;
+            let data = unpack_disk_track(&mut unpacker, &track, &packed, self.track_size)?;
+            self.cache.insert(track.number as usize, data);
+        }
+        self.next = Some((index + 1, unpacker));
+        Ok(())
+    }
+}
+
+impl ADF {
+    pub fn from_dms(dms: &[u8]) -> Result<ADF> {
+        ADF::from_bytes(&unpack_dms(dms)?)
+    }
+
+    pub fn to_dms(&self, mode: DmsMode) -> Result<Vec<u8>> {
+        pack_dms(&self.data, mode, self.now())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    // Replace images already in the output directory instead of failing those archives.
+    pub overwrite: bool,
+    // Worker threads, 0 for one per CPU.
+    pub threads: usize,
+    // Write images of damaged archives with their bad tracks zeroed, see salvage_dms.
+    pub salvage: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConvertStatus {
+    Converted,
+    // Written with options.salvage, with the damaged tracks zeroed.
+    Salvaged,
+    // A CRC or checksum did not match, or the archive is damaged otherwise.
+    Corrupt,
+    // A track uses a compression mode that cannot be unpacked.
+    Unsupported,
+    // The archive could not be read or the image not written.
+    Failed,
+}
+
+impl fmt::Display for ConvertStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertStatus::Converted => write!(f, "converted"),
+            ConvertStatus::Salvaged => write!(f, "salvaged"),
+            ConvertStatus::Corrupt => write!(f, "corrupt"),
+            ConvertStatus::Unsupported => write!(f, "unsupported mode"),
+            ConvertStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConvertResult {
+    pub path: String,
+    // The image written, for converted archives.
+    pub output: Option<String>,
+    pub status: ConvertStatus,
+    pub detail: String,
+}
+
+fn convert_one(path: &Path, out_dir: &Path, options: &ConvertOptions) -> ConvertResult {
+    let output = out_dir.join(path.with_extension("adf").file_name().unwrap_or_default());
+    let mut damaged = Vec::new();
+    let result = std::fs::read(path).and_then(|dms| {
+        let image = match options.salvage {
+            true => {
+                let (image, reports) = salvage_dms(&dms)?;
+                damaged = reports;
+                image
+            }
+            false => unpack_dms(&dms)?,
+        };
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .create_new(!options.overwrite)
+            .open(&output)
+            .map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} already exists", output.display()),
+                ),
+                _ => e,
+            })?;
+        file.write_all(&image)
+    });
+    let (status, detail) = match result {
+        Ok(()) if damaged.is_empty() => (ConvertStatus::Converted, String::new()),
+        Ok(()) => {
+            let tracks: Vec<String> = damaged.iter().map(|r| r.number.to_string()).collect();
+            let detail = format!("damaged tracks zeroed: {}", tracks.join(", "));
+            (ConvertStatus::Salvaged, detail)
+        }
+        Err(e) if e.kind() == ErrorKind::Unsupported => (ConvertStatus::Unsupported, e.to_string()),
+        Err(e) if e.kind() == ErrorKind::InvalidData || e.kind() == ErrorKind::UnexpectedEof => {
+            (ConvertStatus::Corrupt, e.to_string())
+        }
+        Err(e) => (ConvertStatus::Failed, e.to_string()),
+    };
+    ConvertResult {
+        path: path.display().to_string(),
+        output: matches!(status, ConvertStatus::Converted | ConvertStatus::Salvaged)
+            .then(|| output.display().to_string()),
+        status,
+        detail,
+    }
+}
+
+// Unpacks every archive in paths to an image of the same name in out_dir, several at a
+// time. A failing archive is reported in its result and does not stop the others; results
+// come in the order of paths.
+pub fn convert_all<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    out_dir: &Path,
+    options: &ConvertOptions,
+) -> Result<Vec<ConvertResult>> {
+    convert_all_cancellable(paths, out_dir, options, &CancelToken::default())
+}
+
+// Like convert_all, checking cancel before each archive.
+pub fn convert_all_cancellable<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    out_dir: &Path,
+    options: &ConvertOptions,
+    cancel: &CancelToken,
+) -> Result<Vec<ConvertResult>> {
+    std::fs::create_dir_all(out_dir)?;
+    let workers = match options.threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        threads => threads,
+    };
+    let chunk_size = paths.len().div_ceil(workers).max(1);
+    let results = std::thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .take_while(|_| !cancel.is_cancelled())
+                        .map(|path| convert_one(path.as_ref(), out_dir, options))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("convert worker panicked"))
+            .collect()
+    });
+    cancel.check()?;
+    Ok(results)
+}
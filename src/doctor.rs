@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// One look at the health of an image: what it is, whether its boot block looks infected,
+// whether checksums, bitmap and directory structure hold up, and how fragmented and full it
+// is. Each check gets a severity, so a front end can colour it, and the report keeps the
+// underlying results for anyone who wants the details.
+
+use crate::bootblock::{BootBlockInfo, BootCode};
+use crate::disk::ADF;
+use crate::optimize::FragmentationReport;
+use crate::validate::{Severity, ValidationReport};
+use serde::Serialize;
+use std::fmt;
+
+// Below this share of free blocks the volume is reported as nearly full.
+const LOW_FREE_PERCENT: u32 = 5;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DoctorCheck {
+    pub name: &'static str,
+    pub severity: Severity,
+    pub summary: String,
+}
+
+impl fmt::Display for DoctorCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.severity, self.name, self.summary)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DoctorReport {
+    pub checks: Vec<DoctorCheck>,
+    pub boot_block: BootBlockInfo,
+    pub validation: ValidationReport,
+    // None when the volume could not be walked.
+    pub fragmentation: Option<FragmentationReport>,
+    pub free_blocks: u32,
+    pub total_blocks: u32,
+}
+
+impl DoctorReport {
+    // The worst severity of any check.
+    pub fn severity(&self) -> Severity {
+        self.checks
+            .iter()
+            .map(|check| check.severity)
+            .max()
+            .unwrap_or(Severity::Info)
+    }
+}
+
+fn check(name: &'static str, severity: Severity, summary: impl Into<String>) -> DoctorCheck {
+    DoctorCheck {
+        name,
+        severity,
+        summary: summary.into(),
+    }
+}
+
+// Turns the validation findings matching filter into one check.
+fn findings_check(
+    name: &'static str,
+    validation: &ValidationReport,
+    filter: impl Fn(&str) -> bool,
+    healthy: &str,
+) -> DoctorCheck {
+    let problems: Vec<_> = validation
+        .findings
+        .iter()
+        .filter(|finding| finding.severity > Severity::Info && finding.block != Some(0))
+        .filter(|finding| filter(&finding.message.to_lowercase()))
+        .collect();
+    match problems.iter().map(|finding| finding.severity).max() {
+        None => check(name, Severity::Info, healthy),
+        Some(severity) => {
+            let summary = match problems.as_slice() {
+                [finding] => finding.to_string(),
+                _ => format!("{} problems, run fsck for details", problems.len()),
+            };
+            check(name, severity, summary)
+        }
+    }
+}
+
+impl ADF {
+    pub fn doctor(&self) -> DoctorReport {
+        let boot_block = self.boot_block_info();
+        let dos = boot_block.dos_type.starts_with("DOS");
+        let mut checks = Vec::new();
+
+        checks.push(if dos {
+            let flags = [
+                (boot_block.intl, " INTL"),
+                (boot_block.dircache, " DIRCACHE"),
+            ];
+            let flags: String = flags
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, flag)| *flag)
+                .collect();
+            let fs = if boot_block.ffs { "FFS" } else { "OFS" };
+            check(
+                "File system",
+                Severity::Info,
+                format!("{} ({}{})", boot_block.dos_type, fs, flags),
+            )
+        } else {
+            check(
+                "File system",
+                Severity::Error,
+                format!(
+                    "Not an AmigaDOS disk (type {}), a custom format or damaged",
+                    boot_block.dos_type
+                ),
+            )
+        });
+
+        checks.push(match boot_block.code {
+            _ if !boot_block.warnings.is_empty() => check(
+                "Boot block",
+                Severity::Error,
+                format!("Possible virus: {}", boot_block.warnings.join("; ")),
+            ),
+            BootCode::None => check("Boot block", Severity::Info, "Not bootable"),
+            _ if !boot_block.checksum_valid() => check(
+                "Boot block",
+                Severity::Warning,
+                "Checksum mismatch, the disk will not boot",
+            ),
+            // The standard code already names itself as boot code.
+            BootCode::Standard => check(
+                "Boot block",
+                Severity::Info,
+                format!("Boots with {}", BootCode::Standard),
+            ),
+            code => check(
+                "Boot block",
+                Severity::Info,
+                format!("Boots with {} code", code),
+            ),
+        });
+
+        let validation = if dos {
+            self.validate()
+        } else {
+            ValidationReport::default()
+        };
+        if dos {
+            checks.push(findings_check(
+                "Checksums",
+                &validation,
+                |message| message.contains("checksum") && !message.contains("bitmap"),
+                "All block checksums match",
+            ));
+            checks.push(findings_check(
+                "Bitmap",
+                &validation,
+                |message| message.contains("bitmap"),
+                "Bitmap matches the blocks in use",
+            ));
+            checks.push(findings_check(
+                "Structure",
+                &validation,
+                |message| !message.contains("checksum") && !message.contains("bitmap"),
+                "Directories and files are consistent",
+            ));
+        }
+
+        let fragmentation = if dos { self.fragmentation().ok() } else { None };
+        if let Some(report) = &fragmentation {
+            let severity = if report.fragmented_files * 4 > report.files {
+                Severity::Warning
+            } else {
+                Severity::Info
+            };
+            checks.push(check(
+                "Fragmentation",
+                severity,
+                format!(
+                    "{} of {} files fragmented, largest free run {} blocks",
+                    report.fragmented_files, report.files, report.largest_free_extent
+                ),
+            ));
+        }
+
+        let counts = self.bitmap_counts();
+        if dos {
+            let percent = counts.free_blocks * 100 / counts.total_blocks.max(1);
+            let severity = if percent < LOW_FREE_PERCENT {
+                Severity::Warning
+            } else {
+                Severity::Info
+            };
+            checks.push(check(
+                "Free space",
+                severity,
+                format!(
+                    "{} of {} blocks free ({}%)",
+                    counts.free_blocks, counts.total_blocks, percent
+                ),
+            ));
+        }
+
+        DoctorReport {
+            checks,
+            boot_block,
+            validation,
+            fragmentation,
+            free_blocks: counts.free_blocks,
+            total_blocks: counts.total_blocks,
+        }
+    }
+}
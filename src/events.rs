@@ -0,0 +1,71 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Change notifications for views of an image, such as a browser or a mounted file system,
+// that would otherwise reread the whole disk after every operation. Observers are called
+// synchronously from the ADF method making the change.
+
+use crate::disk::ADF;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+pub trait Observer: Send + Sync {
+    // Blocks whose contents changed, reported for every write.
+    fn on_block_written(&self, _blocks: Range<usize>) {}
+    // A file or directory was created, or moved or renamed to path.
+    fn on_entry_created(&self, _path: &str, _block: usize) {}
+    // A file or directory was deleted, or moved or renamed away from path.
+    fn on_entry_removed(&self, _path: &str, _block: usize) {}
+}
+
+// A clone of an image starts without observers, as they watch the original.
+#[derive(Default)]
+pub(crate) struct Observers(Vec<Arc<dyn Observer>>);
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Observers::default()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+impl Observers {
+    pub(crate) fn block_written(&self, blocks: Range<usize>) {
+        for observer in &self.0 {
+            observer.on_block_written(blocks.clone());
+        }
+    }
+
+    pub(crate) fn entry_created(&self, path: &str, block: usize) {
+        for observer in &self.0 {
+            observer.on_entry_created(path, block);
+        }
+    }
+
+    pub(crate) fn entry_removed(&self, path: &str, block: usize) {
+        for observer in &self.0 {
+            observer.on_entry_removed(path, block);
+        }
+    }
+}
+
+impl ADF {
+    pub fn add_observer(&mut self, observer: Arc<dyn Observer>) {
+        self.tracking.observers.0.push(observer);
+    }
+
+    pub fn remove_observer(&mut self, observer: &Arc<dyn Observer>) {
+        self.tracking
+            .observers
+            .0
+            .retain(|other| !Arc::ptr_eq(other, observer));
+    }
+}
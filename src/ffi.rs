@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// C entry points named after the ADFlib API, so C and C++ programs can link against this
+// crate instead. Devices, volumes and files are opaque handles; build the shared library with
+// `cargo rustc --lib --release --features cdylib --crate-type cdylib`.
+
+#![allow(non_snake_case, clippy::missing_safety_doc)]
+
+use crate::device::{Device, Volume};
+use log::warn;
+use std::ffi::{c_char, c_int, CStr};
+use std::io::{Error, ErrorKind, Result};
+use std::ptr;
+
+pub const RC_OK: c_int = 0;
+pub const RC_ERROR: c_int = -1;
+
+pub struct AdfDevice {
+    device: Device,
+    read_only: bool,
+}
+
+pub struct AdfVolume {
+    volume: Volume,
+}
+
+// Files are read completely on open; files opened for writing are stored when closed.
+pub struct AdfFile {
+    volume: *mut AdfVolume,
+    path: String,
+    data: Vec<u8>,
+    pos: usize,
+    write: bool,
+}
+
+unsafe fn string_arg<'a>(s: *const c_char) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(Error::new(ErrorKind::InvalidInput, "Null string argument"));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+}
+
+fn into_handle<T>(result: Result<T>, what: &str) -> *mut T {
+    match result {
+        Ok(value) => Box::into_raw(Box::new(value)),
+        Err(e) => {
+            warn!("{} failed: {}", what, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+fn retcode(result: Result<()>, what: &str) -> c_int {
+    match result {
+        Ok(()) => RC_OK,
+        Err(e) => {
+            warn!("{} failed: {}", what, e);
+            RC_ERROR
+        }
+    }
+}
+
+unsafe fn with_volume(
+    vol: *mut AdfVolume,
+    name: *const c_char,
+    what: &str,
+    f: impl FnOnce(&mut Volume, &str) -> Result<()>,
+) -> c_int {
+    let Some(vol) = vol.as_mut() else {
+        return RC_ERROR;
+    };
+    retcode(
+        string_arg(name).and_then(|name| f(&mut vol.volume, name)),
+        what,
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn adfEnvInitDefault() {}
+
+#[no_mangle]
+pub extern "C" fn adfEnvCleanUp() {}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfMountDev(name: *const c_char, ro: c_int) -> *mut AdfDevice {
+    let device = string_arg(name)
+        .and_then(Device::open)
+        .map(|device| AdfDevice {
+            device,
+            read_only: ro != 0,
+        });
+    into_handle(device, "adfMountDev")
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfUnMountDev(dev: *mut AdfDevice) {
+    if !dev.is_null() {
+        drop(Box::from_raw(dev));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfMount(
+    dev: *mut AdfDevice,
+    n_part: c_int,
+    read_only: c_int,
+) -> *mut AdfVolume {
+    let Some(dev) = dev.as_ref() else {
+        return ptr::null_mut();
+    };
+    let index = usize::try_from(n_part)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Negative partition number"));
+    let volume = index.and_then(|index| {
+        if dev.read_only || read_only != 0 {
+            dev.device.mount_read_only(index)
+        } else {
+            dev.device.mount(index)
+        }
+    });
+    into_handle(volume.map(|volume| AdfVolume { volume }), "adfMount")
+}
+
+// Writes the volume back to its device unless it was mounted read-only.
+#[no_mangle]
+pub unsafe extern "C" fn adfUnMount(vol: *mut AdfVolume) {
+    if vol.is_null() {
+        return;
+    }
+    let vol = Box::from_raw(vol);
+    if !vol.volume.is_read_only() {
+        retcode(vol.volume.flush(), "adfUnMount");
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfChangeDir(vol: *mut AdfVolume, name: *const c_char) -> c_int {
+    with_volume(vol, name, "adfChangeDir", |volume, name| volume.cd(name))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfParentDir(vol: *mut AdfVolume) -> c_int {
+    match vol.as_mut() {
+        Some(vol) => retcode(vol.volume.cd("/"), "adfParentDir"),
+        None => RC_ERROR,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfCreateDir(vol: *mut AdfVolume, name: *const c_char) -> c_int {
+    with_volume(vol, name, "adfCreateDir", |volume, name| {
+        volume.create_dir(name)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfRemoveEntry(vol: *mut AdfVolume, name: *const c_char) -> c_int {
+    with_volume(vol, name, "adfRemoveEntry", |volume, name| {
+        volume.delete(name)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfRenameEntry(
+    vol: *mut AdfVolume,
+    old_name: *const c_char,
+    new_name: *const c_char,
+) -> c_int {
+    with_volume(vol, old_name, "adfRenameEntry", |volume, old_name| {
+        volume.rename(old_name, string_arg(new_name)?)
+    })
+}
+
+// mode is "r" to read an existing file or "w" to create or replace one.
+#[no_mangle]
+pub unsafe extern "C" fn adfOpenFile(
+    vol: *mut AdfVolume,
+    name: *const c_char,
+    mode: *const c_char,
+) -> *mut AdfFile {
+    let Some(volume) = vol.as_mut() else {
+        return ptr::null_mut();
+    };
+    let file = (|| {
+        let path = volume.volume.resolve(string_arg(name)?)?;
+        let write = match string_arg(mode)? {
+            "r" => false,
+            "w" => {
+                // Fails with ReadOnlyVolume on read-only mounts.
+                volume.volume.adf_mut()?;
+                true
+            }
+            mode => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Unsupported file mode '{}'", mode),
+                ))
+            }
+        };
+        let data = if write {
+            Vec::new()
+        } else {
+            volume.volume.read_file(&format!(":{}", path))?
+        };
+        Ok(AdfFile {
+            volume: vol,
+            path,
+            data,
+            pos: 0,
+            write,
+        })
+    })();
+    into_handle(file, "adfOpenFile")
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfReadFile(file: *mut AdfFile, n: u32, buffer: *mut u8) -> u32 {
+    let Some(file) = file.as_mut() else {
+        return 0;
+    };
+    if buffer.is_null() || file.write {
+        return 0;
+    }
+    let count = (n as usize).min(file.data.len() - file.pos);
+    ptr::copy_nonoverlapping(file.data[file.pos..].as_ptr(), buffer, count);
+    file.pos += count;
+    count as u32
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfWriteFile(file: *mut AdfFile, n: u32, buffer: *const u8) -> u32 {
+    let Some(file) = file.as_mut() else {
+        return 0;
+    };
+    if buffer.is_null() || !file.write {
+        return 0;
+    }
+    let bytes = std::slice::from_raw_parts(buffer, n as usize);
+    let end = file.pos + bytes.len();
+    if end > file.data.len() {
+        file.data.resize(end, 0);
+    }
+    file.data[file.pos..end].copy_from_slice(bytes);
+    file.pos = end;
+    n
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfEndOfFile(file: *const AdfFile) -> c_int {
+    file.as_ref()
+        .map_or(1, |file| (file.pos >= file.data.len()) as c_int)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfFileSeek(file: *mut AdfFile, pos: u32) -> c_int {
+    match file.as_mut() {
+        Some(file) if file.write || pos as usize <= file.data.len() => {
+            file.pos = pos as usize;
+            RC_OK
+        }
+        _ => RC_ERROR,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn adfFileGetSize(file: *const AdfFile) -> u32 {
+    file.as_ref().map_or(0, |file| file.data.len() as u32)
+}
+
+// Files opened for writing are stored in the volume here; the volume must still be mounted.
+#[no_mangle]
+pub unsafe extern "C" fn adfCloseFile(file: *mut AdfFile) -> c_int {
+    if file.is_null() {
+        return RC_ERROR;
+    }
+    let file = Box::from_raw(file);
+    if !file.write {
+        return RC_OK;
+    }
+    let Some(vol) = file.volume.as_mut() else {
+        return RC_ERROR;
+    };
+    retcode(
+        vol.volume.put(&format!(":{}", file.path), &file.data),
+        "adfCloseFile",
+    )
+}
@@ -0,0 +1,302 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// A facade shaped like std::fs, so code written against the host filesystem can be pointed
+// at a volume instead. Paths are host style: "/" and "." are ignored, ".." goes up a
+// directory and names compare without case, as in AmigaDOS.
+
+use crate::block::{HeaderBlock, SecType};
+use crate::disk::{join_path, ADF};
+use std::io::{self, Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::{Component, Path};
+use std::time::SystemTime;
+
+// Amiga protection bits for write and delete are set when the access is denied.
+const PROTECT_WRITE: u32 = 1 << 2;
+const PROTECT_DELETE: u32 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileType {
+    dir: bool,
+    symlink: bool,
+}
+
+impl FileType {
+    pub fn is_dir(&self) -> bool {
+        self.dir
+    }
+
+    pub fn is_file(&self) -> bool {
+        !self.dir && !self.symlink
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.symlink
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    protection: u32,
+}
+
+impl Permissions {
+    // Like a host file without write permission, the entry cannot be changed or deleted.
+    pub fn readonly(&self) -> bool {
+        self.protection & (PROTECT_WRITE | PROTECT_DELETE) != 0
+    }
+
+    // The raw protection bits, see ADF::format_protection_flags.
+    pub fn protection(&self) -> u32 {
+        self.protection
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    file_type: FileType,
+    len: u64,
+    protection: u32,
+    modified: SystemTime,
+    comment: String,
+    block: usize,
+}
+
+impl Metadata {
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type.is_dir()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type.is_file()
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type.is_symlink()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        Permissions {
+            protection: self.protection,
+        }
+    }
+
+    // AmigaDOS keeps a single date per entry, so this also answers for accessed and created.
+    pub fn modified(&self) -> Result<SystemTime> {
+        Ok(self.modified)
+    }
+
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    // The header block of the entry, which identifies it like an inode number.
+    pub fn block(&self) -> usize {
+        self.block
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    path: String,
+    name: String,
+    metadata: Metadata,
+}
+
+impl DirEntry {
+    // The volume path of the entry, e.g. "Devs/Keymaps/d".
+    pub fn path(&self) -> String {
+        self.path.clone()
+    }
+
+    pub fn file_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn metadata(&self) -> Result<Metadata> {
+        Ok(self.metadata.clone())
+    }
+
+    pub fn file_type(&self) -> Result<FileType> {
+        Ok(self.metadata.file_type)
+    }
+}
+
+// Entries are read when the directory is opened, in hash table order.
+#[derive(Debug)]
+pub struct ReadDir {
+    entries: std::vec::IntoIter<Result<DirEntry>>,
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next()
+    }
+}
+
+// An open file; its contents are read completely when it is opened.
+#[derive(Debug)]
+pub struct File {
+    contents: Cursor<Vec<u8>>,
+    metadata: Metadata,
+}
+
+impl File {
+    pub fn metadata(&self) -> Result<Metadata> {
+        Ok(self.metadata.clone())
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.contents.seek(pos)
+    }
+}
+
+// The volume path for a host style path.
+fn volume_path(path: &Path) -> Result<String> {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_str().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid file name {:?}", name),
+                )
+            })?),
+            Component::ParentDir => {
+                components.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    Ok(components.join("/"))
+}
+
+impl ADF {
+    fn fs_metadata(&self, block: usize) -> Result<Metadata> {
+        let header = HeaderBlock::from_bytes(self.read_sector(block), &self.layout())?;
+        // Hard links report the entry they point to, like a second name on a host filesystem.
+        let (block, header) = match header.sec_type {
+            SecType::LinkFile | SecType::LinkDir => {
+                let real = header
+                    .real_entry
+                    .index()
+                    .filter(|&real| real != block && real < self.num_blocks())
+                    .ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Hard link in block {} is broken", block),
+                        )
+                    })?;
+                (
+                    real,
+                    HeaderBlock::from_bytes(self.read_sector(real), &self.layout())?,
+                )
+            }
+            _ => (block, header),
+        };
+        let root = header.sec_type == SecType::Root;
+        let dir = root || header.sec_type == SecType::UserDir;
+        Ok(Metadata {
+            file_type: FileType {
+                dir,
+                symlink: header.sec_type == SecType::SoftLink,
+            },
+            len: if dir { 0 } else { header.byte_size as u64 },
+            // The root block keeps bitmap pointers where other headers store these fields.
+            protection: if root { 0 } else { header.protect },
+            modified: header.date.to_system_time(),
+            comment: if root { String::new() } else { header.comment },
+            block,
+        })
+    }
+
+    pub fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<Metadata> {
+        self.fs_metadata(self.find_entry(&volume_path(path.as_ref())?)?)
+    }
+
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<File> {
+        let path = volume_path(path.as_ref())?;
+        let metadata = self.metadata(&path)?;
+        if !metadata.is_file() {
+            return Err(Error::new(
+                ErrorKind::IsADirectory,
+                format!("'{}' is not a file", path),
+            ));
+        }
+        Ok(File {
+            contents: Cursor::new(self.read_file_contents(metadata.block)?),
+            metadata,
+        })
+    }
+
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> Result<ReadDir> {
+        let path = volume_path(path.as_ref())?;
+        let dir = self.metadata(&path)?;
+        if !dir.is_dir() {
+            return Err(Error::new(
+                ErrorKind::NotADirectory,
+                format!("'{}' is not a directory", path),
+            ));
+        }
+        let entries: Vec<Result<DirEntry>> = self
+            .directory_entries(dir.block)
+            .into_iter()
+            .map(|block| {
+                let name = self.entry_name(block);
+                Ok(DirEntry {
+                    path: join_path(&path, &name),
+                    name,
+                    metadata: self.fs_metadata(block)?,
+                })
+            })
+            .collect();
+        Ok(ReadDir {
+            entries: entries.into_iter(),
+        })
+    }
+
+    // Creates every missing directory of path; existing directories are fine.
+    pub fn create_dir_all<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = volume_path(path.as_ref())?;
+        let mut current = String::new();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            current = join_path(&current, component);
+            match self.metadata(&current) {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => {
+                    return Err(Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!("'{}' exists and is not a directory", current),
+                    ))
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => self.create_dir(&current)?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
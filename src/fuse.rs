@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use crate::block::{HeaderBlock, SecType};
+use crate::disk::{from_amiga_date, ADF, ADF_SECTOR_SIZE, MAX_NAME_LEN};
+use fuser::{
+    Config, Errno, FileAttr, FileHandle, FileType, Filesystem, Generation, INodeNo, LockOwner,
+    MountOption, OpenFlags, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
+    ReplyStatfs, ReplyXattr, Request, SessionACL,
+};
+use log::debug;
+use std::ffi::OsStr;
+use std::io::Result;
+use std::path::Path;
+use std::time::Duration;
+
+// The image cannot change behind our back while it is mounted.
+const TTL: Duration = Duration::from_secs(60);
+
+// Amiga protection bits for read, write and execute are set when the access is denied.
+const PROTECT_READ: u32 = 1 << 3;
+const PROTECT_WRITE: u32 = 1 << 2;
+const PROTECT_EXECUTE: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Default)]
+pub struct MountOptions {
+    // Let other users access the mount; needs user_allow_other in /etc/fuse.conf.
+    pub allow_other: bool,
+    // Unmount when the process exits, even if it is killed.
+    pub auto_unmount: bool,
+}
+
+// Inode numbers are header block numbers, except for the root directory which FUSE expects
+// at inode 1. Block 1 belongs to the boot block, so the two never collide.
+struct AdfFs {
+    adf: ADF,
+}
+
+impl AdfFs {
+    fn block(&self, ino: INodeNo) -> usize {
+        if ino == INodeNo::ROOT {
+            self.adf.root_block()
+        } else {
+            u64::from(ino) as usize
+        }
+    }
+
+    fn inode(&self, block: usize) -> INodeNo {
+        if block == self.adf.root_block() {
+            INodeNo::ROOT
+        } else {
+            INodeNo(block as u64)
+        }
+    }
+
+    // Hard links are shown as the entry they point to.
+    fn header(&self, block: usize) -> std::result::Result<(usize, HeaderBlock), Errno> {
+        if block < 2 || block >= self.adf.num_blocks() {
+            return Err(Errno::ENOENT);
+        }
+        let header = HeaderBlock::from_bytes(self.adf.read_sector(block), &self.adf.layout())
+            .map_err(|_| Errno::EIO)?;
+        match header.sec_type {
+            SecType::LinkFile | SecType::LinkDir => match header.real_entry.index() {
+                Some(real) if real != block => self.header(real),
+                _ => Err(Errno::EIO),
+            },
+            _ => Ok((block, header)),
+        }
+    }
+
+    fn attr(&self, req: &Request, block: usize) -> std::result::Result<FileAttr, Errno> {
+        let (block, header) = self.header(block)?;
+        let date = from_amiga_date(header.date.days, header.date.mins, header.date.ticks);
+        let dir = matches!(header.sec_type, SecType::Root | SecType::UserDir);
+        let mut perm = 0;
+        if header.protect & PROTECT_READ == 0 {
+            perm |= 0o444;
+        }
+        if header.protect & PROTECT_WRITE == 0 {
+            perm |= 0o200;
+        }
+        if dir || header.protect & PROTECT_EXECUTE == 0 {
+            perm |= 0o111;
+        }
+        let size = if dir { 0 } else { header.byte_size as u64 };
+        Ok(FileAttr {
+            ino: self.inode(block),
+            size,
+            blocks: size.div_ceil(512),
+            atime: date,
+            mtime: date,
+            ctime: date,
+            crtime: date,
+            kind: if dir {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            },
+            perm,
+            nlink: if dir { 2 } else { 1 },
+            uid: req.uid(),
+            gid: req.gid(),
+            rdev: 0,
+            blksize: ADF_SECTOR_SIZE as u32,
+            flags: 0,
+        })
+    }
+
+    fn directory(&self, ino: INodeNo) -> std::result::Result<(usize, HeaderBlock), Errno> {
+        let (block, header) = self.header(self.block(ino))?;
+        if !matches!(header.sec_type, SecType::Root | SecType::UserDir) {
+            return Err(Errno::ENOTDIR);
+        }
+        Ok((block, header))
+    }
+}
+
+impl Filesystem for AdfFs {
+    fn lookup(&self, req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEntry) {
+        let result = self.directory(parent).and_then(|(dir, _)| {
+            let name = name.to_str().ok_or(Errno::ENOENT)?;
+            let entry = self
+                .adf
+                .directory_entries(dir)
+                .into_iter()
+                .find(|&entry| self.adf.entry_name(entry).eq_ignore_ascii_case(name))
+                .ok_or(Errno::ENOENT)?;
+            self.attr(req, entry)
+        });
+        match result {
+            Ok(attr) => reply.entry(&TTL, &attr, Generation(0)),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn getattr(&self, req: &Request, ino: INodeNo, _fh: Option<FileHandle>, reply: ReplyAttr) {
+        match self.attr(req, self.block(ino)) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn read(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        size: u32,
+        _flags: OpenFlags,
+        _lock_owner: Option<LockOwner>,
+        reply: ReplyData,
+    ) {
+        let mut buf = vec![0; size as usize];
+        let read = self.header(self.block(ino)).and_then(|(block, _)| {
+            self.adf
+                .read_contents_range(block, offset, &mut buf)
+                .map_err(|_| Errno::EIO)
+        });
+        match read {
+            Ok(read) => reply.data(&buf[..read]),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn readdir(
+        &self,
+        _req: &Request,
+        ino: INodeNo,
+        _fh: FileHandle,
+        offset: u64,
+        mut reply: ReplyDirectory,
+    ) {
+        let (dir, header) = match self.directory(ino) {
+            Ok(dir) => dir,
+            Err(e) => return reply.error(e),
+        };
+        let parent = header.parent.index().unwrap_or(dir);
+        let mut entries = vec![
+            (self.inode(dir), FileType::Directory, ".".to_string()),
+            (self.inode(parent), FileType::Directory, "..".to_string()),
+        ];
+        for entry in self.adf.directory_entries(dir) {
+            let (block, kind) = match self.header(entry) {
+                Ok((block, header)) if header.sec_type == SecType::UserDir => {
+                    (block, FileType::Directory)
+                }
+                Ok((block, _)) => (block, FileType::RegularFile),
+                Err(_) => continue,
+            };
+            entries.push((self.inode(block), kind, self.adf.entry_name(entry)));
+        }
+        for (i, (ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(ino, (i + 1) as u64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    // Nothing is buffered on a read-only mount.
+    fn flush(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        _fh: FileHandle,
+        _lock_owner: LockOwner,
+        reply: ReplyEmpty,
+    ) {
+        reply.ok();
+    }
+
+    // Amiga files carry no extended attributes.
+    fn getxattr(
+        &self,
+        _req: &Request,
+        _ino: INodeNo,
+        _name: &OsStr,
+        _size: u32,
+        reply: ReplyXattr,
+    ) {
+        reply.error(Errno::NO_XATTR);
+    }
+
+    fn listxattr(&self, _req: &Request, _ino: INodeNo, size: u32, reply: ReplyXattr) {
+        if size == 0 {
+            reply.size(0);
+        } else {
+            reply.data(&[]);
+        }
+    }
+
+    fn statfs(&self, _req: &Request, _ino: INodeNo, reply: ReplyStatfs) {
+        let free = self.adf.free_block_count() as u64;
+        reply.statfs(
+            self.adf.num_blocks() as u64,
+            free,
+            free,
+            0,
+            0,
+            ADF_SECTOR_SIZE as u32,
+            MAX_NAME_LEN as u32,
+            ADF_SECTOR_SIZE as u32,
+        );
+    }
+}
+
+// Exposes the volume read-only below mountpoint and blocks until it is unmounted.
+pub fn mount<P: AsRef<Path>>(adf: ADF, mountpoint: P, options: &MountOptions) -> Result<()> {
+    let label = adf.entry_name(adf.root_block());
+    let mut config = Config::default();
+    config.mount_options = vec![
+        MountOption::RO,
+        MountOption::FSName(label.clone()),
+        MountOption::Subtype("adf".to_string()),
+    ];
+    if options.auto_unmount {
+        config.mount_options.push(MountOption::AutoUnmount);
+    }
+    if options.allow_other {
+        config.acl = SessionACL::All;
+    } else if options.auto_unmount {
+        // auto_unmount is refused unless root may access the mount as well.
+        config.acl = SessionACL::RootAndOwner;
+    }
+    debug!("Mounting {} at {}", label, mountpoint.as_ref().display());
+    fuser::mount(AdfFs { adf }, mountpoint, &config)
+}
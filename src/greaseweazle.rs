@@ -0,0 +1,393 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Imaging real floppies with a Greaseweazle: the flux of each track is read over the USB
+// serial protocol of the Greaseweazle firmware and decoded as Amiga MFM, and written disks
+// are encoded the other way round.
+
+use crate::disk::{ADF, ADF_NUM_TRACKS, ADF_SECTOR_SIZE};
+use crate::mfm::{decode_sectors, encode_track, fill_gap, flux_to_mfm, mfm_to_flux};
+use log::{debug, warn};
+use serialport::{ClearBuffer, SerialPort};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::time::Duration;
+
+#[cfg(windows)]
+pub const DEFAULT_PORT: &str = "COM3";
+#[cfg(not(windows))]
+pub const DEFAULT_PORT: &str = "/dev/ttyACM0";
+
+const CMD_GET_INFO: u8 = 0;
+const CMD_SEEK: u8 = 2;
+const CMD_HEAD: u8 = 3;
+const CMD_MOTOR: u8 = 6;
+const CMD_READ_FLUX: u8 = 7;
+const CMD_WRITE_FLUX: u8 = 8;
+const CMD_GET_FLUX_STATUS: u8 = 9;
+const CMD_SELECT: u8 = 12;
+const CMD_DESELECT: u8 = 13;
+const CMD_SET_BUS_TYPE: u8 = 14;
+
+const BUS_SHUGART: u8 = 2;
+const FLUX_OP_INDEX: u8 = 1;
+const FLUX_OP_SPACE: u8 = 2;
+
+// Setting this baud rate makes the firmware drop any command in progress.
+const BAUD_CLEAR_COMMS: u32 = 10000;
+const BAUD_NORMAL: u32 = 9600;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const DD_SECTORS: usize = 11;
+// Bit cells of one revolution at 300 rpm for 2 µs (DD) cells.
+const DD_REVOLUTION_BYTES: usize = 100_000 / 8;
+
+#[derive(Debug, Clone)]
+pub struct FirmwareInfo {
+    pub major: u8,
+    pub minor: u8,
+    pub sample_freq: u32,
+    pub hw_model: u8,
+    pub hw_submodel: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    // Drive unit on the Shugart bus; Amiga drives are usually unit 0.
+    pub drive: u8,
+    pub high_density: bool,
+    pub revolutions: u16,
+    // Further reads of a track while sectors are missing.
+    pub retries: usize,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            drive: 0,
+            high_density: false,
+            revolutions: 2,
+            retries: 5,
+        }
+    }
+}
+
+fn ack_error(cmd: u8, ack: u8) -> Error {
+    let (kind, message) = match ack {
+        1 => (ErrorKind::Unsupported, "bad command"),
+        2 => (ErrorKind::NotFound, "no index pulse, is a disk inserted?"),
+        3 => (ErrorKind::NotFound, "track 0 not found"),
+        4 => (ErrorKind::Other, "flux overflow"),
+        5 => (ErrorKind::Other, "flux underflow"),
+        6 => (ErrorKind::PermissionDenied, "disk is write protected"),
+        7 => (ErrorKind::NotFound, "no drive unit selected"),
+        8 => (ErrorKind::NotFound, "no bus type set"),
+        9 => (ErrorKind::InvalidInput, "bad drive unit"),
+        11 => (ErrorKind::InvalidInput, "bad cylinder"),
+        _ => (ErrorKind::Other, "unknown error"),
+    };
+    Error::new(
+        kind,
+        format!("Greaseweazle command {} failed: {}", cmd, message),
+    )
+}
+
+fn read_28bit(stream: &[u8]) -> u32 {
+    stream.iter().enumerate().fold(0, |value, (i, &byte)| {
+        value | ((byte as u32) >> 1) << (7 * i)
+    })
+}
+
+fn write_28bit(value: u32) -> [u8; 4] {
+    [0, 7, 14, 21].map(|shift| 1 | ((value >> shift) << 1) as u8)
+}
+
+// Flux intervals in sample ticks from a read stream, without its terminating zero. Index
+// pulses are dropped since Amiga tracks are not aligned to the index.
+pub fn decode_flux_stream(stream: &[u8]) -> Result<Vec<u32>> {
+    let truncated = || Error::new(ErrorKind::UnexpectedEof, "Flux stream ends early");
+    let mut flux = Vec::with_capacity(stream.len());
+    let mut ticks = 0u32;
+    let mut pos = 0;
+    while pos < stream.len() {
+        let byte = stream[pos];
+        pos += 1;
+        match byte {
+            0 => break,
+            255 => {
+                let op = *stream.get(pos).ok_or_else(truncated)?;
+                let value = read_28bit(stream.get(pos + 1..pos + 5).ok_or_else(truncated)?);
+                pos += 5;
+                match op {
+                    FLUX_OP_INDEX => {}
+                    FLUX_OP_SPACE => ticks += value,
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Unknown flux opcode {}", op),
+                        ))
+                    }
+                }
+            }
+            1..=249 => {
+                flux.push(ticks + byte as u32);
+                ticks = 0;
+            }
+            _ => {
+                let low = *stream.get(pos).ok_or_else(truncated)?;
+                pos += 1;
+                flux.push(ticks + 250 + (byte as u32 - 250) * 255 + low as u32 - 1);
+                ticks = 0;
+            }
+        }
+    }
+    Ok(flux)
+}
+
+// The reverse of decode_flux_stream, terminated by a zero byte.
+pub fn encode_flux_stream(flux: &[u32]) -> Vec<u8> {
+    let mut stream = Vec::with_capacity(flux.len() + 1);
+    for &ticks in flux {
+        match ticks {
+            0 => {}
+            1..=249 => stream.push(ticks as u8),
+            _ if (ticks - 250) / 255 < 5 => {
+                stream.push(250 + ((ticks - 250) / 255) as u8);
+                stream.push(1 + ((ticks - 250) % 255) as u8);
+            }
+            _ => {
+                stream.extend_from_slice(&[255, FLUX_OP_SPACE]);
+                stream.extend_from_slice(&write_28bit(ticks - 249));
+                stream.push(249);
+            }
+        }
+    }
+    stream.push(0);
+    stream
+}
+
+pub struct Greaseweazle {
+    port: Box<dyn SerialPort>,
+    info: FirmwareInfo,
+}
+
+impl Greaseweazle {
+    pub fn open(path: &str) -> Result<Self> {
+        let mut port = serialport::new(path, BAUD_NORMAL).timeout(TIMEOUT).open()?;
+        port.set_baud_rate(BAUD_CLEAR_COMMS)?;
+        port.set_baud_rate(BAUD_NORMAL)?;
+        port.clear(ClearBuffer::All)?;
+        let mut gw = Greaseweazle {
+            port,
+            info: FirmwareInfo {
+                major: 0,
+                minor: 0,
+                sample_freq: 0,
+                hw_model: 0,
+                hw_submodel: 0,
+            },
+        };
+        gw.command(&[CMD_GET_INFO, 3, 0])?;
+        let mut info = [0u8; 32];
+        gw.port.read_exact(&mut info)?;
+        gw.info = FirmwareInfo {
+            major: info[0],
+            minor: info[1],
+            sample_freq: u32::from_le_bytes([info[4], info[5], info[6], info[7]]),
+            hw_model: info[8],
+            hw_submodel: info[9],
+        };
+        if gw.info.sample_freq == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} does not look like a Greaseweazle", path),
+            ));
+        }
+        debug!(
+            "Greaseweazle F{}.{} firmware {}.{}, {} Hz",
+            gw.info.hw_model,
+            gw.info.hw_submodel,
+            gw.info.major,
+            gw.info.minor,
+            gw.info.sample_freq
+        );
+        Ok(gw)
+    }
+
+    pub fn info(&self) -> &FirmwareInfo {
+        &self.info
+    }
+
+    // Commands are the command byte, the total length and the arguments; the firmware echoes
+    // the command byte followed by an acknowledgement code.
+    fn command(&mut self, command: &[u8]) -> Result<()> {
+        self.port.write_all(command)?;
+        let mut reply = [0u8; 2];
+        self.port.read_exact(&mut reply)?;
+        if reply[0] != command[0] {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unexpected reply to Greaseweazle command {}", command[0]),
+            ));
+        }
+        match reply[1] {
+            0 => Ok(()),
+            ack => Err(ack_error(command[0], ack)),
+        }
+    }
+
+    fn select(&mut self, drive: u8) -> Result<()> {
+        self.command(&[CMD_SET_BUS_TYPE, 3, BUS_SHUGART])?;
+        self.command(&[CMD_SELECT, 3, drive])?;
+        self.command(&[CMD_MOTOR, 4, drive, 1])
+    }
+
+    fn deselect(&mut self, drive: u8) -> Result<()> {
+        self.command(&[CMD_MOTOR, 4, drive, 0])?;
+        self.command(&[CMD_DESELECT, 2])
+    }
+
+    fn seek(&mut self, cylinder: usize, head: usize) -> Result<()> {
+        self.command(&[CMD_SEEK, 3, cylinder as u8])?;
+        self.command(&[CMD_HEAD, 3, head as u8])
+    }
+
+    fn read_flux(&mut self, revolutions: u16) -> Result<Vec<u32>> {
+        let mut command = vec![CMD_READ_FLUX, 8];
+        command.extend_from_slice(&0u32.to_le_bytes());
+        command.extend_from_slice(&(revolutions + 1).to_le_bytes());
+        self.command(&command)?;
+        let mut stream = Vec::new();
+        let mut buffer = [0u8; 4096];
+        // The stream never contains a zero byte except for its terminator.
+        while stream.last() != Some(&0) {
+            let n = self.port.read(&mut buffer)?;
+            stream.extend_from_slice(&buffer[..n]);
+        }
+        self.command(&[CMD_GET_FLUX_STATUS, 2])?;
+        decode_flux_stream(&stream)
+    }
+
+    fn write_flux(&mut self, flux: &[u32]) -> Result<()> {
+        // Start at the index pulse and stop at the next one.
+        self.command(&[CMD_WRITE_FLUX, 4, 1, 1])?;
+        self.port.write_all(&encode_flux_stream(flux))?;
+        let mut sync = [0u8; 1];
+        self.port.read_exact(&mut sync)?;
+        self.command(&[CMD_GET_FLUX_STATUS, 2])
+    }
+
+    fn cell_ticks(&self, high_density: bool) -> f64 {
+        let cell = if high_density { 1e-6 } else { 2e-6 };
+        self.info.sample_freq as f64 * cell
+    }
+
+    pub fn read_adf(&mut self, options: &ReadOptions) -> Result<ADF> {
+        self.read_adf_with_progress(options, &mut |_, _| {})
+    }
+
+    // Sectors that cannot be read after all retries fail the whole image.
+    pub fn read_adf_with_progress(
+        &mut self,
+        options: &ReadOptions,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<ADF> {
+        let sectors = if options.high_density {
+            2 * DD_SECTORS
+        } else {
+            DD_SECTORS
+        };
+        let cell_ticks = self.cell_ticks(options.high_density);
+        self.select(options.drive)?;
+        let mut image = Vec::with_capacity(ADF_NUM_TRACKS * sectors * ADF_SECTOR_SIZE);
+        let result = (|| {
+            for track in 0..ADF_NUM_TRACKS {
+                self.seek(track / 2, track % 2)?;
+                let mut found = vec![None; sectors];
+                for attempt in 0..=options.retries {
+                    let mfm = flux_to_mfm(&self.read_flux(options.revolutions)?, cell_ticks);
+                    for (slot, sector) in found.iter_mut().zip(decode_sectors(&mfm, track, sectors))
+                    {
+                        if slot.is_none() {
+                            *slot = sector;
+                        }
+                    }
+                    if found.iter().all(Option::is_some) {
+                        break;
+                    }
+                    if attempt < options.retries {
+                        warn!("Track {}: retrying missing sectors", track);
+                    }
+                }
+                let missing: Vec<String> = (0..sectors)
+                    .filter(|&sector| found[sector].is_none())
+                    .map(|sector| sector.to_string())
+                    .collect();
+                if !missing.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Cylinder {} head {}: sectors {} unreadable",
+                            track / 2,
+                            track % 2,
+                            missing.join(", ")
+                        ),
+                    ));
+                }
+                image.extend(found.into_iter().flatten().flatten());
+                progress(track + 1, ADF_NUM_TRACKS);
+            }
+            Ok(())
+        })();
+        self.deselect(options.drive)?;
+        result?;
+        ADF::from_bytes(&image)
+    }
+
+    pub fn write_adf(&mut self, adf: &ADF, drive: u8) -> Result<()> {
+        self.write_adf_with_progress(adf, drive, &mut |_, _| {})
+    }
+
+    pub fn write_adf_with_progress(
+        &mut self,
+        adf: &ADF,
+        drive: u8,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        let track_size = adf.data.len() / ADF_NUM_TRACKS;
+        let sectors = track_size / ADF_SECTOR_SIZE;
+        if sectors != DD_SECTORS && sectors != 2 * DD_SECTORS
+            || adf.data.len() != ADF_NUM_TRACKS * track_size
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Only DD and HD floppy images can be written",
+            ));
+        }
+        let high_density = sectors != DD_SECTORS;
+        let cell_ticks = self.cell_ticks(high_density);
+        // A little more than one revolution, so the gap reaches the index even on slow drives.
+        let track_len = DD_REVOLUTION_BYTES * sectors / DD_SECTORS * 21 / 20;
+        self.select(drive)?;
+        let result = (|| {
+            for (track, data) in adf.data.chunks_exact(track_size).enumerate() {
+                self.seek(track / 2, track % 2)?;
+                let mut mfm = encode_track(track, data);
+                fill_gap(&mut mfm, track_len);
+                self.write_flux(&mfm_to_flux(&mfm, cell_ticks))?;
+                progress(track + 1, ADF_NUM_TRACKS);
+            }
+            Ok(())
+        })();
+        self.deselect(drive)?;
+        result
+    }
+}
+
+impl ADF {
+    // Reads the disk in drive 0 of the Greaseweazle at port in one step.
+    pub fn from_greaseweazle(port: &str) -> Result<ADF> {
+        Greaseweazle::open(port)?.read_adf(&ReadOptions::default())
+    }
+}
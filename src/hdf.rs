@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Hardfiles as WinUAE writes them: a single volume whose filesystem may use blocks of 512 to
+// 4096 bytes. The block size is found from the root block, which sits in the middle of the
+// volume whatever the geometry. Blocks are read from the file when they are needed, so
+// directories and files of a hardfile of any size can be read without loading it. Writing
+// goes through ADF, which holds the volume in memory: only hardfiles with 512 byte blocks
+// and at most MAX_VOLUME_BLOCKS blocks convert into one.
+
+use crate::disk::ADF;
+use crate::layout::{Layout, MAX_VOLUME_BLOCKS};
+use crate::raw::{self, BlockSource, Entry, RawError, VolumeReader};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::Path;
+
+// Reads the blocks of a hardfile through a seekable reader.
+#[derive(Debug)]
+pub struct Sectors<R> {
+    reader: RefCell<R>,
+    size: u64,
+}
+
+impl<R: Read + Seek> Sectors<R> {
+    fn new(mut reader: R) -> Result<Sectors<R>> {
+        let size = reader.seek(SeekFrom::End(0))?;
+        Ok(Sectors {
+            reader: RefCell::new(reader),
+            size,
+        })
+    }
+}
+
+impl<R: Read + Seek> BlockSource for Sectors<R> {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_block(&self, block: usize, block_size: usize) -> raw::Result<Cow<'_, [u8]>> {
+        let mut data = vec![0; block_size];
+        let mut reader = self.reader.borrow_mut();
+        reader
+            .seek(SeekFrom::Start(block as u64 * block_size as u64))
+            .and_then(|_| reader.read_exact(&mut data))
+            .map_err(|_| RawError::ReadFailed(block))?;
+        Ok(Cow::Owned(data))
+    }
+}
+
+#[derive(Debug)]
+pub struct Hdf<R> {
+    volume: VolumeReader<Sectors<R>>,
+}
+
+impl Hdf<File> {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Hdf<File>> {
+        Hdf::open(File::open(path)?)
+    }
+}
+
+impl Hdf<Cursor<Vec<u8>>> {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Hdf<Cursor<Vec<u8>>>> {
+        Hdf::open(Cursor::new(data))
+    }
+}
+
+impl<R: Read + Seek> Hdf<R> {
+    pub fn open(reader: R) -> Result<Hdf<R>> {
+        let sectors = Sectors::new(reader)?;
+        let size = sectors.size;
+        let volume = VolumeReader::detect(sectors).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "No root block found in {} bytes for any block size from 512 to 4096",
+                    size
+                ),
+            )
+        })?;
+        Ok(Hdf { volume })
+    }
+
+    // For volumes whose root block does not validate, so detection cannot work.
+    pub fn with_block_size(reader: R, block_size: usize) -> Result<Hdf<R>> {
+        let volume = VolumeReader::with_block_size(Sectors::new(reader)?, block_size)?;
+        Ok(Hdf { volume })
+    }
+
+    pub fn volume(&self) -> &VolumeReader<Sectors<R>> {
+        &self.volume
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.volume.block_size()
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.volume.layout()
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.volume.num_blocks()
+    }
+
+    pub fn root_block(&self) -> usize {
+        self.volume.root_block()
+    }
+
+    pub fn is_ffs(&self) -> bool {
+        self.volume.is_ffs()
+    }
+
+    pub fn label(&self) -> Result<String> {
+        Ok(self.volume.entry(self.root_block())?.name)
+    }
+
+    pub fn list_directory(&self, path: &str) -> Result<Vec<Entry>> {
+        Ok(self.volume.entries(self.volume.find(path)?)?)
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        Ok(self.volume.read_file(self.volume.find(path)?)?)
+    }
+
+    pub fn free_blocks(&self) -> Result<usize> {
+        Ok(self.volume.free_blocks()?)
+    }
+
+    // The volume loaded into an ADF, which needs 512 byte blocks and a volume small enough
+    // to hold in memory.
+    pub fn into_adf(self) -> Result<ADF> {
+        if self.block_size() != Layout::FLOPPY.block_size {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Hardfile uses {} byte blocks, only 512 byte blocks can be written",
+                    self.block_size()
+                ),
+            ));
+        }
+        if self.num_blocks() > MAX_VOLUME_BLOCKS {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Hardfile has {} blocks, at most {} can be loaded for writing",
+                    self.num_blocks(),
+                    MAX_VOLUME_BLOCKS
+                ),
+            ));
+        }
+        let mut reader = self.volume.source().reader.borrow_mut();
+        let mut data = Vec::new();
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_to_end(&mut data)?;
+        ADF::from_bytes(&data)
+    }
+}
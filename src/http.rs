@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Loading images straight from web-hosted collections. The blocking functions must not be
+// called from an async runtime; the async ones need a Tokio runtime, like reqwest itself.
+
+use crate::disk::{ADF, ADF_SECTOR_SIZE, MAX_VOLUME_BLOCKS};
+use log::debug;
+use std::io::{Error, ErrorKind, Read, Result};
+
+// Nothing larger than the largest volume can hold an image, so longer bodies are refused
+// instead of being buffered.
+const MAX_DOWNLOAD: usize = MAX_VOLUME_BLOCKS * ADF_SECTOR_SIZE;
+
+fn too_large(url: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("{} is larger than {} bytes", url, MAX_DOWNLOAD),
+    )
+}
+
+fn check_length(url: &str, content_length: Option<u64>) -> Result<()> {
+    match content_length {
+        Some(len) if len > MAX_DOWNLOAD as u64 => Err(too_large(url)),
+        _ => Ok(()),
+    }
+}
+
+pub fn fetch(url: &str) -> Result<Vec<u8>> {
+    debug!("Downloading {}", url);
+    let response = reqwest::blocking::get(url)
+        .and_then(|response| response.error_for_status())
+        .map_err(Error::other)?;
+    check_length(url, response.content_length())?;
+    let mut data = Vec::new();
+    response
+        .take(MAX_DOWNLOAD as u64 + 1)
+        .read_to_end(&mut data)?;
+    if data.len() > MAX_DOWNLOAD {
+        return Err(too_large(url));
+    }
+    Ok(data)
+}
+
+pub async fn fetch_async(url: &str) -> Result<Vec<u8>> {
+    debug!("Downloading {}", url);
+    let mut response = reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(Error::other)?;
+    check_length(url, response.content_length())?;
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(Error::other)? {
+        if data.len() + chunk.len() > MAX_DOWNLOAD {
+            return Err(too_large(url));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+impl ADF {
+    // The body must be a plain image; see open_any_url for archives.
+    pub fn from_url(url: &str) -> Result<ADF> {
+        ADF::from_bytes(&fetch(url)?)
+    }
+
+    pub fn open_any_url(url: &str) -> Result<ADF> {
+        ADF::open_any(&fetch(url)?)
+    }
+
+    pub async fn from_url_async(url: &str) -> Result<ADF> {
+        ADF::from_bytes(&fetch_async(url).await?)
+    }
+
+    pub async fn open_any_url_async(url: &str) -> Result<ADF> {
+        ADF::open_any(&fetch_async(url).await?)
+    }
+}
@@ -3,9 +3,11 @@
 // Copyright (c) 2023
 // - Volker Schwaberow <volker@schwaberow.de>
 
+use crate::sansio::{decode_reader, decode_slice, parse, Decoded, Decoder, Input, Parsed, Stop};
+use log::warn;
 use std::fmt;
 use std::fs::File;
-use std::io::{self, BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::io::{self, BufReader, Error, ErrorKind, Read, Seek, Write};
 use std::path::Path;
 
 const HUNK_HEADER: u32 = 1011;
@@ -67,6 +69,8 @@ pub struct Hunk {
     pub alloc_size: usize,
     pub data_size: usize,
     pub code_data: Option<Vec<u8>>,
+    // Where code_data starts in the file.
+    pub data_offset: Option<usize>,
     pub reloc_32: Option<Vec<RelocInfo32>>,
     pub symbols: Option<Vec<Symbol>>,
     pub line_debug_info: Option<Vec<SourceFile>>,
@@ -80,6 +84,7 @@ impl Default for Hunk {
             alloc_size: 0,
             data_size: 0,
             code_data: None,
+            data_offset: None,
             reloc_32: None,
             symbols: None,
             line_debug_info: None,
@@ -97,182 +102,178 @@ impl fmt::Display for Hunk {
     }
 }
 
-pub struct HunkParser;
-
-impl HunkParser {
-    pub fn parse_file<P: AsRef<Path>>(filename: P) -> io::Result<Vec<Hunk>> {
-        let file = File::open(filename)?;
-        let mut reader = BufReader::new(file);
-        Self::parse_hunks(&mut reader)
+impl Hunk {
+    // Writes the contents of the segment: the bytes of a code or data hunk, or as many
+    // zeroes as a BSS hunk allocates. Returns the number of bytes written.
+    pub fn write_payload<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<usize> {
+        match (&self.hunk_type, &self.code_data) {
+            (HunkType::Bss, _) => {
+                io::copy(&mut io::repeat(0).take(self.data_size as u64), writer)?;
+                Ok(self.data_size)
+            }
+            (_, Some(data)) => {
+                writer.write_all(data)?;
+                Ok(data.len())
+            }
+            (_, None) => Ok(0),
+        }
     }
+}
 
-    pub fn parse_hunks<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<Hunk>> {
-        Self::validate_hunk_header(reader)?;
-        let (hunk_count, _hunk_sizes) = Self::read_hunk_table(reader)?;
-        let mut hunks = Vec::with_capacity(hunk_count);
+// One block of a hunk as stored in the file; a hunk ends with HUNK_END.
+enum Block {
+    Code(HunkType, MemoryType, Vec<u8>),
+    Bss(MemoryType, usize),
+    Reloc32(Vec<RelocInfo32>),
+    Symbols(Vec<Symbol>),
+    LineDebug(SourceFile),
+    Skipped,
+    End,
+}
 
-        for _ in 0..hunk_count {
-            hunks.push(Self::parse_hunk(reader)?);
-        }
+enum HunkState {
+    Header,
+    Hunks(usize),
+    Done,
+}
 
-        Ok(hunks)
+// Decodes a load file one block at a time, yielding each hunk when its HUNK_END is read.
+pub struct HunkDecoder {
+    state: HunkState,
+    hunk: Hunk,
+    // Offset of the input in the file.
+    pos: usize,
+}
+
+impl Default for HunkDecoder {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn validate_hunk_header<R: Read>(reader: &mut R) -> io::Result<()> {
-        let hunk_header = Self::read_u32(reader)?;
-        if hunk_header != HUNK_HEADER {
-            return Err(Error::new(ErrorKind::InvalidData, "Invalid HUNK_HEADER"));
+impl HunkDecoder {
+    pub fn new() -> Self {
+        HunkDecoder {
+            state: HunkState::Header,
+            hunk: Hunk::default(),
+            pos: 0,
         }
-        Self::read_u32(reader)?; // Skip header/string section
-        Ok(())
     }
 
-    fn read_hunk_table<R: Read>(reader: &mut R) -> io::Result<(usize, Vec<u32>)> {
-        let table_size = Self::read_u32(reader)?;
-        let first_hunk = Self::read_u32(reader)?;
-        let last_hunk = Self::read_u32(reader)?;
-
+    fn read_header(input: &mut Input<'_>) -> std::result::Result<usize, Stop> {
+        if input.u32()? != HUNK_HEADER {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid HUNK_HEADER").into());
+        }
+        input.u32()?; // Skip header/string section
+        let _table_size = input.u32()?;
+        let first_hunk = input.u32()?;
+        let last_hunk = input.u32()?;
         if last_hunk < first_hunk {
             return Err(Error::new(
                 ErrorKind::InvalidData,
                 "Last hunk index is less than first hunk index",
-            ));
+            )
+            .into());
         }
-
-        let hunk_count = (last_hunk - first_hunk + 1) as usize;
-
-        let hunk_sizes = (0..hunk_count)
-            .map(|_| Self::read_u32(reader))
-            .collect::<io::Result<Vec<_>>>()?;
-
-        Ok((hunk_count, hunk_sizes))
+        let hunk_count = (last_hunk - first_hunk) as usize + 1;
+        input.take(hunk_count.saturating_mul(4))?; // Hunk sizes
+        Ok(hunk_count)
     }
 
-    fn parse_hunk<R: Read + Seek>(reader: &mut R) -> io::Result<Hunk> {
-        let mut hunk = Hunk::default();
-
-        loop {
-            let hunk_type = Self::read_u32(reader)?;
-            match hunk_type {
-                HUNK_CODE => Self::parse_code_or_data(HunkType::Code, &mut hunk, reader)?,
-                HUNK_DATA => Self::parse_code_or_data(HunkType::Data, &mut hunk, reader)?,
-                HUNK_BSS => Self::parse_bss(&mut hunk, reader)?,
-                HUNK_RELOC32 => Self::parse_reloc32(&mut hunk, reader)?,
-                HUNK_SYMBOL => Self::parse_symbols(&mut hunk, reader)?,
-                HUNK_DEBUG => Self::parse_debug(&mut hunk, reader)?,
-                HUNK_END => return Ok(hunk),
-                _ => Self::skip_hunk(reader, hunk_type)?,
+    fn read_block(input: &mut Input<'_>) -> std::result::Result<Block, Stop> {
+        let hunk_type = input.u32()?;
+        Ok(match hunk_type {
+            HUNK_CODE | HUNK_DATA => {
+                let (size, mem_type) = Self::get_size_type(input.u32()?);
+                let kind = if hunk_type == HUNK_CODE {
+                    HunkType::Code
+                } else {
+                    HunkType::Data
+                };
+                Block::Code(kind, mem_type, input.take(size)?.to_vec())
             }
-        }
-    }
-
-    fn parse_code_or_data<R: Read>(
-        hunk_type: HunkType,
-        hunk: &mut Hunk,
-        reader: &mut R,
-    ) -> io::Result<()> {
-        let (size, mem_type) = Self::get_size_type(Self::read_u32(reader)?);
-        let mut code_data = vec![0; size];
-        reader.read_exact(&mut code_data)?;
-
-        hunk.hunk_type = hunk_type;
-        hunk.mem_type = mem_type;
-        hunk.data_size = size;
-        hunk.code_data = Some(code_data);
-        Ok(())
-    }
-
-    fn parse_bss<R: Read>(hunk: &mut Hunk, reader: &mut R) -> io::Result<()> {
-        let (size, mem_type) = Self::get_size_type(Self::read_u32(reader)?);
-        hunk.hunk_type = HunkType::Bss;
-        hunk.mem_type = mem_type;
-        hunk.data_size = size;
-        Ok(())
+            HUNK_BSS => {
+                let (size, mem_type) = Self::get_size_type(input.u32()?);
+                Block::Bss(mem_type, size)
+            }
+            HUNK_RELOC32 => Block::Reloc32(Self::read_reloc32(input)?),
+            HUNK_SYMBOL => Block::Symbols(Self::read_symbols(input)?),
+            HUNK_DEBUG => Self::read_debug(input)?,
+            HUNK_END => Block::End,
+            _ => {
+                warn!("Skipping unknown hunk type: {:#x}", hunk_type);
+                let num_longs = input.u32()? as usize;
+                input.take(num_longs.saturating_mul(4))?;
+                Block::Skipped
+            }
+        })
     }
 
-    fn parse_reloc32<R: Read>(hunk: &mut Hunk, reader: &mut R) -> io::Result<()> {
+    fn read_reloc32(input: &mut Input<'_>) -> std::result::Result<Vec<RelocInfo32>, Stop> {
         let mut relocs = Vec::new();
         loop {
-            let count = Self::read_u32(reader)? as usize;
+            let count = input.u32()? as usize;
             if count == 0 {
                 break;
             }
-            let target = Self::read_u32(reader)? as usize;
+            let target = input.u32()? as usize;
             let offsets = (0..count)
-                .map(|_| Self::read_u32(reader))
-                .collect::<io::Result<Vec<_>>>()?;
+                .map(|_| input.u32())
+                .collect::<std::result::Result<Vec<_>, _>>()?;
             relocs.push(RelocInfo32 { target, offsets });
         }
-        hunk.reloc_32 = Some(relocs);
-        Ok(())
+        Ok(relocs)
     }
 
-    fn parse_symbols<R: Read>(hunk: &mut Hunk, reader: &mut R) -> io::Result<()> {
+    fn read_symbols(input: &mut Input<'_>) -> std::result::Result<Vec<Symbol>, Stop> {
         let mut symbols = Vec::new();
         loop {
-            let num_longs = Self::read_u32(reader)?;
+            let num_longs = input.u32()?;
             if num_longs == 0 {
                 break;
             }
-            let name = Self::read_name(reader, num_longs)?;
-            let offset = Self::read_u32(reader)?;
+            let name = Self::read_name(input, num_longs)?;
+            let offset = input.u32()?;
             symbols.push(Symbol { name, offset });
         }
-        if !symbols.is_empty() {
-            symbols.sort_by_key(|s| s.offset);
-            hunk.symbols = Some(symbols);
-        }
-        Ok(())
+        symbols.sort_by_key(|s| s.offset);
+        Ok(symbols)
     }
 
-    fn parse_debug<R: Read + Seek>(hunk: &mut Hunk, reader: &mut R) -> io::Result<()> {
-        let num_longs = Self::read_u32(reader)? - 2;
-        let base_offset = Self::read_u32(reader)?;
-        let debug_tag = Self::read_u32(reader)?;
+    fn read_debug(input: &mut Input<'_>) -> std::result::Result<Block, Stop> {
+        let num_longs = input.u32()?.checked_sub(2).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "HUNK_DEBUG is shorter than its header",
+            )
+        })?;
+        let base_offset = input.u32()?;
+        let debug_tag = input.u32()?;
 
         if debug_tag != DEBUG_LINE {
-            reader.seek(SeekFrom::Current((num_longs * 4) as i64))?;
-            return Ok(());
+            input.take((num_longs as usize).saturating_mul(4))?;
+            return Ok(Block::Skipped);
         }
 
-        let source_file = Self::fill_debug_info(base_offset, num_longs, reader)?;
-        hunk.line_debug_info
-            .get_or_insert_with(Vec::new)
-            .push(source_file);
-        Ok(())
-    }
-
-    fn fill_debug_info<R: Read>(
-        base_offset: u32,
-        num_longs: u32,
-        reader: &mut R,
-    ) -> io::Result<SourceFile> {
-        let num_name_longs = Self::read_u32(reader)?;
-        let name = Self::read_name(reader, num_name_longs)?;
-        let num_lines = (num_longs - num_name_longs - 1) / 2;
+        let num_name_longs = input.u32()?;
+        let name = Self::read_name(input, num_name_longs)?;
+        let num_lines = num_longs.saturating_sub(num_name_longs + 1) / 2;
         let lines = (0..num_lines)
             .map(|_| {
-                let line_no = Self::read_u32(reader)? & 0xffffff;
-                let offset = Self::read_u32(reader)?;
+                let line_no = input.u32()? & 0xffffff;
+                let offset = input.u32()?;
                 Ok(SourceLine {
                     line: line_no,
-                    offset: base_offset + offset,
+                    offset: base_offset.wrapping_add(offset),
                 })
             })
-            .collect::<io::Result<Vec<_>>>()?;
+            .collect::<std::result::Result<Vec<_>, Stop>>()?;
 
-        Ok(SourceFile {
+        Ok(Block::LineDebug(SourceFile {
             name,
             base_offset,
             lines,
-        })
-    }
-
-    fn skip_hunk<R: Read + Seek>(reader: &mut R, hunk_type: u32) -> io::Result<()> {
-        println!("Skipping unknown hunk type: {:#x}", hunk_type);
-        let seek_offset = Self::read_u32(reader)? as i64;
-        reader.seek(SeekFrom::Current(seek_offset * 4))?;
-        Ok(())
+        }))
     }
 
     fn get_size_type(t: u32) -> (usize, MemoryType) {
@@ -285,17 +286,167 @@ impl HunkParser {
         (size as usize, mem_type)
     }
 
-    fn read_name<R: Read>(reader: &mut R, num_longs: u32) -> io::Result<String> {
-        let len = num_longs as usize * 4;
-        let mut buffer = vec![0u8; len];
-        reader.read_exact(&mut buffer)?;
+    fn read_name(input: &mut Input<'_>, num_longs: u32) -> std::result::Result<String, Stop> {
+        let buffer = input.take((num_longs as usize).saturating_mul(4))?;
         let end = buffer.iter().position(|&x| x == 0).unwrap_or(buffer.len());
         Ok(String::from_utf8_lossy(&buffer[..end]).into_owned())
     }
 
-    fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
-        let mut buffer = [0u8; 4];
-        reader.read_exact(&mut buffer)?;
-        Ok(u32::from_be_bytes(buffer))
+    // Adds a block to the current hunk, returning the hunk once it is complete.
+    fn apply(&mut self, block: Block) -> Option<Hunk> {
+        let hunk = &mut self.hunk;
+        match block {
+            Block::Code(hunk_type, mem_type, code_data) => {
+                hunk.hunk_type = hunk_type;
+                hunk.mem_type = mem_type;
+                hunk.data_size = code_data.len();
+                hunk.code_data = Some(code_data);
+            }
+            Block::Bss(mem_type, size) => {
+                hunk.hunk_type = HunkType::Bss;
+                hunk.mem_type = mem_type;
+                hunk.data_size = size;
+            }
+            Block::Reloc32(relocs) => hunk.reloc_32 = Some(relocs),
+            Block::Symbols(symbols) if !symbols.is_empty() => hunk.symbols = Some(symbols),
+            Block::LineDebug(source_file) => hunk
+                .line_debug_info
+                .get_or_insert_with(Vec::new)
+                .push(source_file),
+            Block::Symbols(_) | Block::Skipped => {}
+            Block::End => return Some(std::mem::take(hunk)),
+        }
+        None
+    }
+}
+
+impl Decoder for HunkDecoder {
+    type Item = Hunk;
+
+    fn decode(&mut self, input: &[u8], _eof: bool) -> io::Result<Decoded<Hunk>> {
+        match self.state {
+            HunkState::Done => Ok(Decoded::Done),
+            HunkState::Header => Ok(match parse(input, Self::read_header)? {
+                Parsed::Incomplete(needed) => Decoded::Need(needed),
+                Parsed::Complete(used, hunk_count) => {
+                    self.state = HunkState::Hunks(hunk_count);
+                    self.pos += used;
+                    Decoded::Consumed(used, None)
+                }
+            }),
+            HunkState::Hunks(remaining) => Ok(match parse(input, Self::read_block)? {
+                Parsed::Incomplete(needed) => Decoded::Need(needed),
+                Parsed::Complete(used, block) => {
+                    if let Block::Code(..) = block {
+                        // After the block type and size longs.
+                        self.hunk.data_offset = Some(self.pos + 8);
+                    }
+                    self.pos += used;
+                    let hunk = self.apply(block);
+                    if hunk.is_some() {
+                        self.state = match remaining {
+                            1 => HunkState::Done,
+                            _ => HunkState::Hunks(remaining - 1),
+                        };
+                    }
+                    Decoded::Consumed(used, hunk)
+                }
+            }),
+        }
+    }
+}
+
+pub struct HunkParser;
+
+impl HunkParser {
+    pub fn parse_file<P: AsRef<Path>>(filename: P) -> io::Result<Vec<Hunk>> {
+        let file = File::open(filename)?;
+        let mut reader = BufReader::new(file);
+        Self::parse_hunks(&mut reader)
+    }
+
+    pub fn parse_hunks<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<Hunk>> {
+        decode_reader(HunkDecoder::new(), reader).collect()
+    }
+
+    pub fn parse_bytes(data: &[u8]) -> io::Result<Vec<Hunk>> {
+        decode_slice(HunkDecoder::new(), data).collect()
+    }
+}
+
+// An executable kept as loaded, for changing code and data in place. Every other block,
+// known to the parser or not, stays exactly as it was.
+#[derive(Debug, Clone)]
+pub struct HunkFile {
+    data: Vec<u8>,
+    hunks: Vec<Hunk>,
+}
+
+impl HunkFile {
+    pub fn parse(data: Vec<u8>) -> io::Result<HunkFile> {
+        let hunks = HunkParser::parse_bytes(&data)?;
+        Ok(HunkFile { data, hunks })
+    }
+
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    // Overwrites bytes at offset within the contents of a code or data hunk. A patch that
+    // touches a longword the loader relocates is refused, as the loader would add the hunk
+    // address to whatever was written there.
+    pub fn patch(&mut self, hunk_index: usize, offset: usize, bytes: &[u8]) -> io::Result<()> {
+        let count = self.hunks.len();
+        let hunk = self.hunks.get_mut(hunk_index).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("No hunk {}, the file has {}", hunk_index, count),
+            )
+        })?;
+        let (Some(code), Some(data_offset)) = (hunk.code_data.as_mut(), hunk.data_offset) else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Hunk {} has no contents to patch", hunk_index),
+            ));
+        };
+        let end = offset.saturating_add(bytes.len());
+        if end > code.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Patch of {} bytes at offset {} ends past the {} bytes of hunk {}",
+                    bytes.len(),
+                    offset,
+                    code.len(),
+                    hunk_index
+                ),
+            ));
+        }
+        let relocated = hunk
+            .reloc_32
+            .iter()
+            .flatten()
+            .flat_map(|reloc| reloc.offsets.iter().map(|&at| at as usize))
+            .find(|&at| at < end && offset < at + 4);
+        if let Some(at) = relocated {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Patch at offset {} overlaps the relocated longword at offset {} of hunk {}",
+                    offset, at, hunk_index
+                ),
+            ));
+        }
+        code[offset..end].copy_from_slice(bytes);
+        self.data[data_offset + offset..data_offset + end].copy_from_slice(bytes);
+        Ok(())
     }
 }
@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Workbench .info files: a DiskObject with its embedded Gadget, followed by the optional
+// parts the DiskObject points to, in a fixed order. Pointers only say whether a part is
+// present; their values are addresses from the machine that saved the icon.
+
+use crate::disk::{ExtractedFile, ADF};
+use crate::render::BitmapImage;
+use serde::Serialize;
+use std::io::{Error, ErrorKind, Result};
+
+pub const ICON_MAGIC: u16 = 0xe310;
+const DISK_OBJECT_SIZE: usize = 78;
+const DRAWER_DATA_SIZE: usize = 56;
+const DRAWER_DATA2_SIZE: usize = 6;
+const IMAGE_SIZE: usize = 20;
+
+// Workbench 2.x colours, extended with the MagicWB ones for eight colour icons.
+const PALETTE: [[u8; 3]; 8] = [
+    [0x95, 0x95, 0x95],
+    [0x00, 0x00, 0x00],
+    [0xff, 0xff, 0xff],
+    [0x3b, 0x67, 0xa2],
+    [0x7b, 0x7b, 0x7b],
+    [0xaf, 0xaf, 0xaf],
+    [0xaa, 0x90, 0x7c],
+    [0xff, 0xa9, 0x97],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum IconType {
+    Disk,
+    Drawer,
+    Tool,
+    Project,
+    Garbage,
+    Device,
+    Kick,
+    AppIcon,
+    Unknown(u8),
+}
+
+impl IconType {
+    fn from_raw(raw: u8) -> Self {
+        match raw {
+            1 => IconType::Disk,
+            2 => IconType::Drawer,
+            3 => IconType::Tool,
+            4 => IconType::Project,
+            5 => IconType::Garbage,
+            6 => IconType::Device,
+            7 => IconType::Kick,
+            8 => IconType::AppIcon,
+            other => IconType::Unknown(other),
+        }
+    }
+}
+
+// An Intuition Image: depth bitplanes of height rows, each row padded to 16 bits.
+#[derive(Debug, Clone, Serialize)]
+pub struct IconImage {
+    pub left: i16,
+    pub top: i16,
+    pub width: u16,
+    pub height: u16,
+    pub depth: u16,
+    #[serde(skip)]
+    pub planes: Vec<u8>,
+}
+
+impl IconImage {
+    fn row_bytes(&self) -> usize {
+        (self.width as usize).div_ceil(16) * 2
+    }
+
+    fn plane_size(&self) -> usize {
+        self.row_bytes() * self.height as usize
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        let offset = y * self.row_bytes() + x / 8;
+        (0..self.depth as usize).fold(0, |color, plane| {
+            let byte = self.planes[plane * self.plane_size() + offset];
+            color | ((byte >> (7 - x % 8)) & 1) << plane
+        })
+    }
+
+    // Colours beyond the palette wrap around; icons rarely use more than eight.
+    pub fn to_bitmap(&self) -> BitmapImage {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.extend_from_slice(&PALETTE[self.pixel(x, y) as usize % PALETTE.len()]);
+            }
+        }
+        BitmapImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskObject {
+    pub version: u16,
+    pub icon_type: IconType,
+    pub width: u16,
+    pub height: u16,
+    pub default_tool: Option<String>,
+    pub tool_types: Vec<String>,
+    // Position in the drawer window, or None when Workbench may place the icon freely.
+    pub current_x: Option<i32>,
+    pub current_y: Option<i32>,
+    pub stack_size: u32,
+    pub image: Option<IconImage>,
+    pub selected_image: Option<IconImage>,
+}
+
+// NO_ICON_POSITION in workbench/workbench.h.
+const NO_POSITION: i32 = i32::MIN;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Icon ends early"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn image(&mut self) -> Result<IconImage> {
+        let header = self.take(IMAGE_SIZE)?;
+        let word = |offset: usize| u16::from_be_bytes([header[offset], header[offset + 1]]);
+        let mut image = IconImage {
+            left: word(0) as i16,
+            top: word(2) as i16,
+            width: word(4),
+            height: word(6),
+            depth: word(8),
+            planes: Vec::new(),
+        };
+        if image.depth > 8 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Icon image has {} bitplanes", image.depth),
+            ));
+        }
+        // The planes are only stored when ImageData was set.
+        if u32::from_be_bytes([header[10], header[11], header[12], header[13]]) != 0 {
+            image.planes = self
+                .take(image.plane_size() * image.depth as usize)?
+                .to_vec();
+        } else {
+            image.planes = vec![0; image.plane_size() * image.depth as usize];
+        }
+        Ok(image)
+    }
+
+    // Strings are stored with their length, which includes the terminating NUL.
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        let text = bytes.split(|&b| b == 0).next().unwrap_or_default();
+        Ok(text.iter().map(|&b| b as char).collect())
+    }
+}
+
+impl DiskObject {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader { data, pos: 0 };
+        let header = reader.take(DISK_OBJECT_SIZE)?;
+        let word = |offset: usize| u16::from_be_bytes([header[offset], header[offset + 1]]);
+        let long = |offset: usize| {
+            u32::from_be_bytes([
+                header[offset],
+                header[offset + 1],
+                header[offset + 2],
+                header[offset + 3],
+            ])
+        };
+        if word(0) != ICON_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a Workbench icon"));
+        }
+        let position = |value: u32| Some(value as i32).filter(|&v| v != NO_POSITION);
+        // The low byte of the gadget's UserData is the icon revision; OS 2.x icons set it.
+        let revision = header[47];
+        let has_drawer_data = long(66) != 0;
+        let mut icon = DiskObject {
+            version: word(2),
+            icon_type: IconType::from_raw(header[48]),
+            width: word(12),
+            height: word(14),
+            default_tool: None,
+            tool_types: Vec::new(),
+            current_x: position(long(58)),
+            current_y: position(long(62)),
+            stack_size: long(74),
+            image: None,
+            selected_image: None,
+        };
+
+        if has_drawer_data {
+            reader.take(DRAWER_DATA_SIZE)?;
+        }
+        if long(22) != 0 {
+            icon.image = Some(reader.image()?);
+        }
+        if long(26) != 0 {
+            icon.selected_image = Some(reader.image()?);
+        }
+        if long(50) != 0 {
+            icon.default_tool = Some(reader.string()?);
+        }
+        if long(54) != 0 {
+            // The count covers the NULL-terminated pointer array, four bytes per entry.
+            let count = (reader.u32()? as usize / 4).saturating_sub(1);
+            for _ in 0..count {
+                icon.tool_types.push(reader.string()?);
+            }
+        }
+        if long(70) != 0 {
+            reader.string()?;
+        }
+        if has_drawer_data && revision > 0 {
+            reader.take(DRAWER_DATA2_SIZE)?;
+        }
+        Ok(icon)
+    }
+
+    // Tool types are "NAME=value" or plain flags such as "DONOTWAIT"; names ignore case.
+    pub fn tool_type(&self, name: &str) -> Option<&str> {
+        self.tool_types.iter().find_map(|entry| {
+            let (key, value) = entry.split_once('=').unwrap_or((entry, ""));
+            key.trim().eq_ignore_ascii_case(name).then_some(value)
+        })
+    }
+}
+
+impl ExtractedFile {
+    pub fn as_icon(&self) -> Result<DiskObject> {
+        DiskObject::from_bytes(self.as_bytes())
+    }
+}
+
+impl ADF {
+    // Reads the icon of path, which may be given with or without its .info suffix. The disk
+    // icon is Disk.info in the root directory.
+    pub fn read_icon(&self, path: &str) -> Result<DiskObject> {
+        let info_path = if path.to_lowercase().ends_with(".info") {
+            path.to_string()
+        } else {
+            format!("{}.info", path)
+        };
+        DiskObject::from_bytes(&self.read_file(&info_path)?)
+    }
+}
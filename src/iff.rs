@@ -0,0 +1,235 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// EA IFF 85 files as found on Amiga disks: a FORM chunk holding a four character type and
+// the chunks of that type. Chunks are padded to an even length. Only ILBM pictures are
+// decoded, enough to preview them.
+
+use crate::render::BitmapImage;
+use serde::Serialize;
+use std::io::{Error, ErrorKind, Result};
+
+const COMPRESSION_BYTERUN1: u8 = 1;
+const MASKING_HAS_MASK: u8 = 1;
+const CAMG_HAM: u32 = 0x800;
+const CAMG_EHB: u32 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk<'a> {
+    pub id: [u8; 4],
+    pub data: &'a [u8],
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn is_id(id: &[u8]) -> bool {
+    id.len() == 4 && id.iter().all(|&c| (0x20..=0x7e).contains(&c))
+}
+
+// The FORM type when data starts like an IFF file, e.g. "ILBM", "8SVX" or "FTXT".
+pub fn form_type(data: &[u8]) -> Option<String> {
+    if data.len() < 12 || &data[0..4] != b"FORM" || !is_id(&data[8..12]) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&data[8..12]).into_owned())
+}
+
+// Walks the chunks of data; a chunk running past the end is an error.
+pub fn chunks(mut data: &[u8]) -> Result<Vec<Chunk<'_>>> {
+    let mut chunks = Vec::new();
+    while data.len() >= 8 {
+        let id = [data[0], data[1], data[2], data[3]];
+        let len = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        let chunk = data
+            .get(8..8 + len)
+            .ok_or_else(|| invalid("IFF chunk runs past the end of the file"))?;
+        chunks.push(Chunk { id, data: chunk });
+        data = data.get(8 + len + len % 2..).unwrap_or_default();
+    }
+    Ok(chunks)
+}
+
+// The type and chunks of the FORM at the start of data.
+pub fn parse_form(data: &[u8]) -> Result<(String, Vec<Chunk<'_>>)> {
+    let form_type = form_type(data).ok_or_else(|| invalid("Not an IFF FORM"))?;
+    let form = chunks(data)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid("Not an IFF FORM"))?;
+    Ok((form_type, chunks(form.data.get(4..).unwrap_or_default())?))
+}
+
+// Decodes ByteRun1: n < 128 copies the next n + 1 bytes, n > 128 repeats the next byte
+// 257 - n times and 128 is skipped.
+pub fn unpack_byterun1(packed: &[u8], unpacked_len: usize) -> Result<(Vec<u8>, usize)> {
+    let truncated = || invalid("ByteRun1 data ends early");
+    let mut output = Vec::with_capacity(unpacked_len);
+    let mut pos = 0;
+    while output.len() < unpacked_len {
+        let n = *packed.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        match n {
+            0..=127 => {
+                let literal = packed
+                    .get(pos..pos + n as usize + 1)
+                    .ok_or_else(truncated)?;
+                output.extend_from_slice(literal);
+                pos += literal.len();
+            }
+            128 => {}
+            _ => {
+                let byte = *packed.get(pos).ok_or_else(truncated)?;
+                pos += 1;
+                output.resize(output.len() + 257 - n as usize, byte);
+            }
+        }
+    }
+    output.truncate(unpacked_len);
+    Ok((output, pos))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Ilbm {
+    pub width: u16,
+    pub height: u16,
+    pub planes: u8,
+    pub masking: u8,
+    pub compression: u8,
+    pub palette: Vec<[u8; 3]>,
+    // Amiga display mode from the CAMG chunk, 0 when there is none.
+    pub mode: u32,
+    // One colour register number per pixel, row by row.
+    #[serde(skip)]
+    pub pixels: Vec<u8>,
+}
+
+impl Ilbm {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let (form_type, chunks) = parse_form(data)?;
+        if form_type != "ILBM" {
+            return Err(invalid("Not an ILBM picture"));
+        }
+        let find = |id: &[u8; 4]| chunks.iter().find(|chunk| &chunk.id == id);
+        let bmhd = find(b"BMHD")
+            .filter(|chunk| chunk.data.len() >= 20)
+            .ok_or_else(|| invalid("ILBM has no BMHD chunk"))?
+            .data;
+        let mut ilbm = Ilbm {
+            width: u16::from_be_bytes([bmhd[0], bmhd[1]]),
+            height: u16::from_be_bytes([bmhd[2], bmhd[3]]),
+            planes: bmhd[8],
+            masking: bmhd[9],
+            compression: bmhd[10],
+            palette: find(b"CMAP")
+                .map(|chunk| {
+                    chunk
+                        .data
+                        .chunks_exact(3)
+                        .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+                        .collect()
+                })
+                .unwrap_or_default(),
+            mode: find(b"CAMG")
+                .filter(|chunk| chunk.data.len() >= 4)
+                .map_or(0, |chunk| {
+                    u32::from_be_bytes([chunk.data[0], chunk.data[1], chunk.data[2], chunk.data[3]])
+                }),
+            pixels: Vec::new(),
+        };
+        if ilbm.planes == 0 || ilbm.planes > 8 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("ILBM with {} bitplanes is not supported", ilbm.planes),
+            ));
+        }
+        if ilbm.compression > COMPRESSION_BYTERUN1 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("ILBM compression {} is not supported", ilbm.compression),
+            ));
+        }
+        let body = find(b"BODY")
+            .ok_or_else(|| invalid("ILBM has no BODY chunk"))?
+            .data;
+
+        // Each row holds one line of every plane, then the mask plane if there is one.
+        let (width, height) = (ilbm.width as usize, ilbm.height as usize);
+        let row_bytes = width.div_ceil(16) * 2;
+        let stored_planes = ilbm.planes as usize + (ilbm.masking == MASKING_HAS_MASK) as usize;
+        let row_len = row_bytes * stored_planes;
+        let mut pos = 0;
+        ilbm.pixels = vec![0; width * height];
+        for y in 0..height {
+            let row = if ilbm.compression == COMPRESSION_BYTERUN1 {
+                let (row, used) = unpack_byterun1(&body[pos.min(body.len())..], row_len)?;
+                pos += used;
+                row
+            } else {
+                let row = body
+                    .get(pos..pos + row_len)
+                    .ok_or_else(|| invalid("ILBM BODY ends early"))?;
+                pos += row_len;
+                row.to_vec()
+            };
+            for plane in 0..ilbm.planes as usize {
+                let line = &row[plane * row_bytes..(plane + 1) * row_bytes];
+                for x in 0..width {
+                    let bit = line[x / 8] >> (7 - x % 8) & 1;
+                    ilbm.pixels[y * width + x] |= bit << plane;
+                }
+            }
+        }
+        Ok(ilbm)
+    }
+
+    // Resolves HAM and extra half-brite pixels to colours; registers missing from the
+    // palette are shown as grey levels.
+    pub fn to_bitmap(&self) -> BitmapImage {
+        let color = |index: usize| -> [u8; 3] {
+            match self.palette.get(index) {
+                Some(&rgb) => rgb,
+                None if self.mode & CAMG_EHB != 0 && index >= 32 => self
+                    .palette
+                    .get(index - 32)
+                    .map_or([0; 3], |rgb| rgb.map(|c| c / 2)),
+                None => {
+                    let level = (index * 255 / ((1 << self.planes) - 1).max(1)) as u8;
+                    [level; 3]
+                }
+            }
+        };
+        let ham = self.mode & CAMG_HAM != 0 && self.planes >= 6;
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for row in self.pixels.chunks_exact(width.max(1)).take(height) {
+            let mut last = color(0);
+            for &pixel in row {
+                let rgb = if ham {
+                    // The top two bits pick the operation, the rest is a register or a level.
+                    let bits = self.planes as u32 - 2;
+                    let value = pixel & ((1 << bits) - 1);
+                    let level = (value << (8 - bits)) | (value >> (2 * bits).saturating_sub(8));
+                    match pixel >> bits {
+                        0 => color(value as usize),
+                        1 => [last[0], last[1], level],
+                        2 => [level, last[1], last[2]],
+                        _ => [last[0], level, last[2]],
+                    }
+                } else {
+                    color(pixel as usize)
+                };
+                pixels.extend_from_slice(&rgb);
+                last = rgb;
+            }
+        }
+        BitmapImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use serde::Serialize;
+
+// The root block holds a fixed number of bitmap page pointers whatever the block size.
+const ROOT_BITMAP_PAGES: usize = 25;
+// Longwords of a header block that are not part of its hash table.
+const HEADER_LONGS: usize = 56;
+const OFS_HEADER_SIZE: usize = 24;
+
+// Sizes and offsets of the filesystem structures for a volume. Most header fields are
+// addressed from the end of the block, so their offsets follow the block size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Layout {
+    pub block_size: usize,
+    pub blocks: usize,
+}
+
+impl Layout {
+    // Double density floppy.
+    pub const FLOPPY: Layout = Layout::new(512, 1760);
+
+    pub const fn new(block_size: usize, blocks: usize) -> Self {
+        Layout { block_size, blocks }
+    }
+
+    // AmigaDOS places the root block in the middle of the volume.
+    pub const fn root_block(&self) -> usize {
+        self.blocks.div_ceil(2)
+    }
+
+    pub const fn hash_table_size(&self) -> usize {
+        self.block_size / 4 - HEADER_LONGS
+    }
+
+    pub const fn ofs_data_size(&self) -> usize {
+        self.block_size - OFS_HEADER_SIZE
+    }
+
+    pub const fn root_bitmap_pages(&self) -> usize {
+        ROOT_BITMAP_PAGES
+    }
+
+    // Every bitmap page starts with its checksum; the remaining bits map blocks from 2 on.
+    pub const fn bits_per_bitmap_page(&self) -> usize {
+        (self.block_size - 4) * 8
+    }
+
+    pub const fn bitmap_pages(&self) -> usize {
+        self.blocks
+            .saturating_sub(2)
+            .div_ceil(self.bits_per_bitmap_page())
+    }
+
+    // Largest volume whose bitmap fits in the pages listed by the root block.
+    pub const fn max_blocks(&self) -> usize {
+        2 + ROOT_BITMAP_PAGES * self.bits_per_bitmap_page()
+    }
+
+    const fn tail(&self, offset: usize) -> usize {
+        self.block_size - offset
+    }
+
+    pub const fn bm_flag(&self) -> usize {
+        self.tail(200)
+    }
+
+    pub const fn bm_pages(&self) -> usize {
+        self.tail(196)
+    }
+
+    pub const fn protect(&self) -> usize {
+        self.tail(192)
+    }
+
+    pub const fn byte_size(&self) -> usize {
+        self.tail(188)
+    }
+
+    pub const fn comment(&self) -> usize {
+        self.tail(184)
+    }
+
+    pub const fn bm_ext(&self) -> usize {
+        self.tail(96)
+    }
+
+    pub const fn days(&self) -> usize {
+        self.tail(92)
+    }
+
+    pub const fn mins(&self) -> usize {
+        self.tail(88)
+    }
+
+    pub const fn ticks(&self) -> usize {
+        self.tail(84)
+    }
+
+    pub const fn name(&self) -> usize {
+        self.tail(80)
+    }
+
+    pub const fn real_entry(&self) -> usize {
+        self.tail(44)
+    }
+
+    // Hard links chain through next_link; the root keeps its volume date there instead.
+    pub const fn next_link(&self) -> usize {
+        self.tail(40)
+    }
+
+    pub const fn volume_days(&self) -> usize {
+        self.tail(40)
+    }
+
+    pub const fn creation_days(&self) -> usize {
+        self.tail(28)
+    }
+
+    pub const fn hash_chain(&self) -> usize {
+        self.tail(16)
+    }
+
+    pub const fn parent(&self) -> usize {
+        self.tail(12)
+    }
+
+    pub const fn extension(&self) -> usize {
+        self.tail(8)
+    }
+
+    pub const fn sec_type(&self) -> usize {
+        self.tail(4)
+    }
+}
+
+// Offsets and sizes of 512-byte blocks; code that depends on the volume goes through
+// ADF::layout instead.
+const LAYOUT: Layout = Layout::FLOPPY;
+pub(crate) const HT_SIZE: usize = LAYOUT.hash_table_size();
+pub const MAX_COMMENT_LEN: usize = 79;
+pub(crate) const BM_PAGES: usize = LAYOUT.root_bitmap_pages();
+pub(crate) const BITS_PER_BITMAP_PAGE: usize = LAYOUT.bits_per_bitmap_page();
+pub const MAX_VOLUME_BLOCKS: usize = LAYOUT.max_blocks();
+pub const MAX_NAME_LEN: usize = 30;
+pub(crate) const OFS_DATA_SIZE: usize = LAYOUT.ofs_data_size();
+
+pub(crate) const BLOCK_TYPE: usize = 0;
+pub(crate) const BLOCK_HEADER_KEY: usize = 4;
+pub(crate) const BLOCK_HIGH_SEQ: usize = 8;
+pub(crate) const BLOCK_TABLE_SIZE: usize = 12;
+pub(crate) const BLOCK_FIRST_DATA: usize = 16;
+pub(crate) const BLOCK_CHECKSUM: usize = 20;
+pub(crate) const BLOCK_HASH_TABLE: usize = 24;
+pub(crate) const BLOCK_BM_FLAG: usize = LAYOUT.bm_flag();
+pub(crate) const BLOCK_BM_PAGES: usize = LAYOUT.bm_pages();
+pub(crate) const BLOCK_PROTECT: usize = LAYOUT.protect();
+pub(crate) const BLOCK_BYTE_SIZE: usize = LAYOUT.byte_size();
+pub(crate) const BLOCK_COMMENT: usize = LAYOUT.comment();
+pub(crate) const BLOCK_DAYS: usize = LAYOUT.days();
+pub(crate) const BLOCK_MINS: usize = LAYOUT.mins();
+pub(crate) const BLOCK_TICKS: usize = LAYOUT.ticks();
+pub(crate) const BLOCK_NAME: usize = LAYOUT.name();
+pub(crate) const BLOCK_HASH_CHAIN: usize = LAYOUT.hash_chain();
+pub(crate) const BLOCK_PARENT: usize = LAYOUT.parent();
+pub(crate) const BLOCK_EXTENSION: usize = LAYOUT.extension();
+pub(crate) const BLOCK_SEC_TYPE: usize = LAYOUT.sec_type();
+
+pub(crate) const OFS_SEQ_NUM: usize = 8;
+pub(crate) const OFS_DATA_LEN: usize = 12;
+pub(crate) const OFS_NEXT_DATA: usize = 16;
+
+pub(crate) const T_HEADER: u32 = 2;
+pub(crate) const T_DATA: u32 = 8;
+pub(crate) const T_LIST: u32 = 16;
+
+pub(crate) const ST_ROOT: u32 = 1;
+pub(crate) const ST_USERDIR: u32 = 2;
+pub(crate) const ST_FILE: u32 = -3i32 as u32;
@@ -3,12 +3,85 @@
 // Copyright (c) 2023
 // - Volker Schwaberow <volker@schwaberow.de>
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![allow(dead_code)]
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 
-#[cfg(test)]
+extern crate alloc;
+
+#[cfg(all(test, feature = "std"))]
+#[allow(clippy::module_inception)]
 mod tests;
 
+// Without the std feature only layout and raw are built.
+#[cfg(feature = "std")]
+pub mod archive;
+#[cfg(feature = "std")]
+pub mod badblocks;
+#[cfg(feature = "std")]
+pub mod block;
+#[cfg(feature = "std")]
+pub mod bootblock;
+#[cfg(feature = "std")]
+pub mod cancel;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod dat;
+#[cfg(feature = "std")]
+pub mod device;
+#[cfg(feature = "std")]
 pub mod disk;
+#[cfg(feature = "std")]
+pub mod diskset;
+#[cfg(feature = "std")]
+pub mod dms;
+#[cfg(feature = "std")]
+pub mod doctor;
+#[cfg(feature = "std")]
+pub mod events;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod fs;
+#[cfg(feature = "fuse")]
+pub mod fuse;
+#[cfg(feature = "hardware")]
+pub mod greaseweazle;
+#[cfg(feature = "std")]
+pub mod hdf;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "std")]
 pub mod hunk;
+#[cfg(feature = "std")]
+pub mod icon;
+#[cfg(feature = "std")]
+pub mod iff;
+pub mod layout;
+#[cfg(feature = "std")]
+pub mod manifest;
+#[cfg(feature = "std")]
+pub mod metadata;
+#[cfg(feature = "std")]
+pub mod mfm;
+#[cfg(feature = "std")]
+pub mod optimize;
+#[cfg(feature = "std")]
+pub mod pattern;
+#[cfg(feature = "std")]
+pub mod provenance;
+pub mod raw;
+#[cfg(feature = "std")]
+pub mod rdb;
+#[cfg(feature = "std")]
+pub mod render;
+#[cfg(feature = "std")]
+pub mod sansio;
+#[cfg(feature = "arbitrary")]
+pub mod test_utils;
+#[cfg(feature = "std")]
+pub mod validate;
+#[cfg(feature = "std")]
+pub mod verify;
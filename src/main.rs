@@ -3,14 +3,51 @@
 // Copyright (c) 2023
 // - Volker Schwaberow <volker@schwaberow.de>
 
+mod browse;
+mod cli;
+mod config;
+mod template;
+
+use adflib::archive::ArchiveFormat;
+use adflib::block::{block_type_name, BlockPtr, DateStamp, HeaderBlock, RootBlock};
+use adflib::bootblock::{BootBlockInfo, Kickstart, BOOT_BLOCK_SIZE};
+use adflib::cancel::CancelToken;
+use adflib::dat::{Dat, Fingerprint};
+use adflib::device::device_type_name;
 use adflib::disk::{
-    BitmapInfo, DiskInfo, DiskType, FileInfo, ADF, ADF_NUM_SECTORS, ADF_NUM_TRACKS, ADF_TRACK_SIZE,
+    find_strings, hex_dump, join_path, parse_protection_flags, DiskInfo, DiskType, DiskUsage,
+    FileInfo, FormatOptions, ADF, ADF_HD_NUM_SECTORS, ADF_NUM_SECTORS, ADF_SECTOR_SIZE,
+    ADF_TRACK_SIZE, AMIGADOS_FILL, AMIGA_EPOCH_OFFSET,
+};
+use adflib::diskset::DiskSet;
+use adflib::dms::{
+    convert_all_cancellable, pack_dms_with_extras, parse_header, read_dms_extras, verify_dms,
+    ConvertOptions, ConvertStatus, DmsExtras, DmsMode, TrackStatus,
 };
-use chrono::{DateTime, Utc};
-use clap::{Arg, Command};
+use adflib::hunk::{HunkFile, HunkParser};
+use adflib::iff::Ilbm;
+use adflib::manifest::HashAlgo;
+use adflib::metadata::DiskMetadata;
+use adflib::pattern::Pattern;
+use adflib::provenance::{Provenance, SidecarFormat};
+use adflib::validate::{Finding, Severity};
+use adflib::verify::{self, MismatchKind};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use clap_complete::Shell;
+use cli::build_cli;
+use config::Config;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+use signal_hook::consts::SIGINT;
 use std::fs::File;
-use std::io::Write;
-use std::time::UNIX_EPOCH;
+use std::io::{BufWriter, ErrorKind, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use template::Template;
+use zip::CompressionMethod;
 
 fn print_disk_info(info: &DiskInfo, file_path: &str) {
     println!("ADF Information for: {}", file_path);
@@ -24,6 +61,7 @@ fn print_disk_info(info: &DiskInfo, file_path: &str) {
     println!("Creation Date:  {}", creation_date);
     println!("Disk Size:      {} bytes", info.disk_size);
     println!("Geometry:");
+    println!("  Device Type:  {}", device_type_name(info.device_type));
     println!("  Heads:        {}", info.heads);
     println!("  Tracks:       {}", info.tracks);
     println!("  Sectors/Track:{}", info.sectors_per_track);
@@ -35,15 +73,522 @@ fn print_disk_info(info: &DiskInfo, file_path: &str) {
     );
 }
 
-fn display_bitmap_info(info: &BitmapInfo, full: bool) {
-    println!("Bitmap size: {} blocks", info.total_blocks);
-    println!("Free blocks: {}", info.free_blocks);
-    println!("Used blocks: {}", info.used_blocks);
-    println!("Disk usage: {:.2}%", info.disk_usage_percentage);
+#[derive(Serialize)]
+struct InfoReport {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    info: Option<DiskInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct InfoRow<'a> {
+    path: &'a str,
+    filesystem: Option<&'a str>,
+    disk_name: Option<&'a str>,
+    creation_date: Option<u32>,
+    disk_size: Option<u32>,
+    error: Option<&'a str>,
+}
+
+impl<'a> From<&'a InfoReport> for InfoRow<'a> {
+    fn from(report: &'a InfoReport) -> Self {
+        let info = report.info.as_ref();
+        InfoRow {
+            path: &report.path,
+            filesystem: info.map(|i| i.filesystem.as_str()),
+            disk_name: info.map(|i| i.disk_name.as_str()),
+            creation_date: info.map(|i| i.creation_date),
+            disk_size: info.map(|i| i.disk_size),
+            error: report.error.as_deref(),
+        }
+    }
+}
+
+fn collect_adf_files(dir: &Path, paths: &mut Vec<String>) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_adf_files(&path, paths)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("adf"))
+        {
+            paths.push(path.to_string_lossy().into_owned());
+        }
+    }
+    Ok(())
+}
+
+fn batch_info(paths: &[String], cancel: &CancelToken) -> std::io::Result<Vec<InfoReport>> {
+    let bar = progress_bar("Analyzing");
+    bar.set_length(paths.len() as u64);
+    let analyze = |path: &String| match load_adf(path).and_then(|adf| adf.information()) {
+        Ok(info) => InfoReport {
+            path: path.clone(),
+            info: Some(info),
+            error: None,
+        },
+        Err(error) => InfoReport {
+            path: path.clone(),
+            info: None,
+            error: Some(error.to_string()),
+        },
+    };
+
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = paths.len().div_ceil(workers).max(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let bar = bar.clone();
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .take_while(|_| !cancel.is_cancelled())
+                        .map(|path| {
+                            let report = analyze(path);
+                            bar.inc(1);
+                            report
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let reports = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("info worker panicked"))
+            .collect();
+        bar.finish_and_clear();
+        cancel.check()?;
+        Ok(reports)
+    })
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = record.level().to_string().to_lowercase();
+        let color = match record.level() {
+            log::Level::Error => "31",
+            log::Level::Warn => "33",
+            log::Level::Info => "32",
+            log::Level::Debug => "36",
+            log::Level::Trace => "90",
+        };
+        eprintln!("{}: {}", paint(&level, color), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+fn init_output(matches: &clap::ArgMatches) {
+    let quiet = matches.get_flag("quiet");
+    QUIET.store(quiet, Ordering::Relaxed);
+    let color = match matches.get_one::<String>("color").map(String::as_str) {
+        Some("always") => true,
+        Some("never") => false,
+        _ => {
+            std::env::var_os("NO_COLOR").is_none()
+                && std::io::stdout().is_terminal()
+                && std::io::stderr().is_terminal()
+        }
+    };
+    COLOR.store(color, Ordering::Relaxed);
+    let level = match (quiet, matches.get_count("verbose")) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Warn,
+        (false, 1) => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+fn paint_finding(finding: &Finding) -> String {
+    let code = match finding.severity {
+        Severity::Error => "31",
+        Severity::Warning => "33",
+        Severity::Info => "36",
+    };
+    paint(&finding.to_string(), code)
+}
+
+fn paint(text: &str, code: &str) -> String {
+    if COLOR.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn progress_bar(message: &str) -> ProgressBar {
+    if QUIET.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::new(0);
+    bar.set_style(
+        ProgressStyle::with_template("{msg:10} [{bar:40}] {pos}/{len}")
+            .expect("valid progress template")
+            .progress_chars("=> "),
+    );
+    bar.set_message(message.to_string());
+    bar
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum DecodedBlock {
+    Boot(BootBlockInfo),
+    Root(RootBlock),
+    Header(HeaderBlock),
+}
+
+fn decode_block(adf: &ADF, spec: &str) -> Result<DecodedBlock, Box<dyn std::error::Error>> {
+    let spec = spec.to_ascii_lowercase();
+    let block = match spec.as_str() {
+        "boot" => return Ok(DecodedBlock::Boot(adf.boot_block_info())),
+        "root" => {
+            return Ok(DecodedBlock::Root(RootBlock::from_bytes(
+                adf.read_sector(adf.root_block()),
+                &adf.layout(),
+            )?))
+        }
+        _ => match spec.strip_prefix("header:") {
+            Some(number) => cli::parse_number(number)?,
+            None => {
+                return Err(
+                    format!("Invalid block '{}', expected root, boot or header:N", spec).into(),
+                )
+            }
+        },
+    };
+    if block >= adf.num_blocks() {
+        return Err(format!("Block {} is beyond the end of the image", block).into());
+    }
+    Ok(DecodedBlock::Header(HeaderBlock::from_bytes(
+        adf.read_sector(block),
+        &adf.layout(),
+    )?))
+}
+
+fn checksum_note(stored: u32, computed: u32) -> String {
+    if stored == computed {
+        format!("0x{:08x} ({})", stored, paint("valid", "32"))
+    } else {
+        format!(
+            "0x{:08x} ({}, expected 0x{:08x})",
+            stored,
+            paint("invalid", "31"),
+            computed
+        )
+    }
+}
+
+fn format_datestamp(date: &DateStamp) -> String {
+    let secs = date
+        .to_system_time()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    format!(
+        "{} (days {}, mins {}, ticks {})",
+        DateTime::<Utc>::from_timestamp(secs as i64, 0).map_or("invalid".to_string(), |d| d
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()),
+        date.days,
+        date.mins,
+        date.ticks
+    )
+}
+
+fn print_hash_table(adf: &ADF, table: &[BlockPtr]) {
+    println!("Hash Table:");
+    for (slot, block) in table.iter().enumerate() {
+        let Some(index) = block.index() else {
+            continue;
+        };
+        let name = match index {
+            b if b < adf.num_blocks() => HeaderBlock::from_bytes(adf.read_sector(b), &adf.layout())
+                .map(|header| header.name)
+                .unwrap_or_default(),
+            _ => "<out of range>".to_string(),
+        };
+        println!("  [{:>2}] {:>6}  {}", slot, block, name);
+    }
+}
+
+fn print_decoded_block(adf: &ADF, decoded: &DecodedBlock) {
+    match decoded {
+        DecodedBlock::Boot(info) => println!("{}", info),
+        DecodedBlock::Root(root) => {
+            println!(
+                "Type:          {} ({})",
+                root.block_type,
+                block_type_name(root.block_type)
+            );
+            println!("Secondary:     {}", root.sec_type);
+            println!(
+                "Checksum:      {}",
+                checksum_note(root.checksum, root.computed_checksum)
+            );
+            println!("Name:          {}", root.name);
+            println!("Hash Size:     {}", root.hash_table_size);
+            println!(
+                "Bitmap Flag:   0x{:08x} ({})",
+                root.bm_flag,
+                if root.bm_flag == u32::MAX {
+                    "valid"
+                } else {
+                    "invalid"
+                }
+            );
+            let pages: Vec<String> = root
+                .bm_pages
+                .iter()
+                .filter(|p| !p.is_null())
+                .map(BlockPtr::to_string)
+                .collect();
+            println!("Bitmap Pages:  {}", pages.join(", "));
+            println!("Bitmap Ext:    {}", root.bm_ext);
+            println!("Modified:      {}", format_datestamp(&root.modified));
+            println!("Vol. Modified: {}", format_datestamp(&root.volume_modified));
+            println!("Created:       {}", format_datestamp(&root.created));
+            println!("Extension:     {}", root.extension);
+            print_hash_table(adf, &root.hash_table);
+        }
+        DecodedBlock::Header(header) => {
+            println!(
+                "Type:          {} ({})",
+                header.block_type,
+                block_type_name(header.block_type)
+            );
+            println!("Secondary:     {}", header.sec_type);
+            println!("Header Key:    {}", header.header_key);
+            println!(
+                "Checksum:      {}",
+                checksum_note(header.checksum, header.computed_checksum)
+            );
+            println!("Name:          {}", header.name);
+            println!("Comment:       {}", header.comment);
+            println!(
+                "Protection:    {}",
+                adf.format_protection_flags(header.protect)
+            );
+            println!("Date:          {}", format_datestamp(&header.date));
+            println!("Parent:        {}", header.parent);
+            println!("Hash Chain:    {}", header.hash_chain);
+            println!("Extension:     {}", header.extension);
+            if header.is_dir() {
+                print_hash_table(adf, &header.table);
+            } else {
+                println!("Byte Size:     {}", header.byte_size);
+                println!("High Seq:      {}", header.high_seq);
+                println!("First Data:    {}", header.first_data);
+                let blocks: Vec<String> = header
+                    .table
+                    .iter()
+                    .rev()
+                    .filter(|b| !b.is_null())
+                    .map(BlockPtr::to_string)
+                    .collect();
+                println!("Data Blocks:   {}", blocks.join(", "));
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TemplateRow {
+    name: String,
+    description: String,
+    filesystem: String,
+    size: String,
+    source: String,
+}
+
+#[derive(Serialize)]
+struct IdentifyRow {
+    path: String,
+    size: Option<u64>,
+    crc32: Option<String>,
+    md5: Option<String>,
+    sha1: Option<String>,
+    title: Option<String>,
+    rom: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ToolTypeRow<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+// One line of the metadata export; failed images keep their line so catalogues can list them.
+#[derive(Serialize)]
+struct MetadataReport {
+    path: String,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    metadata: Option<DiskMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn identify(path: &str, dat: Option<&Dat>) -> IdentifyRow {
+    let mut row = IdentifyRow {
+        path: path.to_string(),
+        size: None,
+        crc32: None,
+        md5: None,
+        sha1: None,
+        title: None,
+        rom: None,
+        error: None,
+    };
+    match read_input(path) {
+        Ok(data) => {
+            let fingerprint = Fingerprint::of(&data);
+            if let Some(entry) = dat.and_then(|dat| dat.identify(&fingerprint)) {
+                row.title = Some(entry.title.clone());
+                row.rom = Some(entry.rom.clone());
+            }
+            row.size = Some(fingerprint.size);
+            row.crc32 = Some(fingerprint.crc32);
+            row.md5 = Some(fingerprint.md5);
+            row.sha1 = Some(fingerprint.sha1);
+        }
+        Err(error) => row.error = Some(error.to_string()),
+    }
+    row
+}
+
+static STDOUT_IS_DATA: AtomicBool = AtomicBool::new(false);
+
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if STDOUT_IS_DATA.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+fn read_input(path: &str) -> std::io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut data = Vec::new();
+        std::io::stdin().read_to_end(&mut data)?;
+        Ok(data)
+    } else {
+        std::fs::read(path)
+    }
+}
+
+fn load_adf(path: &str) -> std::io::Result<ADF> {
+    #[cfg(feature = "http")]
+    if path.starts_with("http://") || path.starts_with("https://") {
+        return ADF::open_any_url(path);
+    }
+    if path == "-" {
+        ADF::from_bytes(&read_input(path)?)
+    } else {
+        ADF::from_file(path)
+    }
+}
+
+fn load_adf_for_update(path: &str) -> std::io::Result<ADF> {
+    if path == "-" {
+        STDOUT_IS_DATA.store(true, Ordering::Relaxed);
+    }
+    load_adf(path)
+}
+
+fn write_output<P: AsRef<Path>>(path: P, data: &[u8]) -> std::io::Result<()> {
+    if path.as_ref() == Path::new("-") {
+        STDOUT_IS_DATA.store(true, Ordering::Relaxed);
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(data)?;
+        stdout.flush()
+    } else {
+        std::fs::write(path, data)
+    }
+}
+
+// Saving back to the image that was loaded only writes the blocks that changed.
+fn save_adf(adf: &mut ADF, path: &str) -> std::io::Result<()> {
+    if path == "-" {
+        write_output(path, &adf.data)
+    } else {
+        adf.save(path)
+    }
+}
+
+fn kickstart(matches: &clap::ArgMatches) -> std::io::Result<Kickstart> {
+    matches.get_one::<String>("kickstart").unwrap().parse()
+}
+
+fn output_format<'a>(default: &'a str, matches: &'a clap::ArgMatches) -> &'a str {
+    matches
+        .get_one::<String>("output")
+        .map(String::as_str)
+        .unwrap_or(default)
+}
+
+fn print_structured<T: Serialize>(
+    format: &str,
+    rows: &[T],
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        "json" => {
+            let json = match rows {
+                [single] => serde_json::to_string_pretty(single)?,
+                _ => serde_json::to_string_pretty(rows)?,
+            };
+            println!("{}", json);
+        }
+        "csv" => {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+        _ => return Err(format!("Unsupported output format '{}'", format).into()),
+    }
+    Ok(())
+}
+
+fn display_bitmap_info(adf: &ADF, full: bool) {
+    let counts = adf.bitmap_counts();
+    println!("Bitmap size: {} blocks", counts.total_blocks);
+    println!("Free blocks: {}", counts.free_blocks);
+    println!("Used blocks: {}", counts.used_blocks);
+    println!(
+        "Disk usage: {:.2}%",
+        counts.used_blocks as f64 / counts.total_blocks as f64 * 100.0
+    );
 
     if full {
         println!("\nBlock allocation map:");
-        for (i, &is_used) in info.block_allocation_map.iter().enumerate() {
+        for (i, is_used) in adf.allocation_map().enumerate() {
             print!("{}", if is_used { '#' } else { '.' });
             if (i + 1) % 44 == 0 {
                 println!();
@@ -53,237 +598,1851 @@ fn display_bitmap_info(info: &BitmapInfo, full: bool) {
     }
 }
 
-fn print_directory_listing(file_path: &str, files: &[FileInfo]) {
+fn print_directory_listing(adf: &ADF, file_path: &str, files: &[FileInfo]) {
     println!("Directory of {}", file_path);
-    println!("Name                 Size    Flags   Creation Date");
-    println!("----                 ----    -----   -------------");
+    println!("Name                 Size  Flags      Creation Date");
+    println!("----                 ----  -----      -------------");
 
     for file in files {
-        let flags = if file.is_dir { "  d" } else { "---" };
+        let kind = if file.is_dir { 'd' } else { '-' };
+        let flags = adf.format_protection_flags(file.protection);
         let date = file
             .creation_date
             .duration_since(UNIX_EPOCH)
             .map(|d| d.as_secs())
             .unwrap_or(0);
-        println!("{:<20} {:>5}  {}     {}", file.name, file.size, flags, date);
+        println!(
+            "{:<20} {:>5}  {}{}  {}",
+            file.name, file.size, kind, flags, date
+        );
     }
 
     println!("{} files", files.len());
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cmd = Command::new("adflibtesttool")
-        .bin_name("adflibtesttool")
-        .version("0.0.1")
-        .author("Volker Schwaberow <volker@schwaberow.de>")
-        .about("ADFlib test tool")
-        .subcommand(
-            Command::new("list")
-                .about("Lists contents of an ADF file")
-                .arg(Arg::new("FILE").required(true).help("The ADF file to read"))
-                .arg(
-                    Arg::new("directory")
-                        .short('d')
-                        .long("directory")
-                        .value_name("DIR")
-                        .help("Specify a directory to list (default: root)"),
-                ),
-        )
-        .subcommand(
-            Command::new("extract")
-                .about("Extracts a file from an ADF")
-                .arg(
-                    Arg::new("ADF_FILE")
-                        .required(true)
-                        .help("The ADF file to read from"),
-                )
-                .arg(
-                    Arg::new("FILE_NAME")
-                        .required(true)
-                        .help("The name of the file to extract within the ADF"),
-                )
-                .arg(
-                    Arg::new("OUTPUT")
-                        .short('o')
-                        .long("output")
-                        .value_name("FILE")
-                        .help("Output file (default: stdout)"),
-                ),
-        )
-        .subcommand(
-            Command::new("bitmap")
-                .about("Performs bitmap operations on an ADF file")
-                .subcommand(
-                    Command::new("info")
-                        .about("Displays information about the bitmap of an ADF file")
-                        .arg(
-                            Arg::new("FILE")
-                                .required(true)
-                                .help("The ADF file to analyze"),
-                        )
-                        .arg(
-                            Arg::new("full")
-                                .short('f')
-                                .long("full")
-                                .help("Display full block allocation map")
-                                .action(clap::ArgAction::SetTrue),
-                        ),
-                )
-                .subcommand(
-                    Command::new("set")
-                        .about("Sets the status of a block")
-                        .arg(
-                            Arg::new("FILE")
-                                .required(true)
-                                .help("The ADF file to modify"),
-                        )
-                        .arg(
-                            Arg::new("BLOCK")
-                                .required(true)
-                                .help("The block number to set"),
-                        )
-                        .arg(
-                            Arg::new("STATUS")
-                                .required(true)
-                                .help("The status to set (free/used)"),
-                        ),
-                )
-                .subcommand(
-                    Command::new("defragment")
-                        .about("Defragments the ADF file")
-                        .arg(
-                            Arg::new("FILE")
-                                .required(true)
-                                .help("The ADF file to defragment"),
-                        ),
-                ),
-        )
-        .subcommand(
-            Command::new("format")
-                .about("Formats an ADF file")
-                .arg(
-                    Arg::new("FILE")
-                        .required(true)
-                        .help("The ADF file to format"),
-                )
-                .arg(
-                    Arg::new("TYPE")
-                        .short('t')
-                        .long("type")
-                        .value_name("TYPE")
-                        .help("Disk type (OFS, FFS)")
-                        .default_value("OFS"),
-                )
-                .arg(
-                    Arg::new("NAME")
-                        .short('n')
-                        .long("name")
-                        .value_name("NAME")
-                        .help("Disk name")
-                        .default_value("Untitled"),
-                ),
-        )
-        .subcommand(
-            Command::new("info")
-                .about("Displays information about an ADF file")
-                .arg(
-                    Arg::new("FILE")
-                        .required(true)
-                        .help("The ADF file to analyze"),
-                ),
-        )
-        .subcommand(
-            Command::new("create")
-                .about("Creates a new empty ADF file")
-                .arg(
-                    Arg::new("FILE")
-                        .required(true)
-                        .help("The ADF file to create"),
-                ),
-        );
+enum Location<'a> {
+    Adf(&'a str, &'a str),
+    Host(&'a str),
+}
+
+fn parse_location(value: &str) -> Location<'_> {
+    match value.split_once(':') {
+        Some((image, path)) if image.len() > 1 => Location::Adf(image, path),
+        _ => Location::Host(value),
+    }
+}
+
+fn base_name(path: &str) -> &str {
+    path.trim_end_matches(['/', '\\'])
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(path)
+}
+
+fn copy_command(source: &str, dest: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let source_location = parse_location(source);
+    let source_name = match source_location {
+        Location::Adf(_, path) | Location::Host(path) => base_name(path).to_string(),
+    };
+    if source_name.is_empty() {
+        return Err("Source must name a file or directory".into());
+    }
+
+    match (source_location, parse_location(dest)) {
+        (Location::Adf(src_image, from), Location::Adf(dst_image, to)) => {
+            let source_adf = load_adf(src_image)?;
+            let mut dest_adf = if src_image == dst_image {
+                source_adf.clone()
+            } else {
+                load_adf_for_update(dst_image)?
+            };
+            let to = adf_destination(&dest_adf, to, &source_name);
+            dest_adf.copy_from(&source_adf, from, &to)?;
+            save_adf(&mut dest_adf, dst_image)?;
+        }
+        (Location::Adf(src_image, from), Location::Host(to)) => {
+            let adf = load_adf(src_image)?;
+            let mut to = Path::new(to).to_path_buf();
+            if to.is_dir() {
+                to.push(&source_name);
+            }
+            if adf.is_dir(from) {
+                adf.export_dir(from, &to)?;
+            } else {
+                write_output(&to, &adf.read_file(from)?)?;
+            }
+        }
+        (Location::Host(from), Location::Adf(dst_image, to)) => {
+            let mut adf = load_adf_for_update(dst_image)?;
+            let to = adf_destination(&adf, to, &source_name);
+            if Path::new(from).is_dir() {
+                adf.create_dir(&to)?;
+                adf.import_dir(from, &to)?;
+            } else {
+                adf.write_file(&to, &read_input(from)?)?;
+            }
+            save_adf(&mut adf, dst_image)?;
+        }
+        (Location::Host(_), Location::Host(_)) => {
+            return Err("At least one side must be inside an ADF (image.adf:path)".into());
+        }
+    }
+
+    status!("Copied {} to {}", source, dest);
+    Ok(())
+}
+
+fn adf_destination(adf: &ADF, to: &str, source_name: &str) -> String {
+    if to.is_empty() || to.ends_with('/') || adf.is_dir(to) {
+        join_path(to, source_name)
+    } else {
+        to.to_string()
+    }
+}
 
-    let matches = cmd.get_matches();
+fn confirm(prompt: &str) -> std::io::Result<bool> {
+    print!("{} [y/N] ", prompt);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+// Escapes backslashes, tabs and line breaks so a field can't split its TSV row.
+fn tsv_field(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn parse_hex(value: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let digits: String = value
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!("Invalid hex string '{}'", value).into());
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("Invalid hex string '{}'", value).into())
+        })
+        .collect()
+}
+
+// A --fill style pattern: hex bytes, or "dos" for the AmigaDOS Format fill.
+fn fill_pattern(value: Option<&String>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    match value.map(String::as_str) {
+        Some("dos") => Ok(AMIGADOS_FILL.to_vec()),
+        Some(pattern) => parse_hex(pattern),
+        None => Ok(Vec::new()),
+    }
+}
+
+// The reproducible builds timestamp, no earlier than the Amiga epoch, which is the default.
+fn source_date_epoch() -> Result<SystemTime, Box<dyn std::error::Error>> {
+    let secs = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) => value
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid SOURCE_DATE_EPOCH '{}'", value))?,
+        Err(_) => 0,
+    };
+    Ok(UNIX_EPOCH + Duration::from_secs(secs.max(AMIGA_EPOCH_OFFSET)))
+}
+
+fn parse_date(value: &str) -> Result<SystemTime, Box<dyn std::error::Error>> {
+    let date_time = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|d| d.and_hms_opt(0, 0, 0).unwrap_or_default())
+        })
+        .map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD [HH:MM[:SS]]", value))?;
+    Ok(SystemTime::from(date_time.and_utc()))
+}
+
+const EXIT_CHECK_FAILED: u8 = 1;
+const EXIT_NOT_FOUND: u8 = 3;
+const EXIT_CORRUPT_IMAGE: u8 = 4;
+const EXIT_DISK_FULL: u8 = 5;
+const EXIT_UNSUPPORTED: u8 = 6;
+const EXIT_ALREADY_EXISTS: u8 = 7;
+const EXIT_INVALID_ARGUMENT: u8 = 8;
+const EXIT_IO_ERROR: u8 = 9;
+// 128 + SIGINT, what shells report for a program ended with Ctrl-C.
+const EXIT_CANCELLED: u8 = 130;
+
+// A token cancelled by the first Ctrl-C, for commands that stop cleanly; a second Ctrl-C
+// ends the program right away.
+fn cancel_on_interrupt() -> std::io::Result<CancelToken> {
+    let flag = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register_conditional_shutdown(SIGINT, EXIT_CANCELLED.into(), flag.clone())?;
+    signal_hook::flag::register(SIGINT, flag.clone())?;
+    Ok(CancelToken::from_flag(flag))
+}
+
+fn error_kind(error: &(dyn std::error::Error + 'static)) -> (&'static str, u8) {
+    let Some(io_error) = error.downcast_ref::<std::io::Error>() else {
+        return ("invalid_argument", EXIT_INVALID_ARGUMENT);
+    };
+    match io_error.kind() {
+        ErrorKind::NotFound => ("not_found", EXIT_NOT_FOUND),
+        ErrorKind::InvalidData | ErrorKind::UnexpectedEof => ("corrupt_image", EXIT_CORRUPT_IMAGE),
+        ErrorKind::StorageFull => ("disk_full", EXIT_DISK_FULL),
+        ErrorKind::Unsupported => ("unsupported", EXIT_UNSUPPORTED),
+        ErrorKind::AlreadyExists => ("already_exists", EXIT_ALREADY_EXISTS),
+        ErrorKind::InvalidInput => ("invalid_argument", EXIT_INVALID_ARGUMENT),
+        ErrorKind::Interrupted => ("cancelled", EXIT_CANCELLED),
+        _ => ("io_error", EXIT_IO_ERROR),
+    }
+}
+
+fn requested_output(matches: &clap::ArgMatches, default: &str) -> String {
+    let mut output = output_format(default, matches).to_string();
+    let mut current = matches;
+    while let Some((_, sub_matches)) = current.subcommand() {
+        if let Ok(Some(format)) = sub_matches.try_get_one::<String>("output") {
+            output = format.clone();
+        }
+        current = sub_matches;
+    }
+    output
+}
+
+fn main() -> ExitCode {
+    let matches = build_cli().get_matches();
+    init_output(&matches);
+    let mut default_output = "text".to_string();
+    let result = Config::load().map_err(Into::into).and_then(|config| {
+        if let Some(output) = &config.output {
+            default_output = output.clone();
+        }
+        run(&matches, &config)
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            let (kind, code) = error_kind(error.as_ref());
+            let message = match error.downcast_ref::<std::io::Error>() {
+                Some(io_error) => io_error
+                    .get_ref()
+                    .map(|inner| inner.to_string())
+                    .unwrap_or_else(|| io_error.to_string()),
+                None => error.to_string(),
+            };
+            if requested_output(&matches, &default_output) == "json" {
+                let report = serde_json::json!({
+                    "error": { "kind": kind, "code": code, "message": message }
+                });
+                eprintln!("{}", report);
+            } else {
+                eprintln!("{}: {}", paint("Error", "31"), message);
+            }
+            ExitCode::from(code)
+        }
+    }
+}
+
+fn run(matches: &clap::ArgMatches, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let output = output_format(config.output.as_deref().unwrap_or("text"), matches);
 
     match matches.subcommand() {
         Some(("list", sub_matches)) => {
+            let output = output_format(output, sub_matches);
             let file_path = sub_matches.get_one::<String>("FILE").unwrap();
-            let adf = ADF::from_file(file_path)?;
+            let adf = load_adf(file_path)?;
 
             let directory = sub_matches
                 .get_one::<String>("directory")
-                .map(|s| s.parse::<usize>().unwrap_or(880))
-                .unwrap_or(880);
+                .and_then(|s| s.parse::<usize>().ok())
+                .unwrap_or(adf.root_block());
 
-            let files = adf
-                .list_directory(directory)
-                .collect::<Result<Vec<FileInfo>, _>>()?;
-            print_directory_listing(file_path, &files);
+            let order = sub_matches.get_one::<String>("sort").unwrap().parse()?;
+            let files = adf.list_directory_sorted(directory, order)?;
+            match output {
+                "text" => print_directory_listing(&adf, file_path, &files),
+                format => print_structured(format, &files)?,
+            }
         }
         Some(("extract", sub_matches)) => {
             let adf_path = sub_matches.get_one::<String>("ADF_FILE").unwrap();
             let file_name = sub_matches.get_one::<String>("FILE_NAME").unwrap();
             let output_path = sub_matches.get_one::<String>("OUTPUT");
+            let target_dir = sub_matches.get_one::<String>("to");
 
-            let adf = ADF::from_file(adf_path)?;
-            let contents = adf.extract_file(file_name)?;
+            let adf = load_adf(adf_path)?;
+            if target_dir.is_some() || Pattern::is_wild(file_name) {
+                let target_dir = target_dir.map_or(".", String::as_str);
+                let extracted = adf.extract_matching(&Pattern::new(file_name)?, target_dir)?;
+                if extracted.is_empty() {
+                    return Err(std::io::Error::new(
+                        ErrorKind::NotFound,
+                        format!("No files match '{}'", file_name),
+                    )
+                    .into());
+                }
+                for path in &extracted {
+                    status!("Extracted {}", path);
+                }
+                status!("{} entries extracted to {}", extracted.len(), target_dir);
+                return Ok(());
+            }
 
+            let contents = adf.read_file(file_name)?;
             match output_path {
-                Some(path) => {
-                    let mut file = File::create(path)?;
-                    file.write_all(contents.as_bytes())?;
-                }
+                Some(path) => write_output(path, &contents)?,
                 None => {
-                    std::io::stdout().write_all(contents.as_bytes())?;
+                    std::io::stdout().write_all(&contents)?;
                 }
             }
         }
+        Some(("archive", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let output_path = sub_matches.get_one::<String>("OUTPUT").unwrap();
+            let format = match sub_matches.get_one::<String>("format") {
+                Some(format) => format.parse::<ArchiveFormat>()?,
+                None if output_path == "-" || output_path.ends_with(".tar") => ArchiveFormat::Tar,
+                None => ArchiveFormat::Zip,
+            };
+            let adf = load_adf(file_path)?;
+            if output_path == "-" {
+                if format != ArchiveFormat::Tar {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "Only tar archives can be written to stdout",
+                    )
+                    .into());
+                }
+                adf.export_tar(std::io::stdout().lock())?;
+            } else {
+                adf.export_archive(format, BufWriter::new(File::create(output_path)?))?;
+                status!("Archived {} to {}", file_path, output_path);
+            }
+        }
         Some(("info", sub_matches)) => {
+            let output = output_format(output, sub_matches);
+            let mut paths: Vec<String> = sub_matches
+                .get_many::<String>("FILE")
+                .map(|files| files.cloned().collect())
+                .unwrap_or_default();
+            let recurse = sub_matches.get_one::<String>("recurse");
+            if let Some(dir) = recurse {
+                collect_adf_files(Path::new(dir), &mut paths)?;
+            }
+
+            if let ([file_path], None) = (paths.as_slice(), recurse) {
+                let adf = load_adf(file_path)?;
+                let info = adf.information()?;
+                match output {
+                    "text" => print_disk_info(&info, file_path),
+                    format => print_structured(format, std::slice::from_ref(&info))?,
+                }
+            } else {
+                let reports = batch_info(&paths, &cancel_on_interrupt()?)?;
+                match output {
+                    "text" => {
+                        for report in &reports {
+                            match (&report.info, &report.error) {
+                                (Some(info), _) => print_disk_info(info, &report.path),
+                                (_, Some(error)) => println!("{}: {}", report.path, error),
+                                _ => {}
+                            }
+                            println!();
+                        }
+                        let failed = reports.iter().filter(|r| r.error.is_some()).count();
+                        println!("{} files, {} failed", reports.len(), failed);
+                    }
+                    "json" => println!("{}", serde_json::to_string_pretty(&reports)?),
+                    format => {
+                        let rows: Vec<InfoRow> = reports.iter().map(InfoRow::from).collect();
+                        print_structured(format, &rows)?
+                    }
+                }
+            }
+        }
+        Some(("identify", sub_matches)) => {
+            let output = output_format(output, sub_matches);
+            let mut paths: Vec<String> = sub_matches
+                .get_many::<String>("FILE")
+                .map(|files| files.cloned().collect())
+                .unwrap_or_default();
+            if let Some(dir) = sub_matches.get_one::<String>("recurse") {
+                collect_adf_files(Path::new(dir), &mut paths)?;
+            }
+            let dat = sub_matches
+                .get_one::<String>("dat")
+                .map(Dat::from_file)
+                .transpose()?;
+
+            let bar = progress_bar("Hashing");
+            bar.set_length(paths.len() as u64);
+            let rows: Vec<IdentifyRow> = paths
+                .iter()
+                .map(|path| {
+                    let row = identify(path, dat.as_ref());
+                    bar.inc(1);
+                    row
+                })
+                .collect();
+            bar.finish_and_clear();
+
+            match output {
+                "text" => {
+                    for row in &rows {
+                        println!("{}", row.path);
+                        if let Some(error) = &row.error {
+                            println!("  Error: {}", error);
+                        } else {
+                            println!("  Size:  {}", row.size.unwrap_or_default());
+                            println!("  CRC32: {}", row.crc32.as_deref().unwrap_or_default());
+                            println!("  MD5:   {}", row.md5.as_deref().unwrap_or_default());
+                            println!("  SHA1:  {}", row.sha1.as_deref().unwrap_or_default());
+                        }
+                        if dat.is_some() {
+                            match (&row.title, &row.rom) {
+                                (Some(title), Some(rom)) => {
+                                    println!("  Match: {} ({})", title, rom)
+                                }
+                                _ => println!("  Match: none"),
+                            }
+                        }
+                    }
+                    if let (Some(_), true) = (&dat, rows.len() > 1) {
+                        let matched = rows.iter().filter(|r| r.title.is_some()).count();
+                        println!("{} files, {} matched", rows.len(), matched);
+                    }
+                }
+                format => print_structured(format, &rows)?,
+            }
+        }
+        Some(("diskset", sub_matches)) => {
+            let output = output_format(output, sub_matches);
+            let paths: Vec<&String> = sub_matches.get_many::<String>("FILE").unwrap().collect();
+            let sets = DiskSet::group(&paths)?;
+            if let Some(path) = sub_matches.get_one::<String>("find") {
+                for set in &sets {
+                    let (member, _) = set.resolve(path)?;
+                    println!(
+                        "{}: disk {} ({})",
+                        set.title,
+                        member.number,
+                        member.path.display()
+                    );
+                }
+                return Ok(());
+            }
+            let manifests = sets
+                .iter()
+                .map(DiskSet::manifest)
+                .collect::<Result<Vec<_>, _>>()?;
+            match output {
+                "text" => {
+                    for manifest in &manifests {
+                        println!("{} ({} disks)", manifest.title, manifest.total);
+                        for disk in &manifest.disks {
+                            println!(
+                                "  Disk {}: {} [{}] {} files, SHA1 {}",
+                                disk.number, disk.file, disk.label, disk.files, disk.sha1
+                            );
+                        }
+                        for number in &manifest.missing {
+                            println!("  Disk {}: missing", number);
+                        }
+                    }
+                }
+                "json" => println!("{}", serde_json::to_string_pretty(&manifests)?),
+                format => {
+                    let disks: Vec<_> = manifests.iter().flat_map(|m| m.disks.clone()).collect();
+                    print_structured(format, &disks)?
+                }
+            }
+            for set in &sets {
+                if let Err(e) = set.validate() {
+                    status!("{}", e);
+                    std::process::exit(EXIT_CHECK_FAILED.into());
+                }
+            }
+        }
+        Some(("provenance", sub_matches)) => {
+            let output = output_format(output, sub_matches);
             let file_path = sub_matches.get_one::<String>("FILE").unwrap();
-            let adf = ADF::from_file(file_path)?;
-            let info = adf.information()?;
-            print_disk_info(&info, file_path);
+            let data = std::fs::read(file_path)?;
+            let image = Path::new(file_path);
+            let setters = ["source", "dumped", "dumper"]
+                .map(|name| sub_matches.get_one::<String>(name).cloned());
+            if setters.iter().any(Option::is_some) {
+                let format = if sub_matches.get_flag("json") {
+                    SidecarFormat::Json
+                } else {
+                    SidecarFormat::Toml
+                };
+                let path = Provenance::find(image)
+                    .unwrap_or_else(|| Provenance::sidecar_path(image, format));
+                let mut provenance = match path.is_file() {
+                    true => Provenance::read(&path)?,
+                    false => Provenance::new(&data),
+                };
+                let [source, dumped, dumper] = setters;
+                provenance.source = source.or(provenance.source);
+                provenance.dumped = dumped.or(provenance.dumped);
+                provenance.dumper = dumper.or(provenance.dumper);
+                provenance.write(&path)?;
+                status!("Wrote {}", path.display());
+                return Ok(());
+            }
+            let provenance = Provenance::load(image)?.ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "{} has no provenance sidecar, create one with --source",
+                        file_path
+                    ),
+                )
+            })?;
+            match output {
+                "text" => {
+                    for (label, value) in [
+                        ("Source", &provenance.source),
+                        ("Dumped", &provenance.dumped),
+                        ("Dumper", &provenance.dumper),
+                    ] {
+                        println!("{}: {}", label, value.as_deref().unwrap_or("unknown"));
+                    }
+                    println!("SHA1:   {}", provenance.sha1);
+                    for event in &provenance.history {
+                        println!("{}  {}", event.date, event.action);
+                    }
+                }
+                "json" => println!("{}", serde_json::to_string_pretty(&provenance)?),
+                format => print_structured(format, &provenance.history)?,
+            }
+            if !provenance.matches(&data) {
+                status!("{} no longer matches the hashes in its sidecar", file_path);
+                std::process::exit(EXIT_CHECK_FAILED.into());
+            }
+        }
+        Some(("metadata", sub_matches)) => {
+            let mut paths: Vec<String> = sub_matches
+                .get_many::<String>("FILE")
+                .map(|files| files.cloned().collect())
+                .unwrap_or_default();
+            if let Some(dir) = sub_matches.get_one::<String>("recurse") {
+                collect_adf_files(Path::new(dir), &mut paths)?;
+            }
+            let mut stdout = std::io::stdout().lock();
+            for path in paths {
+                let report = match load_adf(&path).and_then(|adf| adf.extract_metadata()) {
+                    Ok(metadata) => MetadataReport {
+                        path,
+                        metadata: Some(metadata),
+                        error: None,
+                    },
+                    Err(error) => MetadataReport {
+                        path,
+                        metadata: None,
+                        error: Some(error.to_string()),
+                    },
+                };
+                writeln!(stdout, "{}", serde_json::to_string(&report)?)?;
+            }
         }
         Some(("bitmap", sub_matches)) => match sub_matches.subcommand() {
             Some(("info", info_matches)) => {
+                let output = output_format(output, info_matches);
                 let file_path = info_matches.get_one::<String>("FILE").unwrap();
                 let full = info_matches.get_flag("full");
-                let adf = ADF::from_file(file_path)?;
-                let bitmap_info = adf.get_bitmap_info();
-                println!("Bitmap information for {}:", file_path);
-                display_bitmap_info(&bitmap_info, full);
+                let adf = load_adf(file_path)?;
+                match output {
+                    "text" => {
+                        println!("Bitmap information for {}:", file_path);
+                        display_bitmap_info(&adf, full);
+                    }
+                    format => print_structured(format, &[adf.get_bitmap_info()])?,
+                }
+            }
+            Some(("render", render_matches)) => {
+                let file_path = render_matches.get_one::<String>("FILE").unwrap();
+                let png_path = render_matches.get_one::<String>("output").unwrap();
+                let adf = load_adf(file_path)?;
+                let image = adf.render_bitmap(render_matches.get_flag("by-file"));
+                write_output(png_path, &image.to_png()?)?;
+                status!(
+                    "Rendered {} blocks to {} ({}x{})",
+                    adf.num_blocks(),
+                    png_path,
+                    image.width,
+                    image.height
+                );
             }
             Some(("set", set_matches)) => {
                 let file_path = set_matches.get_one::<String>("FILE").unwrap();
                 let block = set_matches.get_one::<String>("BLOCK").unwrap();
                 let status = set_matches.get_one::<String>("STATUS").unwrap();
-                let mut adf = ADF::from_file(file_path)?;
+                let mut adf = load_adf_for_update(file_path)?;
                 let block_index = block.parse::<usize>()?;
                 let status = status.parse::<bool>()?;
                 adf.set_block_status(block_index, status)?;
-                adf.write_to_file(file_path)?;
-                println!("Block {} set to {}", block_index, status);
+                save_adf(&mut adf, file_path)?;
+                status!("Block {} set to {}", block_index, status);
             }
             Some(("defragment", defragment_matches)) => {
                 let file_path = defragment_matches.get_one::<String>("FILE").unwrap();
-                let mut adf = ADF::from_file(file_path)?;
+                let mut adf = load_adf_for_update(file_path)?;
+                adf.defragment()?;
+                save_adf(&mut adf, file_path)?;
+                status!("ADF file defragmented");
+            }
+            _ => unreachable!("Exhaustive subcommand matching should prevent this"),
+        },
+        Some(("setdate", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let path = sub_matches.get_one::<String>("PATH").unwrap();
+
+            let time = if sub_matches.get_flag("now") {
+                SystemTime::now()
+            } else if let Some(host_file) = sub_matches.get_one::<String>("from-file") {
+                std::fs::metadata(host_file)?.modified()?
+            } else {
+                parse_date(sub_matches.get_one::<String>("DATE").unwrap())?
+            };
+
+            let mut adf = load_adf_for_update(file_path)?;
+            adf.set_date(path, time)?;
+            save_adf(&mut adf, file_path)?;
+
+            let date = DateTime::<Utc>::from(time).format("%Y-%m-%d %H:%M:%S UTC");
+            status!("Set date of {} to {}", path, date);
+        }
+        Some(("protect", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let path = sub_matches.get_one::<String>("PATH").unwrap();
+            let mut adf = load_adf_for_update(file_path)?;
+
+            let flags = sub_matches
+                .get_many::<String>("FLAGS")
+                .unwrap()
+                .try_fold(adf.get_protection(path)?, |flags, spec| {
+                    parse_protection_flags(spec, flags)
+                })?;
+            adf.set_protection(path, flags)?;
+            save_adf(&mut adf, file_path)?;
+
+            status!(
+                "Set protection of {} to {}",
+                path,
+                adf.format_protection_flags(flags)
+            );
+        }
+        Some(("comment", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let path = sub_matches.get_one::<String>("PATH").unwrap();
+            let text = sub_matches
+                .get_one::<String>("TEXT")
+                .map(String::as_str)
+                .unwrap_or("");
+
+            let mut adf = load_adf_for_update(file_path)?;
+            adf.set_comment(path, text)?;
+            save_adf(&mut adf, file_path)?;
+
+            if text.is_empty() {
+                status!("Cleared comment of {}", path);
+            } else {
+                status!("Set comment of {} to \"{}\"", path, text);
+            }
+        }
+        Some(("doctor", sub_matches)) => {
+            let output = output_format(output, sub_matches);
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let report = load_adf(file_path)?.doctor();
+            match output {
+                "text" => {
+                    for check in &report.checks {
+                        let code = match check.severity {
+                            Severity::Error => "31",
+                            Severity::Warning => "33",
+                            Severity::Info => "32",
+                        };
+                        println!(
+                            "{:<14} {}  {}",
+                            check.name,
+                            paint(&format!("{:<7}", check.severity), code),
+                            check.summary
+                        );
+                    }
+                }
+                "json" => print_structured(output, std::slice::from_ref(&report))?,
+                format => print_structured(format, &report.checks)?,
+            }
+            if report.severity() == Severity::Error {
+                std::process::exit(EXIT_CHECK_FAILED.into());
+            }
+        }
+        Some(("fsck", sub_matches)) => {
+            let output = output_format(output, sub_matches);
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let mut adf = load_adf_for_update(file_path)?;
+
+            let text = output == "text";
+            if text {
+                status!("Checking {}", file_path);
+            }
+            let bar = progress_bar("Checking");
+            let cancel = cancel_on_interrupt()?;
+            let mut report = adf.validate_cancellable(
+                &mut |done, total| {
+                    bar.set_length(total as u64);
+                    bar.set_position(done as u64);
+                },
+                &cancel,
+            )?;
+            bar.finish_and_clear();
+            if text {
+                for finding in &report.findings {
+                    status!("{}", paint_finding(finding));
+                }
+            }
+
+            if sub_matches.get_flag("repair") && report.has_problems() {
+                report = adf.repair()?;
+                save_adf(&mut adf, file_path)?;
+                if text {
+                    status!("Repaired {}, remaining findings:", file_path);
+                    for finding in &report.findings {
+                        status!("{}", paint_finding(finding));
+                    }
+                }
+            }
+
+            match output {
+                "text" => status!(
+                    "{} errors, {} warnings",
+                    report.count(Severity::Error),
+                    report.count(Severity::Warning)
+                ),
+                "json" => print_structured(output, std::slice::from_ref(&report))?,
+                format => print_structured(format, &report.findings)?,
+            }
+            if report.has_problems() {
+                std::process::exit(EXIT_CHECK_FAILED.into());
+            }
+        }
+        Some(("badblocks", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let mark: Vec<usize> = sub_matches
+                .get_many::<usize>("mark")
+                .map(|blocks| blocks.copied().collect())
+                .unwrap_or_default();
+            let clear: Vec<usize> = sub_matches
+                .get_many::<usize>("clear")
+                .map(|blocks| blocks.copied().collect())
+                .unwrap_or_default();
+            if mark.is_empty() && clear.is_empty() {
+                let bad = load_adf(file_path)?.bad_blocks()?;
+                for block in &bad {
+                    println!("{}", block);
+                }
+                status!("{} bad blocks", bad.len());
+            } else {
+                let mut adf = load_adf_for_update(file_path)?;
+                for &block in &mark {
+                    adf.mark_bad(block)?;
+                }
+                for &block in &clear {
+                    adf.clear_bad(block)?;
+                }
+                save_adf(&mut adf, file_path)?;
+                status!(
+                    "Marked {} and cleared {} bad blocks on {}",
+                    mark.len(),
+                    clear.len(),
+                    file_path
+                );
+            }
+        }
+        Some(("pack", sub_matches)) => {
+            let source = sub_matches.get_one::<String>("SOURCE").unwrap();
+            let output = sub_matches.get_one::<String>("OUTPUT").unwrap();
+            let label = match sub_matches.get_one::<String>("label") {
+                Some(label) => label.clone(),
+                None => Path::new(source)
+                    .canonicalize()?
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| "Untitled".to_string()),
+            };
+            let disk_type = if sub_matches.get_flag("ffs") {
+                DiskType::FFS
+            } else {
+                DiskType::OFS
+            };
+
+            let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+            if sub_matches.get_flag("deterministic") {
+                adf.set_fixed_time(Some(source_date_epoch()?));
+            }
+            adf.format(disk_type, &label)?;
+            if sub_matches.get_flag("bootable") {
+                adf.install_bootblock_for(kickstart(sub_matches)?)?;
+            }
+            let bar = progress_bar("Packing");
+            let cancel = cancel_on_interrupt()?;
+            adf.import_dir_cancellable(
+                source,
+                "",
+                &mut |done, total| {
+                    bar.set_length(total as u64);
+                    bar.set_position(done as u64);
+                },
+                &cancel,
+            )?;
+            bar.finish_and_clear();
+            if output.to_lowercase().ends_with(".zip") {
+                let inner_name = Path::new(output).with_extension("adf");
+                let inner_name = inner_name.file_name().unwrap().to_string_lossy();
+                let file = BufWriter::new(File::create(output)?);
+                adf.save_to_zip(file, &inner_name, CompressionMethod::Deflated)?;
+            } else {
+                save_adf(&mut adf, output)?;
+            }
+
+            let info = adf.bitmap_counts();
+            status!(
+                "Packed {} into {} (Type: {:?}, Name: {}, {} blocks free)",
+                source,
+                output,
+                disk_type,
+                label,
+                info.free_blocks
+            );
+        }
+        Some(("unpack", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let output = sub_matches.get_one::<String>("OUTPUT").unwrap();
+            let adf = load_adf(file_path)?;
+
+            let bar = progress_bar("Unpacking");
+            adf.export_dir_with_progress("", output, &mut |done, total| {
+                bar.set_length(total as u64);
+                bar.set_position(done as u64);
+            })?;
+            bar.finish_and_clear();
+            let entries = adf.walk("")?;
+
+            // A .json sidecar gets an array of objects, anything else tab separated values.
+            if let Some(metadata_path) = sub_matches.get_one::<String>("metadata") {
+                let rows = entries.iter().map(|(path, info)| {
+                    let date = DateTime::<Utc>::from(info.creation_date)
+                        .format("%Y-%m-%d %H:%M:%S")
+                        .to_string();
+                    (
+                        path,
+                        adf.format_protection_flags(info.protection),
+                        date,
+                        &info.comment,
+                    )
+                });
+                let mut metadata = BufWriter::new(File::create(metadata_path)?);
+                if metadata_path.to_lowercase().ends_with(".json") {
+                    let rows: Vec<_> = rows
+                        .map(|(path, protection, date, comment)| {
+                            serde_json::json!({
+                                "path": path,
+                                "protection": protection,
+                                "date": date,
+                                "comment": comment,
+                            })
+                        })
+                        .collect();
+                    writeln!(metadata, "{}", serde_json::to_string_pretty(&rows)?)?;
+                } else {
+                    writeln!(metadata, "# path\tprotection\tdate\tcomment")?;
+                    for (path, protection, date, comment) in rows {
+                        writeln!(
+                            metadata,
+                            "{}\t{}\t{}\t{}",
+                            tsv_field(path),
+                            protection,
+                            date,
+                            tsv_field(comment)
+                        )?;
+                    }
+                }
+                metadata.flush()?;
+            }
+
+            println!(
+                "Unpacked {} entries from {} into {}",
+                entries.len(),
+                file_path,
+                output
+            );
+        }
+        Some(("split", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let dir = Path::new(sub_matches.get_one::<String>("DIR").unwrap());
+            let tracks = load_adf(file_path)?.split_tracks()?;
+            std::fs::create_dir_all(dir)?;
+            for (index, track) in tracks.iter().enumerate() {
+                std::fs::write(dir.join(format!("track{:03}.bin", index)), track)?;
+            }
+            status!(
+                "Split {} into {} tracks in {}",
+                file_path,
+                tracks.len(),
+                dir.display()
+            );
+        }
+        Some(("join", sub_matches)) => {
+            let output_path = sub_matches.get_one::<String>("OUTPUT").unwrap();
+            let tracks = sub_matches
+                .get_many::<String>("TRACKS")
+                .unwrap()
+                .map(|path| {
+                    let data = std::fs::read(path)?;
+                    data.try_into().map_err(|data: Vec<u8>| {
+                        std::io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "{} has {} bytes, a track has {}",
+                                path,
+                                data.len(),
+                                ADF_TRACK_SIZE
+                            ),
+                        )
+                    })
+                })
+                .collect::<std::io::Result<Vec<_>>>()?;
+            let count = tracks.len();
+            let mut adf = ADF::from_tracks(tracks)?;
+            save_adf(&mut adf, output_path)?;
+            status!("Joined {} tracks into {}", count, output_path);
+        }
+        Some(("manifest", sub_matches)) => {
+            let output = output_format(output, sub_matches);
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let algo: HashAlgo = sub_matches.get_one::<String>("algo").unwrap().parse()?;
+            let manifest = load_adf(file_path)?.manifest(algo)?;
+            match output {
+                "text" => {
+                    for entry in &manifest {
+                        println!("{}  {}", entry.hash, entry.path);
+                    }
+                }
+                format => print_structured(format, &manifest)?,
+            }
+        }
+        Some(("verify", sub_matches)) => {
+            let output = output_format(output, sub_matches);
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let host_dir = sub_matches.get_one::<String>("DIR").unwrap();
+            let adf = load_adf(file_path)?;
+            let report = verify::against_dir(&adf, host_dir)?;
+            match output {
+                "text" => {
+                    for mismatch in &report.mismatches {
+                        let detail = match mismatch.kind {
+                            MismatchKind::OnlyOnImage => "only on the image".to_string(),
+                            MismatchKind::OnlyOnHost => "only on the host".to_string(),
+                            MismatchKind::Type => {
+                                "file on one side, directory on the other".to_string()
+                            }
+                            MismatchKind::Size => format!(
+                                "{} bytes on the image, {} on the host",
+                                mismatch.image_size.unwrap_or_default(),
+                                mismatch.host_size.unwrap_or_default()
+                            ),
+                            MismatchKind::Content => "contents differ".to_string(),
+                        };
+                        println!("{}: {}", mismatch.path, detail);
+                    }
+                    println!(
+                        "{} files match, {} differences",
+                        report.matched,
+                        report.mismatches.len()
+                    );
+                }
+                format => print_structured(format, &report.mismatches)?,
+            }
+            if !report.is_match() {
+                std::process::exit(EXIT_CHECK_FAILED.into());
+            }
+        }
+        Some(("copy", sub_matches)) => {
+            let source = sub_matches.get_one::<String>("SOURCE").unwrap();
+            let dest = sub_matches.get_one::<String>("DEST").unwrap();
+            copy_command(source, dest)?;
+        }
+        Some(("sync", sub_matches)) => {
+            let source = sub_matches.get_one::<String>("SOURCE").unwrap();
+            let dest = sub_matches.get_one::<String>("DEST").unwrap();
+            let report = match (parse_location(source), parse_location(dest)) {
+                (Location::Host(host_dir), Location::Adf(image, dir)) => {
+                    let mut adf = load_adf_for_update(image)?;
+                    let report = adf.sync_from_host(host_dir, dir)?;
+                    if !report.copied.is_empty() {
+                        save_adf(&mut adf, image)?;
+                    }
+                    report
+                }
+                (Location::Adf(image, dir), Location::Host(host_dir)) => {
+                    load_adf(image)?.sync_to_host(dir, host_dir)?
+                }
+                _ => return Err("sync needs one host directory and one ADF location".into()),
+            };
+            for path in &report.copied {
+                status!("Copied {}", path);
+            }
+            status!(
+                "{} files copied, {} unchanged",
+                report.copied.len(),
+                report.unchanged
+            );
+        }
+        Some(("mv", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let from = sub_matches.get_one::<String>("FROM").unwrap();
+            let to = sub_matches.get_one::<String>("TO").unwrap();
+
+            let mut adf = load_adf_for_update(file_path)?;
+            adf.rename(from, to)?;
+            save_adf(&mut adf, file_path)?;
+
+            status!("Moved {} to {}", from, to);
+        }
+        Some(("find", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let pattern_text = sub_matches.get_one::<String>("PATTERN").unwrap();
+            let pattern = Pattern::new(pattern_text)?;
+            let full_path = pattern_text.contains('/');
+
+            let adf = load_adf(file_path)?;
+            let mut matches = 0;
+            for (path, info) in adf.walk("")? {
+                let subject = if full_path { &path } else { &info.name };
+                if !pattern.is_match(subject) {
+                    continue;
+                }
+                let size = if info.is_dir {
+                    "<dir>".to_string()
+                } else {
+                    info.size.to_string()
+                };
+                let date = DateTime::<Utc>::from(info.creation_date).format("%Y-%m-%d %H:%M:%S");
+                println!("{:<40} {:>8}  {}", path, size, date);
+                matches += 1;
+            }
+            println!("{} matches", matches);
+        }
+        Some(("du", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let path = sub_matches
+                .get_one::<String>("PATH")
+                .map(String::as_str)
+                .unwrap_or("");
+
+            let adf = load_adf(file_path)?;
+            let total_blocks = adf.bitmap_counts().total_blocks as f64;
+            println!("{:>7} {:>10} {:>7}  Directory", "Blocks", "Bytes", "Disk");
+            for usage in adf.disk_usage(path)? {
+                let name = if usage.path.is_empty() {
+                    "/"
+                } else {
+                    usage.path.as_str()
+                };
+                println!(
+                    "{:>7} {:>10} {:>6.1}%  {}",
+                    usage.blocks,
+                    usage.bytes,
+                    usage.blocks as f64 * 100.0 / total_blocks,
+                    name
+                );
+            }
+        }
+        Some(("search", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let needle = match sub_matches.get_one::<String>("hex") {
+                Some(hex) => parse_hex(hex)?,
+                None => sub_matches
+                    .get_one::<String>("text")
+                    .unwrap()
+                    .chars()
+                    .map(|c| u8::try_from(c as u32).map_err(|_| "Text must be Latin-1"))
+                    .collect::<Result<Vec<u8>, _>>()?,
+            };
+
+            let adf = load_adf(file_path)?;
+            let owners = adf.block_owners()?;
+            let matches = adf.search(&needle);
+            for offset in &matches {
+                let sector = offset / ADF_SECTOR_SIZE;
+                let owner = owners
+                    .get(&sector)
+                    .map(String::as_str)
+                    .unwrap_or("<unused>");
+                println!(
+                    "0x{:06x}  sector {:>4} +0x{:03x}  {}",
+                    offset,
+                    sector,
+                    offset % ADF_SECTOR_SIZE,
+                    owner
+                );
+            }
+            println!("{} matches", matches.len());
+        }
+        Some(("strings", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let min_len = *sub_matches.get_one::<usize>("min-len").unwrap();
+            let adf = load_adf(file_path)?;
+
+            match sub_matches.get_one::<String>("PATH") {
+                Some(path) => {
+                    let contents = adf.read_file(path)?;
+                    let blocks = adf.data_blocks(path)?;
+                    let (block_size, header_size) = if adf.is_ffs() {
+                        (ADF_SECTOR_SIZE, 0)
+                    } else {
+                        (ADF_SECTOR_SIZE - 24, 24)
+                    };
+                    for (offset, text) in find_strings(&contents, min_len) {
+                        let sector = blocks.get(offset / block_size).copied().unwrap_or(0);
+                        println!(
+                            "0x{:06x}  sector {:>4} +0x{:03x}  {}",
+                            offset,
+                            sector,
+                            header_size + offset % block_size,
+                            text
+                        );
+                    }
+                }
+                None => {
+                    for (offset, text) in find_strings(&adf.data, min_len) {
+                        println!(
+                            "0x{:06x}  sector {:>4} +0x{:03x}  {}",
+                            offset,
+                            offset / ADF_SECTOR_SIZE,
+                            offset % ADF_SECTOR_SIZE,
+                            text
+                        );
+                    }
+                }
+            }
+        }
+        Some(("dump", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let offset = *sub_matches.get_one::<usize>("offset").unwrap();
+            let adf = load_adf(file_path)?;
+
+            if let Some(spec) = sub_matches.get_one::<String>("decode") {
+                let decoded = decode_block(&adf, spec)?;
+                match output_format(output, sub_matches) {
+                    "text" => print_decoded_block(&adf, &decoded),
+                    "csv" => return Err("Decoded blocks cannot be written as CSV".into()),
+                    format => print_structured(format, &[decoded])?,
+                }
+                return Ok(());
+            }
+
+            let (data, base) = if let Some(path) = sub_matches.get_one::<String>("file") {
+                (adf.read_file(path)?, 0)
+            } else if let Some(block) = sub_matches.get_one::<String>("block") {
+                let (start, len) = adf.block_range(block)?;
+                (adf.data[start..start + len].to_vec(), start)
+            } else {
+                (adf.data.clone(), 0)
+            };
+
+            if offset > data.len() {
+                return Err(
+                    format!("Offset {} is beyond the end ({} bytes)", offset, data.len()).into(),
+                );
+            }
+            let end = match sub_matches.get_one::<usize>("length") {
+                Some(&length) => (offset + length).min(data.len()),
+                None => data.len(),
+            };
+            print!("{}", hex_dump(&data[offset..end], base + offset));
+        }
+        Some(("block", sub_matches)) => match sub_matches.subcommand() {
+            Some(("write", write_matches)) => {
+                let file_path = write_matches.get_one::<String>("FILE").unwrap();
+                let block = write_matches.get_one::<String>("BLOCK").unwrap();
+                let mut adf = load_adf_for_update(file_path)?;
+                let (start, len) = adf.block_range(block)?;
+
+                let data = match write_matches.get_one::<usize>("fill") {
+                    Some(&fill) => vec![u8::try_from(fill)?; len],
+                    None => read_input(write_matches.get_one::<String>("from").unwrap())?,
+                };
+                if data.len() != len {
+                    return Err(format!(
+                        "Block data must be exactly {} bytes, got {}",
+                        len,
+                        data.len()
+                    )
+                    .into());
+                }
+
+                let first = start / ADF_SECTOR_SIZE;
+                let reserved = (first..first + len / ADF_SECTOR_SIZE)
+                    .any(|sector| adf.is_reserved_block(sector));
+                if reserved
+                    && !write_matches.get_flag("force")
+                    && !confirm(&format!("Block {} is reserved. Overwrite it?", first))?
+                {
+                    return Err(std::io::Error::new(
+                        ErrorKind::Interrupted,
+                        format!("Aborted, block {} left unchanged", first),
+                    )
+                    .into());
+                }
+
+                for (i, chunk) in data.chunks(ADF_SECTOR_SIZE).enumerate() {
+                    adf.write_sector(first + i, chunk)?;
+                }
+                save_adf(&mut adf, file_path)?;
+                status!("Wrote {} bytes to block {}", len, first);
+            }
+            _ => unreachable!("Exhaustive subcommand matching should prevent this"),
+        },
+        Some(("patch", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let sector = *sub_matches.get_one::<usize>("sector").unwrap();
+            let offset = *sub_matches.get_one::<usize>("offset").unwrap();
+            let bytes = parse_hex(sub_matches.get_one::<String>("bytes").unwrap())?;
+            let expected = sub_matches
+                .get_one::<String>("verify-old")
+                .map(|hex| parse_hex(hex))
+                .transpose()?;
+
+            let mut adf = load_adf_for_update(file_path)?;
+            let old = adf.patch_sector(sector, offset, &bytes, expected.as_deref())?;
+            if sub_matches.get_flag("fix-checksum") {
+                adf.fix_checksum(sector)?;
+            }
+            save_adf(&mut adf, file_path)?;
+
+            let format_bytes = |bytes: &[u8]| {
+                bytes
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            status!(
+                "Patched sector {} offset 0x{:03x}: {} -> {}",
+                sector,
+                offset,
+                format_bytes(&old),
+                format_bytes(&bytes)
+            );
+        }
+        Some(("bootblock", sub_matches)) => match sub_matches.subcommand() {
+            Some(("show", show_matches)) => {
+                let output = output_format(output, show_matches);
+                let file_path = show_matches.get_one::<String>("FILE").unwrap();
+                let adf = load_adf(file_path)?;
+                let info = adf.boot_block_info();
+                match output {
+                    "text" => {
+                        println!("Boot block of {}:", file_path);
+                        println!("{}", info);
+                        for warning in &info.warnings {
+                            println!("Warning: {}", warning);
+                        }
+                        if show_matches.get_flag("hex") {
+                            print!("{}", hex_dump(&adf.data[..BOOT_BLOCK_SIZE], 0));
+                        }
+                    }
+                    format => print_structured(format, std::slice::from_ref(&info))?,
+                }
+            }
+            Some(("export", export_matches)) => {
+                let file_path = export_matches.get_one::<String>("FILE").unwrap();
+                let output_path = export_matches.get_one::<String>("OUTPUT").unwrap();
+                let adf = load_adf(file_path)?;
+                write_output(output_path, &adf.data[..BOOT_BLOCK_SIZE])?;
+                status!("Boot block written to {}", output_path);
+            }
+            Some(("install", install_matches)) => {
+                let file_path = install_matches.get_one::<String>("FILE").unwrap();
+                let mut adf = load_adf_for_update(file_path)?;
+                let kickstart = kickstart(install_matches)?;
+                adf.install_bootblock_for(kickstart)?;
+                save_adf(&mut adf, file_path)?;
+                status!(
+                    "Installed Kickstart {} boot code on {}",
+                    kickstart,
+                    file_path
+                );
+            }
+            Some(("scan", scan_matches)) => {
+                let file_path = scan_matches.get_one::<String>("FILE").unwrap();
+                let adf = load_adf(file_path)?;
+                let info = adf.boot_block_info();
+                println!("Boot code: {}", info.code);
+                if info.warnings.is_empty() {
+                    println!("No suspicious code found");
+                } else {
+                    for warning in &info.warnings {
+                        println!("Warning: {}", warning);
+                    }
+                    std::process::exit(EXIT_CHECK_FAILED.into());
+                }
+            }
+            Some(("checksum", checksum_matches)) => {
+                let file_path = checksum_matches.get_one::<String>("FILE").unwrap();
+                let mut adf = load_adf_for_update(file_path)?;
+                let info = adf.boot_block_info();
+                if info.checksum_valid() {
+                    status!("Boot block checksum 0x{:08x} is valid", info.checksum);
+                } else if checksum_matches.get_flag("fix") {
+                    adf.fix_checksum(0)?;
+                    save_adf(&mut adf, file_path)?;
+                    status!(
+                        "Boot block checksum fixed: 0x{:08x} -> 0x{:08x}",
+                        info.checksum,
+                        info.computed_checksum
+                    );
+                } else {
+                    status!(
+                        "Boot block checksum 0x{:08x} is invalid, expected 0x{:08x}",
+                        info.checksum,
+                        info.computed_checksum
+                    );
+                    std::process::exit(EXIT_CHECK_FAILED.into());
+                }
+            }
+            _ => unreachable!("Exhaustive subcommand matching should prevent this"),
+        },
+        Some(("browse", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let adf = ADF::from_file(file_path)?;
+            if let Some(mut adf) = browse::browse(adf, file_path)? {
+                save_adf(&mut adf, file_path)?;
+                status!("Saved changes to {}", file_path);
+            }
+        }
+        Some(("dms", sub_matches)) => match sub_matches.subcommand() {
+            Some(("pack", pack_matches)) => {
+                let file_path = pack_matches.get_one::<String>("FILE").unwrap();
+                let output = pack_matches.get_one::<String>("OUTPUT").unwrap();
+                let mode = match pack_matches.get_one::<String>("mode").unwrap().as_str() {
+                    "none" => DmsMode::None,
+                    _ => DmsMode::Rle,
+                };
+                let read_text = |name: &str| {
+                    pack_matches
+                        .get_one::<String>(name)
+                        .map(std::fs::read)
+                        .transpose()
+                };
+                let extras = DmsExtras {
+                    banner: read_text("banner")?,
+                    file_id_diz: read_text("diz")?,
+                };
+                let adf = load_adf(file_path)?;
+                let dms = pack_dms_with_extras(
+                    &adf.data,
+                    mode,
+                    SystemTime::now(),
+                    &extras,
+                    &cancel_on_interrupt()?,
+                )?;
+                write_output(output, &dms)?;
+                status!(
+                    "Packed {} into {} ({} of {} bytes)",
+                    file_path,
+                    output,
+                    dms.len(),
+                    adf.data.len()
+                );
+            }
+            Some(("info", info_matches)) => {
+                let output = output_format(output, info_matches);
+                let file_path = info_matches.get_one::<String>("FILE").unwrap();
+                let dms = read_input(file_path)?;
+                let header = parse_header(&dms)?;
+                match output {
+                    "text" => {
+                        println!("{}", header);
+                        let extras = read_dms_extras(&dms)?;
+                        if let Some(banner) = extras.banner_text() {
+                            println!("\nBanner:\n{}", banner.trim_end());
+                        }
+                        if let Some(diz) = extras.file_id_diz_text() {
+                            println!("\nFILE_ID.DIZ:\n{}", diz.trim_end());
+                        }
+                    }
+                    _ => print_structured(output, &[header])?,
+                }
+            }
+            Some(("verify", verify_matches)) => {
+                let output = output_format(output, verify_matches);
+                let file_path = verify_matches.get_one::<String>("FILE").unwrap();
+                let reports = verify_dms(&read_input(file_path)?)?;
+                match output {
+                    "text" => {
+                        println!("Track  Mode    Packed  Unpacked  Status");
+                        for report in &reports {
+                            let status = match report.status {
+                                TrackStatus::Ok => report.status.to_string(),
+                                _ => format!("{} ({})", report.status, report.detail),
+                            };
+                            println!(
+                                "{:>5}  {:<6}  {:>6}  {:>8}  {}",
+                                report.number,
+                                report.mode,
+                                report.packed_len,
+                                report.unpacked_len,
+                                status
+                            );
+                        }
+                    }
+                    _ => print_structured(output, &reports)?,
+                }
+                let count =
+                    |status: TrackStatus| reports.iter().filter(|r| r.status == status).count();
+                let corrupt = count(TrackStatus::Corrupt);
+                let unsupported = count(TrackStatus::Unsupported);
+                if output == "text" {
+                    println!(
+                        "{} tracks, {} OK, {} corrupt, {} unsupported",
+                        reports.len(),
+                        count(TrackStatus::Ok),
+                        corrupt,
+                        unsupported
+                    );
+                }
+                if corrupt > 0 {
+                    std::process::exit(EXIT_CHECK_FAILED.into());
+                } else if unsupported > 0 {
+                    std::process::exit(EXIT_UNSUPPORTED.into());
+                }
+            }
+            Some(("convert", convert_matches)) => {
+                let output = output_format(output, convert_matches);
+                let paths: Vec<&String> = convert_matches
+                    .get_many::<String>("FILES")
+                    .unwrap()
+                    .collect();
+                let out_dir = Path::new(convert_matches.get_one::<String>("to").unwrap());
+                let options = ConvertOptions {
+                    overwrite: convert_matches.get_flag("overwrite"),
+                    salvage: convert_matches.get_flag("salvage"),
+                    ..ConvertOptions::default()
+                };
+                let results =
+                    convert_all_cancellable(&paths, out_dir, &options, &cancel_on_interrupt()?)?;
+                match output {
+                    "text" => {
+                        println!("{:<16}  {:<40}  Output", "Status", "Archive");
+                        for result in &results {
+                            let detail = match result.status {
+                                ConvertStatus::Converted => {
+                                    result.output.clone().unwrap_or_default()
+                                }
+                                ConvertStatus::Salvaged => format!(
+                                    "{} ({})",
+                                    result.output.clone().unwrap_or_default(),
+                                    result.detail
+                                ),
+                                _ => result.detail.clone(),
+                            };
+                            println!(
+                                "{:<16}  {:<40}  {}",
+                                result.status.to_string(),
+                                result.path,
+                                detail
+                            );
+                        }
+                    }
+                    _ => print_structured(output, &results)?,
+                }
+                let count =
+                    |status: ConvertStatus| results.iter().filter(|r| r.status == status).count();
+                let corrupt = count(ConvertStatus::Corrupt);
+                let salvaged = count(ConvertStatus::Salvaged);
+                let unsupported = count(ConvertStatus::Unsupported);
+                let failed = count(ConvertStatus::Failed);
+                if output == "text" {
+                    println!(
+                        "{} archives, {} converted, {} salvaged, {} corrupt, {} unsupported, {} \
+                         failed",
+                        results.len(),
+                        count(ConvertStatus::Converted),
+                        salvaged,
+                        corrupt,
+                        unsupported,
+                        failed
+                    );
+                }
+                if corrupt + salvaged > 0 {
+                    std::process::exit(EXIT_CHECK_FAILED.into());
+                } else if unsupported > 0 {
+                    std::process::exit(EXIT_UNSUPPORTED.into());
+                } else if failed > 0 {
+                    std::process::exit(EXIT_IO_ERROR.into());
+                }
+            }
+            _ => unreachable!("Exhaustive subcommand matching should prevent this"),
+        },
+        Some(("hunk", sub_matches)) => match sub_matches.subcommand() {
+            Some(("extract", extract_matches)) => {
+                let file_path = extract_matches.get_one::<String>("FILE").unwrap();
+                let index = *extract_matches.get_one::<usize>("hunk").unwrap();
+                let data = match parse_location(file_path) {
+                    Location::Adf(image, path) => load_adf(image)?.read_file(path)?,
+                    Location::Host(path) => read_input(path)?,
+                };
+                let hunks = HunkParser::parse_bytes(&data)?;
+                let hunk = hunks.get(index).ok_or_else(|| {
+                    std::io::Error::new(
+                        ErrorKind::NotFound,
+                        format!("{} has {} hunks, no hunk {}", file_path, hunks.len(), index),
+                    )
+                })?;
+                let mut payload = Vec::new();
+                hunk.write_payload(&mut payload)?;
+                let output = extract_matches
+                    .get_one::<String>("OUTPUT")
+                    .map_or("-", String::as_str);
+                write_output(output, &payload)?;
+                status!(
+                    "Extracted {:?} hunk {} ({} bytes)",
+                    hunk.hunk_type,
+                    index,
+                    payload.len()
+                );
+            }
+            Some(("patch", patch_matches)) => {
+                let file_path = patch_matches.get_one::<String>("FILE").unwrap();
+                let index = *patch_matches.get_one::<usize>("hunk").unwrap();
+                let offset = *patch_matches.get_one::<usize>("offset").unwrap();
+                let bytes = parse_hex(patch_matches.get_one::<String>("bytes").unwrap())?;
+                let mut file = HunkFile::parse(read_input(file_path)?)?;
+                file.patch(index, offset, &bytes)?;
+                let output = patch_matches
+                    .get_one::<String>("OUTPUT")
+                    .unwrap_or(file_path);
+                write_output(output, file.as_bytes())?;
+                status!(
+                    "Patched {} bytes at offset {:#x} of hunk {}",
+                    bytes.len(),
+                    offset,
+                    index
+                );
+            }
+            _ => unreachable!("Exhaustive subcommand matching should prevent this"),
+        },
+        Some(("mount", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let mountpoint = sub_matches.get_one::<String>("MOUNTPOINT").unwrap();
+            #[cfg(feature = "fuse")]
+            {
+                let adf = load_adf(file_path)?;
+                let options = adflib::fuse::MountOptions {
+                    allow_other: sub_matches.get_flag("allow-other"),
+                    auto_unmount: sub_matches.get_flag("auto-unmount"),
+                };
+                status!("Mounted {} at {}", file_path, mountpoint);
+                adflib::fuse::mount(adf, mountpoint, &options)?;
+            }
+            #[cfg(not(feature = "fuse"))]
+            return Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Cannot mount {} at {}: built without the fuse feature",
+                    file_path, mountpoint
+                ),
+            )
+            .into());
+        }
+        Some(("gw", sub_matches)) => {
+            #[cfg(feature = "hardware")]
+            {
+                use adflib::greaseweazle::{Greaseweazle, ReadOptions, DEFAULT_PORT};
+                let port = sub_matches
+                    .get_one::<String>("port")
+                    .map_or(DEFAULT_PORT, String::as_str);
+                let drive = *sub_matches.get_one::<u8>("drive").unwrap();
+                let mut gw = Greaseweazle::open(port)?;
+                match sub_matches.subcommand() {
+                    Some(("info", _)) => {
+                        let info = gw.info();
+                        println!("Port:      {}", port);
+                        println!("Model:     F{}.{}", info.hw_model, info.hw_submodel);
+                        println!("Firmware:  {}.{}", info.major, info.minor);
+                        println!("Sample:    {} Hz", info.sample_freq);
+                    }
+                    Some(("read", read_matches)) => {
+                        let output = read_matches.get_one::<String>("OUTPUT").unwrap();
+                        let options = ReadOptions {
+                            drive,
+                            high_density: read_matches.get_flag("hd"),
+                            revolutions: *read_matches.get_one::<u16>("revs").unwrap(),
+                            retries: *read_matches.get_one::<usize>("retries").unwrap(),
+                        };
+                        let bar = progress_bar("Reading");
+                        let adf = gw.read_adf_with_progress(&options, &mut |done, total| {
+                            bar.set_length(total as u64);
+                            bar.set_position(done as u64);
+                        });
+                        bar.finish_and_clear();
+                        write_output(output, &adf?.data)?;
+                        status!("Read disk into {}", output);
+                    }
+                    Some(("write", write_matches)) => {
+                        let file_path = write_matches.get_one::<String>("FILE").unwrap();
+                        let adf = load_adf(file_path)?;
+                        let bar = progress_bar("Writing");
+                        let result = gw.write_adf_with_progress(&adf, drive, &mut |done, total| {
+                            bar.set_length(total as u64);
+                            bar.set_position(done as u64);
+                        });
+                        bar.finish_and_clear();
+                        result?;
+                        status!("Wrote {} to disk", file_path);
+                    }
+                    _ => unreachable!("Exhaustive subcommand matching should prevent this"),
+                }
+            }
+            #[cfg(not(feature = "hardware"))]
+            return Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Cannot use gw {}: built without the hardware feature",
+                    sub_matches.subcommand_name().unwrap_or_default()
+                ),
+            )
+            .into());
+        }
+        Some(("completions", sub_matches)) => {
+            let shell: Shell = sub_matches.get_one::<String>("SHELL").unwrap().parse()?;
+            let mut cmd = build_cli();
+            let name = cmd.get_bin_name().unwrap_or("adflibtesttool").to_string();
+            clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+        }
+        Some(("man", _)) => {
+            std::io::stdout().write_all(include_bytes!(concat!(
+                env!("OUT_DIR"),
+                "/adflibtesttool.1"
+            )))?;
+        }
+        Some(("icon", sub_matches)) => {
+            let output = output_format(output, sub_matches);
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let path = sub_matches.get_one::<String>("PATH").unwrap();
+            let adf = load_adf(file_path)?;
+            let icon = adf.read_icon(path)?;
+            match output {
+                "text" => {
+                    println!("Icon of {}:", path);
+                    println!("  Type:          {:?}", icon.icon_type);
+                    println!("  Size:          {}x{}", icon.width, icon.height);
+                    if let Some(tool) = &icon.default_tool {
+                        println!("  Default tool:  {}", tool);
+                    }
+                    if icon.stack_size > 0 {
+                        println!("  Stack:         {}", icon.stack_size);
+                    }
+                    if let (Some(x), Some(y)) = (icon.current_x, icon.current_y) {
+                        println!("  Position:      {},{}", x, y);
+                    }
+                    for tool_type in &icon.tool_types {
+                        println!("  Tool type:     {}", tool_type);
+                    }
+                }
+                "csv" => {
+                    let rows: Vec<ToolTypeRow> = icon
+                        .tool_types
+                        .iter()
+                        .map(|entry| {
+                            let (name, value) = entry.split_once('=').unwrap_or((entry, ""));
+                            ToolTypeRow { name, value }
+                        })
+                        .collect();
+                    print_structured("csv", &rows)?
+                }
+                format => print_structured(format, std::slice::from_ref(&icon))?,
+            }
+            if let Some(png_path) = sub_matches.get_one::<String>("png") {
+                let image = if sub_matches.get_flag("selected") {
+                    icon.selected_image.as_ref()
+                } else {
+                    icon.image.as_ref()
+                };
+                let image = image.ok_or_else(|| {
+                    std::io::Error::new(ErrorKind::NotFound, "Icon has no such image")
+                })?;
+                write_output(png_path, &image.to_bitmap().to_png()?)?;
+                status!(
+                    "Rendered icon to {} ({}x{})",
+                    png_path,
+                    image.width,
+                    image.height
+                );
+            }
+        }
+        Some(("preview", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let path = sub_matches.get_one::<String>("PATH").unwrap();
+            let png_path = sub_matches.get_one::<String>("OUTPUT").unwrap();
+            let adf = load_adf(file_path)?;
+            let picture = Ilbm::from_bytes(&adf.read_file(path)?)?;
+            write_output(png_path, &picture.to_bitmap().to_png()?)?;
+            status!(
+                "Rendered {} to {} ({}x{}, {} planes)",
+                path,
+                png_path,
+                picture.width,
+                picture.height,
+                picture.planes
+            );
+        }
+        Some(("optimize", sub_matches)) => {
+            let file_path = sub_matches.get_one::<String>("FILE").unwrap();
+            let scrub_pattern = fill_pattern(sub_matches.get_one::<String>("scrub-pattern"))?;
+            let scrub = sub_matches.get_flag("scrub-free") || !scrub_pattern.is_empty();
+            let mut defrag = sub_matches.get_flag("defrag");
+            let mut sort_dirs = sub_matches.get_flag("sort-dirs");
+            if !defrag && !sort_dirs && !scrub {
+                (defrag, sort_dirs) = (true, true);
+            }
+
+            let mut adf = load_adf_for_update(file_path)?;
+            let before = adf.fragmentation()?;
+            if defrag {
                 adf.defragment()?;
-                adf.write_to_file(file_path)?;
-                println!("ADF file defragmented");
+            }
+            if sort_dirs {
+                adf.sort_directories()?;
+            }
+            let scrubbed = if scrub {
+                adf.scrub_free_blocks(&scrub_pattern)
+            } else {
+                0
+            };
+            let after = adf.fragmentation()?;
+            save_adf(&mut adf, file_path)?;
+
+            status!("{:<20} {:>8} {:>8}", "", "Before", "After");
+            for (label, before, after) in [
+                ("Files", before.files, after.files),
+                (
+                    "Fragmented files",
+                    before.fragmented_files,
+                    after.fragmented_files,
+                ),
+                ("File extents", before.extents, after.extents),
+                ("Free extents", before.free_extents, after.free_extents),
+                (
+                    "Largest free extent",
+                    before.largest_free_extent,
+                    after.largest_free_extent,
+                ),
+            ] {
+                status!("{:<20} {:>8} {:>8}", label, before, after);
+            }
+            if scrub {
+                status!("Scrubbed {} free blocks", scrubbed);
+            }
+        }
+        Some(("template", sub_matches)) => match sub_matches.subcommand() {
+            Some(("list", list_matches)) => {
+                let rows: Vec<TemplateRow> = Template::all()?
+                    .into_iter()
+                    .map(|t| TemplateRow {
+                        source: t
+                            .origin
+                            .map_or("built-in".to_string(), |p| p.display().to_string()),
+                        name: t.name,
+                        description: t.description,
+                        filesystem: t.filesystem,
+                        size: t.size,
+                    })
+                    .collect();
+                match output_format(output, list_matches) {
+                    "text" => {
+                        println!("{:<16} {:<4} {:<4} Description", "Name", "FS", "Size");
+                        for row in &rows {
+                            println!(
+                                "{:<16} {:<4} {:<4} {}",
+                                row.name, row.filesystem, row.size, row.description
+                            );
+                        }
+                    }
+                    format => print_structured(format, &rows)?,
+                }
+            }
+            Some(("apply", apply_matches)) => {
+                let name = apply_matches.get_one::<String>("TEMPLATE").unwrap();
+                let file_path = apply_matches.get_one::<String>("FILE").unwrap();
+                let template = Template::find(name)?;
+                let mut adf =
+                    template.apply(apply_matches.get_one::<String>("NAME").map(|s| s.as_str()))?;
+                save_adf(&mut adf, file_path)?;
+                status!("Created {} from template {}", file_path, template.name);
             }
             _ => unreachable!("Exhaustive subcommand matching should prevent this"),
         },
         Some(("create", sub_matches)) => {
             let file_path = sub_matches.get_one::<String>("FILE").unwrap();
-            let adf = ADF {
-                data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
-                bitmap: vec![false; ADF_NUM_SECTORS],
+            let hardfile = sub_matches.get_one::<String>("type").unwrap() == "hdf";
+            let size = match sub_matches.get_one::<usize>("size") {
+                Some(&size) => size,
+                None if hardfile => return Err("Hardfiles require --size".into()),
+                None => ADF_NUM_SECTORS * ADF_SECTOR_SIZE,
             };
-            adf.write_to_file(file_path)?;
-            println!("Created empty ADF file: {}", file_path);
+            if !hardfile
+                && size != ADF_NUM_SECTORS * ADF_SECTOR_SIZE
+                && size != ADF_HD_NUM_SECTORS * ADF_SECTOR_SIZE
+            {
+                return Err("Floppy images must be dd or hd sized, use --type hdf".into());
+            }
+            let mut adf = ADF::new(size / ADF_SECTOR_SIZE, ADF_SECTOR_SIZE);
+            if let Some(name) = sub_matches.get_one::<String>("format") {
+                let disk_type = match config.disk_type.as_deref().map(str::to_ascii_uppercase) {
+                    Some(t) if t == "FFS" => DiskType::FFS,
+                    _ => DiskType::OFS,
+                };
+                adf.format(disk_type, name)?;
+            }
+            save_adf(&mut adf, file_path)?;
+            status!(
+                "Created empty {} file: {} ({} blocks)",
+                if hardfile { "HDF" } else { "ADF" },
+                file_path,
+                adf.num_blocks()
+            );
+            if hardfile && file_path != "-" {
+                let usage = DiskUsage::of(file_path)?;
+                status!(
+                    "{} bytes, {} allocated on the host",
+                    usage.apparent,
+                    usage.allocated
+                );
+            }
         }
         Some(("format", sub_matches)) => {
             let file_path = sub_matches.get_one::<String>("FILE").unwrap();
-            let disk_type_str = sub_matches.get_one::<String>("TYPE").unwrap();
+            let disk_type_str = sub_matches
+                .get_one::<String>("TYPE")
+                .or(config.disk_type.as_ref())
+                .map(|t| t.to_ascii_uppercase())
+                .unwrap_or_else(|| "OFS".to_string());
             let disk_name = sub_matches.get_one::<String>("NAME").unwrap();
 
             let disk_type = match disk_type_str.as_str() {
@@ -292,21 +2451,37 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 _ => return Err("Invalid disk type".into()),
             };
 
-            let mut adf = if let Ok(existing_adf) = ADF::from_file(file_path) {
-                existing_adf
-            } else {
-                ADF {
-                    data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
-                    bitmap: vec![false; ADF_NUM_SECTORS],
+            // Unformatted images keep their size; missing files become DD disks.
+            let mut adf = match load_adf(file_path) {
+                Ok(existing_adf) => existing_adf,
+                Err(_) => {
+                    let size = std::fs::metadata(file_path)
+                        .map(|m| m.len() as usize)
+                        .ok()
+                        .filter(|&len| len > 0 && len.is_multiple_of(ADF_SECTOR_SIZE))
+                        .unwrap_or(ADF_NUM_SECTORS * ADF_SECTOR_SIZE);
+                    ADF::new(size / ADF_SECTOR_SIZE, ADF_SECTOR_SIZE)
                 }
             };
 
-            adf.format(disk_type, disk_name)?;
-            adf.write_to_file(file_path)?;
+            let options = FormatOptions {
+                intl: sub_matches.get_flag("intl"),
+                dircache: sub_matches.get_flag("dircache"),
+                bootable: sub_matches.get_flag("bootable"),
+                kickstart: kickstart(sub_matches)?,
+                quick: sub_matches.get_flag("quick"),
+                fill: fill_pattern(sub_matches.get_one::<String>("fill"))?,
+                reserved: *sub_matches.get_one::<usize>("reserved").unwrap(),
+            };
+            adf.format_with_options(disk_type, disk_name, &options)?;
+            save_adf(&mut adf, file_path)?;
 
-            println!(
-                "Formatted ADF file: {} (Type: {:?}, Name: {})",
-                file_path, disk_type, disk_name
+            status!(
+                "Formatted ADF file: {} (Type: {:?}, DOS\\{}, Name: {})",
+                file_path,
+                disk_type,
+                adf.data[3],
+                disk_name
             );
         }
         _ => unreachable!("Exhaustive subcommand matching should prevent this"),
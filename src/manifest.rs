@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Per-file hashes of a volume, for spotting the same file across image variants. The text
+// form matches sha256sum and friends, so an extracted tree can be checked with them.
+
+use crate::dat::to_hex;
+use crate::disk::ADF;
+use md5::Md5;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum HashAlgo {
+    Crc32,
+    Md5,
+    Sha1,
+    #[default]
+    Sha256,
+}
+
+impl HashAlgo {
+    pub fn digest(self, data: &[u8]) -> String {
+        match self {
+            HashAlgo::Crc32 => format!("{:08x}", crc32fast::hash(data)),
+            HashAlgo::Md5 => to_hex(&Md5::digest(data)),
+            HashAlgo::Sha1 => to_hex(&Sha1::digest(data)),
+            HashAlgo::Sha256 => to_hex(&Sha256::digest(data)),
+        }
+    }
+}
+
+impl FromStr for HashAlgo {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<HashAlgo> {
+        match name.to_ascii_lowercase().as_str() {
+            "crc32" => Ok(HashAlgo::Crc32),
+            "md5" => Ok(HashAlgo::Md5),
+            "sha1" => Ok(HashAlgo::Sha1),
+            "sha256" => Ok(HashAlgo::Sha256),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown hash '{}', use crc32, md5, sha1 or sha256", name),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HashAlgo::Crc32 => "crc32",
+            HashAlgo::Md5 => "md5",
+            HashAlgo::Sha1 => "sha1",
+            HashAlgo::Sha256 => "sha256",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u32,
+    pub hash: String,
+}
+
+impl ADF {
+    // Every file on the volume in walk order, directories left out.
+    pub fn manifest(&self, algo: HashAlgo) -> Result<Vec<ManifestEntry>> {
+        let mut entries = Vec::new();
+        for (path, info) in self.walk("")? {
+            if info.is_dir {
+                continue;
+            }
+            let hash = algo.digest(&self.read_file(&path)?);
+            entries.push(ManifestEntry {
+                path,
+                size: info.size,
+                hash,
+            });
+        }
+        Ok(entries)
+    }
+}
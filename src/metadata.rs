@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use crate::block::{HeaderBlock, RootBlock};
+use crate::bootblock::BootCode;
+use crate::dat::Fingerprint;
+use crate::disk::{join_path, serialize_unix_time, ADF, ADF_SECTOR_SIZE};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::Result;
+use std::time::SystemTime;
+
+// Bumped whenever a field changes meaning or is removed; new fields keep the version.
+pub const METADATA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskMetadata {
+    pub version: u32,
+    pub volume: VolumeMetadata,
+    pub bitmap: BitmapSummary,
+    pub boot_block: BootBlockMetadata,
+    pub image: Fingerprint,
+    pub root: EntryMetadata,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeMetadata {
+    pub name: String,
+    pub dos_type: String,
+    pub ffs: bool,
+    pub intl: bool,
+    pub dircache: bool,
+    pub blocks: usize,
+    pub block_size: usize,
+    pub root_block: usize,
+    #[serde(serialize_with = "serialize_unix_time")]
+    pub created: SystemTime,
+    #[serde(serialize_with = "serialize_unix_time")]
+    pub modified: SystemTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BitmapSummary {
+    pub total_blocks: usize,
+    pub free_blocks: usize,
+    pub used_blocks: usize,
+    // Set when the root block says the bitmap is up to date.
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BootBlockMetadata {
+    pub bootable: bool,
+    pub checksum_valid: bool,
+    pub code: BootCode,
+    pub sha1: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryMetadata {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u32,
+    pub protection: String,
+    pub protection_bits: u32,
+    pub comment: String,
+    #[serde(serialize_with = "serialize_unix_time")]
+    pub date: SystemTime,
+    pub header_block: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<EntryMetadata>,
+}
+
+impl ADF {
+    // Everything a disk catalogue needs in one serializable value.
+    pub fn extract_metadata(&self) -> Result<DiskMetadata> {
+        let root = RootBlock::from_bytes(self.read_sector(self.root_block()), &self.layout())?;
+        let boot = self.boot_block_info();
+        let flags = self.data[3];
+        let allocation = self.read_allocation_map();
+        let free_blocks = allocation.iter().skip(2).filter(|&&free| free).count();
+        Ok(DiskMetadata {
+            version: METADATA_VERSION,
+            volume: VolumeMetadata {
+                name: root.name.clone(),
+                dos_type: boot.dos_type.clone(),
+                ffs: flags & 1 != 0,
+                intl: flags & 2 != 0,
+                dircache: flags & 4 != 0,
+                blocks: self.num_blocks(),
+                block_size: ADF_SECTOR_SIZE,
+                root_block: self.root_block(),
+                created: root.created.to_system_time(),
+                modified: root.volume_modified.to_system_time(),
+            },
+            bitmap: BitmapSummary {
+                total_blocks: self.num_blocks(),
+                free_blocks,
+                used_blocks: self.num_blocks() - free_blocks,
+                valid: root.bm_flag == u32::MAX,
+            },
+            boot_block: BootBlockMetadata {
+                bootable: self.data.starts_with(b"DOS")
+                    && boot.checksum_valid()
+                    && boot.code != BootCode::None,
+                checksum_valid: boot.checksum_valid(),
+                code: boot.code,
+                sha1: Fingerprint::of(self.read_boot_block()).sha1,
+            },
+            image: Fingerprint::of(&self.data),
+            root: EntryMetadata {
+                name: root.name,
+                path: String::new(),
+                is_dir: true,
+                size: 0,
+                protection: self.format_protection_flags(0),
+                protection_bits: 0,
+                comment: String::new(),
+                date: root.modified.to_system_time(),
+                header_block: self.root_block(),
+                children: self.entry_metadata(self.root_block(), "", &mut HashSet::new())?,
+            },
+        })
+    }
+
+    // Each directory is visited once, so a corrupt image with directory loops still finishes.
+    fn entry_metadata(
+        &self,
+        dir: usize,
+        path: &str,
+        visited: &mut HashSet<usize>,
+    ) -> Result<Vec<EntryMetadata>> {
+        let mut entries = Vec::new();
+        visited.insert(dir);
+        for block in self.directory_entries(dir) {
+            let header = HeaderBlock::from_bytes(self.read_sector(block), &self.layout())?;
+            let entry_path = join_path(path, &header.name);
+            let children = if header.is_dir() && !visited.contains(&block) {
+                self.entry_metadata(block, &entry_path, visited)?
+            } else {
+                Vec::new()
+            };
+            entries.push(EntryMetadata {
+                is_dir: header.is_dir(),
+                size: header.byte_size,
+                protection: self.format_protection_flags(header.protect),
+                protection_bits: header.protect,
+                date: header.date.to_system_time(),
+                header_block: block,
+                path: entry_path,
+                name: header.name,
+                comment: header.comment,
+                children,
+            });
+        }
+        entries.sort_by_key(|entry| entry.name.to_lowercase());
+        Ok(entries)
+    }
+}
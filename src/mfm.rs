@@ -0,0 +1,232 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Amiga trackdisk MFM: every sector starts with two 0x4489 sync words and stores its fields
+// as the odd bits followed by the even bits of each longword. MFM data is kept as bytes,
+// most significant bit first, one bit per bit cell.
+
+use crate::disk::ADF_SECTOR_SIZE;
+use std::io::{Error, ErrorKind, Result};
+
+pub const SYNC_WORD: u16 = 0x4489;
+const SYNC: u32 = (SYNC_WORD as u32) << 16 | SYNC_WORD as u32;
+const GAP_WORD: u16 = 0xaaaa;
+const FORMAT_AMIGA: u32 = 0xff;
+const LABEL_LONGS: usize = 4;
+const DATA_LONGS: usize = ADF_SECTOR_SIZE / 4;
+// MFM longwords of a sector after the sync: info, label, header and data checksums, data.
+const SECTOR_LONGS: usize = 2 + 2 * LABEL_LONGS + 2 + 2 + 2 * DATA_LONGS;
+// How far the clock of the decoder may drift from the nominal bit cell, and how fast it
+// follows the timing of the disk.
+const CLOCK_RANGE: f64 = 0.1;
+const CLOCK_GAIN: f64 = 0.05;
+
+fn odd_bits(value: u32) -> u32 {
+    (value >> 1) & 0x5555_5555
+}
+
+fn even_bits(value: u32) -> u32 {
+    value & 0x5555_5555
+}
+
+fn join_bits(odd: u32, even: u32) -> u32 {
+    (odd & 0x5555_5555) << 1 | (even & 0x5555_5555)
+}
+
+struct MfmWriter {
+    mfm: Vec<u8>,
+    bits: usize,
+    last: bool,
+}
+
+impl MfmWriter {
+    fn bit(&mut self, bit: bool) {
+        if self.bits.is_multiple_of(8) {
+            self.mfm.push(0);
+        }
+        if bit {
+            *self.mfm.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+        }
+        self.bits += 1;
+        self.last = bit;
+    }
+
+    fn raw(&mut self, word: u16) {
+        for i in (0..16).rev() {
+            self.bit(word >> i & 1 != 0);
+        }
+    }
+
+    // Writes the data bits of value with the clock bits MFM needs between them.
+    fn long(&mut self, value: u32) {
+        for i in (0..16).rev() {
+            let data = value >> (2 * i) & 1 != 0;
+            self.bit(!self.last && !data);
+            self.bit(data);
+        }
+    }
+}
+
+// Encodes the sectors of one track as written by trackdisk.device; fill_gap adds the track
+// gap behind them.
+pub fn encode_track(track: usize, data: &[u8]) -> Vec<u8> {
+    let sectors = data.len() / ADF_SECTOR_SIZE;
+    let mut writer = MfmWriter {
+        mfm: Vec::with_capacity(sectors * (SECTOR_LONGS + 2) * 4),
+        bits: 0,
+        last: false,
+    };
+    for (sector, contents) in data.chunks_exact(ADF_SECTOR_SIZE).enumerate() {
+        let info = FORMAT_AMIGA << 24
+            | (track as u32 & 0xff) << 16
+            | (sector as u32) << 8
+            | (sectors - sector) as u32;
+        let longs: Vec<u32> = contents
+            .chunks_exact(4)
+            .map(|long| u32::from_be_bytes([long[0], long[1], long[2], long[3]]))
+            .collect();
+        let mut header = vec![odd_bits(info), even_bits(info)];
+        header.extend([0; 2 * LABEL_LONGS]);
+        let header_sum = header.iter().fold(0, |sum, long| sum ^ long);
+        let mut body: Vec<u32> = longs.iter().map(|&long| odd_bits(long)).collect();
+        body.extend(longs.iter().map(|&long| even_bits(long)));
+        let data_sum = body.iter().fold(0, |sum, long| sum ^ long);
+
+        writer.raw(GAP_WORD);
+        writer.raw(GAP_WORD);
+        writer.raw(SYNC_WORD);
+        writer.raw(SYNC_WORD);
+        for long in header
+            .into_iter()
+            .chain([odd_bits(header_sum), even_bits(header_sum)])
+            .chain([odd_bits(data_sum), even_bits(data_sum)])
+            .chain(body)
+        {
+            writer.long(long);
+        }
+    }
+    writer.mfm
+}
+
+// Pads the track to len bytes with clock-only cells, or with set data bits when the last
+// cell is set, since MFM never has two adjacent set cells.
+pub fn fill_gap(mfm: &mut Vec<u8>, len: usize) {
+    let filler = match mfm.last() {
+        Some(byte) if byte & 1 != 0 => 0x55,
+        _ => 0xaa,
+    };
+    if mfm.len() < len {
+        mfm.resize(len, filler);
+    }
+}
+
+fn read_long(mfm: &[u8], bit: usize) -> u32 {
+    (0..32).fold(0, |long, i| {
+        let pos = bit + i;
+        long << 1 | (mfm[pos / 8] >> (7 - pos % 8) & 1) as u32
+    })
+}
+
+// Decodes every sector with valid checksums that belongs to track. A stream covering more
+// than one revolution finds each sector at least once, wherever the index falls.
+pub fn decode_sectors(mfm: &[u8], track: usize, sectors: usize) -> Vec<Option<Vec<u8>>> {
+    let mut found = vec![None; sectors];
+    let bits = mfm.len() * 8;
+    let mut shift = 0u32;
+    let mut pos = 0;
+    while pos < bits {
+        shift = shift << 1 | (mfm[pos / 8] >> (7 - pos % 8) & 1) as u32;
+        pos += 1;
+        if shift != SYNC || pos + SECTOR_LONGS * 32 > bits {
+            continue;
+        }
+        // Two sync words may be followed by a third one.
+        let mut start = pos;
+        while start + 16 + SECTOR_LONGS * 32 <= bits
+            && read_long(mfm, start) >> 16 == SYNC_WORD as u32
+        {
+            start += 16;
+        }
+        let longs: Vec<u32> = (0..SECTOR_LONGS)
+            .map(|i| read_long(mfm, start + i * 32))
+            .collect();
+        let info = join_bits(longs[0], longs[1]);
+        let header_sum = longs[..2 + 2 * LABEL_LONGS]
+            .iter()
+            .fold(0, |sum, long| sum ^ long)
+            & 0x5555_5555;
+        let data = &longs[SECTOR_LONGS - 2 * DATA_LONGS..];
+        let data_sum = data.iter().fold(0, |sum, long| sum ^ long) & 0x5555_5555;
+        let sector = (info >> 8 & 0xff) as usize;
+        if info >> 24 != FORMAT_AMIGA
+            || (info >> 16 & 0xff) as usize != track
+            || sector >= sectors
+            || header_sum != join_bits(longs[10], longs[11])
+            || data_sum != join_bits(longs[12], longs[13])
+        {
+            continue;
+        }
+        let contents = (0..DATA_LONGS)
+            .flat_map(|i| join_bits(data[i], data[DATA_LONGS + i]).to_be_bytes())
+            .collect();
+        found[sector] = Some(contents);
+        pos = start + SECTOR_LONGS * 32;
+        shift = 0;
+    }
+    found
+}
+
+pub fn decode_track(mfm: &[u8], track: usize, sectors: usize) -> Result<Vec<u8>> {
+    let found = decode_sectors(mfm, track, sectors);
+    let missing: Vec<String> = (0..sectors)
+        .filter(|&sector| found[sector].is_none())
+        .map(|sector| sector.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Track {}: sectors {} not found", track, missing.join(", ")),
+        ));
+    }
+    Ok(found.into_iter().flatten().flatten().collect())
+}
+
+// Turns flux transition intervals into bit cells. The clock follows slow speed changes of
+// the drive within CLOCK_RANGE of the nominal cell length.
+pub fn flux_to_mfm(flux: &[u32], cell_ticks: f64) -> Vec<u8> {
+    let mut writer = MfmWriter {
+        mfm: Vec::new(),
+        bits: 0,
+        last: false,
+    };
+    let mut clock = cell_ticks;
+    for &interval in flux {
+        let cells = (interval as f64 / clock).round().max(1.0);
+        for _ in 1..cells as usize {
+            writer.bit(false);
+        }
+        writer.bit(true);
+        clock += (interval as f64 / cells - clock) * CLOCK_GAIN;
+        clock = clock.clamp(
+            cell_ticks * (1.0 - CLOCK_RANGE),
+            cell_ticks * (1.0 + CLOCK_RANGE),
+        );
+    }
+    writer.mfm
+}
+
+// Intervals between the set bits, in ticks; leading clear bits are part of the first one.
+pub fn mfm_to_flux(mfm: &[u8], cell_ticks: f64) -> Vec<u32> {
+    let mut flux = Vec::new();
+    let mut cells = 0u32;
+    for pos in 0..mfm.len() * 8 {
+        cells += 1;
+        if mfm[pos / 8] >> (7 - pos % 8) & 1 != 0 {
+            flux.push((cells as f64 * cell_ticks).round() as u32);
+            cells = 0;
+        }
+    }
+    flux
+}
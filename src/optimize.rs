@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use crate::block::{BLOCK_CREATION_DAYS, BLOCK_VOLUME_DAYS};
+use crate::disk::{
+    read_u32_be, write_u32_be, DiskType, ADF, ADF_SECTOR_SIZE, BLOCK_DAYS, BLOCK_HASH_CHAIN,
+    BLOCK_HASH_TABLE,
+};
+use crate::validate::Severity;
+use log::debug;
+use serde::Serialize;
+use std::io::{Error, ErrorKind, Result};
+
+const ROOT_DATES: [usize; 3] = [BLOCK_DAYS, BLOCK_VOLUME_DAYS, BLOCK_CREATION_DAYS];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FragmentationReport {
+    pub files: usize,
+    pub fragmented_files: usize,
+    pub extents: usize,
+    pub free_extents: usize,
+    pub largest_free_extent: usize,
+}
+
+fn count_runs(blocks: &[usize]) -> usize {
+    match blocks {
+        [] => 0,
+        _ => 1 + blocks.windows(2).filter(|w| w[1] != w[0] + 1).count(),
+    }
+}
+
+impl ADF {
+    // A file's extents are the contiguous runs of its header followed by its data blocks.
+    pub fn fragmentation(&self) -> Result<FragmentationReport> {
+        let mut report = FragmentationReport::default();
+        for (path, info) in self.walk("")? {
+            if info.is_dir {
+                continue;
+            }
+            let header = self.find_entry(&path)?;
+            let (data_blocks, _) = self.file_blocks(header)?;
+            let mut blocks = vec![header];
+            blocks.extend(data_blocks);
+            let runs = count_runs(&blocks);
+            report.files += 1;
+            report.extents += runs;
+            if runs > 1 {
+                report.fragmented_files += 1;
+            }
+        }
+
+        let mut run = 0;
+        for &free in self.get_bitmap().iter().skip(2).chain([false].iter()) {
+            if free {
+                run += 1;
+            } else if run > 0 {
+                report.free_extents += 1;
+                report.largest_free_extent = report.largest_free_extent.max(run);
+                run = 0;
+            }
+        }
+        Ok(report)
+    }
+
+    // Rebuilds the volume by copying every entry onto a freshly formatted image, so each file
+    // ends up in one contiguous run. The boot block, volume name and dates are kept.
+    pub fn defragment(&mut self) -> Result<()> {
+        if self.validate().count(Severity::Error) > 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Cannot defragment a damaged filesystem, run fsck --repair first",
+            ));
+        }
+        let root = self.root_block();
+        let disk_type = if self.is_ffs() {
+            DiskType::FFS
+        } else {
+            DiskType::OFS
+        };
+        let mut fresh = ADF::new(self.num_blocks(), ADF_SECTOR_SIZE);
+        fresh.set_clock(self.clock());
+        fresh.format(disk_type, &self.entry_name(root))?;
+        fresh.data[..2 * ADF_SECTOR_SIZE].copy_from_slice(&self.data[..2 * ADF_SECTOR_SIZE]);
+        fresh.reserve_mapped_blocks(self)?;
+
+        for entry in self.directory_entries(root) {
+            let name = self.entry_name(entry);
+            fresh.copy_from(self, &name, &name)?;
+        }
+
+        for date in ROOT_DATES {
+            let offset = root * ADF_SECTOR_SIZE + date;
+            fresh.data[offset..offset + 12].copy_from_slice(&self.data[offset..offset + 12]);
+        }
+        fresh.update_block_checksum(root);
+        debug!("Defragmented {} blocks", self.num_blocks());
+        // Formatting marked the whole image dirty, so it can still be saved in place.
+        fresh.tracking.saved_to = self.tracking.saved_to.take();
+        fresh.tracking.changes = std::mem::take(&mut self.tracking.changes);
+        fresh.note_change("Defragmented".to_string());
+        *self = fresh;
+        Ok(())
+    }
+
+    // Orders every hash chain by name so directory listings come out sorted within a slot.
+    pub fn sort_directories(&mut self) -> Result<()> {
+        let mut dirs = vec![self.root_block()];
+        for (path, info) in self.walk("")? {
+            if info.is_dir {
+                dirs.push(self.find_entry(&path)?);
+            }
+        }
+        for dir in dirs {
+            for slot in 0..self.layout().hash_table_size() {
+                let table_offset = BLOCK_HASH_TABLE + slot * 4;
+                let mut chain = Vec::new();
+                let mut entry = read_u32_be(self.read_sector(dir), table_offset) as usize;
+                while entry != 0 && entry < self.num_blocks() && chain.len() < self.num_blocks() {
+                    chain.push(entry);
+                    entry = read_u32_be(self.read_sector(entry), BLOCK_HASH_CHAIN) as usize;
+                }
+                if chain.len() < 2 {
+                    continue;
+                }
+                chain.sort_by_key(|&block| self.entry_name(block).to_lowercase());
+                for (i, &block) in chain.iter().enumerate() {
+                    let next = chain.get(i + 1).copied().unwrap_or(0) as u32;
+                    let offset = block * ADF_SECTOR_SIZE;
+                    write_u32_be(
+                        self.data_mut(offset..offset + ADF_SECTOR_SIZE),
+                        BLOCK_HASH_CHAIN,
+                        next,
+                    );
+                    self.update_block_checksum(block);
+                }
+                let offset = dir * ADF_SECTOR_SIZE;
+                write_u32_be(
+                    self.data_mut(offset..offset + ADF_SECTOR_SIZE),
+                    table_offset,
+                    chain[0] as u32,
+                );
+                self.update_block_checksum(dir);
+            }
+        }
+        self.note_change("Sorted directories".to_string());
+        Ok(())
+    }
+
+    // Zeroes every free block and returns how many were cleared.
+    pub fn scrub_free(&mut self) -> usize {
+        self.scrub_free_blocks(&[])
+    }
+
+    // Overwrites every free block with pattern, repeated from the start of each block, so
+    // nothing of deleted files is left in a distributed image. An empty pattern zeroes.
+    pub fn scrub_free_blocks(&mut self, pattern: &[u8]) -> usize {
+        let free: Vec<usize> = (2..self.num_blocks())
+            .filter(|&block| self.get_bitmap()[block])
+            .collect();
+        for &block in &free {
+            let offset = block * ADF_SECTOR_SIZE;
+            let sector = self.data_mut(offset..offset + ADF_SECTOR_SIZE);
+            if pattern.is_empty() {
+                sector.fill(0);
+            } else {
+                for chunk in sector.chunks_mut(pattern.len()) {
+                    chunk.copy_from_slice(&pattern[..chunk.len()]);
+                }
+            }
+        }
+        let action = if pattern.is_empty() {
+            "Zeroed"
+        } else {
+            "Scrubbed"
+        };
+        self.note_change(format!("{} {} free blocks", action, free.len()));
+        free.len()
+    }
+}
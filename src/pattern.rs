@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Empty,
+    Class(Vec<(char, char)>, bool),
+    Repeat(Box<Node>),
+    Group(Vec<Vec<Node>>),
+    Not(Box<Node>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    alternatives: Vec<Vec<Node>>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let chars: Vec<char> = pattern.chars().map(fold_case).collect();
+        let mut pos = 0;
+        let alternatives = parse_alternatives(&chars, &mut pos)?;
+        if pos < chars.len() {
+            return Err(invalid(pattern, "unbalanced ')'"));
+        }
+        Ok(Pattern { alternatives })
+    }
+
+    pub fn is_wild(pattern: &str) -> bool {
+        pattern
+            .chars()
+            .any(|c| matches!(c, '#' | '?' | '*' | '%' | '(' | '|' | '~' | '['))
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        let input: Vec<char> = name.chars().map(fold_case).collect();
+        self.alternatives
+            .iter()
+            .any(|seq| match_sequence(seq, &input, 0).contains(&input.len()))
+    }
+}
+
+fn invalid(pattern: &str, reason: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("Invalid pattern '{}': {}", pattern, reason),
+    )
+}
+
+fn fold_case(c: char) -> char {
+    match c {
+        'a'..='z' => c.to_ascii_uppercase(),
+        'à'..='þ' if c != '÷' => char::from_u32(c as u32 - 32).unwrap_or(c),
+        _ => c,
+    }
+}
+
+fn parse_alternatives(chars: &[char], pos: &mut usize) -> Result<Vec<Vec<Node>>> {
+    let mut alternatives = vec![Vec::new()];
+    while let Some(&c) = chars.get(*pos) {
+        match c {
+            ')' => break,
+            '|' => {
+                *pos += 1;
+                alternatives.push(Vec::new());
+            }
+            _ => {
+                let node = parse_item(chars, pos)?;
+                alternatives.last_mut().unwrap().push(node);
+            }
+        }
+    }
+    Ok(alternatives)
+}
+
+fn parse_item(chars: &[char], pos: &mut usize) -> Result<Node> {
+    let pattern: String = chars.iter().collect();
+    let Some(&c) = chars.get(*pos) else {
+        return Err(invalid(&pattern, "unexpected end of pattern"));
+    };
+    *pos += 1;
+    match c {
+        '?' => Ok(Node::Any),
+        '*' => Ok(Node::Repeat(Box::new(Node::Any))),
+        '%' => Ok(Node::Empty),
+        '#' => Ok(Node::Repeat(Box::new(parse_item(chars, pos)?))),
+        '~' => Ok(Node::Not(Box::new(parse_item(chars, pos)?))),
+        '\'' => match chars.get(*pos) {
+            Some(&escaped) => {
+                *pos += 1;
+                Ok(Node::Char(escaped))
+            }
+            None => Err(invalid(&pattern, "dangling escape")),
+        },
+        '(' => {
+            let alternatives = parse_alternatives(chars, pos)?;
+            if chars.get(*pos) != Some(&')') {
+                return Err(invalid(&pattern, "missing ')'"));
+            }
+            *pos += 1;
+            Ok(Node::Group(alternatives))
+        }
+        '[' => {
+            let negated = matches!(chars.get(*pos), Some('~') | Some('^'));
+            if negated {
+                *pos += 1;
+            }
+            let mut ranges = Vec::new();
+            loop {
+                let Some(&start) = chars.get(*pos) else {
+                    return Err(invalid(&pattern, "missing ']'"));
+                };
+                *pos += 1;
+                if start == ']' {
+                    break;
+                }
+                match (chars.get(*pos), chars.get(*pos + 1)) {
+                    (Some('-'), Some(&end)) if end != ']' => {
+                        *pos += 2;
+                        ranges.push((start, end));
+                    }
+                    _ => ranges.push((start, start)),
+                }
+            }
+            Ok(Node::Class(ranges, negated))
+        }
+        _ => Ok(Node::Char(c)),
+    }
+}
+
+fn match_sequence(nodes: &[Node], input: &[char], start: usize) -> BTreeSet<usize> {
+    let mut positions = BTreeSet::from([start]);
+    for node in nodes {
+        positions = positions
+            .into_iter()
+            .flat_map(|pos| match_node(node, input, pos))
+            .collect();
+        if positions.is_empty() {
+            break;
+        }
+    }
+    positions
+}
+
+fn match_node(node: &Node, input: &[char], pos: usize) -> BTreeSet<usize> {
+    match node {
+        Node::Char(c) => match input.get(pos) {
+            Some(actual) if actual == c => BTreeSet::from([pos + 1]),
+            _ => BTreeSet::new(),
+        },
+        Node::Any => {
+            if pos < input.len() {
+                BTreeSet::from([pos + 1])
+            } else {
+                BTreeSet::new()
+            }
+        }
+        Node::Empty => BTreeSet::from([pos]),
+        Node::Class(ranges, negated) => match input.get(pos) {
+            Some(&c) if ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated => {
+                BTreeSet::from([pos + 1])
+            }
+            _ => BTreeSet::new(),
+        },
+        Node::Repeat(inner) => {
+            let mut reached = BTreeSet::from([pos]);
+            let mut frontier = vec![pos];
+            while let Some(current) = frontier.pop() {
+                for next in match_node(inner, input, current) {
+                    if reached.insert(next) {
+                        frontier.push(next);
+                    }
+                }
+            }
+            reached
+        }
+        Node::Group(alternatives) => alternatives
+            .iter()
+            .flat_map(|seq| match_sequence(seq, input, pos))
+            .collect(),
+        Node::Not(inner) => {
+            let matched = match_node(inner, input, pos);
+            (pos..=input.len())
+                .filter(|end| !matched.contains(end))
+                .collect()
+        }
+    }
+}
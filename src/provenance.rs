@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Provenance sidecars: a TOML or JSON file next to an image, "game.adf.provenance.toml",
+// recording where the dump came from, when it was made, the hashes of the image and every
+// change made to it since. ADF::save updates an existing sidecar with the changes it wrote,
+// so the history stays in step with the image.
+
+use crate::dat::Fingerprint;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SidecarFormat {
+    Toml,
+    Json,
+}
+
+impl SidecarFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            SidecarFormat::Toml => "provenance.toml",
+            SidecarFormat::Json => "provenance.json",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvenanceEvent {
+    // RFC 3339, UTC.
+    pub date: String,
+    pub action: String,
+    // SHA1 of the image after the change.
+    pub sha1: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dumped: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dumper: Option<String>,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub crc32: String,
+    #[serde(default)]
+    pub md5: String,
+    #[serde(default)]
+    pub sha1: String,
+    #[serde(default)]
+    pub history: Vec<ProvenanceEvent>,
+}
+
+fn format_date(date: SystemTime) -> String {
+    DateTime::<Utc>::from(date).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+impl Provenance {
+    pub fn new(data: &[u8]) -> Provenance {
+        let mut provenance = Provenance::default();
+        provenance.set_hashes(data);
+        provenance
+    }
+
+    fn set_hashes(&mut self, data: &[u8]) {
+        let fingerprint = Fingerprint::of(data);
+        self.size = fingerprint.size;
+        self.crc32 = fingerprint.crc32;
+        self.md5 = fingerprint.md5;
+        self.sha1 = fingerprint.sha1;
+    }
+
+    // Whether data is still the image the hashes were taken of.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        let fingerprint = Fingerprint::of(data);
+        fingerprint.size == self.size && fingerprint.sha1 == self.sha1
+    }
+
+    // Appends a change and takes the hashes of the image as it is now.
+    pub fn record(&mut self, data: &[u8], action: &str) {
+        self.record_at(data, action, SystemTime::now());
+    }
+
+    pub fn record_at(&mut self, data: &[u8], action: &str, date: SystemTime) {
+        self.set_hashes(data);
+        self.history.push(ProvenanceEvent {
+            date: format_date(date),
+            action: action.to_string(),
+            sha1: self.sha1.clone(),
+        });
+    }
+
+    pub fn sidecar_path(image: &Path, format: SidecarFormat) -> PathBuf {
+        let mut name = image.as_os_str().to_owned();
+        name.push(".");
+        name.push(format.extension());
+        PathBuf::from(name)
+    }
+
+    // The sidecar of image, if it has one; TOML is preferred when both exist.
+    pub fn find(image: &Path) -> Option<PathBuf> {
+        [SidecarFormat::Toml, SidecarFormat::Json]
+            .into_iter()
+            .map(|format| Provenance::sidecar_path(image, format))
+            .find(|path| path.is_file())
+    }
+
+    pub fn load(image: &Path) -> Result<Option<Provenance>> {
+        Provenance::find(image)
+            .map(|path| Provenance::read(&path))
+            .transpose()
+    }
+
+    fn format_of(path: &Path) -> SidecarFormat {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => SidecarFormat::Json,
+            _ => SidecarFormat::Toml,
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Provenance> {
+        let text = std::fs::read_to_string(path)?;
+        let invalid = |message: String| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid provenance file {}: {}", path.display(), message),
+            )
+        };
+        match Provenance::format_of(path) {
+            SidecarFormat::Json => serde_json::from_str(&text).map_err(|e| invalid(e.to_string())),
+            SidecarFormat::Toml => {
+                toml::from_str(&text).map_err(|e| invalid(e.message().to_string()))
+            }
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let text = match Provenance::format_of(path) {
+            SidecarFormat::Json => serde_json::to_string_pretty(self)?,
+            SidecarFormat::Toml => toml::to_string(self).map_err(Error::other)?,
+        };
+        std::fs::write(path, text)
+    }
+
+    // Records actions, or a generic change when there are none, in the sidecar of image,
+    // if it has one, dated date. Returns whether there was a sidecar to update.
+    pub fn update_sidecar(
+        image: &Path,
+        data: &[u8],
+        actions: &[String],
+        date: SystemTime,
+    ) -> Result<bool> {
+        let Some(path) = Provenance::find(image) else {
+            return Ok(false);
+        };
+        let mut provenance = Provenance::read(&path)?;
+        if actions.is_empty() {
+            if provenance.matches(data) {
+                return Ok(true);
+            }
+            provenance.record_at(data, "Modified", date);
+        }
+        for action in actions {
+            provenance.record_at(data, action, date);
+        }
+        provenance.write(&path)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,1166 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// The parts of the library that only need core and alloc: sector access, directory parsing,
+// checksums and DMS track decompression. They build without the std feature, e.g. for
+// firmware or WASM, and the std modules are implemented on top of them.
+
+use crate::layout::{
+    Layout, BLOCK_CHECKSUM, BLOCK_HASH_TABLE, BLOCK_HIGH_SEQ, BLOCK_SEC_TYPE, BLOCK_TYPE,
+    MAX_NAME_LEN, OFS_DATA_LEN, ST_FILE, ST_ROOT, ST_USERDIR, T_HEADER, T_LIST,
+};
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+const SECTOR_SIZE: usize = Layout::FLOPPY.block_size;
+pub const BOOT_BLOCK_SIZE: usize = 2 * SECTOR_SIZE;
+pub(crate) const RLE_MARKER: u8 = 0x90;
+const OFS_HEADER_SIZE: usize = 24;
+const BLOCK_SIZES: [usize; 4] = [512, 1024, 2048, 4096];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawError {
+    BadImageSize(usize),
+    BlockOutOfRange(usize),
+    NotFound,
+    NotADirectory(usize),
+    NotAFile(usize),
+    Corrupt(usize),
+    Truncated,
+    Overflow,
+    UnsupportedMode(u8),
+    BadCompressedData,
+    ReadFailed(usize),
+}
+
+impl fmt::Display for RawError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RawError::BadImageSize(size) => write!(f, "Invalid image size {} bytes", size),
+            RawError::BlockOutOfRange(block) => write!(f, "Block {} is out of range", block),
+            RawError::NotFound => write!(f, "Entry not found"),
+            RawError::NotADirectory(block) => write!(f, "Block {} is not a directory", block),
+            RawError::NotAFile(block) => write!(f, "Block {} is not a file header", block),
+            RawError::Corrupt(block) => write!(f, "Block {} is corrupt", block),
+            RawError::Truncated => write!(f, "Data ends early"),
+            RawError::Overflow => write!(f, "Data is longer than expected"),
+            RawError::UnsupportedMode(mode) => {
+                write!(f, "Compression mode {} is not supported", mode)
+            }
+            RawError::BadCompressedData => write!(f, "Compressed data is corrupt"),
+            RawError::ReadFailed(block) => write!(f, "Block {} could not be read", block),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RawError {}
+
+#[cfg(feature = "std")]
+impl From<RawError> for std::io::Error {
+    fn from(error: RawError) -> Self {
+        let kind = match error {
+            RawError::NotFound => std::io::ErrorKind::NotFound,
+            RawError::NotADirectory(_) | RawError::NotAFile(_) => std::io::ErrorKind::InvalidInput,
+            RawError::UnsupportedMode(_) => std::io::ErrorKind::Unsupported,
+            RawError::ReadFailed(_) => std::io::ErrorKind::Other,
+            _ => std::io::ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, error.to_string())
+    }
+}
+
+pub type Result<T> = core::result::Result<T, RawError>;
+
+pub fn read_u32_be(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+pub fn write_u32_be(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+pub fn latin1_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+// Big endian longwords of block, a trailing partial word is ignored.
+fn words(block: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    block
+        .chunks_exact(4)
+        .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+fn stored_word(block: &[u8], offset: usize) -> u32 {
+    block.get(offset..offset + 4).map_or(0, |word| {
+        u32::from_be_bytes([word[0], word[1], word[2], word[3]])
+    })
+}
+
+// Sums every word and takes the stored checksum back out, which keeps the loop free of
+// branches so it vectorizes.
+fn sum_checksum(block: &[u8], checksum_offset: usize) -> u32 {
+    words(block)
+        .fold(0u32, u32::wrapping_add)
+        .wrapping_sub(stored_word(block, checksum_offset))
+        .wrapping_neg()
+}
+
+// Header, list and OFS data blocks: the longwords, checksum included, add up to zero.
+pub fn normal_checksum(block: &[u8]) -> u32 {
+    sum_checksum(block, BLOCK_CHECKSUM)
+}
+
+// Bitmap pages keep their checksum in the first longword.
+pub fn bitmap_checksum(block: &[u8]) -> u32 {
+    sum_checksum(block, 0)
+}
+
+// The boot block sum carries the overflow back into bit 0 and is stored inverted. Adding
+// in 64 bits and folding the carries in at the end gives the same result.
+pub fn bootblock_checksum(boot_block: &[u8; BOOT_BLOCK_SIZE]) -> u32 {
+    let mut sum = words(boot_block).map(u64::from).sum::<u64>() - stored_word(boot_block, 4) as u64;
+    while sum > u32::MAX as u64 {
+        sum = (sum & u32::MAX as u64) + (sum >> 32);
+    }
+    !(sum as u32)
+}
+
+// The checksum schemes of the blocks that have one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumKind {
+    Boot,
+    Normal,
+    Bitmap,
+}
+
+impl ChecksumKind {
+    pub fn offset(self) -> usize {
+        match self {
+            ChecksumKind::Boot => 4,
+            ChecksumKind::Normal => BLOCK_CHECKSUM,
+            ChecksumKind::Bitmap => 0,
+        }
+    }
+
+    // None for a boot block that is not BOOT_BLOCK_SIZE bytes long.
+    pub fn checksum(self, data: &[u8]) -> Option<u32> {
+        match self {
+            ChecksumKind::Boot => Some(bootblock_checksum(data.try_into().ok()?)),
+            ChecksumKind::Normal => Some(normal_checksum(data)),
+            ChecksumKind::Bitmap => Some(bitmap_checksum(data)),
+        }
+    }
+
+    pub fn is_valid(self, data: &[u8]) -> bool {
+        self.checksum(data) == Some(stored_word(data, self.offset()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub block: usize,
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u32,
+}
+
+impl Entry {
+    // The entry described by sector, the header block numbered block.
+    pub fn from_sector(layout: &Layout, block: usize, sector: &[u8]) -> Entry {
+        let name = layout.name();
+        let name_len = (sector[name] as usize).min(MAX_NAME_LEN);
+        let sec_type = read_u32_be(sector, layout.sec_type());
+        Entry {
+            block,
+            name: latin1_string(&sector[name + 1..name + 1 + name_len]),
+            is_dir: sec_type == ST_USERDIR || sec_type == ST_ROOT,
+            size: read_u32_be(sector, layout.byte_size()),
+        }
+    }
+}
+
+// Where the blocks of a volume come from. A slice hands out its blocks in place; a source that
+// reads on demand, like a hardfile on disk, returns a copy.
+pub trait BlockSource {
+    // Length in bytes.
+    fn size(&self) -> u64;
+    fn read_block(&self, block: usize, block_size: usize) -> Result<Cow<'_, [u8]>>;
+}
+
+impl BlockSource for [u8] {
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn read_block(&self, block: usize, block_size: usize) -> Result<Cow<'_, [u8]>> {
+        self.get(block * block_size..(block + 1) * block_size)
+            .map(Cow::Borrowed)
+            .ok_or(RawError::BlockOutOfRange(block))
+    }
+}
+
+impl<S: BlockSource + ?Sized> BlockSource for &S {
+    fn size(&self) -> u64 {
+        (**self).size()
+    }
+
+    fn read_block(&self, block: usize, block_size: usize) -> Result<Cow<'_, [u8]>> {
+        (**self).read_block(block, block_size)
+    }
+}
+
+// A read-only view of a volume whose blocks come from source.
+#[derive(Debug, Clone, Copy)]
+pub struct VolumeReader<S> {
+    source: S,
+    layout: Layout,
+}
+
+// A volume held in memory.
+pub type Image<'a> = VolumeReader<&'a [u8]>;
+
+impl<'a> Image<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        Image::with_block_size(data, SECTOR_SIZE)
+    }
+}
+
+// The layout of a source of size bytes in block_size byte blocks, a power of two from 512 to
+// 4096 as hardfiles may use.
+fn source_layout(size: u64, block_size: usize) -> Result<Layout> {
+    if !BLOCK_SIZES.contains(&block_size)
+        || !size.is_multiple_of(block_size as u64)
+        || size < 4 * block_size as u64
+    {
+        return Err(RawError::BadImageSize(size as usize));
+    }
+    Ok(Layout::new(block_size, (size / block_size as u64) as usize))
+}
+
+impl<S: BlockSource> VolumeReader<S> {
+    pub fn with_block_size(source: S, block_size: usize) -> Result<Self> {
+        let layout = source_layout(source.size(), block_size)?;
+        Ok(VolumeReader { source, layout })
+    }
+
+    // Finds the block size by looking for a valid root block in the middle of the volume,
+    // trying 512 byte blocks first.
+    pub fn detect(source: S) -> Result<Self> {
+        let size = source.size();
+        let layout = BLOCK_SIZES
+            .iter()
+            .filter_map(|&block_size| source_layout(size, block_size).ok())
+            .find(|&layout| {
+                VolumeReader {
+                    source: &source,
+                    layout,
+                }
+                .has_root_block()
+            })
+            .ok_or(RawError::BadImageSize(size as usize))?;
+        Ok(VolumeReader { source, layout })
+    }
+
+    fn has_root_block(&self) -> bool {
+        self.sector(self.root_block()).is_ok_and(|root| {
+            read_u32_be(&root, BLOCK_TYPE) == T_HEADER
+                && read_u32_be(&root, self.layout.sec_type()) == ST_ROOT
+                && normal_checksum(&root) == read_u32_be(&root, BLOCK_CHECKSUM)
+        })
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.layout.block_size
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.layout.blocks
+    }
+
+    pub fn root_block(&self) -> usize {
+        self.layout.root_block()
+    }
+
+    pub fn is_ffs(&self) -> bool {
+        self.sector(0).is_ok_and(|boot| boot[3] & 1 == 1)
+    }
+
+    pub fn sector(&self, block: usize) -> Result<Cow<'_, [u8]>> {
+        if block >= self.num_blocks() {
+            return Err(RawError::BlockOutOfRange(block));
+        }
+        self.source.read_block(block, self.layout.block_size)
+    }
+
+    // A valid non-zero pointer to another block of the volume.
+    fn pointer(&self, block: &[u8], offset: usize, owner: usize) -> Result<Option<usize>> {
+        match read_u32_be(block, offset) as usize {
+            0 => Ok(None),
+            pointer if pointer < self.num_blocks() => Ok(Some(pointer)),
+            _ => Err(RawError::Corrupt(owner)),
+        }
+    }
+
+    pub fn entry(&self, block: usize) -> Result<Entry> {
+        let sector = self.sector(block)?;
+        if read_u32_be(&sector, BLOCK_TYPE) != T_HEADER {
+            return Err(RawError::Corrupt(block));
+        }
+        Ok(Entry::from_sector(&self.layout, block, &sector))
+    }
+
+    // Walks every hash chain of the directory; the chain length is bounded by the volume size
+    // so a looping chain cannot hang the caller.
+    pub fn entries(&self, dir: usize) -> Result<Vec<Entry>> {
+        if !self.entry(dir)?.is_dir {
+            return Err(RawError::NotADirectory(dir));
+        }
+        let sector = self.sector(dir)?;
+        let mut entries = Vec::new();
+        for slot in 0..self.layout.hash_table_size() {
+            let mut next = self.pointer(&sector, BLOCK_HASH_TABLE + slot * 4, dir)?;
+            while let Some(block) = next {
+                if entries.len() >= self.num_blocks() {
+                    return Err(RawError::Corrupt(dir));
+                }
+                entries.push(self.entry(block)?);
+                next = self.pointer(&self.sector(block)?, self.layout.hash_chain(), block)?;
+            }
+        }
+        Ok(entries)
+    }
+
+    // Looks up a '/' separated path below the root, ignoring case like AmigaDOS.
+    pub fn find(&self, path: &str) -> Result<usize> {
+        let mut block = self.root_block();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            block = self
+                .entries(block)?
+                .into_iter()
+                .find(|entry| entry.name.eq_ignore_ascii_case(component))
+                .ok_or(RawError::NotFound)?
+                .block;
+        }
+        Ok(block)
+    }
+
+    // Data block pointers of a file, from its header and extension blocks.
+    fn data_blocks(&self, header: usize) -> Result<Vec<usize>> {
+        let hash_table_size = self.layout.hash_table_size();
+        let mut data_blocks = Vec::new();
+        let mut next = Some(header);
+        while let Some(block) = next {
+            let sector = self.sector(block)?;
+            if block != header && read_u32_be(&sector, BLOCK_TYPE) != T_LIST {
+                return Err(RawError::Corrupt(block));
+            }
+            let count = (read_u32_be(&sector, BLOCK_HIGH_SEQ) as usize).min(hash_table_size);
+            for i in 0..count {
+                let offset = BLOCK_HASH_TABLE + (hash_table_size - 1 - i) * 4;
+                let data_block = self
+                    .pointer(&sector, offset, block)?
+                    .ok_or(RawError::Corrupt(block))?;
+                data_blocks.push(data_block);
+            }
+            if data_blocks.len() > self.num_blocks() {
+                return Err(RawError::Corrupt(header));
+            }
+            next = self.pointer(&sector, self.layout.extension(), block)?;
+        }
+        Ok(data_blocks)
+    }
+
+    // Bitmap pages listed in the root block and, on volumes that need more than the root has
+    // room for, in the chain of bitmap extension blocks that bm_ext starts.
+    pub fn bitmap_pages(&self) -> Result<Vec<usize>> {
+        let root = self.root_block();
+        let sector = self.sector(root)?;
+        let mut pages = Vec::new();
+        for page_index in 0..self.layout.root_bitmap_pages() {
+            let offset = self.layout.bm_pages() + page_index * 4;
+            match self.pointer(&sector, offset, root)? {
+                Some(page) => pages.push(page),
+                None => return Ok(pages),
+            }
+        }
+        // An extension block is all page pointers but for the link to the next one.
+        let per_extension = self.layout.block_size / 4 - 1;
+        let mut next = self.pointer(&sector, self.layout.bm_ext(), root)?;
+        while let Some(extension) = next {
+            if pages.len() >= self.layout.bitmap_pages() {
+                return Err(RawError::Corrupt(extension));
+            }
+            let sector = self.sector(extension)?;
+            for page_index in 0..per_extension {
+                match self.pointer(&sector, page_index * 4, extension)? {
+                    Some(page) => pages.push(page),
+                    None => return Ok(pages),
+                }
+            }
+            next = self.pointer(&sector, per_extension * 4, extension)?;
+        }
+        Ok(pages)
+    }
+
+    // Free blocks according to the bitmap pages.
+    pub fn free_blocks(&self) -> Result<usize> {
+        let bits = self.layout.bits_per_bitmap_page();
+        let mut free = 0;
+        for (page_index, page) in self.bitmap_pages()?.into_iter().enumerate() {
+            let covered = self
+                .num_blocks()
+                .saturating_sub(2 + page_index * bits)
+                .min(bits);
+            let sector = self.sector(page)?;
+            let words = words(&sector[4..]).take(covered.div_ceil(32));
+            for (word_index, word) in words.enumerate() {
+                free += match covered - word_index * 32 {
+                    32.. => word.count_ones(),
+                    valid => (word & ((1 << valid) - 1)).count_ones(),
+                } as usize;
+            }
+        }
+        Ok(free)
+    }
+
+    pub fn read_file(&self, header: usize) -> Result<Vec<u8>> {
+        let sector = self.sector(header)?;
+        if read_u32_be(&sector, BLOCK_TYPE) != T_HEADER
+            || read_u32_be(&sector, self.layout.sec_type()) != ST_FILE
+        {
+            return Err(RawError::NotAFile(header));
+        }
+        let size = read_u32_be(&sector, self.layout.byte_size()) as usize;
+        let is_ffs = self.is_ffs();
+        let mut contents = Vec::with_capacity(size);
+        for block in self.data_blocks(header)? {
+            if contents.len() >= size {
+                break;
+            }
+            let data = self.sector(block)?;
+            let payload = if is_ffs {
+                &data[..]
+            } else {
+                let len =
+                    (read_u32_be(&data, OFS_DATA_LEN) as usize).min(self.layout.ofs_data_size());
+                &data[OFS_HEADER_SIZE..OFS_HEADER_SIZE + len]
+            };
+            let remaining = size - contents.len();
+            contents.extend_from_slice(&payload[..payload.len().min(remaining)]);
+        }
+        if contents.len() != size {
+            return Err(RawError::Truncated);
+        }
+        Ok(contents)
+    }
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, &byte| {
+        let mut crc = crc ^ byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+// Decodes a DMS RLE track: 0x90 0x00 is a literal marker, 0x90 n b repeats b n times and
+// 0x90 0xff b hi lo repeats b for a 16-bit count.
+pub fn unpack_rle(packed: &[u8], unpacked_len: usize) -> Result<Vec<u8>> {
+    let mut input = packed.iter().copied();
+    let mut output = Vec::with_capacity(unpacked_len);
+    while output.len() < unpacked_len {
+        let byte = input.next().ok_or(RawError::Truncated)?;
+        if byte != RLE_MARKER {
+            output.push(byte);
+            continue;
+        }
+        let count = input.next().ok_or(RawError::Truncated)?;
+        if count == 0 {
+            output.push(RLE_MARKER);
+            continue;
+        }
+        let value = input.next().ok_or(RawError::Truncated)?;
+        let run = if count == 0xff {
+            let hi = input.next().ok_or(RawError::Truncated)?;
+            let lo = input.next().ok_or(RawError::Truncated)?;
+            u16::from_be_bytes([hi, lo]) as usize
+        } else {
+            count as usize
+        };
+        if output.len() + run > unpacked_len {
+            return Err(RawError::Overflow);
+        }
+        output.resize(output.len() + run, value);
+    }
+    Ok(output)
+}
+
+// Reads bits most significant first, keeping at least 16 in hand like the DMS decrunchers.
+// Past the end of the data it reads zero bits; callers stop once they have their output.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        let mut reader = BitReader {
+            data,
+            pos: 0,
+            buf: 0,
+            count: 0,
+        };
+        reader.refill();
+        reader
+    }
+
+    fn refill(&mut self) {
+        while self.count < 16 {
+            let byte = self.data.get(self.pos).copied().unwrap_or(0);
+            self.pos += 1;
+            self.buf = (self.buf << 8) | u32::from(byte);
+            self.count += 8;
+        }
+    }
+
+    // The next n bits, n at most 16, without consuming them.
+    fn peek(&self, n: u32) -> u16 {
+        (self.buf >> (self.count - n)) as u16
+    }
+
+    fn skip(&mut self, n: u32) {
+        self.count -= n.min(self.count);
+        self.buf &= (1 << self.count) - 1;
+        self.refill();
+    }
+
+    fn bits(&mut self, n: u32) -> u16 {
+        let value = self.peek(n);
+        self.skip(n);
+        value
+    }
+}
+
+// The LZ modes share one window. Each keeps its own position in it, which moves on by the
+// mode's longest match after every track.
+const WINDOW_SIZE: usize = 0x4000;
+// A reset clears the window up to here and keeps the rest, as Init_Decrunchers in xDMS does.
+const WINDOW_CLEAR: usize = 0x3fc8;
+const QUICK_MASK: u16 = 0xff;
+const QUICK_START: u16 = 251;
+const MEDIUM_MASK: u16 = 0x3fff;
+const MEDIUM_START: u16 = 0x3fbe;
+const DEEP_MASK: u16 = 0x3fff;
+const DEEP_START: u16 = 0x3fc4;
+
+// DEEP codes literals and match lengths of 3 to 60 with the adaptive Huffman tree of LHarc:
+// DEEP_CHARS leaves, rebuilt with halved counts when the root count reaches DEEP_MAX_FREQ.
+const DEEP_LOOKAHEAD: usize = 60;
+const DEEP_CHARS: usize = 256 - 2 + DEEP_LOOKAHEAD;
+const DEEP_NODES: usize = 2 * DEEP_CHARS - 1;
+const DEEP_ROOT: usize = DEEP_NODES - 1;
+const DEEP_MAX_FREQ: u16 = 0x8000;
+
+// HEAVY is LZH with static Huffman tables sent in the track when flag bit 1 is set and kept
+// otherwise: one for literals and match lengths of 3 to 256, one for the bit length of
+// match offsets. The offset table's last symbol repeats the previous offset.
+const HEAVY_CHARS: usize = 510;
+const HEAVY_CHAR_BITS: u32 = 12;
+const HEAVY_OFFSET_CODES: usize = 20;
+const HEAVY_OFFSET_BITS: u32 = 8;
+// Bits 3 of the flags, set by the caller for HEAVY2, selects an 8K window over a 4K one.
+const HEAVY_BIG_WINDOW: u8 = 8;
+
+// Prefix codes of the high bits of a match offset, indexed by the next 8 input bits: D_LEN
+// is the length of the code, D_CODE its value. These are the tables of LHarc.
+const fn offset_tables() -> ([u8; 256], [u8; 256]) {
+    // Code lengths with the number of codes of that length.
+    const GROUPS: [(u8, usize); 6] = [(3, 1), (4, 3), (5, 8), (6, 12), (7, 24), (8, 16)];
+    let mut code = [0u8; 256];
+    let mut len = [0u8; 256];
+    let (mut index, mut value, mut group) = (0, 0, 0);
+    while group < GROUPS.len() {
+        let (bits, codes) = GROUPS[group];
+        let mut i = 0;
+        while i < codes << (8 - bits) {
+            code[index] = value + (i >> (8 - bits)) as u8;
+            len[index] = bits;
+            index += 1;
+            i += 1;
+        }
+        value += codes as u8;
+        group += 1;
+    }
+    (code, len)
+}
+
+const OFFSET_TABLES: ([u8; 256], [u8; 256]) = offset_tables();
+const D_CODE: [u8; 256] = OFFSET_TABLES.0;
+const D_LEN: [u8; 256] = OFFSET_TABLES.1;
+
+// Nodes are kept in order of their counts. son holds the first of two children, or a leaf
+// as its symbol plus DEEP_NODES; prnt maps nodes, and leaves at DEEP_NODES on, to parents.
+#[derive(Debug, Clone)]
+pub(crate) struct DeepTree {
+    pub(crate) freq: Vec<u16>,
+    pub(crate) prnt: Vec<usize>,
+    pub(crate) son: Vec<usize>,
+}
+
+impl DeepTree {
+    pub(crate) fn new() -> Self {
+        let mut tree = DeepTree {
+            freq: alloc::vec![0; DEEP_NODES + 1],
+            prnt: alloc::vec![0; DEEP_NODES + DEEP_CHARS],
+            son: alloc::vec![0; DEEP_NODES],
+        };
+        for i in 0..DEEP_CHARS {
+            tree.freq[i] = 1;
+            tree.son[i] = i + DEEP_NODES;
+            tree.prnt[i + DEEP_NODES] = i;
+        }
+        for (j, i) in (DEEP_CHARS..=DEEP_ROOT).zip((0..).step_by(2)) {
+            tree.freq[j] = tree.freq[i] + tree.freq[i + 1];
+            tree.son[j] = i;
+            tree.prnt[i] = j;
+            tree.prnt[i + 1] = j;
+        }
+        // A sentinel that stops the search in update.
+        tree.freq[DEEP_NODES] = u16::MAX;
+        tree.prnt[DEEP_ROOT] = 0;
+        tree
+    }
+
+    fn decode(&mut self, input: &mut BitReader) -> usize {
+        let mut node = self.son[DEEP_ROOT];
+        while node < DEEP_NODES {
+            node = self.son[node + usize::from(input.bits(1))];
+        }
+        let symbol = node - DEEP_NODES;
+        self.update(symbol);
+        symbol
+    }
+
+    // Counts symbol, swapping nodes up the tree to keep it ordered by count.
+    pub(crate) fn update(&mut self, symbol: usize) {
+        if self.freq[DEEP_ROOT] == DEEP_MAX_FREQ {
+            self.rebuild();
+        }
+        let mut node = self.prnt[symbol + DEEP_NODES];
+        loop {
+            self.freq[node] += 1;
+            let count = self.freq[node];
+            if count > self.freq[node + 1] {
+                let mut swap = node + 1;
+                while count > self.freq[swap + 1] {
+                    swap += 1;
+                }
+                self.freq[node] = self.freq[swap];
+                self.freq[swap] = count;
+
+                let child = self.son[node];
+                self.prnt[child] = swap;
+                if child < DEEP_NODES {
+                    self.prnt[child + 1] = swap;
+                }
+                let other = self.son[swap];
+                self.son[swap] = child;
+                self.prnt[other] = node;
+                if other < DEEP_NODES {
+                    self.prnt[other + 1] = node;
+                }
+                self.son[node] = other;
+                node = swap;
+            }
+            node = self.prnt[node];
+            if node == 0 {
+                break;
+            }
+        }
+    }
+
+    // Halves the leaf counts and builds the tree again from them.
+    fn rebuild(&mut self) {
+        let mut leaves = 0;
+        for i in 0..DEEP_NODES {
+            if self.son[i] >= DEEP_NODES {
+                self.freq[leaves] = self.freq[i].div_ceil(2);
+                self.son[leaves] = self.son[i];
+                leaves += 1;
+            }
+        }
+        for (j, i) in (DEEP_CHARS..DEEP_NODES).zip((0..).step_by(2)) {
+            let count = self.freq[i] + self.freq[i + 1];
+            let mut k = j - 1;
+            while count < self.freq[k] {
+                k -= 1;
+            }
+            k += 1;
+            self.freq.copy_within(k..j, k + 1);
+            self.freq[k] = count;
+            self.son.copy_within(k..j, k + 1);
+            self.son[k] = i;
+        }
+        for i in 0..DEEP_NODES {
+            let child = self.son[i];
+            self.prnt[child] = i;
+            if child < DEEP_NODES {
+                self.prnt[child + 1] = i;
+            }
+        }
+    }
+}
+
+// Fills a lookup table of table_bits bits from the code lengths of a canonical Huffman code.
+// Codes longer than the table continue as a tree in left and right, its nodes numbered from
+// the number of symbols on.
+struct TableBuilder<'a> {
+    lens: &'a [u8],
+    table: &'a mut [u16],
+    left: &'a mut [u16],
+    right: &'a mut [u16],
+    symbol: Option<usize>,
+    len: u32,
+    depth: u32,
+    max_depth: u32,
+    avail: usize,
+    codeword: usize,
+    bit: usize,
+}
+
+impl TableBuilder<'_> {
+    fn build(
+        lens: &[u8],
+        table_bits: u32,
+        table: &mut [u16],
+        left: &mut [u16],
+        right: &mut [u16],
+    ) -> Result<()> {
+        let size = 1 << table_bits;
+        let mut builder = TableBuilder {
+            lens,
+            table: &mut table[..size],
+            left,
+            right,
+            symbol: None,
+            len: 1,
+            depth: 1,
+            max_depth: table_bits + 1,
+            avail: lens.len(),
+            codeword: 0,
+            bit: size / 2,
+        };
+        builder.node()?;
+        builder.node()?;
+        if builder.codeword != size {
+            return Err(RawError::BadCompressedData);
+        }
+        Ok(())
+    }
+
+    // Places the next code of the current length, or descends a level; returns the symbol
+    // or tree node placed.
+    fn node(&mut self) -> Result<u16> {
+        let size = self.table.len();
+        if self.len == self.depth {
+            let from = self.symbol.map_or(0, |symbol| symbol + 1);
+            if let Some(symbol) =
+                (from..self.lens.len()).find(|&i| u32::from(self.lens[i]) == self.len)
+            {
+                self.symbol = Some(symbol);
+                let start = self.codeword;
+                self.codeword += self.bit;
+                if self.codeword > size {
+                    return Err(RawError::BadCompressedData);
+                }
+                self.table[start..self.codeword].fill(symbol as u16);
+                return Ok(symbol as u16);
+            }
+            self.symbol = None;
+            self.len += 1;
+            self.bit >>= 1;
+        }
+        self.depth += 1;
+        let mut node = 0;
+        if self.depth < self.max_depth {
+            self.node()?;
+            self.node()?;
+        } else if self.depth > 32 {
+            return Err(RawError::BadCompressedData);
+        } else {
+            node = self.avail;
+            self.avail += 1;
+            if node >= 2 * self.lens.len() - 1 || node >= self.left.len() {
+                return Err(RawError::BadCompressedData);
+            }
+            self.left[node] = self.node()?;
+            self.right[node] = self.node()?;
+            if self.codeword >= size {
+                return Err(RawError::BadCompressedData);
+            }
+            if self.depth == self.max_depth {
+                self.table[self.codeword] = node as u16;
+                self.codeword += 1;
+            }
+        }
+        self.depth -= 1;
+        Ok(node as u16)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct HeavyTables {
+    char_lens: Vec<u8>,
+    char_table: Vec<u16>,
+    offset_lens: Vec<u8>,
+    offset_table: Vec<u16>,
+    // Tree nodes of codes longer than the tables, shared by both codes.
+    left: Vec<u16>,
+    right: Vec<u16>,
+    last_offset: u16,
+}
+
+impl HeavyTables {
+    fn new() -> Self {
+        HeavyTables {
+            char_lens: alloc::vec![0; HEAVY_CHARS],
+            char_table: alloc::vec![0; 1 << HEAVY_CHAR_BITS],
+            offset_lens: alloc::vec![0; HEAVY_OFFSET_CODES],
+            offset_table: alloc::vec![0; 1 << HEAVY_OFFSET_BITS],
+            left: alloc::vec![0; 2 * HEAVY_CHARS - 1],
+            right: alloc::vec![0; 2 * HEAVY_CHARS - 1],
+            last_offset: 0,
+        }
+    }
+
+    // A code length table: a count and that many lengths, or a zero count and the one
+    // symbol every code stands for, which is returned.
+    fn read_lens(
+        input: &mut BitReader,
+        count_bits: u32,
+        len_bits: u32,
+        lens: &mut [u8],
+    ) -> Result<Option<u16>> {
+        let count = usize::from(input.bits(count_bits));
+        if count > lens.len() {
+            return Err(RawError::BadCompressedData);
+        }
+        if count == 0 {
+            lens.fill(0);
+            return Ok(Some(input.bits(count_bits)));
+        }
+        for len in &mut lens[..count] {
+            *len = input.bits(len_bits) as u8;
+        }
+        lens[count..].fill(0);
+        Ok(None)
+    }
+
+    fn read_tables(&mut self, input: &mut BitReader, offset_codes: usize) -> Result<()> {
+        match HeavyTables::read_lens(input, 9, 5, &mut self.char_lens)? {
+            Some(symbol) => self.char_table.fill(symbol),
+            None => TableBuilder::build(
+                &self.char_lens,
+                HEAVY_CHAR_BITS,
+                &mut self.char_table,
+                &mut self.left,
+                &mut self.right,
+            )?,
+        }
+        let offset_lens = &mut self.offset_lens[..offset_codes];
+        match HeavyTables::read_lens(input, 5, 4, offset_lens)? {
+            Some(symbol) => self.offset_table.fill(symbol),
+            None => TableBuilder::build(
+                offset_lens,
+                HEAVY_OFFSET_BITS,
+                &mut self.offset_table,
+                &mut self.left,
+                &mut self.right,
+            )?,
+        }
+        Ok(())
+    }
+
+    // Looks up the next symbol in table, following the tree past table_bits.
+    fn decode(
+        &self,
+        input: &mut BitReader,
+        table: &[u16],
+        lens: &[u8],
+        table_bits: u32,
+    ) -> Result<usize> {
+        let symbols = lens.len();
+        let mut symbol = usize::from(table[usize::from(input.peek(table_bits))]);
+        if symbol < symbols {
+            input.skip(u32::from(lens[symbol]));
+            return Ok(symbol);
+        }
+        input.skip(table_bits);
+        let code = input.peek(16);
+        let mut mask = 0x8000u16;
+        while symbol >= symbols {
+            let nodes = if code & mask != 0 {
+                &self.right
+            } else {
+                &self.left
+            };
+            symbol = usize::from(*nodes.get(symbol).ok_or(RawError::BadCompressedData)?);
+            mask >>= 1;
+            if mask == 0 && symbol >= symbols {
+                return Err(RawError::BadCompressedData);
+            }
+        }
+        input.skip(u32::from(lens[symbol]).saturating_sub(table_bits));
+        Ok(symbol)
+    }
+
+    fn decode_offset(&mut self, input: &mut BitReader, offset_codes: usize) -> Result<u16> {
+        let lens = &self.offset_lens[..offset_codes];
+        let code = self.decode(input, &self.offset_table, lens, HEAVY_OFFSET_BITS)?;
+        if code != offset_codes - 1 {
+            self.last_offset = match code as u32 {
+                0 => 0,
+                bits => input.bits(bits - 1) | (1 << (bits - 1)),
+            };
+        }
+        Ok(self.last_offset)
+    }
+}
+
+// Decompresses DMS tracks. The LZ modes carry their window from one track to the next;
+// the archive says per track whether to keep it, and it is cleared otherwise.
+#[derive(Debug, Clone)]
+pub struct TrackUnpacker {
+    text: Vec<u8>,
+    quick_pos: u16,
+    medium_pos: u16,
+    deep_pos: u16,
+    // Built on the first DEEP track after a reset.
+    deep: Option<DeepTree>,
+    heavy_pos: u16,
+    // Only replaced by tracks that bring new tables; a reset clears just the last offset.
+    heavy: HeavyTables,
+}
+
+impl Default for TrackUnpacker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TrackUnpacker {
+    pub fn new() -> Self {
+        TrackUnpacker {
+            text: alloc::vec![0; WINDOW_SIZE],
+            quick_pos: QUICK_START,
+            medium_pos: MEDIUM_START,
+            deep_pos: DEEP_START,
+            deep: None,
+            heavy_pos: 0,
+            heavy: HeavyTables::new(),
+        }
+    }
+
+    // Back to the state at the start of an archive, except for the HEAVY tables, which
+    // DMS keeps until a track replaces them, and the end of the window.
+    pub fn reset(&mut self) {
+        self.text[..WINDOW_CLEAR].fill(0);
+        self.quick_pos = QUICK_START;
+        self.medium_pos = MEDIUM_START;
+        self.deep_pos = DEEP_START;
+        self.deep = None;
+        self.heavy_pos = 0;
+        self.heavy.last_offset = 0;
+    }
+
+    // Unpacks a track of the given compression mode. raw_len is the length after the LZ
+    // stage, which the two stage modes then expand with RLE to unpacked_len. Bit 0 of
+    // flags keeps the window for the next track.
+    pub fn unpack(
+        &mut self,
+        mode: u8,
+        flags: u8,
+        packed: &[u8],
+        raw_len: usize,
+        unpacked_len: usize,
+    ) -> Result<Vec<u8>> {
+        let track = match mode {
+            0 => packed
+                .get(..unpacked_len)
+                .ok_or(RawError::Truncated)?
+                .to_vec(),
+            1 => unpack_rle(packed, unpacked_len)?,
+            2 => unpack_rle(&self.unpack_quick(packed, raw_len), unpacked_len)?,
+            3 => unpack_rle(&self.unpack_medium(packed, raw_len), unpacked_len)?,
+            4 => unpack_rle(&self.unpack_deep(packed, raw_len), unpacked_len)?,
+            5 | 6 => {
+                // HEAVY1 honours flag bits 0 to 2 only.
+                let flags = match mode {
+                    5 => flags & 7,
+                    _ => flags | HEAVY_BIG_WINDOW,
+                };
+                let lz = self.unpack_heavy(packed, flags, raw_len)?;
+                if flags & 4 != 0 {
+                    unpack_rle(&lz, unpacked_len)?
+                } else {
+                    lz.get(..unpacked_len).ok_or(RawError::Truncated)?.to_vec()
+                }
+            }
+            _ => return Err(RawError::UnsupportedMode(mode)),
+        };
+        if flags & 1 == 0 {
+            self.reset();
+        }
+        Ok(track)
+    }
+
+    // Copies len bytes from offset + 1 bytes back in the window to the output.
+    fn copy_match(&mut self, pos: &mut u16, mask: u16, offset: u16, len: usize, out: &mut Vec<u8>) {
+        let mut from = pos.wrapping_sub(offset).wrapping_sub(1);
+        for _ in 0..len {
+            let byte = self.text[usize::from(from & mask)];
+            self.text[usize::from(*pos & mask)] = byte;
+            out.push(byte);
+            *pos = pos.wrapping_add(1);
+            from = from.wrapping_add(1);
+        }
+    }
+
+    fn literal(&mut self, pos: &mut u16, mask: u16, byte: u8, out: &mut Vec<u8>) {
+        self.text[usize::from(*pos & mask)] = byte;
+        out.push(byte);
+        *pos = pos.wrapping_add(1);
+    }
+
+    // A flag bit, then a literal byte or a match of 2 to 5 bytes within the last 256.
+    fn unpack_quick(&mut self, packed: &[u8], raw_len: usize) -> Vec<u8> {
+        let mut input = BitReader::new(packed);
+        let mut out = Vec::with_capacity(raw_len + 5);
+        let mut pos = self.quick_pos;
+        while out.len() < raw_len {
+            if input.bits(1) != 0 {
+                let byte = input.bits(8) as u8;
+                self.literal(&mut pos, QUICK_MASK, byte, &mut out);
+            } else {
+                let len = usize::from(input.bits(2)) + 2;
+                let offset = input.bits(8);
+                self.copy_match(&mut pos, QUICK_MASK, offset, len, &mut out);
+            }
+        }
+        self.quick_pos = pos.wrapping_add(5) & QUICK_MASK;
+        out.truncate(raw_len);
+        out
+    }
+
+    // A flag bit, then a literal byte or a match of 3 to 66 bytes within the last 16K, its
+    // length and the high bits of its offset coded with D_CODE and D_LEN.
+    fn unpack_medium(&mut self, packed: &[u8], raw_len: usize) -> Vec<u8> {
+        let mut input = BitReader::new(packed);
+        let mut out = Vec::with_capacity(raw_len + 66);
+        let mut pos = self.medium_pos;
+        while out.len() < raw_len {
+            if input.bits(1) != 0 {
+                let byte = input.bits(8) as u8;
+                self.literal(&mut pos, MEDIUM_MASK, byte, &mut out);
+            } else {
+                let code = usize::from(input.bits(8));
+                let len = usize::from(D_CODE[code]) + 3;
+                let bits = u32::from(D_LEN[code]);
+                let code = usize::from(((code << bits) as u16 | input.bits(bits)) & 0xff);
+                let bits = u32::from(D_LEN[code]);
+                let low = ((code << bits) as u16 | input.bits(bits)) & 0xff;
+                let offset = (u16::from(D_CODE[code]) << 8) | low;
+                self.copy_match(&mut pos, MEDIUM_MASK, offset, len, &mut out);
+            }
+        }
+        self.medium_pos = pos.wrapping_add(66) & MEDIUM_MASK;
+        out.truncate(raw_len);
+        out
+    }
+
+    // Literals and match lengths from the adaptive tree, match offsets within the last 16K
+    // coded like MEDIUM's.
+    fn unpack_deep(&mut self, packed: &[u8], raw_len: usize) -> Vec<u8> {
+        let mut input = BitReader::new(packed);
+        let mut out = Vec::with_capacity(raw_len + DEEP_LOOKAHEAD);
+        let mut pos = self.deep_pos;
+        let mut tree = self.deep.take().unwrap_or_else(DeepTree::new);
+        while out.len() < raw_len {
+            let symbol = tree.decode(&mut input);
+            if symbol < 256 {
+                self.literal(&mut pos, DEEP_MASK, symbol as u8, &mut out);
+            } else {
+                let len = symbol - 253;
+                let code = usize::from(input.bits(8));
+                let bits = u32::from(D_LEN[code]);
+                let low = ((code << bits) as u16 | input.bits(bits)) & 0xff;
+                let offset = (u16::from(D_CODE[code]) << 8) | low;
+                self.copy_match(&mut pos, DEEP_MASK, offset, len, &mut out);
+            }
+        }
+        self.deep = Some(tree);
+        self.deep_pos = pos.wrapping_add(DEEP_LOOKAHEAD as u16) & DEEP_MASK;
+        out.truncate(raw_len);
+        out
+    }
+
+    // Literals and match lengths of 3 to 256 from one Huffman code, match offsets within the
+    // last 4K or 8K as a bit length from another, then the bits below the top one. Flag bit 1
+    // brings new codes, bit 3 the bigger window.
+    fn unpack_heavy(&mut self, packed: &[u8], flags: u8, raw_len: usize) -> Result<Vec<u8>> {
+        let (offset_codes, mask) = match flags & HEAVY_BIG_WINDOW {
+            0 => (14, 0x0fff),
+            _ => (15, 0x1fff),
+        };
+        let mut input = BitReader::new(packed);
+        if flags & 2 != 0 {
+            self.heavy.read_tables(&mut input, offset_codes)?;
+        }
+        let mut out = Vec::with_capacity(raw_len + 256);
+        let mut pos = self.heavy_pos;
+        while out.len() < raw_len {
+            let heavy = &self.heavy;
+            let symbol = heavy.decode(
+                &mut input,
+                &heavy.char_table,
+                &heavy.char_lens,
+                HEAVY_CHAR_BITS,
+            )?;
+            if symbol < 256 {
+                self.literal(&mut pos, mask, symbol as u8, &mut out);
+            } else {
+                let offset = self.heavy.decode_offset(&mut input, offset_codes)?;
+                self.copy_match(&mut pos, mask, offset, symbol - 253, &mut out);
+            }
+        }
+        self.heavy_pos = pos;
+        out.truncate(raw_len);
+        Ok(out)
+    }
+}
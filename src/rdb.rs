@@ -0,0 +1,224 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use crate::disk::{latin1_string, read_u32_be, write_u32_be, ADF_SECTOR_SIZE};
+use serde::Serialize;
+use std::io::{Error, ErrorKind, Result};
+
+// The rigid disk block may sit in any of the first 16 blocks of a hard disk.
+pub const RDB_SEARCH_BLOCKS: usize = 16;
+// End of a block list; unlike filesystem pointers, RDB lists are terminated with -1.
+pub const RDB_END: u32 = u32::MAX;
+
+const SUMMED_LONGS: usize = 4;
+const CHECKSUM: usize = 8;
+const HOST_ID: usize = 12;
+const RDSK_BLOCK_BYTES: usize = 16;
+const RDSK_BAD_BLOCKS: usize = 24;
+const RDSK_PARTITIONS: usize = 28;
+const RDSK_FILESYSTEMS: usize = 32;
+const RDSK_DRIVE_INIT: usize = 36;
+const RDSK_CYLINDERS: usize = 64;
+const RDSK_SECTORS: usize = 68;
+const RDSK_HEADS: usize = 72;
+const PART_NEXT: usize = 16;
+const PART_DRIVE_NAME: usize = 36;
+const PART_ENVIRONMENT: usize = 128;
+const ENV_TABLE_SIZE: usize = PART_ENVIRONMENT;
+const ENV_SIZE_BLOCK: usize = PART_ENVIRONMENT + 4;
+const ENV_SURFACES: usize = PART_ENVIRONMENT + 12;
+const ENV_BLOCKS_PER_TRACK: usize = PART_ENVIRONMENT + 20;
+const ENV_RESERVED: usize = PART_ENVIRONMENT + 24;
+const ENV_LOW_CYL: usize = PART_ENVIRONMENT + 36;
+const ENV_HIGH_CYL: usize = PART_ENVIRONMENT + 40;
+const ENV_DOS_TYPE: usize = PART_ENVIRONMENT + 64;
+const RDB_LONGS: u32 = 64;
+const MAX_DRIVE_NAME_LEN: usize = 31;
+
+// Sums the block's first summed_longs words, the checksum word included; valid blocks add up
+// to zero.
+fn rdb_checksum(block: &[u8]) -> u32 {
+    let longs = (read_u32_be(block, SUMMED_LONGS) as usize).min(block.len() / 4);
+    let sum = (0..longs)
+        .filter(|&i| i != CHECKSUM / 4)
+        .fold(0u32, |sum, i| sum.wrapping_add(read_u32_be(block, i * 4)));
+    sum.wrapping_neg()
+}
+
+fn check_block(block: &[u8], id: &[u8; 4]) -> Result<()> {
+    if block.len() < ADF_SECTOR_SIZE || !block.starts_with(id) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Not a {} block", String::from_utf8_lossy(id)),
+        ));
+    }
+    if rdb_checksum(block) != read_u32_be(block, CHECKSUM) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} block checksum mismatch", String::from_utf8_lossy(id)),
+        ));
+    }
+    Ok(())
+}
+
+fn finish_block(block: &mut [u8]) {
+    write_u32_be(block, SUMMED_LONGS, RDB_LONGS);
+    write_u32_be(block, HOST_ID, 7);
+    let checksum = rdb_checksum(block);
+    write_u32_be(block, CHECKSUM, checksum);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RigidDisk {
+    pub block_bytes: u32,
+    pub partition_list: u32,
+    pub cylinders: u32,
+    pub sectors: u32,
+    pub heads: u32,
+}
+
+impl RigidDisk {
+    pub fn from_bytes(block: &[u8]) -> Result<Self> {
+        check_block(block, b"RDSK")?;
+        Ok(RigidDisk {
+            block_bytes: read_u32_be(block, RDSK_BLOCK_BYTES),
+            partition_list: read_u32_be(block, RDSK_PARTITIONS),
+            cylinders: read_u32_be(block, RDSK_CYLINDERS),
+            sectors: read_u32_be(block, RDSK_SECTORS),
+            heads: read_u32_be(block, RDSK_HEADS),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut block = vec![0u8; ADF_SECTOR_SIZE];
+        block[..4].copy_from_slice(b"RDSK");
+        write_u32_be(&mut block, RDSK_BLOCK_BYTES, self.block_bytes);
+        for offset in [RDSK_BAD_BLOCKS, RDSK_FILESYSTEMS, RDSK_DRIVE_INIT] {
+            write_u32_be(&mut block, offset, RDB_END);
+        }
+        write_u32_be(&mut block, RDSK_PARTITIONS, self.partition_list);
+        write_u32_be(&mut block, RDSK_CYLINDERS, self.cylinders);
+        write_u32_be(&mut block, RDSK_SECTORS, self.sectors);
+        write_u32_be(&mut block, RDSK_HEADS, self.heads);
+        finish_block(&mut block);
+        block
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Partition {
+    pub next: u32,
+    pub drive_name: String,
+    pub size_block: u32,
+    pub surfaces: u32,
+    pub blocks_per_track: u32,
+    pub reserved: u32,
+    pub low_cyl: u32,
+    pub high_cyl: u32,
+    pub dos_type: u32,
+}
+
+impl Partition {
+    pub fn from_bytes(block: &[u8]) -> Result<Self> {
+        check_block(block, b"PART")?;
+        let name_len = (block[PART_DRIVE_NAME] as usize).min(MAX_DRIVE_NAME_LEN);
+        Ok(Partition {
+            next: read_u32_be(block, PART_NEXT),
+            drive_name: latin1_string(&block[PART_DRIVE_NAME + 1..PART_DRIVE_NAME + 1 + name_len]),
+            size_block: read_u32_be(block, ENV_SIZE_BLOCK),
+            surfaces: read_u32_be(block, ENV_SURFACES),
+            blocks_per_track: read_u32_be(block, ENV_BLOCKS_PER_TRACK),
+            reserved: read_u32_be(block, ENV_RESERVED),
+            low_cyl: read_u32_be(block, ENV_LOW_CYL),
+            high_cyl: read_u32_be(block, ENV_HIGH_CYL),
+            dos_type: read_u32_be(block, ENV_DOS_TYPE),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut block = vec![0u8; ADF_SECTOR_SIZE];
+        block[..4].copy_from_slice(b"PART");
+        write_u32_be(&mut block, PART_NEXT, self.next);
+        let name = self.drive_name.as_bytes();
+        let name_len = name.len().min(MAX_DRIVE_NAME_LEN);
+        block[PART_DRIVE_NAME] = name_len as u8;
+        block[PART_DRIVE_NAME + 1..PART_DRIVE_NAME + 1 + name_len]
+            .copy_from_slice(&name[..name_len]);
+        write_u32_be(&mut block, ENV_TABLE_SIZE, 16);
+        write_u32_be(&mut block, ENV_SIZE_BLOCK, self.size_block);
+        write_u32_be(&mut block, ENV_SURFACES, self.surfaces);
+        write_u32_be(&mut block, ENV_BLOCKS_PER_TRACK, self.blocks_per_track);
+        write_u32_be(&mut block, ENV_RESERVED, self.reserved);
+        write_u32_be(&mut block, ENV_LOW_CYL, self.low_cyl);
+        write_u32_be(&mut block, ENV_HIGH_CYL, self.high_cyl);
+        write_u32_be(&mut block, ENV_DOS_TYPE, self.dos_type);
+        finish_block(&mut block);
+        block
+    }
+
+    fn cylinder_blocks(&self) -> usize {
+        self.surfaces as usize * self.blocks_per_track as usize
+    }
+
+    pub fn first_block(&self) -> usize {
+        self.low_cyl as usize * self.cylinder_blocks()
+    }
+
+    pub fn blocks(&self) -> usize {
+        (self.high_cyl as usize + 1).saturating_sub(self.low_cyl as usize) * self.cylinder_blocks()
+    }
+}
+
+// Locates the rigid disk block and walks its partition list. read_block is handed block
+// numbers and returns their contents; None means the image has no RDB.
+pub fn read_partitions<F>(
+    blocks: usize,
+    mut read_block: F,
+) -> Result<Option<(RigidDisk, Vec<Partition>)>>
+where
+    F: FnMut(usize) -> Result<Vec<u8>>,
+{
+    let mut rigid_disk = None;
+    for block in 0..blocks.min(RDB_SEARCH_BLOCKS) {
+        let data = read_block(block)?;
+        if data.starts_with(b"RDSK") {
+            rigid_disk = Some(RigidDisk::from_bytes(&data)?);
+            break;
+        }
+    }
+    let Some(rigid_disk) = rigid_disk else {
+        return Ok(None);
+    };
+    if rigid_disk.block_bytes as usize != ADF_SECTOR_SIZE {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("Unsupported RDB block size {}", rigid_disk.block_bytes),
+        ));
+    }
+
+    let mut partitions: Vec<Partition> = Vec::new();
+    let mut next = rigid_disk.partition_list;
+    while next != RDB_END {
+        if next as usize >= blocks || partitions.len() >= blocks {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Partition list points to invalid block {}", next),
+            ));
+        }
+        let partition = Partition::from_bytes(&read_block(next as usize)?)?;
+        if partition.blocks() == 0 || partition.first_block() + partition.blocks() > blocks {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Partition {} is empty or extends past the end of the disk",
+                    partition.drive_name
+                ),
+            ));
+        }
+        next = partition.next;
+        partitions.push(partition);
+    }
+    Ok(Some((rigid_disk, partitions)))
+}
@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use crate::disk::{ADF, ADF_HD_NUM_SECTORS, ADF_NUM_SECTORS, ADF_NUM_TRACKS};
+use std::io::Result;
+
+const CELL: usize = 8;
+const HARDFILE_ROWS: usize = 64;
+const BACKGROUND: [u8; 3] = [0x20, 0x20, 0x20];
+const FREE: [u8; 3] = [0x50, 0x50, 0x50];
+const USED: [u8; 3] = [0x40, 0x80, 0xe0];
+const SYSTEM: [u8; 3] = [0xe0, 0x40, 0x40];
+const ORPHAN: [u8; 3] = [0xf0, 0xc0, 0x30];
+
+#[derive(Debug, Clone)]
+pub struct BitmapImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl BitmapImage {
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(png)
+    }
+}
+
+// Hashes the owner path onto a hue so every file keeps a stable colour between renders.
+fn owner_color(owner: &str) -> [u8; 3] {
+    let hash = owner.bytes().fold(0x811c_9dc5u32, |h, b| {
+        (h ^ b as u32).wrapping_mul(0x0100_0193)
+    });
+    let hue = (hash % 360) as f32 / 60.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let scale = |c: f32| (0x40 as f32 + c * 0xa0 as f32) as u8;
+    [scale(r), scale(g), scale(b)]
+}
+
+impl ADF {
+    // Floppies get one column per cylinder with the sectors of both heads stacked vertically,
+    // hardfiles are wrapped into columns of a fixed height. Used blocks that no file owns, as
+    // on a damaged disk, are highlighted.
+    pub fn render_bitmap(&self, by_file: bool) -> BitmapImage {
+        let blocks = self.num_blocks();
+        let rows = if blocks == ADF_NUM_SECTORS || blocks == ADF_HD_NUM_SECTORS {
+            blocks / (ADF_NUM_TRACKS / 2)
+        } else {
+            HARDFILE_ROWS
+        };
+        let columns = blocks.div_ceil(rows);
+        let free = self.read_allocation_map();
+        let owners = self.block_owners().unwrap_or_default();
+
+        let width = columns * CELL + 1;
+        let height = rows * CELL + 1;
+        let mut pixels = BACKGROUND.repeat(width * height);
+        for (block, &is_free) in free.iter().enumerate() {
+            let color = match owners.get(&block) {
+                Some(owner) if owner.starts_with('<') => SYSTEM,
+                Some(owner) if by_file => owner_color(owner),
+                Some(_) => USED,
+                None if !is_free => ORPHAN,
+                None => FREE,
+            };
+            let (x0, y0) = ((block / rows) * CELL + 1, (block % rows) * CELL + 1);
+            for y in y0..y0 + CELL - 1 {
+                for x in x0..x0 + CELL - 1 {
+                    pixels[(y * width + x) * 3..][..3].copy_from_slice(&color);
+                }
+            }
+        }
+        BitmapImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
@@ -0,0 +1,261 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Parsers that do no I/O of their own. A decoder looks at the input it has been given and
+// produces an item, asks for more bytes or asks to continue at another offset. The adapters
+// below feed decoders from a slice, a Read + Seek source or an async read function, so the
+// same parsing code serves files, sockets and buffers handed over by a WASM host.
+
+use std::future::Future;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+
+// Upper bound for a single read, so a hostile length field cannot make an adapter allocate
+// more than the input actually holds.
+const READ_CHUNK: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decoded<T> {
+    // At least this many bytes from the current position are needed.
+    Need(usize),
+    // Bytes used from the front of the input, and the item they completed, if any.
+    Consumed(usize, Option<T>),
+    // Continue with the input at this absolute offset.
+    Seek(u64),
+    Done,
+}
+
+pub trait Decoder {
+    type Item;
+
+    // input starts at the current position; eof says that nothing follows it.
+    fn decode(&mut self, input: &[u8], eof: bool) -> Result<Decoded<Self::Item>>;
+}
+
+fn ends_early() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "Input ends early")
+}
+
+// Reads big endian values from the front of the input. Running out of input stops the
+// parse with Stop::Incomplete, so the decoder can ask for more and parse again.
+pub(crate) struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+pub(crate) enum Stop {
+    Incomplete(usize),
+    Error(Error),
+}
+
+impl From<Error> for Stop {
+    fn from(error: Error) -> Self {
+        Stop::Error(error)
+    }
+}
+
+impl<'a> Input<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> std::result::Result<&'a [u8], Stop> {
+        let end = self.pos.saturating_add(len);
+        let bytes = self.data.get(self.pos..end).ok_or(Stop::Incomplete(end))?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u32(&mut self) -> std::result::Result<u32, Stop> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+pub(crate) enum Parsed<T> {
+    Complete(usize, T),
+    Incomplete(usize),
+}
+
+// Parses one record from the front of input, reporting how much it used or needs.
+pub(crate) fn parse<'a, T>(
+    input: &'a [u8],
+    record: impl FnOnce(&mut Input<'a>) -> std::result::Result<T, Stop>,
+) -> Result<Parsed<T>> {
+    let mut input = Input {
+        data: input,
+        pos: 0,
+    };
+    match record(&mut input) {
+        Ok(value) => Ok(Parsed::Complete(input.pos, value)),
+        Err(Stop::Incomplete(needed)) => Ok(Parsed::Incomplete(needed)),
+        Err(Stop::Error(e)) => Err(e),
+    }
+}
+
+// Decodes data held in memory without copying it.
+pub struct SliceDecoder<'a, D> {
+    decoder: D,
+    data: &'a [u8],
+    pos: usize,
+    done: bool,
+}
+
+pub fn decode_slice<D: Decoder>(decoder: D, data: &[u8]) -> SliceDecoder<'_, D> {
+    SliceDecoder {
+        decoder,
+        data,
+        pos: 0,
+        done: false,
+    }
+}
+
+impl<D: Decoder> Iterator for SliceDecoder<'_, D> {
+    type Item = Result<D::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let input = self.data.get(self.pos..).unwrap_or_default();
+            match self.decoder.decode(input, true) {
+                Ok(Decoded::Need(_)) => {
+                    self.done = true;
+                    return Some(Err(ends_early()));
+                }
+                Ok(Decoded::Consumed(used, item)) => {
+                    self.pos += used;
+                    if item.is_some() {
+                        return item.map(Ok);
+                    }
+                }
+                Ok(Decoded::Seek(offset)) => {
+                    self.pos = usize::try_from(offset).unwrap_or(usize::MAX);
+                }
+                Ok(Decoded::Done) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+// Decodes from a file or anything else that reads and seeks, reading only what the decoder
+// asks for.
+pub struct ReaderDecoder<D, R> {
+    decoder: D,
+    reader: R,
+    buffer: Vec<u8>,
+    start: usize,
+    eof: bool,
+    done: bool,
+}
+
+pub fn decode_reader<D: Decoder, R: Read + Seek>(decoder: D, reader: R) -> ReaderDecoder<D, R> {
+    ReaderDecoder {
+        decoder,
+        reader,
+        buffer: Vec::new(),
+        start: 0,
+        eof: false,
+        done: false,
+    }
+}
+
+impl<D: Decoder, R: Read + Seek> ReaderDecoder<D, R> {
+    fn fill(&mut self, needed: usize) -> Result<()> {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+        // Read at least once, a decoder asking for what it already has gets more input.
+        let needed = needed.max(self.buffer.len() + 1);
+        while self.buffer.len() < needed && !self.eof {
+            let len = self.buffer.len();
+            self.buffer
+                .resize(len + (needed - len).clamp(512, READ_CHUNK), 0);
+            let read = match self.reader.read(&mut self.buffer[len..]) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => 0,
+                Err(e) => {
+                    self.buffer.truncate(len);
+                    return Err(e);
+                }
+                Ok(read) => {
+                    self.eof = read == 0;
+                    read
+                }
+            };
+            self.buffer.truncate(len + read);
+        }
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Option<D::Item>> {
+        while !self.done {
+            match self.decoder.decode(&self.buffer[self.start..], self.eof)? {
+                Decoded::Need(_) if self.eof => return Err(ends_early()),
+                Decoded::Need(needed) => self.fill(needed)?,
+                Decoded::Consumed(used, item) => {
+                    self.start += used;
+                    if item.is_some() {
+                        return Ok(item);
+                    }
+                }
+                Decoded::Seek(offset) => {
+                    self.reader.seek(SeekFrom::Start(offset))?;
+                    self.buffer.clear();
+                    self.start = 0;
+                    self.eof = false;
+                }
+                Decoded::Done => self.done = true,
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<D: Decoder, R: Read + Seek> Iterator for ReaderDecoder<D, R> {
+    type Item = Result<D::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.step().transpose();
+        if matches!(item, Some(Err(_))) {
+            self.done = true;
+        }
+        item
+    }
+}
+
+// Decodes from an async source. read_at(offset, len) returns about len bytes starting at
+// offset, or nothing at the end of the input. A source that cannot seek, such as a socket,
+// may ignore offset as long as the decoder never asks to seek; the DMS and hunk decoders
+// read straight through.
+pub async fn decode_async<D, F, Fut>(mut decoder: D, mut read_at: F) -> Result<Vec<D::Item>>
+where
+    D: Decoder,
+    F: FnMut(u64, usize) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>>>,
+{
+    let mut items = Vec::new();
+    let mut buffer = Vec::new();
+    let mut offset = 0u64;
+    let mut eof = false;
+    loop {
+        match decoder.decode(&buffer, eof)? {
+            Decoded::Need(_) if eof => return Err(ends_early()),
+            Decoded::Need(needed) => {
+                let len = needed.saturating_sub(buffer.len()).clamp(1, READ_CHUNK);
+                let chunk = read_at(offset + buffer.len() as u64, len).await?;
+                eof = chunk.is_empty();
+                buffer.extend_from_slice(&chunk);
+            }
+            Decoded::Consumed(used, item) => {
+                buffer.drain(..used);
+                offset += used as u64;
+                items.extend(item);
+            }
+            Decoded::Seek(to) => {
+                buffer.clear();
+                offset = to;
+                eof = false;
+            }
+            Decoded::Done => return Ok(items),
+        }
+    }
+}
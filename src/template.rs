@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use crate::cli::parse_disk_size;
+use crate::config::Config;
+use adflib::disk::{DiskType, FormatOptions, ADF, ADF_SECTOR_SIZE};
+use serde::Deserialize;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+const WORKBENCH_DIRS: &[&str] = &[
+    "C",
+    "Devs",
+    "Devs/Keymaps",
+    "Devs/Printers",
+    "Fonts",
+    "L",
+    "Libs",
+    "Prefs",
+    "S",
+    "System",
+    "Utilities",
+];
+const WORKBENCH_STARTUP: &str = "; Startup-Sequence\n\
+    ; Copy the Workbench commands into C: and Libs: to boot this disk.\n\
+    LoadWB\n\
+    EndCLI >NIL:\n";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TemplateFile {
+    pub path: String,
+    pub content: Option<String>,
+    // Host file to copy, relative to the template file.
+    pub source: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Template {
+    #[serde(skip_deserializing)]
+    pub name: String,
+    pub description: String,
+    pub filesystem: String,
+    pub size: String,
+    pub volume: String,
+    pub intl: bool,
+    pub dircache: bool,
+    pub bootable: bool,
+    pub directories: Vec<String>,
+    pub files: Vec<TemplateFile>,
+    #[serde(skip)]
+    pub origin: Option<PathBuf>,
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Template {
+            name: String::new(),
+            description: String::new(),
+            filesystem: "OFS".to_string(),
+            size: "dd".to_string(),
+            volume: "Empty".to_string(),
+            intl: false,
+            dircache: false,
+            bootable: false,
+            directories: Vec::new(),
+            files: Vec::new(),
+            origin: None,
+        }
+    }
+}
+
+impl Template {
+    pub fn builtin() -> Vec<Template> {
+        vec![
+            Template {
+                name: "blank-ofs".to_string(),
+                description: "Empty OFS floppy".to_string(),
+                ..Template::default()
+            },
+            Template {
+                name: "blank-ffs-intl".to_string(),
+                description: "Empty international FFS floppy".to_string(),
+                filesystem: "FFS".to_string(),
+                intl: true,
+                ..Template::default()
+            },
+            Template {
+                name: "blank-hd".to_string(),
+                description: "Empty international FFS high density floppy".to_string(),
+                filesystem: "FFS".to_string(),
+                size: "hd".to_string(),
+                intl: true,
+                ..Template::default()
+            },
+            Template {
+                name: "workbench".to_string(),
+                description: "Bootable Workbench directory skeleton".to_string(),
+                volume: "Workbench".to_string(),
+                bootable: true,
+                directories: WORKBENCH_DIRS.iter().map(|d| d.to_string()).collect(),
+                files: vec![TemplateFile {
+                    path: "S/Startup-Sequence".to_string(),
+                    content: Some(WORKBENCH_STARTUP.to_string()),
+                    source: None,
+                }],
+                ..Template::default()
+            },
+        ]
+    }
+
+    pub fn user_dir() -> Option<PathBuf> {
+        Some(Config::path()?.parent()?.join("templates"))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Template> {
+        let text = std::fs::read_to_string(path)?;
+        let mut template: Template = toml::from_str(&text).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid template {}: {}", path.display(), e.message()),
+            )
+        })?;
+        template.name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        template.origin = Some(path.to_path_buf());
+        Ok(template)
+    }
+
+    // User templates in <config dir>/templates/*.toml replace built-ins of the same name.
+    pub fn all() -> Result<Vec<Template>> {
+        let mut templates = Template::builtin();
+        let Some(dir) = Template::user_dir().filter(|dir| dir.is_dir()) else {
+            return Ok(templates);
+        };
+        let mut paths = std::fs::read_dir(&dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.sort();
+        for path in paths {
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                let template = Template::from_file(&path)?;
+                templates.retain(|t| t.name != template.name);
+                templates.push(template);
+            }
+        }
+        Ok(templates)
+    }
+
+    pub fn find(name: &str) -> Result<Template> {
+        Template::all()?
+            .into_iter()
+            .find(|t| t.name == name)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("Template '{}' not found", name),
+                )
+            })
+    }
+
+    pub fn disk_type(&self) -> Result<DiskType> {
+        match self.filesystem.to_ascii_uppercase().as_str() {
+            "OFS" => Ok(DiskType::OFS),
+            "FFS" => Ok(DiskType::FFS),
+            other => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid filesystem '{}' in template {}", other, self.name),
+            )),
+        }
+    }
+
+    pub fn apply(&self, volume: Option<&str>) -> Result<ADF> {
+        let size = parse_disk_size(&self.size).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid size in template {}: {}", self.name, e),
+            )
+        })?;
+        let mut adf = ADF::new(size / ADF_SECTOR_SIZE, ADF_SECTOR_SIZE);
+        let options = FormatOptions {
+            intl: self.intl,
+            dircache: self.dircache,
+            bootable: self.bootable,
+            ..FormatOptions::default()
+        };
+        adf.format_with_options(self.disk_type()?, volume.unwrap_or(&self.volume), &options)?;
+        for dir in &self.directories {
+            adf.create_dir(dir)?;
+        }
+        for file in &self.files {
+            let contents = match (&file.content, &file.source) {
+                (Some(content), None) => content.clone().into_bytes(),
+                (None, Some(source)) => {
+                    let base = self.origin.as_deref().and_then(Path::parent);
+                    std::fs::read(base.map_or(source.clone(), |base| base.join(source)))?
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "File {} in template {} needs either content or source",
+                            file.path, self.name
+                        ),
+                    ))
+                }
+            };
+            adf.write_file(&file.path, &contents)?;
+        }
+        Ok(adf)
+    }
+}
@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Disk generators for fuzzing and property tests. RandomDisk builds a volume through the
+// public API, so it is structurally valid. HostileDisk then overwrites words of blocks in
+// use with random or boundary values and fixes their checksums, so the damage reaches the
+// parsers instead of being rejected by the checksum checks.
+
+use crate::disk::{
+    join_path, write_u32_be, DiskType, FormatOptions, ADF, ADF_NUM_SECTORS, ADF_SECTOR_SIZE,
+};
+use arbitrary::{Arbitrary, Error, Result, Unstructured};
+
+const MAX_ENTRIES: usize = 48;
+const MAX_FILE_SIZE: usize = 32 * 1024;
+const MAX_DAMAGED_WORDS: usize = 16;
+const NAME_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789._-+!";
+
+fn name(u: &mut Unstructured<'_>) -> Result<String> {
+    let len = u.int_in_range(1..=30)?;
+    (0..len)
+        .map(|_| u.choose(NAME_CHARS).map(|&c| c as char))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct RandomDisk(pub ADF);
+
+impl<'a> Arbitrary<'a> for RandomDisk {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let disk_type = if u.arbitrary()? {
+            DiskType::FFS
+        } else {
+            DiskType::OFS
+        };
+        let options = FormatOptions {
+            intl: u.arbitrary()?,
+            bootable: u.arbitrary()?,
+            ..FormatOptions::default()
+        };
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format_with_options(disk_type, &name(u)?, &options)
+            .map_err(|_| Error::IncorrectFormat)?;
+
+        // Entries that do not fit, or clash with an existing name, are left out.
+        let mut dirs = vec![String::new()];
+        for _ in 0..u.int_in_range(0..=MAX_ENTRIES)? {
+            let path = join_path(u.choose(&dirs)?, &name(u)?);
+            if u.ratio(1, 4)? {
+                if adf.create_dir(&path).is_ok() {
+                    dirs.push(path);
+                }
+                continue;
+            }
+            let len = u.int_in_range(0..=MAX_FILE_SIZE)?.min(u.len());
+            if adf.write_file(&path, u.bytes(len)?).is_err() {
+                continue;
+            }
+            if u.ratio(1, 4)? {
+                adf.set_comment(&path, &name(u)?).ok();
+            }
+            if u.ratio(1, 4)? {
+                adf.set_protection(&path, u.int_in_range(0..=0xff)?).ok();
+            }
+        }
+        Ok(RandomDisk(adf))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HostileDisk(pub ADF);
+
+impl<'a> Arbitrary<'a> for HostileDisk {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let RandomDisk(mut adf) = RandomDisk::arbitrary(u)?;
+        let num_blocks = adf.num_blocks() as u32;
+        let used: Vec<usize> = adf
+            .allocation_map()
+            .enumerate()
+            .skip(2)
+            .filter(|&(_, used)| used)
+            .map(|(block, _)| block)
+            .collect();
+        for _ in 0..u.int_in_range(1..=MAX_DAMAGED_WORDS)? {
+            let block = *u.choose(&used)?;
+            let offset = block * ADF_SECTOR_SIZE + u.choose_index(ADF_SECTOR_SIZE / 4)? * 4;
+            // Block numbers just inside and outside the volume find more bugs than noise.
+            let random = u.arbitrary()?;
+            let value = *u.choose(&[
+                0,
+                1,
+                block as u32,
+                num_blocks - 1,
+                num_blocks,
+                u32::MAX,
+                random,
+            ])?;
+            write_u32_be(adf.data_mut(offset..offset + 4), 0, value);
+            adf.fix_checksum(block).ok();
+        }
+        Ok(HostileDisk(adf))
+    }
+}
@@ -1,13 +1,42 @@
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::block::{
+        bitmap_checksum, bootblock_checksum, normal_checksum, BitmapBlock, BlockPtr, BootBlock,
+        ChecksumKind, DataBlock, FileExtBlock, HeaderBlock, LinkBlock, RootBlock, SecType,
+    };
+    use crate::bootblock::{scan, BootCode};
+    use crate::dat::{Dat, Fingerprint};
+    use crate::device::{
+        Device, Geometry, NativeDevice, ReadOnlyVolume, Volume, DEVICETYPE_FLOPHD,
+        DEVICETYPE_HARDDISK, DEVICETYPE_HARDFILE,
+    };
     use crate::disk::{
-        format_creation_date, load_adf_from_zip, DiskType, ADF, ADF_NUM_SECTORS, ADF_NUM_TRACKS,
-        ADF_SECTOR_SIZE, ADF_TRACK_SIZE, ROOT_BLOCK,
+        find_strings, format_creation_date, from_amiga_date, hash_name, hash_name_for, hex_dump,
+        load_adf_from_zip, parse_protection_flags, read_u32_be, DiskType, DiskUsage, FormatOptions,
+        ADF, ADF_HD_NUM_SECTORS, ADF_NUM_SECTORS, ADF_NUM_TRACKS, ADF_SECTOR_SIZE, ADF_TRACK_SIZE,
+        AMIGADOS_FILL, AMIGA_EPOCH_OFFSET, MAX_VOLUME_BLOCKS, ROOT_BLOCK,
+    };
+    use crate::dms::{
+        crc16, pack_dms, pack_rle, parse_header, salvage_dms, unpack_dms, unpack_rle, verify_dms,
+        DmsMode, TrackStatus, DMS_CYLINDERS, DMS_HEADER_SIZE, DMS_TRACK_HEADER_SIZE,
+        DMS_TRACK_SIZE,
+    };
+    use crate::hdf::Hdf;
+    use crate::icon::{DiskObject, IconType, ICON_MAGIC};
+    use crate::iff::{form_type, unpack_byterun1, Ilbm};
+    use crate::layout::Layout;
+    use crate::metadata::METADATA_VERSION;
+    use crate::mfm::{
+        decode_sectors, decode_track, encode_track, fill_gap, flux_to_mfm, mfm_to_flux,
     };
+    use crate::pattern::Pattern;
+    use crate::raw::{Image, RawError, TrackUnpacker};
+    use crate::rdb::{Partition, RigidDisk, RDB_END};
+    use crate::validate::Severity;
     use std::{
         io::Write,
-        time::{SystemTime, UNIX_EPOCH},
+        time::{Duration, SystemTime, UNIX_EPOCH},
     };
 
     use zip::write::{ExtendedFileOptions, FileOptions};
@@ -17,6 +46,7 @@ mod tests {
         let adf = ADF {
             data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
             bitmap: vec![false; ADF_NUM_SECTORS],
+            tracking: Default::default(),
         };
         assert_eq!(adf.data.len(), ADF_TRACK_SIZE * ADF_NUM_TRACKS);
     }
@@ -26,6 +56,7 @@ mod tests {
         let mut adf = ADF {
             data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
             bitmap: vec![false; ADF_NUM_SECTORS],
+            tracking: Default::default(),
         };
         adf.format(DiskType::OFS, "TestDisk").unwrap();
 
@@ -34,7 +65,7 @@ mod tests {
         assert_eq!(boot_block[3], 0); // OFS
 
         let root_block = adf.read_sector(ROOT_BLOCK);
-        assert_eq!(root_block[0], 2); // T_HEADER
+        assert_eq!(u32::from_be_bytes(root_block[0..4].try_into().unwrap()), 2); // T_HEADER
         assert_eq!(root_block[ADF_SECTOR_SIZE - 4], 0); // OFS
 
         let name_len = root_block[ADF_SECTOR_SIZE - 80] as usize;
@@ -49,6 +80,7 @@ mod tests {
         let mut adf = ADF {
             data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
             bitmap: vec![false; ADF_NUM_SECTORS],
+            tracking: Default::default(),
         };
         adf.format(DiskType::OFS, "TestDisk").unwrap();
 
@@ -61,20 +93,33 @@ mod tests {
         let mut adf = ADF {
             data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
             bitmap: vec![false; ADF_NUM_SECTORS],
+            tracking: Default::default(),
         };
+        let formatted = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        adf.set_clock(Some(std::sync::Arc::new(crate::clock::FixedClock(
+            formatted,
+        ))));
         adf.format(DiskType::FFS, "TestDisk").unwrap();
         let info = adf.information().unwrap();
         assert_eq!(info.disk_name, "TestDisk");
+        assert_eq!(info.filesystem, "FFS");
+        assert_eq!(info.creation_date, 1_000_000_000);
+        assert_eq!(info.bytes_per_sector, 512);
+        assert_eq!(info.hash_table_size, 72);
+        assert_eq!(
+            (info.first_reserved_block, info.last_reserved_block),
+            (0, 1)
+        );
         assert_eq!(info.disk_size, (ADF_TRACK_SIZE * ADF_NUM_TRACKS) as u32);
         assert!(format!("{:?}", info).contains(&format!(
             "Disk Size: {} bytes",
             ADF_TRACK_SIZE * ADF_NUM_TRACKS
         )));
         assert_eq!(info.heads, 2);
-        assert_eq!(info.tracks, (ADF_NUM_TRACKS / 2) as u8);
+        assert_eq!(info.tracks, (ADF_NUM_TRACKS / 2) as u32);
         assert_eq!(
             info.sectors_per_track,
-            (ADF_TRACK_SIZE / ADF_SECTOR_SIZE) as u8
+            (ADF_TRACK_SIZE / ADF_SECTOR_SIZE) as u32
         );
         assert!(format!("{:?}", info).contains(&format!(
             "Sectors per Track: {}",
@@ -88,6 +133,7 @@ mod tests {
         let mut adf = ADF {
             data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
             bitmap: vec![false; ADF_NUM_SECTORS],
+            tracking: Default::default(),
         };
 
         let test_data = [42u8; ADF_SECTOR_SIZE];
@@ -101,6 +147,7 @@ mod tests {
         let adf = ADF {
             data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
             bitmap: vec![false; ADF_NUM_SECTORS],
+            tracking: Default::default(),
         };
         let time = SystemTime::now();
         let result = format_creation_date(time);
@@ -111,6 +158,7 @@ mod tests {
         let adf = ADF {
             data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
             bitmap: vec![false; ADF_NUM_SECTORS],
+            tracking: Default::default(),
         };
         let flags = 0b10101010;
         let result = adf.format_protection_flags(flags);
@@ -133,4 +181,3734 @@ mod tests {
         let adf = load_adf_from_zip(&zip_buffer, "test.adf").unwrap();
         assert_eq!(adf.data.len(), ADF_TRACK_SIZE * ADF_NUM_TRACKS);
     }
+
+    #[test]
+    fn test_set_date() {
+        let mut adf = ADF {
+            data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
+            bitmap: vec![false; ADF_NUM_SECTORS],
+            tracking: Default::default(),
+        };
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+
+        let time = UNIX_EPOCH + Duration::from_secs(678_369_600); // 1991-07-01 12:00 UTC
+        adf.set_date("/", time).unwrap();
+
+        let root_block = adf.read_root_block();
+        let field =
+            |offset: usize| u32::from_be_bytes(root_block[offset..offset + 4].try_into().unwrap());
+        let days = field(ADF_SECTOR_SIZE - 92);
+        assert_eq!(days, 4929);
+        assert_eq!(
+            from_amiga_date(
+                days,
+                field(ADF_SECTOR_SIZE - 88),
+                field(ADF_SECTOR_SIZE - 84)
+            ),
+            time
+        );
+        assert!(adf.set_date("Missing", time).is_err());
+    }
+
+    #[test]
+    fn test_parse_protection_flags() {
+        assert_eq!(parse_protection_flags("0x0f", 0).unwrap(), 0x0f);
+        assert_eq!(parse_protection_flags("+s", 0).unwrap(), 0x40);
+        assert_eq!(parse_protection_flags("-w", 0).unwrap(), 0x04);
+        assert_eq!(parse_protection_flags("rwed", 0xff).unwrap(), 0);
+        assert_eq!(parse_protection_flags("----rwed", 0x40).unwrap(), 0);
+        assert_eq!(parse_protection_flags("sre", 0).unwrap(), 0x45);
+        assert!(parse_protection_flags("+x", 0).is_err());
+
+        let adf = ADF {
+            data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
+            bitmap: vec![false; ADF_NUM_SECTORS],
+            tracking: Default::default(),
+        };
+        assert_eq!(adf.format_protection_flags(0x45), "-s--r-e-");
+        assert_eq!(adf.format_protection_flags(0), "----rwed");
+    }
+
+    #[test]
+    fn test_validate_and_repair() {
+        let mut adf = ADF {
+            data: vec![0; ADF_TRACK_SIZE * ADF_NUM_TRACKS],
+            bitmap: vec![false; ADF_NUM_SECTORS],
+            tracking: Default::default(),
+        };
+        adf.format(DiskType::FFS, "TestDisk").unwrap();
+        assert!(!adf.validate().has_problems());
+
+        adf.data[ROOT_BLOCK * ADF_SECTOR_SIZE + 100] ^= 0xff;
+        adf.data[(ROOT_BLOCK + 1) * ADF_SECTOR_SIZE + 8] = 0;
+        let report = adf.validate();
+        assert!(report.has_problems());
+        assert_eq!(report.count(Severity::Error), 2);
+
+        let report = adf.repair().unwrap();
+        assert!(!report.has_problems());
+    }
+
+    #[test]
+    fn test_write_files() {
+        for disk_type in [DiskType::OFS, DiskType::FFS] {
+            let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+            adf.format(disk_type, "TestDisk").unwrap();
+            adf.install_bootblock().unwrap();
+
+            let contents: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+            adf.create_dir("Libs").unwrap();
+            adf.write_file("Libs/big.bin", &contents).unwrap();
+            adf.write_file("readme", b"hello").unwrap();
+            assert!(adf.write_file("readme", b"again").is_err());
+            assert!(!adf.validate().has_problems());
+
+            let files = adf.list_root_directory().unwrap();
+            assert_eq!(files.len(), 2);
+            assert!(files.iter().any(|f| f.name == "Libs" && f.is_dir));
+            assert!(files.iter().any(|f| f.name == "readme" && f.size == 5));
+            assert_eq!(adf.read_file("LIBS/Big.bin").unwrap(), contents);
+            assert_eq!(adf.read_file("readme").unwrap(), b"hello");
+            assert_eq!(adf.walk("").unwrap().len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_copy_between_disks() {
+        let mut source = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        source.format(DiskType::OFS, "Source").unwrap();
+        source.create_dir("Libs").unwrap();
+        source.write_file("Libs/a.library", b"library").unwrap();
+        source.set_comment("Libs/a.library", "note").unwrap();
+
+        let mut dest = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        dest.format(DiskType::FFS, "Dest").unwrap();
+        dest.copy_from(&source, "Libs", "Libs").unwrap();
+
+        assert!(dest.is_dir("Libs"));
+        assert_eq!(dest.read_file("Libs/a.library").unwrap(), b"library");
+        let entries = dest.walk("").unwrap();
+        assert!(entries.iter().any(|(_, info)| info.comment == "note"));
+        assert!(dest.copy_from(&source, "Libs", "Libs").is_err());
+        assert!(!dest.validate().has_problems());
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        adf.create_dir("Libs").unwrap();
+        adf.create_dir("Libs/Sub").unwrap();
+        adf.write_file("readme", b"hello").unwrap();
+        adf.write_file("other", b"x").unwrap();
+
+        adf.rename("readme", "ReadMe.txt").unwrap();
+        adf.rename("ReadMe.txt", "Libs").unwrap();
+        assert_eq!(adf.read_file("Libs/readme.txt").unwrap(), b"hello");
+        assert!(adf.read_file("readme").is_err());
+
+        assert!(adf.rename("other", "Libs/ReadMe.txt").is_err());
+        assert!(adf.rename("Libs", "Libs/Sub/Libs").is_err());
+        adf.rename("Libs/Sub", "Sub").unwrap();
+        assert!(adf.is_dir("Sub"));
+        assert!(!adf.validate().has_problems());
+    }
+
+    #[test]
+    fn test_amiga_patterns() {
+        let cases = [
+            ("#?.library", "diskfont.library", true),
+            ("#?.library", "diskfont.device", false),
+            ("*.INFO", "Disk.info", true),
+            ("c?t", "CAT", true),
+            ("c?t", "ct", false),
+            ("(foo|bar)%.txt", "bar.txt", true),
+            ("#(ab)c", "ababc", true),
+            ("#(ab)c", "abac", false),
+            ("~(#?.info)", "Startup-Sequence", true),
+            ("~(#?.info)", "Disk.info", false),
+            ("[a-c]#?", "Bin", true),
+            ("[~a-c]#?", "Bin", false),
+            ("'?x", "?x", true),
+        ];
+        for (pattern, name, expected) in cases {
+            let compiled = Pattern::new(pattern).unwrap();
+            assert_eq!(compiled.is_match(name), expected, "{} vs {}", pattern, name);
+        }
+        assert!(Pattern::new("(a|b").is_err());
+        assert!(Pattern::is_wild("#?"));
+        assert!(!Pattern::is_wild("Libs"));
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        adf.create_dir("Libs").unwrap();
+        adf.write_file("Libs/a", &[0; 1000]).unwrap();
+        adf.write_file("b", b"hello").unwrap();
+
+        let usage = adf.disk_usage("").unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].files, 2);
+        assert_eq!(usage[0].bytes, 1005);
+        assert_eq!(usage[1].path, "Libs");
+        assert_eq!(usage[1].blocks, 1 + 1 + 3);
+        assert_eq!(usage[0].blocks, 1 + usage[1].blocks + 2);
+        assert!(adf.disk_usage("b").is_err());
+    }
+
+    #[test]
+    fn test_search_and_block_owners() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "TestDisk").unwrap();
+        adf.create_dir("Libs").unwrap();
+        adf.write_file("Libs/a", b"find the Kickstart here")
+            .unwrap();
+
+        let matches = adf.search(b"Kickstart");
+        assert_eq!(matches.len(), 1);
+        let owners = adf.block_owners().unwrap();
+        assert_eq!(owners[&(matches[0] / ADF_SECTOR_SIZE)], "Libs/a");
+        assert_eq!(owners[&ROOT_BLOCK], "<root>");
+        assert_eq!(adf.search(b"DOS\x01"), vec![0]);
+    }
+
+    #[test]
+    fn test_find_strings() {
+        let data = b"\x00\x01Kickstart\xffab\x00workbench 1.3";
+        let strings = find_strings(data, 4);
+        assert_eq!(
+            strings,
+            vec![
+                (2, "Kickstart".to_string()),
+                (15, "workbench 1.3".to_string())
+            ]
+        );
+        assert_eq!(find_strings(data, 10).len(), 1);
+    }
+
+    #[test]
+    fn test_block_range_and_hex_dump() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        assert_eq!(adf.block_range("boot").unwrap(), (0, 1024));
+        assert_eq!(
+            adf.block_range("ROOT").unwrap(),
+            (ROOT_BLOCK * ADF_SECTOR_SIZE, ADF_SECTOR_SIZE)
+        );
+        assert_eq!(adf.block_range("bitmap").unwrap().0, 881 * ADF_SECTOR_SIZE);
+        assert_eq!(adf.block_range("0x10").unwrap().0, 16 * ADF_SECTOR_SIZE);
+        assert!(adf.block_range("1760").is_err());
+        assert!(adf.block_range("nope").is_err());
+        assert!(adf.is_reserved_block(1));
+        assert!(adf.is_reserved_block(881));
+        assert!(!adf.is_reserved_block(882));
+
+        let dump = hex_dump(b"DOS\x00abcdefghijklmnopq", 0x200);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("00000200  44 4f 53 00 61"));
+        assert!(lines[0].ends_with("|DOS.abcdefghijkl|"));
+        assert!(lines[1].starts_with("00000210  6d 6e 6f 70 71"));
+    }
+
+    #[test]
+    fn test_patch_sector() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        adf.install_bootblock().unwrap();
+
+        let name_offset = ADF_SECTOR_SIZE - 80;
+        assert!(adf
+            .patch_sector(ROOT_BLOCK, name_offset + 1, b"Best", Some(b"Xest"))
+            .is_err());
+        let old = adf
+            .patch_sector(ROOT_BLOCK, name_offset + 1, b"Best", Some(b"Test"))
+            .unwrap();
+        assert_eq!(old, b"Test");
+        assert!(adf.validate().has_problems());
+        adf.fix_checksum(ROOT_BLOCK).unwrap();
+        assert_eq!(adf.information().unwrap().disk_name, "BestDisk");
+
+        adf.patch_sector(0, 12, &[0x4e, 0x71], None).unwrap();
+        adf.fix_checksum(0).unwrap();
+        assert!(!adf.validate().has_problems());
+        assert!(adf.patch_sector(0, 510, b"abc", None).is_err());
+    }
+
+    #[test]
+    fn test_serialize_output_structs() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        adf.write_file("readme", b"hello").unwrap();
+        adf.set_date("readme", from_amiga_date(0, 0, 0)).unwrap();
+
+        let files = adf.walk("").unwrap();
+        let json = serde_json::to_value(&files[0].1).unwrap();
+        assert_eq!(json["name"], "readme");
+        assert_eq!(json["size"], 5);
+        assert_eq!(json["creation_date"], AMIGA_EPOCH_OFFSET);
+
+        let bitmap = serde_json::to_value(adf.get_bitmap_info()).unwrap();
+        let map = bitmap["block_allocation_map"].as_str().unwrap();
+        assert_eq!(map.len(), ADF_NUM_SECTORS);
+        assert_eq!(&map[880..882], "##");
+        assert_eq!(
+            map.matches('#').count() as u64,
+            bitmap["used_blocks"].as_u64().unwrap()
+        );
+
+        let report = serde_json::to_value(adf.validate()).unwrap();
+        assert!(report["findings"].is_array());
+    }
+
+    #[test]
+    fn test_boot_block_analysis() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "TestDisk").unwrap();
+        let info = adf.boot_block_info();
+        assert_eq!(info.dos_type, "DOS\\1");
+        assert!(info.ffs);
+        assert_eq!(info.code, BootCode::None);
+
+        adf.install_bootblock().unwrap();
+        let info = adf.boot_block_info();
+        assert_eq!(info.code, BootCode::Standard);
+        assert!(info.checksum_valid());
+        assert!(info.warnings.is_empty());
+
+        // move.l a0,$2e(a6) installs a CoolCapture vector
+        adf.patch_sector(0, 100, &[0x2d, 0x48, 0x00, 0x2e], None)
+            .unwrap();
+        let info = adf.boot_block_info();
+        assert_eq!(info.code, BootCode::Custom);
+        assert!(!info.checksum_valid());
+        assert_eq!(info.warnings.len(), 1);
+        assert!(info.warnings[0].contains("CoolCapture"));
+        assert_eq!(scan(&[0; 1024]).len(), 0);
+    }
+
+    #[test]
+    fn test_progress_callbacks() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+
+        let host = std::env::temp_dir().join(format!("adflib-progress-{}", std::process::id()));
+        std::fs::create_dir_all(host.join("s")).unwrap();
+        std::fs::write(host.join("readme"), b"hello").unwrap();
+        std::fs::write(host.join("s").join("startup-sequence"), b"echo hi").unwrap();
+
+        let mut calls = Vec::new();
+        adf.import_dir_with_progress(&host, "", &mut |done, total| calls.push((done, total)))
+            .unwrap();
+        assert_eq!(calls.last(), Some(&(3, 3)));
+
+        let mut last = (0, 0);
+        let report = adf.validate_with_progress(&mut |done, total| last = (done, total));
+        assert!(!report.has_problems());
+        assert!(last.1 > 0);
+        assert_eq!(last.0, last.1);
+
+        let out = host.join("out");
+        let mut count = 0;
+        adf.export_dir_with_progress("", &out, &mut |_, _| count += 1)
+            .unwrap();
+        assert_eq!(count, 3);
+        std::fs::remove_dir_all(&host).unwrap();
+    }
+
+    #[test]
+    fn test_dms_pack() {
+        assert_eq!(crc16(b"123456789"), 0xbb3d);
+        assert_eq!(
+            pack_rle(&[1, 0x90, 2, 2, 2, 2, 2, 0x90, 0x90]),
+            vec![1, 0x90, 0, 0x90, 5, 2, 0x90, 2, 0x90]
+        );
+        assert_eq!(pack_rle(&[7; 300]), vec![0x90, 0xff, 7, 0x01, 0x2c]);
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "TestDisk").unwrap();
+        let date = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let dms = pack_dms(&adf.data, DmsMode::Rle, date).unwrap();
+        assert_eq!(&dms[0..4], b"DMS!");
+        assert_eq!(read_u32_be(&dms, 12), 1_000_000_000);
+        assert_eq!(u16::from_be_bytes([dms[50], dms[51]]), 2);
+        assert_eq!(u16::from_be_bytes([dms[54], dms[55]]), crc16(&dms[4..54]));
+        assert_eq!(&dms[DMS_HEADER_SIZE..DMS_HEADER_SIZE + 2], b"TR");
+        assert!(dms.len() < adf.data.len() / 10);
+
+        let raw = pack_dms(&adf.data, DmsMode::None, date).unwrap();
+        assert_eq!(
+            raw.len(),
+            DMS_HEADER_SIZE + DMS_CYLINDERS * (DMS_TRACK_HEADER_SIZE + DMS_TRACK_SIZE)
+        );
+        assert!(pack_dms(&[0; 1024], DmsMode::None, date).is_err());
+    }
+
+    #[test]
+    fn test_dms_verify() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        adf.write_file("s", &[0x90; 1000]).unwrap();
+        let dms = adf.to_dms(DmsMode::Rle).unwrap();
+
+        let header = parse_header(&dms).unwrap();
+        assert_eq!((header.low_track, header.high_track), (0, 79));
+        assert_eq!(header.unpacked_size as usize, adf.data.len());
+        let reports = verify_dms(&dms).unwrap();
+        assert_eq!(reports.len(), DMS_CYLINDERS);
+        assert!(reports.iter().all(|r| r.status == TrackStatus::Ok));
+
+        let mut corrupt = dms.clone();
+        corrupt[DMS_HEADER_SIZE + DMS_TRACK_HEADER_SIZE] ^= 0xff;
+        assert_eq!(
+            verify_dms(&corrupt).unwrap()[0].status,
+            TrackStatus::Corrupt
+        );
+
+        let mut unsupported = dms.clone();
+        let track = DMS_HEADER_SIZE;
+        unsupported[track + 13] = 7;
+        let crc = crc16(&unsupported[track..track + 18]).to_be_bytes();
+        unsupported[track + 18..track + 20].copy_from_slice(&crc);
+        assert_eq!(
+            verify_dms(&unsupported).unwrap()[0].status,
+            TrackStatus::Unsupported
+        );
+
+        assert_eq!(
+            unpack_rle(&pack_rle(&adf.data), adf.data.len()).unwrap(),
+            adf.data
+        );
+        assert!(parse_header(&adf.data).is_err());
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        let free = adf.get_bitmap_info().free_blocks;
+        adf.create_dir("s").unwrap();
+        adf.write_file("s/big", &vec![0x55; 40_000]).unwrap();
+        assert!(adf.delete("s").is_err());
+        assert!(adf.delete("").is_err());
+        adf.delete("s/big").unwrap();
+        assert!(adf.find_entry("s/big").is_err());
+        adf.delete("s").unwrap();
+        assert!(adf.find_entry("s").is_err());
+        assert_eq!(adf.get_bitmap_info().free_blocks, free);
+        assert!(!adf.validate().has_problems());
+    }
+
+    #[test]
+    fn test_delete_file() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        let free = adf.get_bitmap_info().free_blocks;
+        adf.create_dir("s").unwrap();
+        adf.write_file("s/big", &vec![0x55; 40_000]).unwrap();
+        assert!(adf.delete_file("s").is_err());
+        assert!(adf.find_entry("s").is_ok());
+        adf.delete_file("s/big").unwrap();
+        assert!(adf.find_entry("s/big").is_err());
+        assert!(adf.delete_file("s/big").is_err());
+        adf.delete("s").unwrap();
+        assert_eq!(adf.get_bitmap_info().free_blocks, free);
+        assert!(!adf.validate().has_problems());
+    }
+
+    #[test]
+    fn test_sync() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+
+        let host = std::env::temp_dir().join(format!("adflib-sync-{}", std::process::id()));
+        let src = host.join("src");
+        std::fs::create_dir_all(src.join("sub")).unwrap();
+        std::fs::write(src.join("a.txt"), b"one").unwrap();
+        std::fs::write(src.join("sub").join("b.txt"), b"two").unwrap();
+
+        let report = adf.sync_from_host(&src, "Dev/").unwrap();
+        assert_eq!(report.copied, vec!["Dev/a.txt", "Dev/sub/b.txt"]);
+        assert_eq!(adf.sync_from_host(&src, "Dev").unwrap().unchanged, 2);
+
+        adf.set_protection("Dev/a.txt", 0x01).unwrap();
+        std::fs::write(src.join("a.txt"), b"changed").unwrap();
+        let report = adf.sync_from_host(&src, "Dev").unwrap();
+        assert_eq!(report.copied, vec!["Dev/a.txt"]);
+        assert_eq!(adf.read_file("Dev/a.txt").unwrap(), b"changed");
+        assert_eq!(adf.get_protection("Dev/a.txt").unwrap(), 0x01);
+        assert!(!adf.validate().has_problems());
+
+        let out = host.join("out");
+        assert_eq!(adf.sync_to_host("Dev", &out).unwrap().copied.len(), 2);
+        assert_eq!(
+            std::fs::read(out.join("sub").join("b.txt")).unwrap(),
+            b"two"
+        );
+        let report = adf.sync_to_host("Dev", &out).unwrap();
+        assert!(report.copied.is_empty());
+        assert_eq!(report.unchanged, 2);
+        std::fs::remove_dir_all(&host).unwrap();
+    }
+
+    #[test]
+    fn test_dat_identify() {
+        let fingerprint = Fingerprint::of(b"123456789");
+        assert_eq!(fingerprint.crc32, "cbf43926");
+        assert_eq!(fingerprint.md5, "25f9e794323b453885f5181f1b624d0b");
+        assert_eq!(fingerprint.sha1, "f7c3bc1d808e04732adf679965ccc34ca7ae3441");
+
+        let xml = r#"<?xml version="1.0"?>
+            <datafile>
+              <header><name>Test</name></header>
+              <game name="Demo &amp; Disk (1990)">
+                <rom name="demo.adf" size="9" crc="CBF43926"/>
+              </game>
+              <game name="Other"><rom name="other.adf" size="9" crc="00000000"/></game>
+            </datafile>"#;
+        let dat = Dat::parse(xml).unwrap();
+        assert_eq!(dat.entries.len(), 2);
+        let entry = dat.identify(&fingerprint).unwrap();
+        assert_eq!(entry.title, "Demo & Disk (1990)");
+        assert_eq!(entry.rom, "demo.adf");
+
+        let text = r#"clrmamepro ( name "Test" )
+            game (
+                name "Demo Disk"
+                rom ( name demo.adf size 9 sha1 F7C3BC1D808E04732ADF679965CCC34CA7AE3441 )
+            )"#;
+        let dat = Dat::parse(text).unwrap();
+        assert_eq!(dat.identify(&fingerprint).unwrap().title, "Demo Disk");
+        assert!(dat.identify(&Fingerprint::of(b"other")).is_none());
+        assert!(Dat::parse("game ( name \"x\"").is_err());
+    }
+
+    #[test]
+    fn test_format_options() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        let options = FormatOptions {
+            intl: true,
+            bootable: true,
+            reserved: 10,
+            ..FormatOptions::default()
+        };
+        adf.format_with_options(DiskType::FFS, "Boot", &options)
+            .unwrap();
+        assert_eq!(&adf.data[0..4], b"DOS\x03");
+        assert!(adf.is_intl());
+        assert_eq!(adf.boot_block_info().code, BootCode::Standard);
+        assert!(adf.boot_block_info().checksum_valid());
+        assert!((2..12).all(|b| adf.get_block_status(b) == Some(false)));
+        assert_eq!(adf.reserved_blocks().unwrap(), (2..12).collect::<Vec<_>>());
+        assert!(!adf.validate().has_problems());
+        adf.write_file("Data", &[1; 5000]).unwrap();
+        adf.repair().unwrap();
+        adf.defragment().unwrap();
+        assert!((2..12).all(|b| adf.get_block_status(b) == Some(false)));
+        assert!(!adf.validate().has_problems());
+        assert_eq!(adf.read_file("Data").unwrap(), [1; 5000]);
+
+        let dircache = FormatOptions {
+            intl: true,
+            dircache: true,
+            ..FormatOptions::default()
+        };
+        let error = adf
+            .format_with_options(DiskType::OFS, "Cache", &dircache)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+        assert_eq!(&adf.data[0..4], b"DOS\x03");
+
+        adf.data[100 * ADF_SECTOR_SIZE] = 0xaa;
+        let quick = FormatOptions {
+            quick: true,
+            ..FormatOptions::default()
+        };
+        adf.format_with_options(DiskType::OFS, "Quick", &quick)
+            .unwrap();
+        assert_eq!(adf.data[100 * ADF_SECTOR_SIZE], 0xaa);
+        assert_eq!(adf.data[3], 0);
+        adf.format(DiskType::OFS, "Full").unwrap();
+        assert_eq!(adf.data[100 * ADF_SECTOR_SIZE], 0);
+
+        let dos_fill = FormatOptions {
+            fill: AMIGADOS_FILL.to_vec(),
+            ..FormatOptions::default()
+        };
+        adf.format_with_options(DiskType::FFS, "Filled", &dos_fill)
+            .unwrap();
+        assert_eq!(&adf.data[100 * ADF_SECTOR_SIZE..][..8], b"DOS\0DOS\0");
+        assert_eq!(&adf.data[..4], b"DOS\x01");
+        assert!(!adf.validate().has_problems());
+
+        let too_many = FormatOptions {
+            reserved: ROOT_BLOCK,
+            ..FormatOptions::default()
+        };
+        assert!(adf
+            .format_with_options(DiskType::OFS, "X", &too_many)
+            .is_err());
+    }
+
+    #[test]
+    fn test_disk_geometry() {
+        let mut hd = ADF::new(ADF_HD_NUM_SECTORS, ADF_SECTOR_SIZE);
+        hd.format(DiskType::FFS, "HD").unwrap();
+        assert_eq!(hd.root_block(), 1760);
+        assert_eq!(hd.information().unwrap().disk_name, "HD");
+        let hd = ADF::from_bytes(&hd.data).unwrap();
+        assert_eq!(hd.num_blocks(), ADF_HD_NUM_SECTORS);
+        assert!(!hd.validate().has_problems());
+
+        let mut hdf = ADF::new(20480, ADF_SECTOR_SIZE);
+        hdf.format(DiskType::FFS, "Work").unwrap();
+        assert_eq!(hdf.bitmap_pages().len(), 6);
+        let data = vec![0x5a; 3 * 1024 * 1024];
+        hdf.write_file("Big", &data).unwrap();
+        assert_eq!(hdf.read_file("Big").unwrap(), data);
+        assert!(!hdf.validate().has_problems());
+
+        assert!(ADF::from_bytes(&vec![0; 20480 * ADF_SECTOR_SIZE]).is_err());
+        assert!(ADF::new(MAX_VOLUME_BLOCKS + 1, ADF_SECTOR_SIZE)
+            .format(DiskType::FFS, "X")
+            .is_err());
+    }
+
+    #[test]
+    fn test_block_decode() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Decode").unwrap();
+        adf.write_file("Notes", b"hello").unwrap();
+
+        let root = RootBlock::from_bytes(adf.read_sector(ROOT_BLOCK), &Layout::FLOPPY).unwrap();
+        assert_eq!(root.name, "Decode");
+        assert!(root.checksum_valid());
+        assert_eq!(root.bm_pages[0], BlockPtr::new(ROOT_BLOCK + 1));
+        let header_block = root.hash_table.iter().find_map(|b| b.index()).unwrap();
+
+        let header =
+            HeaderBlock::from_bytes(adf.read_sector(header_block), &Layout::FLOPPY).unwrap();
+        assert_eq!(header.name, "Notes");
+        assert_eq!(header.byte_size, 5);
+        assert_eq!(header.parent, BlockPtr::new(ROOT_BLOCK));
+        assert!(!header.is_dir());
+        assert!(header.checksum_valid());
+
+        adf.data[header_block * ADF_SECTOR_SIZE + 100] ^= 1;
+        let header =
+            HeaderBlock::from_bytes(adf.read_sector(header_block), &Layout::FLOPPY).unwrap();
+        assert!(!header.checksum_valid());
+        assert!(HeaderBlock::from_bytes(&[0; 100], &Layout::FLOPPY).is_err());
+    }
+
+    #[test]
+    fn test_block_roundtrip() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        let options = FormatOptions {
+            bootable: true,
+            ..FormatOptions::default()
+        };
+        adf.format_with_options(DiskType::OFS, "Roundtrip", &options)
+            .unwrap();
+        adf.create_dir("Dir").unwrap();
+        adf.write_file("Dir/File", &[0x5a; 600]).unwrap();
+
+        let boot = BootBlock::from_bytes(&adf.data[..2 * ADF_SECTOR_SIZE]).unwrap();
+        assert!(boot.is_dos() && boot.checksum_valid());
+        assert_eq!(boot.to_bytes(), &adf.data[..2 * ADF_SECTOR_SIZE]);
+
+        let root = adf.read_sector(ROOT_BLOCK);
+        assert_eq!(
+            RootBlock::from_bytes(root, &Layout::FLOPPY)
+                .unwrap()
+                .to_bytes(&Layout::FLOPPY),
+            root
+        );
+        let bitmap = adf.read_sector(ROOT_BLOCK + 1);
+        let page = BitmapBlock::from_bytes(bitmap, &Layout::FLOPPY).unwrap();
+        assert!(page.checksum_valid() && !page.is_free(ROOT_BLOCK - 2));
+        assert_eq!(page.to_bytes(&Layout::FLOPPY), bitmap);
+
+        let header_block = adf.find_entry("Dir/File").unwrap();
+        for block in [adf.find_entry("Dir").unwrap(), header_block] {
+            let sector = adf.read_sector(block);
+            assert_eq!(
+                HeaderBlock::from_bytes(sector, &Layout::FLOPPY)
+                    .unwrap()
+                    .to_bytes(&Layout::FLOPPY),
+                sector
+            );
+        }
+        let (data_blocks, _) = adf.file_blocks(header_block).unwrap();
+        let data = DataBlock::from_bytes(adf.read_sector(data_blocks[1]), &Layout::FLOPPY).unwrap();
+        assert_eq!((data.seq_num, data.payload().len()), (2, 112));
+        assert!(data.checksum_valid());
+        assert_eq!(
+            data.to_bytes(&Layout::FLOPPY),
+            adf.read_sector(data_blocks[1])
+        );
+
+        let link = LinkBlock {
+            header_key: BlockPtr::new(1000),
+            target: "Dir/File".to_string(),
+            name: "Link".to_string(),
+            sec_type: SecType::SoftLink,
+            ..LinkBlock::default()
+        };
+        let parsed =
+            LinkBlock::from_bytes(&link.to_bytes(&Layout::FLOPPY), &Layout::FLOPPY).unwrap();
+        assert!(parsed.is_soft());
+        assert_eq!(
+            (parsed.target.as_str(), parsed.name.as_str()),
+            ("Dir/File", "Link")
+        );
+        assert!(BootBlock::from_bytes(&[0; ADF_SECTOR_SIZE]).is_err());
+    }
+
+    #[test]
+    fn test_typed_fields() {
+        assert!(BlockPtr::from_raw(0).is_null());
+        assert_eq!(BlockPtr::new(881).index(), Some(881));
+        assert_eq!(BlockPtr::NULL.raw(), 0);
+        assert_eq!(BlockPtr::new(42).to_string(), "42");
+
+        assert_eq!(SecType::from_raw(-3i32 as u32), SecType::File);
+        assert_eq!(SecType::from_raw(2).raw(), 2);
+        assert_eq!(SecType::from_raw(7), SecType::Unknown(7));
+        assert_eq!(SecType::LinkFile.to_string(), "-4 (ST_LINKFILE)");
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "Typed").unwrap();
+        adf.create_dir("Dir").unwrap();
+        let dir = HeaderBlock::from_bytes(
+            adf.read_sector(adf.find_entry("Dir").unwrap()),
+            &Layout::FLOPPY,
+        )
+        .unwrap();
+        assert_eq!(dir.sec_type, SecType::UserDir);
+        assert!(dir.hash_chain.is_null() && dir.extension.is_null());
+        assert_eq!(
+            serde_json::to_value(&dir).unwrap()["parent"],
+            serde_json::json!(ROOT_BLOCK)
+        );
+    }
+
+    #[test]
+    fn test_extract_matching() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        adf.create_dir("Libs").unwrap();
+        adf.create_dir("Libs/sub").unwrap();
+        adf.write_file("Libs/a.library", b"a").unwrap();
+        adf.write_file("Libs/sub/b.library", b"b").unwrap();
+        adf.write_file("ReadMe", b"readme").unwrap();
+
+        let host = std::env::temp_dir().join(format!("adflib-extract-{}", std::process::id()));
+        let pattern = Pattern::new("Libs/#?").unwrap();
+        let mut extracted = adf.extract_matching(&pattern, &host).unwrap();
+        extracted.sort();
+        assert_eq!(extracted, vec!["Libs/a.library", "Libs/sub"]);
+        assert_eq!(std::fs::read(host.join("Libs/a.library")).unwrap(), b"a");
+        assert_eq!(
+            std::fs::read(host.join("Libs/sub/b.library")).unwrap(),
+            b"b"
+        );
+        assert!(!host.join("ReadMe").exists());
+
+        let none = Pattern::new("#?.info").unwrap();
+        assert!(adf.extract_matching(&none, &host).unwrap().is_empty());
+        std::fs::remove_dir_all(&host).unwrap();
+    }
+
+    #[test]
+    fn test_bitmap_render() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        adf.write_file("a.txt", &[1; 2000]).unwrap();
+
+        let image = adf.render_bitmap(false);
+        assert_eq!((image.width, image.height), (80 * 8 + 1, 22 * 8 + 1));
+        assert_eq!(image.pixels.len(), image.width * image.height * 3);
+        let pixel = |block: usize| {
+            let (x, y) = ((block / 22) * 8 + 1, (block % 22) * 8 + 1);
+            image.pixels[(y * image.width + x) * 3..][..3].to_vec()
+        };
+        assert_ne!(pixel(ROOT_BLOCK), pixel(ROOT_BLOCK + 10));
+        assert_ne!(pixel(2), pixel(ROOT_BLOCK + 10));
+
+        let by_file = adf.render_bitmap(true);
+        assert_eq!(by_file.pixels.len(), image.pixels.len());
+        assert!(image.to_png().unwrap().starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn test_optimize() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        adf.create_dir("Dir").unwrap();
+        adf.write_file("a", &[1; 3000]).unwrap();
+        adf.write_file("b", &[2; 3000]).unwrap();
+        adf.write_file("Dir/c", &[3; 3000]).unwrap();
+        adf.delete("b").unwrap();
+        adf.write_file("d", &[4; 6000]).unwrap();
+        adf.set_comment("d", "moved").unwrap();
+        let freed_start = ROOT_BLOCK - 1;
+        adf.data[freed_start * ADF_SECTOR_SIZE] = 0xff;
+
+        let before = adf.fragmentation().unwrap();
+        assert_eq!(before.files, 3);
+        assert_eq!(before.fragmented_files, 1);
+
+        adf.defragment().unwrap();
+        adf.sort_directories().unwrap();
+        let after = adf.fragmentation().unwrap();
+        assert_eq!(after.fragmented_files, 0);
+        assert_eq!(after.extents, 3);
+        assert_eq!(adf.read_file("d").unwrap(), vec![4; 6000]);
+        assert_eq!(adf.read_file("Dir/c").unwrap(), vec![3; 3000]);
+        assert_eq!(adf.information().unwrap().disk_name, "TestDisk");
+        assert_eq!(
+            adf.list_root_directory()
+                .unwrap()
+                .into_iter()
+                .find(|info| info.name == "d")
+                .unwrap()
+                .comment,
+            "moved"
+        );
+        assert!(!adf.validate().has_problems());
+
+        assert_eq!(adf.scrub_free() as u32, adf.get_bitmap_info().free_blocks);
+        assert_eq!(adf.data[freed_start * ADF_SECTOR_SIZE], 0);
+        assert!(!adf.validate().has_problems());
+
+        let contents = adf.read_file("d").unwrap();
+        assert_eq!(
+            adf.scrub_free_blocks(&[0xde, 0xad, 0xbe]) as u32,
+            adf.get_bitmap_info().free_blocks
+        );
+        let free = adf.find_free_block().unwrap() * ADF_SECTOR_SIZE;
+        assert_eq!(&adf.data[free..free + 4], [0xde, 0xad, 0xbe, 0xde]);
+        assert_eq!(adf.data[free + ADF_SECTOR_SIZE - 1], 0xad);
+        assert_eq!(adf.read_file("d").unwrap(), contents);
+        assert!(!adf.validate().has_problems());
+    }
+
+    #[test]
+    fn test_device_mount() {
+        let path = std::env::temp_dir().join(format!("adflib-device-{}.adf", std::process::id()));
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Device").unwrap();
+        adf.write_to_file(path.to_str().unwrap()).unwrap();
+
+        let device = Device::open(&path).unwrap();
+        assert_eq!(device.volumes().len(), 1);
+        assert_eq!(device.volumes()[0].blocks, ADF_NUM_SECTORS);
+        assert!(device.mount(1).is_err());
+
+        let mut volume = device.mount(0).unwrap();
+        volume.create_dir("S").unwrap();
+        volume.write_file("S/Startup-Sequence", b"LoadWB").unwrap();
+        volume.flush().unwrap();
+
+        let volume = Device::open(&path).unwrap().mount(0).unwrap();
+        assert_eq!(volume.list("S").unwrap()[0].name, "Startup-Sequence");
+        assert_eq!(volume.read_file("S/Startup-Sequence").unwrap(), b"LoadWB");
+        assert!(volume.list("S/Startup-Sequence").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_device_partitions() {
+        let path = std::env::temp_dir().join(format!("adflib-rdb-{}.hdf", std::process::id()));
+        let mut image = vec![0u8; 81 * 32 * ADF_SECTOR_SIZE];
+        let rigid_disk = RigidDisk {
+            block_bytes: ADF_SECTOR_SIZE as u32,
+            partition_list: 1,
+            cylinders: 81,
+            sectors: 32,
+            heads: 1,
+        };
+        image[..ADF_SECTOR_SIZE].copy_from_slice(&rigid_disk.to_bytes());
+        for (i, (name, label)) in [("DH0", "System"), ("DH1", "Work")].iter().enumerate() {
+            let partition = Partition {
+                next: if i == 0 { 2 } else { RDB_END },
+                drive_name: name.to_string(),
+                size_block: 128,
+                surfaces: 1,
+                blocks_per_track: 32,
+                reserved: 2,
+                low_cyl: 1 + 40 * i as u32,
+                high_cyl: 40 + 40 * i as u32,
+                dos_type: 0x444f5301,
+            };
+            let start = (i + 1) * ADF_SECTOR_SIZE;
+            image[start..start + ADF_SECTOR_SIZE].copy_from_slice(&partition.to_bytes());
+            assert_eq!(
+                Partition::from_bytes(&partition.to_bytes()).unwrap(),
+                partition
+            );
+
+            let mut volume = ADF::new(partition.blocks(), ADF_SECTOR_SIZE);
+            volume.format(DiskType::FFS, label).unwrap();
+            let offset = partition.first_block() * ADF_SECTOR_SIZE;
+            image[offset..offset + volume.data.len()].copy_from_slice(&volume.data);
+        }
+        std::fs::write(&path, &image).unwrap();
+
+        let device = Device::open(&path).unwrap();
+        let volumes = device.volumes();
+        assert_eq!(volumes.len(), 2);
+        assert_eq!(volumes[0].device_name.as_deref(), Some("DH0"));
+        assert_eq!((volumes[0].first_block, volumes[0].blocks), (32, 1280));
+        assert_eq!(
+            (volumes[1].label.as_str(), volumes[1].dos_type),
+            ("Work", 0x444f5301)
+        );
+        assert_eq!(volumes[1].size(), 1280 * ADF_SECTOR_SIZE as u64);
+
+        let mut system = device.mount(0).unwrap();
+        let mut work = device.mount(1).unwrap();
+        system.write_file("Startup", b"system").unwrap();
+        work.write_file("Project", b"work").unwrap();
+        work.flush().unwrap();
+        system.flush().unwrap();
+
+        let device = Device::open(&path).unwrap();
+        let (system, work) = (device.mount(0).unwrap(), device.mount(1).unwrap());
+        assert_eq!(system.read_file("Startup").unwrap(), b"system");
+        assert_eq!(work.read_file("Project").unwrap(), b"work");
+        assert!(system.read_file("Project").is_err());
+        assert!(!system.adf().validate().has_problems());
+        assert_eq!(
+            &std::fs::read(&path).unwrap()[..ADF_SECTOR_SIZE],
+            &image[..ADF_SECTOR_SIZE]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_volume_session() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "Session").unwrap();
+        let mut volume = Volume::new(adf);
+        volume.create_dir("Devs").unwrap();
+        volume.create_dir("Devs/Keymaps").unwrap();
+        volume.cd("Devs").unwrap();
+        assert_eq!(volume.pwd(), "Session:Devs");
+
+        volume.put("system-configuration", b"old").unwrap();
+        volume.put("system-configuration", b"new").unwrap();
+        assert_eq!(volume.get(":Devs/system-configuration").unwrap(), b"new");
+        volume.cd("Keymaps").unwrap();
+        assert_eq!(volume.get("/system-configuration").unwrap(), b"new");
+        assert_eq!(volume.resolve("//").unwrap(), "");
+        assert_eq!(volume.resolve("../../S").unwrap(), "S");
+        assert!(volume.resolve("///").is_err());
+
+        volume.cd("/").unwrap();
+        let names: Vec<String> = volume.ls().unwrap().into_iter().map(|f| f.name).collect();
+        assert_eq!(names.len(), 2);
+        assert!(volume.cd("system-configuration").is_err());
+        volume.cd("Session:").unwrap();
+        assert_eq!(volume.current_dir(), "");
+        assert!(volume.put("Devs", b"").is_err());
+        assert!(volume.flush().is_err());
+    }
+
+    #[test]
+    fn test_native_device() {
+        let path = std::env::temp_dir().join(format!("adflib-native-{}.img", std::process::id()));
+        std::fs::write(&path, vec![0u8; 8 * ADF_SECTOR_SIZE]).unwrap();
+
+        let mut device = NativeDevice::init_device(&path, true).unwrap();
+        assert_eq!(
+            (device.size(), device.blocks()),
+            (8 * ADF_SECTOR_SIZE as u64, 8)
+        );
+        assert!(!device.is_block_device());
+        device.write_block(3, &[0xaa; ADF_SECTOR_SIZE]).unwrap();
+        assert_eq!(device.read_block(3).unwrap(), vec![0xaa; ADF_SECTOR_SIZE]);
+        assert!(device.read_blocks(7, 2).is_err());
+        assert!(device.write_block(0, &[0; 100]).is_err());
+
+        let mut device = NativeDevice::init_device(&path, false).unwrap();
+        assert_eq!(device.read_blocks(2, 2).unwrap()[ADF_SECTOR_SIZE], 0xaa);
+        assert_eq!(
+            device
+                .write_block(0, &[0; ADF_SECTOR_SIZE])
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_only_volume() {
+        let path = std::env::temp_dir().join(format!("adflib-master-{}.adf", std::process::id()));
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "Master").unwrap();
+        adf.write_file("Game", b"original").unwrap();
+        adf.write_to_file(path.to_str().unwrap()).unwrap();
+        let before = std::fs::read(&path).unwrap();
+
+        let mut volume = Device::open(&path).unwrap().mount_read_only(0).unwrap();
+        assert!(volume.is_read_only());
+        assert_eq!(volume.get("Game").unwrap(), b"original");
+        let error = volume.put("Game", b"patched").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::ReadOnlyFilesystem);
+        assert!(ReadOnlyVolume::is(&error));
+        assert!(volume.write_file("Save", b"x").is_err());
+        assert!(volume.create_dir("Dir").is_err());
+        assert!(volume.rename("Game", "Other").is_err());
+        assert!(volume.delete("Game").is_err());
+        assert!(volume.adf_mut().is_err());
+        assert!(ReadOnlyVolume::is(&volume.flush().unwrap_err()));
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+
+        let mut volume = Device::open(&path).unwrap().mount(0).unwrap();
+        volume.make_read_only();
+        assert!(volume.delete("Game").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_detect_geometry() {
+        let hd = Device::detect_geometry((ADF_HD_NUM_SECTORS * ADF_SECTOR_SIZE) as u64);
+        assert_eq!((hd.cylinders, hd.heads, hd.sectors), (80, 2, 22));
+        assert_eq!(hd.device_type, DEVICETYPE_FLOPHD);
+
+        let hardfile = Device::detect_geometry(10 * 1024 * 1024);
+        assert_eq!(
+            hardfile,
+            Geometry {
+                cylinders: 640,
+                heads: 1,
+                sectors: 32,
+                device_type: DEVICETYPE_HARDFILE,
+            }
+        );
+        let large = Device::detect_geometry(4 * 1024 * 1024 * 1024);
+        assert_eq!((large.heads, large.sectors), (16, 32));
+        assert_eq!(large.blocks(), 8 * 1024 * 1024);
+        assert_eq!(Device::detect_geometry(7 * 512).cylinders, 7);
+
+        let mut adf = ADF::new(20480, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Hardfile").unwrap();
+        let info = adf.information().unwrap();
+        assert_eq!(
+            (info.tracks, info.heads, info.sectors_per_track),
+            (640, 1, 32)
+        );
+        assert!(format!("{:?}", info).contains("Device Type: Hardfile"));
+
+        let path = std::env::temp_dir().join(format!("adflib-geometry-{}.hdf", std::process::id()));
+        let mut image = vec![0u8; 64 * ADF_SECTOR_SIZE];
+        let rigid_disk = RigidDisk {
+            block_bytes: ADF_SECTOR_SIZE as u32,
+            partition_list: RDB_END,
+            cylinders: 4,
+            sectors: 8,
+            heads: 2,
+        };
+        image[..ADF_SECTOR_SIZE].copy_from_slice(&rigid_disk.to_bytes());
+        std::fs::write(&path, &image).unwrap();
+        let geometry = Device::open(&path).unwrap().geometry();
+        assert_eq!(
+            (geometry.cylinders, geometry.heads, geometry.sectors),
+            (4, 2, 8)
+        );
+        assert_eq!(geometry.device_type, DEVICETYPE_HARDDISK);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_checksum_helpers() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "Sums").unwrap();
+        adf.install_bootblock().unwrap();
+        assert_eq!(bootblock_checksum(adf.read_boot_block()), 0xc0200f19);
+        assert_eq!(read_u32_be(&adf.data, 4), 0xc0200f19);
+
+        let root = adf.read_sector(ROOT_BLOCK);
+        assert_eq!(normal_checksum(root), read_u32_be(root, 20));
+        assert_eq!(adf.calculate_checksum(root), read_u32_be(root, 20));
+        assert_eq!(adf.calculate_checksum(adf.read_boot_block()), 0xc0200f19);
+        let bitmap = adf.read_sector(ROOT_BLOCK + 1);
+        assert_eq!(bitmap_checksum(bitmap), read_u32_be(bitmap, 0));
+
+        let mut block = root.to_vec();
+        block[20..24].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(normal_checksum(&block), read_u32_be(root, 20));
+    }
+
+    #[test]
+    fn test_track_sector_addressing() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "Tracks").unwrap();
+        assert_eq!(adf.ts_to_sector(40, 0, 0).unwrap(), ROOT_BLOCK);
+        assert_eq!(adf.ts_to_sector(79, 1, 10).unwrap(), ADF_NUM_SECTORS - 1);
+        assert_eq!(adf.sector_to_ts(ROOT_BLOCK + 12).unwrap(), (40, 1, 1));
+        assert_eq!(adf.read_ts(40, 0, 0).unwrap(), adf.read_sector(ROOT_BLOCK));
+        assert!(adf.ts_to_sector(0, 0, 11).is_err());
+        assert!(adf.ts_to_sector(80, 0, 0).is_err());
+        assert!(adf.sector_to_ts(ADF_NUM_SECTORS).is_err());
+
+        adf.write_ts(2, 1, 3, &[0x55; ADF_SECTOR_SIZE]).unwrap();
+        assert_eq!(adf.read_sector(2 * 22 + 11 + 3), &[0x55; ADF_SECTOR_SIZE]);
+
+        let hd = ADF::new(ADF_HD_NUM_SECTORS, ADF_SECTOR_SIZE);
+        assert_eq!(hd.ts_to_sector(1, 1, 21).unwrap(), 87);
+        assert_eq!(hd.sector_to_ts(87).unwrap(), (1, 1, 21));
+    }
+
+    #[test]
+    fn test_file_ext_block() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "Large").unwrap();
+        let contents: Vec<u8> = (0..100 * 488).map(|i| (i % 251) as u8).collect();
+        adf.write_file("Big", &contents).unwrap();
+
+        let header = adf.find_entry("Big").unwrap();
+        let (data_blocks, extension_blocks) = adf.file_blocks(header).unwrap();
+        assert_eq!((data_blocks.len(), extension_blocks.len()), (100, 1));
+        let file_header =
+            HeaderBlock::from_bytes(adf.read_sector(header), &Layout::FLOPPY).unwrap();
+        assert_eq!(file_header.high_seq, 72);
+        assert_eq!(file_header.extension, BlockPtr::new(extension_blocks[0]));
+
+        let sector = adf.read_sector(extension_blocks[0]);
+        let extension = FileExtBlock::from_bytes(sector, &Layout::FLOPPY).unwrap();
+        assert!(extension.checksum_valid());
+        assert_eq!(extension.to_bytes(&Layout::FLOPPY), sector);
+        assert_eq!(
+            (extension.high_seq, extension.parent),
+            (28, BlockPtr::new(header))
+        );
+        assert_eq!(extension.data_blocks()[0], BlockPtr::new(data_blocks[72]));
+        assert!(extension.extension.is_null());
+        assert_eq!(adf.read_file("Big").unwrap(), contents);
+    }
+
+    #[test]
+    fn test_layout() {
+        let floppy = Layout::FLOPPY;
+        assert_eq!(
+            (floppy.root_block(), floppy.hash_table_size()),
+            (ROOT_BLOCK, 72)
+        );
+        assert_eq!((floppy.bitmap_pages(), floppy.ofs_data_size()), (1, 488));
+        assert_eq!((floppy.sec_type(), floppy.name()), (508, 432));
+        assert_eq!(floppy.max_blocks(), MAX_VOLUME_BLOCKS);
+
+        let large = Layout::new(1024, 20000);
+        assert_eq!((large.root_block(), large.hash_table_size()), (10000, 200));
+        assert_eq!((large.bitmap_pages(), large.bm_pages()), (3, 828));
+        let name = b"Startup-Sequence";
+        assert_eq!(hash_name(name, false), 49);
+        assert_eq!(hash_name_for(name, false, large.hash_table_size()), 137);
+        let huge = Layout::new(2048, 20000);
+        assert_eq!(huge.hash_table_size(), 456);
+        assert_eq!(hash_name_for(name, false, huge.hash_table_size()), 337);
+
+        let mut hardfile = ADF::new(10000, ADF_SECTOR_SIZE);
+        hardfile.format(DiskType::FFS, "Work").unwrap();
+        let layout = hardfile.layout();
+        assert_eq!(layout, Layout::new(ADF_SECTOR_SIZE, 10000));
+        assert_eq!(hardfile.bitmap_pages().len(), layout.bitmap_pages());
+        assert_eq!(hardfile.root_block(), layout.root_block());
+    }
+
+    #[cfg(feature = "cdylib")]
+    #[test]
+    fn test_ffi() {
+        use crate::ffi::*;
+        use std::ffi::CString;
+
+        let path = std::env::temp_dir().join(format!("adflib-ffi-{}.adf", std::process::id()));
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Ffi").unwrap();
+        std::fs::write(&path, &adf.data).unwrap();
+        let name = CString::new(path.to_str().unwrap()).unwrap();
+        let c = |s: &str| CString::new(s).unwrap();
+
+        unsafe {
+            adfEnvInitDefault();
+            let dev = adfMountDev(name.as_ptr(), 0);
+            let vol = adfMount(dev, 0, 0);
+            assert!(!vol.is_null());
+            assert_eq!(adfCreateDir(vol, c("C").as_ptr()), RC_OK);
+            assert_eq!(adfChangeDir(vol, c("C").as_ptr()), RC_OK);
+            let file = adfOpenFile(vol, c("Dir").as_ptr(), c("w").as_ptr());
+            assert_eq!(adfWriteFile(file, 5, b"hello".as_ptr()), 5);
+            assert_eq!(adfCloseFile(file), RC_OK);
+            assert_eq!(adfParentDir(vol), RC_OK);
+            assert!(adfOpenFile(vol, c("Dir").as_ptr(), c("r").as_ptr()).is_null());
+            adfUnMount(vol);
+
+            let vol = adfMount(dev, 0, 1);
+            assert!(adfOpenFile(vol, c("C/New").as_ptr(), c("w").as_ptr()).is_null());
+            let file = adfOpenFile(vol, c("C/Dir").as_ptr(), c("r").as_ptr());
+            assert_eq!(adfFileGetSize(file), 5);
+            let mut buffer = [0u8; 8];
+            assert_eq!(adfReadFile(file, 8, buffer.as_mut_ptr()), 5);
+            assert_eq!(&buffer[..5], b"hello");
+            assert_eq!(adfEndOfFile(file), 1);
+            assert_eq!(adfCloseFile(file), RC_OK);
+            adfUnMount(vol);
+            adfUnMountDev(dev);
+            adfEnvCleanUp();
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_raw_image() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "Raw").unwrap();
+        adf.create_dir("Dir").unwrap();
+        let contents: Vec<u8> = (0..80 * 488).map(|i| (i % 253) as u8).collect();
+        adf.write_file("Dir/Large", &contents).unwrap();
+        adf.write_file("Small", b"small").unwrap();
+
+        let image = adf.image().unwrap();
+        assert_eq!(image.root_block(), ROOT_BLOCK);
+        let mut names: Vec<String> = image
+            .entries(image.root_block())
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, ["Dir", "Small"]);
+        let large = image.find("dir/LARGE").unwrap();
+        assert_eq!(large, adf.find_entry("Dir/Large").unwrap());
+        assert_eq!(image.read_file(large).unwrap(), contents);
+        assert_eq!(image.entry(large).unwrap().size, contents.len() as u32);
+        assert_eq!(image.find("Dir/Missing"), Err(RawError::NotFound));
+        assert_eq!(
+            image.read_file(image.find("Dir").unwrap()),
+            Err(RawError::NotAFile(adf.find_entry("Dir").unwrap()))
+        );
+        let error: std::io::Error = RawError::NotFound.into();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+        assert!(Image::new(&adf.data[..1000]).is_err());
+    }
+
+    #[test]
+    fn test_metadata() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Catalogue").unwrap();
+        adf.create_dir("Docs").unwrap();
+        adf.write_file("Docs/ReadMe", b"hello").unwrap();
+        adf.set_comment("Docs/ReadMe", "First file").unwrap();
+        adf.set_protection("Docs/ReadMe", 0x01).unwrap();
+        adf.write_file("a", b"x").unwrap();
+
+        let metadata = adf.extract_metadata().unwrap();
+        assert_eq!(metadata.version, METADATA_VERSION);
+        assert_eq!(metadata.volume.name, "Catalogue");
+        assert!(metadata.volume.ffs);
+        assert!(!metadata.volume.intl);
+        assert_eq!(
+            metadata.bitmap.free_blocks + metadata.bitmap.used_blocks,
+            metadata.bitmap.total_blocks
+        );
+        assert_eq!(metadata.bitmap.free_blocks, adf.free_block_count());
+        assert_eq!(metadata.boot_block.sha1.len(), 40);
+
+        let names: Vec<&str> = metadata
+            .root
+            .children
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(names, ["a", "Docs"]);
+        let docs = &metadata.root.children[1];
+        assert!(docs.is_dir);
+        let readme = &docs.children[0];
+        assert_eq!(readme.path, "Docs/ReadMe");
+        assert_eq!(readme.size, 5);
+        assert_eq!(readme.comment, "First file");
+        assert_eq!(readme.protection_bits, 0x01);
+
+        let json = serde_json::to_value(&metadata).unwrap();
+        assert_eq!(
+            json["root"]["children"][1]["children"][0]["comment"],
+            "First file"
+        );
+        assert!(json["root"]["children"][0].get("children").is_none());
+    }
+
+    #[test]
+    fn test_open_any() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Any").unwrap();
+        adf.write_file("File", b"contents").unwrap();
+
+        let dms = pack_dms(&adf.data, DmsMode::Rle, SystemTime::now()).unwrap();
+        assert_eq!(unpack_dms(&dms).unwrap(), adf.data);
+        assert_eq!(ADF::open_any(&dms).unwrap().data, adf.data);
+
+        let mut zip_buffer = Vec::new();
+        {
+            let mut zip = zip::ZipWriter::new(std::io::Cursor::new(&mut zip_buffer));
+            let options: FileOptions<ExtendedFileOptions> = FileOptions::default();
+            zip.start_file("ReadMe.txt", options.clone()).unwrap();
+            zip.write_all(b"not a disk").unwrap();
+            zip.start_file("Disks/Any.ADF", options).unwrap();
+            zip.write_all(&adf.data).unwrap();
+            zip.finish().unwrap();
+        }
+        assert_eq!(ADF::open_any(&zip_buffer).unwrap().data, adf.data);
+        assert_eq!(ADF::open_any(&adf.data).unwrap().data, adf.data);
+
+        let mut corrupt = dms.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xff;
+        assert!(ADF::open_any(&corrupt).is_err());
+        assert!(ADF::open_any(b"PK\x03\x04").is_err());
+    }
+
+    #[test]
+    fn test_mfm() {
+        let track: Vec<u8> = (0..ADF_TRACK_SIZE).map(|i| (i * 7 % 251) as u8).collect();
+        let mut mfm = encode_track(37, &track);
+        fill_gap(&mut mfm, 12500);
+        assert_eq!(mfm.len(), 12500);
+        assert_eq!(decode_track(&mfm, 37, 11).unwrap(), track);
+        assert!(decode_track(&mfm, 36, 11).is_err());
+
+        // Two revolutions read from the middle of the track, with a slightly slow drive and
+        // some jitter on every transition.
+        let revolution = mfm_to_flux(&mfm, 144.0);
+        let flux: Vec<u32> = revolution
+            .iter()
+            .chain(&revolution)
+            .skip(revolution.len() / 3)
+            .enumerate()
+            .map(|(i, &ticks)| ticks * 103 / 100 + (i % 5) as u32 * 2)
+            .collect();
+        assert_eq!(
+            decode_track(&flux_to_mfm(&flux, 144.0), 37, 11).unwrap(),
+            track
+        );
+
+        let mut damaged = mfm.clone();
+        damaged[600] ^= 0x10;
+        let sectors = decode_sectors(&damaged, 37, 11);
+        assert!(sectors[0].is_none());
+        assert_eq!(sectors[1].as_deref(), Some(&track[512..1024]));
+    }
+
+    #[cfg(feature = "hardware")]
+    #[test]
+    fn test_greaseweazle_flux_stream() {
+        use crate::greaseweazle::{decode_flux_stream, encode_flux_stream};
+        let flux = vec![1, 144, 249, 250, 288, 1524, 1525, 100_000, 72];
+        let stream = encode_flux_stream(&flux);
+        assert_eq!(stream.last(), Some(&0));
+        assert!(!stream[..stream.len() - 1].contains(&0));
+        assert_eq!(decode_flux_stream(&stream).unwrap(), flux);
+        // Index pulses carry no flux.
+        let mut indexed = vec![144, 0xff, 1, 3, 5, 7, 9];
+        indexed.extend_from_slice(&stream);
+        assert_eq!(decode_flux_stream(&indexed).unwrap()[1..], flux[..]);
+        assert!(decode_flux_stream(&[0xff, 2, 1]).is_err());
+    }
+
+    #[test]
+    fn test_icon() {
+        let mut icon = vec![0u8; 78];
+        icon[0..2].copy_from_slice(&ICON_MAGIC.to_be_bytes());
+        icon[2..4].copy_from_slice(&1u16.to_be_bytes());
+        icon[12..14].copy_from_slice(&16u16.to_be_bytes());
+        icon[14..16].copy_from_slice(&2u16.to_be_bytes());
+        icon[22..26].copy_from_slice(&1u32.to_be_bytes());
+        icon[48] = 4;
+        icon[50..54].copy_from_slice(&1u32.to_be_bytes());
+        icon[54..58].copy_from_slice(&1u32.to_be_bytes());
+        icon[58..62].copy_from_slice(&i32::MIN.to_be_bytes());
+        icon[62..66].copy_from_slice(&20i32.to_be_bytes());
+        icon[74..78].copy_from_slice(&4096u32.to_be_bytes());
+        // A 16x2 image with two bitplanes.
+        let mut image = vec![0u8; 20];
+        image[4..6].copy_from_slice(&16u16.to_be_bytes());
+        image[6..8].copy_from_slice(&2u16.to_be_bytes());
+        image[8..10].copy_from_slice(&2u16.to_be_bytes());
+        image[10..14].copy_from_slice(&1u32.to_be_bytes());
+        icon.extend_from_slice(&image);
+        icon.extend_from_slice(&[0x80, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x01]);
+        let string = |text: &str| {
+            let mut bytes = (text.len() as u32 + 1).to_be_bytes().to_vec();
+            bytes.extend_from_slice(text.as_bytes());
+            bytes.push(0);
+            bytes
+        };
+        icon.extend(string("SYS:Utilities/MultiView"));
+        icon.extend_from_slice(&12u32.to_be_bytes());
+        icon.extend(string("FILETYPE=ILBM"));
+        icon.extend(string("DONOTWAIT"));
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "Icons").unwrap();
+        adf.write_file("Picture.info", &icon).unwrap();
+        let object = adf.read_icon("picture").unwrap();
+        assert_eq!(object.icon_type, IconType::Project);
+        assert_eq!((object.width, object.height), (16, 2));
+        assert_eq!(
+            object.default_tool.as_deref(),
+            Some("SYS:Utilities/MultiView")
+        );
+        assert_eq!(object.tool_types, ["FILETYPE=ILBM", "DONOTWAIT"]);
+        assert_eq!(object.tool_type("filetype"), Some("ILBM"));
+        assert_eq!(object.tool_type("DONOTWAIT"), Some(""));
+        assert_eq!((object.current_x, object.current_y), (None, Some(20)));
+        assert_eq!(object.stack_size, 4096);
+        assert!(object.selected_image.is_none());
+
+        let image = object.image.unwrap();
+        assert_eq!(image.pixel(0, 0), 3);
+        assert_eq!(image.pixel(1, 0), 0);
+        assert_eq!(image.pixel(15, 1), 2);
+        let bitmap = image.to_bitmap();
+        assert_eq!(bitmap.pixels.len(), 16 * 2 * 3);
+        assert!(DiskObject::from_bytes(&icon[..100]).is_err());
+        assert!(DiskObject::from_bytes(&[0; 78]).is_err());
+    }
+
+    #[test]
+    fn test_iff() {
+        let chunk = |id: &[u8], data: &[u8]| {
+            let mut bytes = id.to_vec();
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                bytes.push(0);
+            }
+            bytes
+        };
+        let form = |form_type: &[u8], chunks: &[Vec<u8>]| {
+            let mut body = form_type.to_vec();
+            chunks.iter().for_each(|c| body.extend_from_slice(c));
+            chunk(b"FORM", &body)
+        };
+        // 4x2 pixels, two planes, ByteRun1: row 0 is colours 0 1 2 3, row 1 is all 3.
+        let mut bmhd = vec![0u8; 20];
+        bmhd[0..2].copy_from_slice(&4u16.to_be_bytes());
+        bmhd[2..4].copy_from_slice(&2u16.to_be_bytes());
+        bmhd[8] = 2;
+        bmhd[10] = 1;
+        let body = [1, 0x50, 0x00, 1, 0x30, 0x00, 0xfd, 0xf0];
+        let cmap = [0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let ilbm = form(
+            b"ILBM",
+            &[
+                chunk(b"BMHD", &bmhd),
+                chunk(b"CMAP", &cmap),
+                chunk(b"ANNO", b"odd"),
+                chunk(b"BODY", &body),
+            ],
+        );
+        assert_eq!(form_type(&ilbm).as_deref(), Some("ILBM"));
+        let picture = Ilbm::from_bytes(&ilbm).unwrap();
+        assert_eq!((picture.width, picture.height, picture.planes), (4, 2, 2));
+        assert_eq!(picture.pixels, [0, 1, 2, 3, 3, 3, 3, 3]);
+        let bitmap = picture.to_bitmap();
+        assert_eq!(&bitmap.pixels[3..6], &[255, 0, 0]);
+        assert_eq!(&bitmap.pixels[21..24], &[0, 0, 255]);
+        assert_eq!(
+            unpack_byterun1(&[0xfe, 7, 0x80, 0, 9], 4).unwrap(),
+            (vec![7, 7, 7, 9], 5)
+        );
+        assert!(unpack_byterun1(&[3, 1], 4).is_err());
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "IFF").unwrap();
+        adf.write_file("Pic", &ilbm).unwrap();
+        adf.write_file("Sound", &form(b"8SVX", &[chunk(b"BODY", &[0; 8])]))
+            .unwrap();
+        adf.write_file("Text", b"FORMAT C:").unwrap();
+        let mut types: Vec<(String, Option<String>)> = adf
+            .list_root_directory()
+            .unwrap()
+            .into_iter()
+            .map(|file| (file.name, file.file_type))
+            .collect();
+        types.sort();
+        assert_eq!(
+            types,
+            [
+                ("Pic".to_string(), Some("ILBM".to_string())),
+                ("Sound".to_string(), Some("8SVX".to_string())),
+                ("Text".to_string(), None),
+            ]
+        );
+        assert!(Ilbm::from_bytes(&form(b"8SVX", &[])).is_err());
+    }
+
+    #[test]
+    fn test_fs() {
+        use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Fs").unwrap();
+        adf.create_dir_all("/Devs/Keymaps").unwrap();
+        adf.create_dir_all("Devs/keymaps/../Printers").unwrap();
+        adf.write_file("Devs/Keymaps/d", b"keymap").unwrap();
+        adf.set_protection("Devs/Keymaps/d", 0x05).unwrap();
+        adf.set_comment("Devs/Keymaps/d", "German").unwrap();
+
+        let metadata = adf.metadata("/devs/KEYMAPS/d").unwrap();
+        assert!(metadata.is_file() && !metadata.is_dir());
+        assert_eq!(metadata.len(), 6);
+        assert_eq!(metadata.comment(), "German");
+        assert!(metadata.permissions().readonly());
+        assert!(adf.metadata("/").unwrap().is_dir());
+        assert_eq!(
+            adf.metadata("Devs/Missing").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+
+        let mut names: Vec<String> = adf
+            .read_dir("Devs")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["Devs/Keymaps", "Devs/Printers"]);
+        assert!(adf.read_dir("Devs/Keymaps/d").is_err());
+
+        let mut file = adf.open("Devs/Keymaps/d").unwrap();
+        file.seek(SeekFrom::Start(3)).unwrap();
+        let mut rest = String::new();
+        file.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "map");
+        assert_eq!(
+            adf.open("Devs").unwrap_err().kind(),
+            ErrorKind::IsADirectory
+        );
+        assert_eq!(
+            adf.create_dir_all("Devs/Keymaps/d/x").unwrap_err().kind(),
+            ErrorKind::AlreadyExists
+        );
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_arbitrary_disks() {
+        use crate::block::{DataBlock, HeaderBlock, RootBlock};
+        use crate::dms::DmsTrackHeader;
+        use crate::test_utils::{HostileDisk, RandomDisk};
+        use arbitrary::{Arbitrary, Unstructured};
+
+        // Walks at most a few levels, hostile disks may contain directory loops.
+        fn walk(adf: &ADF, path: &str, depth: usize) -> usize {
+            let Ok(entries) = adf.read_dir(path) else {
+                return 0;
+            };
+            entries
+                .flatten()
+                .map(|entry| match entry.metadata().unwrap().is_dir() {
+                    true if depth > 0 => 1 + walk(adf, &entry.path(), depth - 1),
+                    true => 1,
+                    false => {
+                        let _ = adf.open(entry.path());
+                        1
+                    }
+                })
+                .sum()
+        }
+
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut entries = 0;
+        for _ in 0..24 {
+            let entropy: Vec<u8> = (0..64 * 1024)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    seed as u8
+                })
+                .collect();
+
+            let RandomDisk(adf) = RandomDisk::arbitrary(&mut Unstructured::new(&entropy)).unwrap();
+            let report = adf.validate();
+            assert!(!report.has_problems(), "{:?}", report.findings);
+            entries += walk(&adf, "/", 8);
+
+            let HostileDisk(mut adf) =
+                HostileDisk::arbitrary(&mut Unstructured::new(&entropy)).unwrap();
+            let _ = adf.information();
+            let _ = adf.extract_metadata();
+            walk(&adf, "/", 8);
+            let _ = adf.repair();
+
+            let mut u = Unstructured::new(&entropy);
+            let header = HeaderBlock::arbitrary(&mut u).unwrap();
+            HeaderBlock::from_bytes(&header.to_bytes(&Layout::FLOPPY), &Layout::FLOPPY).unwrap();
+            RootBlock::from_bytes(
+                &RootBlock::arbitrary(&mut u)
+                    .unwrap()
+                    .to_bytes(&Layout::FLOPPY),
+                &Layout::FLOPPY,
+            )
+            .unwrap();
+            DataBlock::from_bytes(
+                &DataBlock::arbitrary(&mut u)
+                    .unwrap()
+                    .to_bytes(&Layout::FLOPPY),
+                &Layout::FLOPPY,
+            )
+            .unwrap();
+            let track = DmsTrackHeader::arbitrary(&mut u).unwrap().with_header_crc();
+            assert_eq!(
+                DmsTrackHeader::from_bytes(&track.to_bytes()).unwrap(),
+                track
+            );
+        }
+        assert!(entries > 100, "only {} entries generated", entries);
+    }
+
+    #[test]
+    fn test_sansio() {
+        use crate::disk::DirectoryDecoder;
+        use crate::dms::{DmsDecoder, DmsRecord};
+        use crate::hunk::{HunkDecoder, HunkParser, HunkType};
+        use crate::sansio::{decode_async, decode_reader, decode_slice};
+        use std::future::{ready, Future};
+        use std::io::Cursor;
+        use std::task::{Context, Poll, Waker};
+
+        // Feeds data seven bytes at a time; every future is ready, so one poll finishes.
+        fn run_async<D: crate::sansio::Decoder>(decoder: D, data: &[u8]) -> Vec<D::Item> {
+            let future = decode_async(decoder, |offset, len| {
+                let start = (offset as usize).min(data.len());
+                ready(Ok(data[start..].iter().take(len.min(7)).copied().collect()))
+            });
+            let mut future = std::pin::pin!(future);
+            match future
+                .as_mut()
+                .poll(&mut Context::from_waker(Waker::noop()))
+            {
+                Poll::Ready(items) => items.unwrap(),
+                Poll::Pending => panic!("decode_async did not finish"),
+            }
+        }
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "SansIO").unwrap();
+        adf.create_dir("Tools").unwrap();
+        adf.write_file("Readme", b"text").unwrap();
+        adf.write_file("Tools/Run", b"code").unwrap();
+
+        let dms = adf.to_dms(DmsMode::Rle).unwrap();
+        let from_slice: Vec<DmsRecord> = decode_slice(DmsDecoder::new(), &dms)
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        let from_reader: Vec<DmsRecord> = decode_reader(DmsDecoder::new(), Cursor::new(&dms))
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        let from_async = run_async(DmsDecoder::new(), &dms);
+        assert_eq!(from_slice.len(), 1 + 2 * DMS_CYLINDERS);
+        for records in [&from_reader, &from_async] {
+            assert_eq!(format!("{:?}", records), format!("{:?}", from_slice));
+        }
+        let truncated = &dms[..DMS_HEADER_SIZE + DMS_TRACK_HEADER_SIZE + 1];
+        let error = decode_slice(DmsDecoder::new(), truncated)
+            .find_map(|record| record.err())
+            .unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        // One code hunk of a single RTS with a relocation and a symbol.
+        let words: [u32; 20] = [
+            1011,
+            0,
+            1,
+            0,
+            0,
+            1,
+            1001,
+            1,
+            0x4e75_0000,
+            1004,
+            1,
+            0,
+            0,
+            0,
+            1008,
+            1,
+            u32::from_be_bytes(*b"main"),
+            0,
+            0,
+            1010,
+        ];
+        let hunk_file: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        let hunks = HunkParser::parse_bytes(&hunk_file).unwrap();
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].hunk_type, HunkType::Code);
+        assert_eq!(hunks[0].code_data.as_deref(), Some(&[0x4e, 0x75, 0, 0][..]));
+        assert_eq!(hunks[0].reloc_32.as_ref().unwrap()[0].offsets, [0]);
+        assert_eq!(hunks[0].symbols.as_ref().unwrap()[0].name, "main");
+        let from_reader = HunkParser::parse_hunks(&mut Cursor::new(&hunk_file)).unwrap();
+        let from_async = run_async(HunkDecoder::new(), &hunk_file);
+        for parsed in [&from_reader, &from_async] {
+            assert_eq!(format!("{:?}", parsed), format!("{:?}", hunks));
+        }
+        assert!(HunkParser::parse_bytes(&hunk_file[..40]).is_err());
+
+        // The directory decoder seeks from block to block, which the reader adapter follows.
+        let root = adf.layout().root_block();
+        let mut names: Vec<(String, bool)> = decode_reader(
+            DirectoryDecoder::new(adf.layout(), root),
+            Cursor::new(&adf.data),
+        )
+        .map(|entry| entry.map(|entry| (entry.name, entry.is_dir)))
+        .collect::<std::io::Result<_>>()
+        .unwrap();
+        names.sort();
+        assert_eq!(
+            names,
+            [("Readme".to_string(), false), ("Tools".to_string(), true)]
+        );
+        let tools = adf.find_entry("Tools").unwrap();
+        let in_tools = run_async(DirectoryDecoder::new(adf.layout(), tools), &adf.data);
+        assert_eq!(in_tools.len(), 1);
+        assert_eq!(in_tools[0].name, "Run");
+    }
+
+    #[test]
+    fn test_bitmap_counts() {
+        for blocks in [ADF_NUM_SECTORS, ADF_HD_NUM_SECTORS] {
+            let mut adf = ADF::new(blocks, ADF_SECTOR_SIZE);
+            adf.format(DiskType::FFS, "Counts").unwrap();
+            let free = adf.read_allocation_map();
+            let map: Vec<bool> = adf.allocation_map().collect();
+            assert_eq!(map.len(), blocks);
+            assert!(map.iter().zip(&free).all(|(&used, &free)| used != free));
+
+            let counts = adf.bitmap_counts();
+            assert_eq!(counts.total_blocks as usize, blocks);
+            assert_eq!(
+                counts.free_blocks as usize,
+                free.iter().filter(|&&f| f).count()
+            );
+            assert_eq!(counts.used_blocks, counts.total_blocks - counts.free_blocks);
+
+            // The cached counts follow writes.
+            adf.write_file("File", &[1; 3000]).unwrap();
+            let after = adf.bitmap_counts();
+            assert!(after.free_blocks < counts.free_blocks);
+            assert_eq!(
+                after.free_blocks as usize,
+                adf.read_allocation_map().iter().filter(|&&f| f).count()
+            );
+            assert_eq!(adf.get_bitmap_info().free_blocks, after.free_blocks);
+        }
+    }
+
+    #[test]
+    fn test_save_dirty_extents() {
+        let path = std::env::temp_dir().join(format!("adflib-save-{}.adf", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Save").unwrap();
+        adf.write_to_file(path).unwrap();
+
+        let mut adf = ADF::from_file(path).unwrap();
+        assert!(adf.dirty_extents().is_empty());
+        adf.write_sector(100, &[1; ADF_SECTOR_SIZE]).unwrap();
+        adf.write_sector(101, &[2; ADF_SECTOR_SIZE]).unwrap();
+        adf.write_sector(1500, &[3; ADF_SECTOR_SIZE]).unwrap();
+        assert_eq!(
+            adf.dirty_extents(),
+            [
+                100 * ADF_SECTOR_SIZE..102 * ADF_SECTOR_SIZE,
+                1500 * ADF_SECTOR_SIZE..1501 * ADF_SECTOR_SIZE
+            ]
+        );
+
+        // A byte outside the dirty extents survives, so only they were written.
+        let mut on_disk = std::fs::read(path).unwrap();
+        on_disk[1200 * ADF_SECTOR_SIZE] = 0xaa;
+        std::fs::write(path, &on_disk).unwrap();
+        adf.save(path).unwrap();
+        assert!(adf.dirty_extents().is_empty());
+        let saved = std::fs::read(path).unwrap();
+        assert_eq!(saved[1200 * ADF_SECTOR_SIZE], 0xaa);
+        assert_eq!(
+            saved[..1200 * ADF_SECTOR_SIZE],
+            adf.data[..1200 * ADF_SECTOR_SIZE]
+        );
+        assert_eq!(saved[1500 * ADF_SECTOR_SIZE], 3);
+
+        // Another file gets the whole image.
+        let copy = format!("{}.copy", path);
+        adf.write_file("File", &[4; 5000]).unwrap();
+        adf.save(&copy).unwrap();
+        assert_eq!(std::fs::read(&copy).unwrap(), adf.data);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(&copy).unwrap();
+    }
+
+    #[test]
+    fn test_checksum_kinds() {
+        // Reference sums, a word at a time with the checksum word skipped.
+        let reference = |block: &[u8], skip: usize, carry: bool| {
+            let mut sum = 0u32;
+            for i in (0..block.len() / 4).filter(|&i| i != skip) {
+                let (new_sum, overflow) = sum.overflowing_add(read_u32_be(block, i * 4));
+                sum = new_sum.wrapping_add((carry && overflow) as u32);
+            }
+            sum
+        };
+        let mut state = 1u32;
+        for _ in 0..50 {
+            let block: Vec<u8> = (0..2 * ADF_SECTOR_SIZE)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    (state >> 16) as u8 | 0x80
+                })
+                .collect();
+            let boot: &[u8; 1024] = block[..].try_into().unwrap();
+            assert_eq!(bootblock_checksum(boot), !reference(&block, 1, true));
+            let sector = &block[..ADF_SECTOR_SIZE];
+            assert_eq!(
+                normal_checksum(sector),
+                reference(sector, 5, false).wrapping_neg()
+            );
+            assert_eq!(
+                bitmap_checksum(sector),
+                reference(sector, 0, false).wrapping_neg()
+            );
+        }
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Kinds").unwrap();
+        adf.write_file("File", &[1; 2000]).unwrap();
+        let header = adf.find_entry("File").unwrap();
+        let (data_blocks, _) = adf.file_blocks(header).unwrap();
+        assert_eq!(adf.checksum_kind(0), Some(ChecksumKind::Boot));
+        assert_eq!(adf.checksum_kind(ROOT_BLOCK), Some(ChecksumKind::Normal));
+        assert_eq!(
+            adf.checksum_kind(ROOT_BLOCK + 1),
+            Some(ChecksumKind::Bitmap)
+        );
+        assert_eq!(adf.checksum_kind(data_blocks[0]), None);
+        assert!(adf.fix_checksum(data_blocks[0]).is_err());
+
+        adf.data[(ROOT_BLOCK + 1) * ADF_SECTOR_SIZE + 100] ^= 0xff;
+        assert!(!ChecksumKind::Bitmap.is_valid(adf.read_sector(ROOT_BLOCK + 1)));
+        adf.fix_checksum(ROOT_BLOCK + 1).unwrap();
+        assert!(ChecksumKind::Bitmap.is_valid(adf.read_sector(ROOT_BLOCK + 1)));
+        assert!(ChecksumKind::Normal.is_valid(adf.read_sector(ROOT_BLOCK)));
+        adf.fix_checksum(1).unwrap();
+        assert!(ChecksumKind::Boot.is_valid(&adf.data[..2 * ADF_SECTOR_SIZE]));
+    }
+
+    #[test]
+    fn test_read_file_into_and_range() {
+        let contents: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        for disk_type in [DiskType::OFS, DiskType::FFS] {
+            let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+            adf.format(disk_type, "Range").unwrap();
+            adf.write_file("File", &contents).unwrap();
+
+            let mut out = Vec::new();
+            assert_eq!(adf.read_file_into("File", &mut out).unwrap(), 5000);
+            assert_eq!(out, contents);
+
+            let mut buf = [0u8; 1200];
+            for offset in [0, 480, 500, 1023, 4000] {
+                let read = adf.read_file_range("File", offset, 1000, &mut buf).unwrap();
+                let expected = &contents[offset as usize..(offset as usize + 1000).min(5000)];
+                assert_eq!(&buf[..read], expected);
+            }
+            assert_eq!(adf.read_file_range("File", 6000, 10, &mut buf).unwrap(), 0);
+            assert_eq!(
+                adf.read_file_range("File", 0, 2000, &mut buf)
+                    .unwrap_err()
+                    .kind(),
+                std::io::ErrorKind::InvalidInput
+            );
+            assert!(adf.read_file_into("", &mut out).is_err());
+        }
+    }
+
+    #[test]
+    fn test_directory_cache() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Cache").unwrap();
+        adf.create_dir("Dir").unwrap();
+        adf.write_file("Dir/Old", b"data").unwrap();
+        let block = adf.find_entry("Dir/Old").unwrap();
+
+        // Writes through the ADF methods drop the listings they change.
+        adf.rename("Dir/Old", "Dir/New").unwrap();
+        assert!(adf.find_entry("Dir/Old").is_err());
+        assert_eq!(adf.find_entry("Dir/New").unwrap(), block);
+        adf.delete("Dir/New").unwrap();
+        assert!(adf.find_entry("Dir/New").is_err());
+        let dir = adf.find_entry("Dir").unwrap();
+        assert!(adf.directory_entries(dir).is_empty());
+        adf.write_file("Dir/File", b"data").unwrap();
+        let block = adf.find_entry("Dir/File").unwrap();
+        assert_eq!(adf.directory_entries(dir), [block]);
+
+        // A write to data alone goes unnoticed by the listing until refresh, while lookups
+        // follow the hash chain on disk.
+        let slot = dir * ADF_SECTOR_SIZE + 24 + hash_name(b"File", false) * 4;
+        adf.data[slot..slot + 4].fill(0);
+        assert_eq!(adf.directory_entries(dir), [block]);
+        assert!(adf.find_entry("Dir/File").is_err());
+        adf.refresh();
+        assert!(adf.directory_entries(dir).is_empty());
+    }
+
+    #[test]
+    fn test_disk_set() {
+        use crate::diskset::{parse_disk_name, DiskName, DiskSet};
+        assert_eq!(
+            parse_disk_name("dir/Game (1990)(Disk 2 of 3)[cr].adf"),
+            Some(DiskName {
+                title: "Game (1990)".to_string(),
+                number: 2,
+                total: Some(3),
+            })
+        );
+        let plain = parse_disk_name("game_disk1.adf").unwrap();
+        assert_eq!(
+            (plain.title.as_str(), plain.number, plain.total),
+            ("game", 1, None)
+        );
+        assert_eq!(parse_disk_name("Diskmaster.adf"), None);
+
+        let dir = std::env::temp_dir().join(format!("adflib-diskset-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut paths = Vec::new();
+        for number in [1, 3] {
+            let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+            adf.format(DiskType::FFS, &format!("Game{}", number))
+                .unwrap();
+            adf.write_file(&format!("Part{}", number), &[number as u8; 100])
+                .unwrap();
+            adf.write_file("Readme", &[number as u8; 10]).unwrap();
+            let path = dir.join(format!("Game (Disk {} of 3).adf", number));
+            adf.write_to_file(path.to_str().unwrap()).unwrap();
+            paths.push(path);
+        }
+
+        let set = DiskSet::open(&paths).unwrap();
+        assert_eq!(set.title, "Game");
+        assert_eq!(set.total(), 3);
+        assert_eq!(set.missing(), [2]);
+        assert_eq!(
+            set.validate().unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        assert_eq!(set.resolve("Part3").unwrap().0.number, 3);
+        assert_eq!(set.read_file("Readme").unwrap(), [1; 10]);
+        assert_eq!(set.read_file("Game3:Readme").unwrap(), [3; 10]);
+        assert!(set.resolve("Game1:Part3").is_err());
+
+        let manifest = set.manifest().unwrap();
+        assert!(!manifest.complete);
+        assert_eq!(manifest.disks.len(), 2);
+        assert_eq!(manifest.disks[1].label, "Game3");
+        assert_eq!(manifest.disks[1].files, 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_provenance_sidecar() {
+        use crate::provenance::{Provenance, SidecarFormat};
+        let path = std::env::temp_dir().join(format!("adflib-prov-{}.adf", std::process::id()));
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "Dump").unwrap();
+
+        for format in [SidecarFormat::Toml, SidecarFormat::Json] {
+            adf.write_to_file(path.to_str().unwrap()).unwrap();
+            let sidecar = Provenance::sidecar_path(&path, format);
+            let mut provenance = Provenance::new(&adf.data);
+            provenance.source = Some("Original disk".to_string());
+            provenance.write(&sidecar).unwrap();
+            assert_eq!(Provenance::read(&sidecar).unwrap(), provenance);
+
+            let mut image = ADF::from_file(path.to_str().unwrap()).unwrap();
+            image.write_file("File", b"contents").unwrap();
+            image.repair().unwrap();
+            image.save(path.to_str().unwrap()).unwrap();
+
+            let updated = Provenance::load(&path).unwrap().unwrap();
+            assert_eq!(updated.source.as_deref(), Some("Original disk"));
+            assert!(updated.matches(&image.data));
+            assert_eq!(updated.history.len(), 1);
+            assert!(updated.history[0].action.starts_with("Repaired"));
+
+            // Saving without notable changes still records that the image changed.
+            image.write_file("Other", b"contents").unwrap();
+            image.save(path.to_str().unwrap()).unwrap();
+            let updated = Provenance::load(&path).unwrap().unwrap();
+            assert_eq!(updated.history.last().unwrap().action, "Modified");
+            assert!(updated.matches(&image.data));
+            std::fs::remove_file(&sidecar).unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_verify_against_dir() {
+        use crate::verify::{against_dir, MismatchKind};
+        let dir = std::env::temp_dir().join(format!("adflib-verify-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("c")).unwrap();
+        std::fs::write(dir.join("c/Run"), b"run").unwrap();
+        std::fs::write(dir.join("ReadMe"), b"hello").unwrap();
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Verify").unwrap();
+        adf.import_dir(&dir, "").unwrap();
+
+        let report = against_dir(&adf, &dir).unwrap();
+        assert!(report.is_match());
+        assert_eq!(report.matched, 2);
+
+        std::fs::write(dir.join("ReadMe"), b"jello").unwrap();
+        std::fs::write(dir.join("c/Run"), b"runs").unwrap();
+        std::fs::write(dir.join("New"), b"new").unwrap();
+        adf.write_file("Extra", b"extra").unwrap();
+        let report = against_dir(&adf, &dir).unwrap();
+        let found: Vec<(&str, MismatchKind)> = report
+            .mismatches
+            .iter()
+            .map(|m| (m.path.as_str(), m.kind))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("New", MismatchKind::OnlyOnHost),
+                ("ReadMe", MismatchKind::Content),
+                ("c/Run", MismatchKind::Size),
+                ("Extra", MismatchKind::OnlyOnImage),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_manifest() {
+        use crate::manifest::HashAlgo;
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "Manifest").unwrap();
+        adf.create_dir("s").unwrap();
+        adf.write_file("s/Startup-Sequence", b"abc").unwrap();
+        adf.write_file("Empty", b"").unwrap();
+
+        let manifest = adf.manifest(HashAlgo::Sha256).unwrap();
+        assert_eq!(manifest.len(), 2);
+        let startup = manifest
+            .iter()
+            .find(|entry| entry.path == "s/Startup-Sequence")
+            .unwrap();
+        assert_eq!(startup.size, 3);
+        assert_eq!(
+            startup.hash,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            "SHA1".parse::<HashAlgo>().unwrap().digest(b"abc"),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert!("sha512".parse::<HashAlgo>().is_err());
+    }
+
+    #[test]
+    fn test_kickstart_boot_code() {
+        use crate::bootblock::{analyze, Kickstart};
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        let options = FormatOptions {
+            bootable: true,
+            kickstart: Kickstart::V31,
+            ..FormatOptions::default()
+        };
+        let formatted = adf.format_with_options(DiskType::FFS, "Boot", &options);
+        if cfg!(not(feature = "bootblocks")) {
+            assert_eq!(
+                formatted.unwrap_err().kind(),
+                std::io::ErrorKind::Unsupported
+            );
+            return;
+        }
+        formatted.unwrap();
+        let info = analyze(adf.read_boot_block());
+        assert!(info.checksum_valid());
+        assert_eq!(info.code, BootCode::Standard);
+        // 2.x and 3.1 Install write the same code.
+        assert_eq!(
+            Kickstart::identify(&adf.data[12..2 * ADF_SECTOR_SIZE]),
+            Some(Kickstart::V2x)
+        );
+
+        adf.install_bootblock_for(Kickstart::V13).unwrap();
+        assert_eq!(
+            Kickstart::identify(&adf.data[12..2 * ADF_SECTOR_SIZE]),
+            Some(Kickstart::V13)
+        );
+        assert_eq!(read_u32_be(&adf.data, 4), 0xc0200f19 - 1);
+    }
+
+    #[test]
+    fn test_bad_block_map() {
+        use crate::validate::Severity;
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Degraded").unwrap();
+        assert!(adf.bad_blocks().unwrap().is_empty());
+        assert!(adf.mark_bad(0).is_err());
+        assert!(adf.mark_bad(adf.root_block()).is_err());
+
+        let bad = adf.find_free_block().unwrap() + 1;
+        adf.mark_bad(bad).unwrap();
+        adf.mark_bad(bad).unwrap();
+        assert_eq!(adf.bad_blocks().unwrap(), [bad]);
+        assert!(!adf.get_bitmap()[bad]);
+
+        adf.write_file("Big", &vec![0x55; 40 * ADF_SECTOR_SIZE])
+            .unwrap();
+        let (data_blocks, _) = adf.file_blocks(adf.find_entry("Big").unwrap()).unwrap();
+        assert!(!data_blocks.contains(&bad));
+        assert!(!adf.validate().has_problems());
+
+        // Repair rebuilds the bitmap from the directory tree and the map.
+        adf.set_block_free(bad);
+        adf.update_bitmap_blocks().unwrap();
+        adf.repair().unwrap();
+        assert!(!adf.get_bitmap()[bad]);
+
+        let in_use = data_blocks[0];
+        adf.mark_bad(in_use).unwrap();
+        let report = adf.validate();
+        assert!(report
+            .findings
+            .iter()
+            .any(|f| f.severity == Severity::Warning && f.block == Some(in_use)));
+        adf.clear_bad(in_use).unwrap();
+        assert!(!adf.get_bitmap()[in_use]);
+
+        adf.defragment().unwrap();
+        assert_eq!(adf.bad_blocks().unwrap(), [bad]);
+        assert!(!adf.get_bitmap()[bad]);
+        adf.clear_bad(bad).unwrap();
+        assert!(adf.get_bitmap()[bad]);
+        assert!(adf.find_entry(".bad_blocks").is_err());
+        assert!(!adf.validate().has_problems());
+    }
+
+    #[test]
+    fn test_sparse_hardfile() {
+        let path = std::env::temp_dir().join(format!("adflib-sparse-{}.hdf", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let mut adf = ADF::new(20000, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Sparse").unwrap();
+        adf.write_file("Tail", &[0x5a; 3000]).unwrap();
+        adf.save(path_str).unwrap();
+
+        let usage = DiskUsage::of(&path).unwrap();
+        assert_eq!(usage.apparent, (20000 * ADF_SECTOR_SIZE) as u64);
+        // Most of the volume is zero, so the host must have left holes.
+        #[cfg(unix)]
+        assert!(usage.allocated < usage.apparent / 2, "{:?}", usage);
+        assert!(usage.allocated <= usage.apparent);
+        assert_eq!(std::fs::read(&path).unwrap(), adf.data);
+
+        // Trailing zero blocks must still count towards the file size.
+        std::fs::remove_file(&path).unwrap();
+        adf.write_sparse(path_str).unwrap();
+        assert_eq!(ADF::from_file(path_str).unwrap().data, adf.data);
+        #[cfg(unix)]
+        assert!(DiskUsage::of(&path).unwrap().allocated < usage.apparent / 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cancellation() {
+        use crate::cancel::CancelToken;
+        use crate::dms::pack_dms_cancellable;
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Cancel").unwrap();
+        let cancel = CancelToken::new();
+        assert!(adf.validate_cancellable(&mut |_, _| {}, &cancel).is_ok());
+
+        let dir = std::env::temp_dir().join(format!("adflib-cancel-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a", "b", "c"] {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+        let token = cancel.clone();
+        let error = adf
+            .import_dir_cancellable(&dir, "", &mut |_, _| token.cancel(), &cancel)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Interrupted);
+        assert_eq!(adf.read_file("a").unwrap(), b"a");
+        assert!(adf.find_entry("b").is_err());
+        assert!(!adf.validate().has_problems());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(adf.validate_cancellable(&mut |_, _| {}, &cancel).is_err());
+        assert!(pack_dms_cancellable(&adf.data, DmsMode::Rle, UNIX_EPOCH, &cancel).is_err());
+    }
+
+    #[test]
+    fn test_observers() {
+        use crate::events::Observer;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+        impl Observer for Recorder {
+            fn on_block_written(&self, blocks: std::ops::Range<usize>) {
+                self.0.lock().unwrap().push(format!("write {:?}", blocks));
+            }
+            fn on_entry_created(&self, path: &str, _block: usize) {
+                self.0.lock().unwrap().push(format!("create {}", path));
+            }
+            fn on_entry_removed(&self, path: &str, _block: usize) {
+                self.0.lock().unwrap().push(format!("remove {}", path));
+            }
+        }
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Observed").unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let observer: Arc<dyn Observer> = recorder.clone();
+        adf.add_observer(observer.clone());
+        let entries = |recorder: &Recorder| -> Vec<String> {
+            std::mem::take(&mut *recorder.0.lock().unwrap())
+                .into_iter()
+                .filter(|event| !event.starts_with("write"))
+                .collect()
+        };
+
+        adf.create_dir("s").unwrap();
+        adf.write_file("s/Startup-Sequence", b"LoadWB").unwrap();
+        let header = adf.find_entry("s/Startup-Sequence").unwrap();
+        assert!(recorder
+            .0
+            .lock()
+            .unwrap()
+            .contains(&format!("write {:?}", header..header + 1)));
+        assert_eq!(
+            entries(&recorder),
+            ["create s", "create s/Startup-Sequence"]
+        );
+
+        adf.rename("s/Startup-Sequence", "Boot").unwrap();
+        adf.delete("Boot").unwrap();
+        assert_eq!(
+            entries(&recorder),
+            ["remove s/Startup-Sequence", "create Boot", "remove Boot"]
+        );
+
+        // A failed operation reports nothing, and copies start without observers.
+        assert!(adf.delete("Missing").is_err());
+        let mut copy = adf.clone();
+        copy.create_dir("c").unwrap();
+        assert!(recorder.0.lock().unwrap().is_empty());
+
+        adf.remove_observer(&observer);
+        adf.create_dir("c").unwrap();
+        assert!(recorder.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_deterministic_pack() {
+        let dir = std::env::temp_dir().join(format!("adflib-repro-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("s")).unwrap();
+        std::fs::write(dir.join("s/Startup-Sequence"), "LoadWB").unwrap();
+        std::fs::write(dir.join("ReadMe"), vec![7; 3000]).unwrap();
+        let time = UNIX_EPOCH + Duration::from_secs(AMIGA_EPOCH_OFFSET + 86_400);
+        let pack = || {
+            let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+            adf.set_fixed_time(Some(time));
+            adf.format(DiskType::FFS, "Release").unwrap();
+            adf.import_dir(&dir, "").unwrap();
+            adf
+        };
+
+        let first = pack();
+        // Host times are ignored, so touching the input changes nothing.
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("ReadMe"))
+            .unwrap()
+            .set_modified(SystemTime::now())
+            .unwrap();
+        let second = pack();
+        assert_eq!(first.data, second.data);
+        assert_eq!(
+            first.to_dms(DmsMode::Rle).unwrap(),
+            second.to_dms(DmsMode::Rle).unwrap()
+        );
+        let info = first
+            .read_file_header(first.find_entry("ReadMe").unwrap())
+            .unwrap();
+        assert_eq!(info.creation_date, time);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_clock() {
+        use crate::clock::Clock;
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+
+        // Advances a day on every reading.
+        struct StepClock(AtomicU64);
+        impl Clock for StepClock {
+            fn now(&self) -> SystemTime {
+                let days = self.0.fetch_add(1, Ordering::Relaxed);
+                UNIX_EPOCH + Duration::from_secs(AMIGA_EPOCH_OFFSET + days * 86_400)
+            }
+        }
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.set_clock(Some(Arc::new(StepClock(AtomicU64::new(10)))));
+        adf.format(DiskType::FFS, "Clock").unwrap();
+        adf.write_file("a", b"first").unwrap();
+        adf.write_file("b", b"second").unwrap();
+        let date = |adf: &ADF, name: &str| {
+            adf.read_file_header(adf.find_entry(name).unwrap())
+                .unwrap()
+                .creation_date
+        };
+        let a = date(&adf, "a");
+        let b = date(&adf, "b");
+        assert!(a > UNIX_EPOCH + Duration::from_secs(AMIGA_EPOCH_OFFSET + 10 * 86_400));
+        assert!(b > a);
+
+        // Clones and defragmented copies keep the clock.
+        let mut copy = adf.clone();
+        copy.write_file("c", b"third").unwrap();
+        assert!(date(&copy, "c") > b);
+        adf.defragment().unwrap();
+        assert!(adf.clock().is_some());
+        adf.write_file("d", b"fourth").unwrap();
+        assert!(date(&adf, "d") > b);
+
+        adf.set_clock(None);
+        assert!(adf.now() > UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    }
+
+    #[test]
+    fn test_export_archive() {
+        use crate::archive::ArchiveFormat;
+        use std::io::{Cursor, Read};
+        use zip::ZipArchive;
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Archive").unwrap();
+        adf.create_dir("s").unwrap();
+        adf.write_file("s/Startup-Sequence", b"LoadWB").unwrap();
+        let big: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        adf.write_file("ReadMe", &big).unwrap();
+
+        let mut zip_data = Cursor::new(Vec::new());
+        adf.export_archive(ArchiveFormat::Zip, &mut zip_data)
+            .unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(zip_data.into_inner())).unwrap();
+        let mut contents = Vec::new();
+        archive
+            .by_name("ReadMe")
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, big);
+        assert!(archive.by_name("s/").unwrap().is_dir());
+        contents.clear();
+        archive
+            .by_name("s/Startup-Sequence")
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"LoadWB");
+
+        let mut tar = Vec::new();
+        adf.export_archive(ArchiveFormat::Tar, Cursor::new(&mut tar))
+            .unwrap();
+        assert_eq!(tar.len() % 512, 0);
+        let mut offset = 0;
+        let mut files = Vec::new();
+        while tar[offset] != 0 {
+            let header = &tar[offset..offset + 512];
+            let name = String::from_utf8_lossy(&header[..100])
+                .trim_end_matches('\0')
+                .to_string();
+            let octal = |field: &[u8]| {
+                u64::from_str_radix(
+                    std::str::from_utf8(field)
+                        .unwrap()
+                        .trim_matches(['\0', ' ']),
+                    8,
+                )
+                .unwrap()
+            };
+            let sum: u64 = header
+                .iter()
+                .enumerate()
+                .map(|(i, &b)| {
+                    if (148..156).contains(&i) {
+                        32
+                    } else {
+                        u64::from(b)
+                    }
+                })
+                .sum();
+            assert_eq!(octal(&header[148..156]), sum);
+            let size = octal(&header[124..136]) as usize;
+            files.push((name, tar[offset + 512..offset + 512 + size].to_vec()));
+            offset += 512 + size.div_ceil(512) * 512;
+        }
+        assert!(files.contains(&("ReadMe".to_string(), big)));
+        assert!(files.contains(&("s/".to_string(), Vec::new())));
+        assert!(files.contains(&("s/Startup-Sequence".to_string(), b"LoadWB".to_vec())));
+        assert!(tar[offset..].iter().all(|&b| b == 0));
+        assert!("rar".parse::<ArchiveFormat>().is_err());
+    }
+
+    #[test]
+    fn test_save_to_zip() {
+        use std::io::Cursor;
+        use zip::CompressionMethod;
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Zipped").unwrap();
+        adf.write_file("ReadMe", b"Hello").unwrap();
+        for compression in [CompressionMethod::Stored, CompressionMethod::Deflated] {
+            let mut zip = Cursor::new(Vec::new());
+            adf.save_to_zip(&mut zip, "disk.adf", compression).unwrap();
+            let zip = zip.into_inner();
+            let loaded = load_adf_from_zip(&zip, "disk.adf").unwrap();
+            assert_eq!(loaded.data, adf.data);
+            assert_eq!(ADF::open_any(&zip).unwrap().data, adf.data);
+            if compression == CompressionMethod::Deflated {
+                assert!(zip.len() < adf.data.len() / 10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_tracks() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Tracks").unwrap();
+        adf.write_file("ReadMe", b"Track by track").unwrap();
+        let tracks = adf.split_tracks().unwrap();
+        assert_eq!(tracks.len(), ADF_NUM_TRACKS);
+        // The root block sits in the middle of the disk, on track 80.
+        assert_eq!(
+            &tracks[80][..ADF_SECTOR_SIZE],
+            adf.read_sector(adf.root_block())
+        );
+        let joined = ADF::from_tracks(tracks).unwrap();
+        assert_eq!(joined.data, adf.data);
+        assert_eq!(joined.read_file("ReadMe").unwrap(), b"Track by track");
+
+        let odd = ADF::new(ADF_TRACK_SIZE / ADF_SECTOR_SIZE * 4 + 1, ADF_SECTOR_SIZE);
+        assert_eq!(
+            odd.split_tracks().unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+        assert!(ADF::from_tracks([[0; ADF_TRACK_SIZE]; 3]).is_err());
+    }
+
+    #[test]
+    fn test_convert_all_dms() {
+        use crate::dms::{convert_all, ConvertOptions, ConvertStatus};
+
+        let dir = std::env::temp_dir().join(format!("adflib-convert-{}", std::process::id()));
+        let out_dir = dir.join("out");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Batch").unwrap();
+        adf.write_file("ReadMe", b"Packed").unwrap();
+        let dms = adf.to_dms(DmsMode::Rle).unwrap();
+        std::fs::write(dir.join("good.dms"), &dms).unwrap();
+        let mut damaged = dms.clone();
+        let last = damaged.len() - 1;
+        damaged[last] ^= 0xff;
+        std::fs::write(dir.join("damaged.dms"), &damaged).unwrap();
+        let paths = [
+            dir.join("good.dms"),
+            dir.join("damaged.dms"),
+            dir.join("missing.dms"),
+        ];
+
+        let options = ConvertOptions {
+            threads: 2,
+            ..ConvertOptions::default()
+        };
+        let results = convert_all(&paths, &out_dir, &options).unwrap();
+        let statuses: Vec<_> = results.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                ConvertStatus::Converted,
+                ConvertStatus::Corrupt,
+                ConvertStatus::Failed
+            ]
+        );
+        assert_eq!(std::fs::read(out_dir.join("good.adf")).unwrap(), adf.data);
+        assert!(results[1].output.is_none());
+
+        // Existing images are kept unless overwrite is set.
+        let results = convert_all(&paths[..1], &out_dir, &options).unwrap();
+        assert_eq!(results[0].status, ConvertStatus::Failed);
+        let options = ConvertOptions {
+            overwrite: true,
+            ..options
+        };
+        let results = convert_all(&paths[..1], &out_dir, &options).unwrap();
+        assert_eq!(results[0].status, ConvertStatus::Converted);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dms_extras() {
+        use crate::cancel::CancelToken;
+        use crate::dms::{pack_dms_with_extras, read_dms_extras, DmsExtras};
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "Release").unwrap();
+        let extras = DmsExtras {
+            banner: Some(b"Packed by the adflib crew".to_vec()),
+            file_id_diz: Some(b"Release 1/1\nA demo disk".to_vec()),
+        };
+        let dms = pack_dms_with_extras(
+            &adf.data,
+            DmsMode::Rle,
+            UNIX_EPOCH,
+            &extras,
+            &CancelToken::default(),
+        )
+        .unwrap();
+        let header = parse_header(&dms).unwrap();
+        assert_eq!(header.info_flags, 0x108);
+        assert_eq!(read_dms_extras(&dms).unwrap(), extras);
+        assert_eq!(
+            extras.file_id_diz_text().unwrap(),
+            "Release 1/1\nA demo disk"
+        );
+        let padded = DmsExtras {
+            banner: Some(b"Caf\xe9\r\nMenu\0\0".to_vec()),
+            file_id_diz: None,
+        };
+        assert_eq!(padded.banner_text().unwrap(), "Café\nMenu");
+        assert_eq!(padded.file_id_diz_text(), None);
+        assert_eq!(unpack_dms(&dms).unwrap(), adf.data);
+        assert!(verify_dms(&dms)
+            .unwrap()
+            .iter()
+            .all(|report| report.status == TrackStatus::Ok));
+
+        let plain = pack_dms(&adf.data, DmsMode::Rle, UNIX_EPOCH).unwrap();
+        assert_eq!(read_dms_extras(&plain).unwrap(), DmsExtras::default());
+        let too_long = DmsExtras {
+            banner: Some(vec![b' '; 70_000]),
+            ..DmsExtras::default()
+        };
+        assert!(pack_dms_with_extras(
+            &adf.data,
+            DmsMode::Rle,
+            UNIX_EPOCH,
+            &too_long,
+            &CancelToken::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_dms_header_display() {
+        use crate::dms::DmsTrackHeader;
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Header").unwrap();
+        let date = UNIX_EPOCH + Duration::from_secs(700_000_000);
+        let dms = pack_dms(&adf.data, DmsMode::Rle, date).unwrap();
+        let header = parse_header(&dms).unwrap();
+        assert_eq!(header.created, date);
+        let text = header.to_string();
+        assert!(text.contains("1992-03-07 20:26:40 UTC"));
+        assert!(text.contains("FFS (2)"));
+        assert!(text.contains("Flags:       none"));
+
+        let json = serde_json::to_value(&header).unwrap();
+        assert_eq!(json["created"], "1992-03-07T20:26:40Z");
+        assert_eq!(json["date"], 700_000_000);
+
+        let track = DmsTrackHeader::from_bytes(&dms[DMS_HEADER_SIZE..]).unwrap();
+        assert_eq!(
+            track.to_string(),
+            format!("Track 0: SIMPLE, {} of 11264 bytes", track.packed_len)
+        );
+        assert!(serde_json::to_string(&track)
+            .unwrap()
+            .contains("\"number\":0"));
+    }
+
+    #[test]
+    fn test_hunk_payload() {
+        use crate::hunk::{HunkParser, HunkType};
+
+        // A code hunk of two longs and a BSS hunk of three.
+        let words: [u32; 14] = [
+            1011,
+            0,
+            2,
+            0,
+            1,
+            2,
+            3,
+            1001,
+            2,
+            0x4e75_4e71,
+            0x1234_5678,
+            1010,
+            1003,
+            3,
+        ];
+        let mut hunk_file: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        hunk_file.extend_from_slice(&1010u32.to_be_bytes());
+        let hunks = HunkParser::parse_bytes(&hunk_file).unwrap();
+        assert_eq!(hunks[1].hunk_type, HunkType::Bss);
+
+        let mut code = Vec::new();
+        assert_eq!(hunks[0].write_payload(&mut code).unwrap(), 8);
+        assert_eq!(code, [0x4e, 0x75, 0x4e, 0x71, 0x12, 0x34, 0x56, 0x78]);
+        let mut bss = Vec::new();
+        assert_eq!(hunks[1].write_payload(&mut bss).unwrap(), 12);
+        assert_eq!(bss, [0; 12]);
+    }
+
+    #[test]
+    fn test_hunk_patch() {
+        use crate::hunk::HunkFile;
+
+        // A code hunk of three longs, the second relocated, followed by a symbol, and a BSS hunk.
+        let words: [u32; 24] = [
+            1011,
+            0,
+            2,
+            0,
+            1,
+            3,
+            1,
+            1001,
+            3,
+            0x4e75_4e71,
+            0x0000_0000,
+            0x4e71_4e71,
+            1004,
+            1,
+            1,
+            4,
+            0,
+            1008,
+            1,
+            u32::from_be_bytes(*b"main"),
+            0,
+            0,
+            1010,
+            1003,
+        ];
+        let mut original: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        original.extend([0, 0, 0, 1, 0, 0, 3, 0xf2]);
+        let mut file = HunkFile::parse(original.clone()).unwrap();
+        assert_eq!(file.hunks()[0].data_offset, Some(36));
+
+        file.patch(0, 0, &[0x4e, 0x71]).unwrap();
+        file.patch(0, 8, &[0x60, 0xfe]).unwrap();
+        assert_eq!(
+            file.hunks()[0].code_data.as_deref().unwrap()[..2],
+            [0x4e, 0x71]
+        );
+        let error = file.patch(0, 2, &[0; 4]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("offset 4"));
+        assert!(file.patch(0, 10, &[0; 4]).is_err());
+        assert!(file.patch(1, 0, &[0]).is_err());
+        assert!(file.patch(2, 0, &[0]).is_err());
+
+        let patched = file.into_bytes();
+        assert_eq!(patched.len(), original.len());
+        let changed: Vec<usize> = (0..patched.len())
+            .filter(|&i| patched[i] != original[i])
+            .collect();
+        assert_eq!(changed, [37, 44, 45]);
+        let reparsed = HunkFile::parse(patched).unwrap();
+        assert_eq!(
+            reparsed.hunks()[0].symbols.as_ref().unwrap()[0].name,
+            "main"
+        );
+        assert_eq!(
+            reparsed.hunks()[0].code_data.as_deref().unwrap()[8..10],
+            [0x60, 0xfe]
+        );
+    }
+
+    #[test]
+    fn test_list_directory_sorted() {
+        use crate::disk::SortOrder;
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Sorted").unwrap();
+        for (index, name) in ["zeta", "Alpha", "beta", "Gamma"].iter().enumerate() {
+            let time =
+                UNIX_EPOCH + Duration::from_secs(AMIGA_EPOCH_OFFSET + 86_400 * (4 - index as u64));
+            adf.set_fixed_time(Some(time));
+            adf.write_file(name, &vec![1; 100 * (index + 1)]).unwrap();
+        }
+        let names = |order: SortOrder| -> Vec<String> {
+            adf.list_directory_sorted(adf.root_block(), order)
+                .unwrap()
+                .into_iter()
+                .map(|file| file.name)
+                .collect()
+        };
+        assert_eq!(names(SortOrder::Name), ["Alpha", "beta", "Gamma", "zeta"]);
+        assert_eq!(names(SortOrder::Size), ["zeta", "Alpha", "beta", "Gamma"]);
+        assert_eq!(names(SortOrder::Date), ["Gamma", "beta", "Alpha", "zeta"]);
+        let disk: Vec<String> = adf
+            .list_root_directory()
+            .unwrap()
+            .into_iter()
+            .map(|file| file.name)
+            .collect();
+        assert_eq!(names(SortOrder::Disk), disk);
+        assert_eq!("size".parse::<SortOrder>().unwrap(), SortOrder::Size);
+        assert!("random".parse::<SortOrder>().is_err());
+    }
+
+    #[test]
+    fn test_doctor() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "TestDisk").unwrap();
+        adf.install_bootblock().unwrap();
+        adf.write_file("Readme", b"hello\n").unwrap();
+        let report = adf.doctor();
+        assert_eq!(report.severity(), Severity::Info);
+        assert_eq!(report.checks.len(), 7);
+        assert!(report.fragmentation.is_some());
+        let boot = report
+            .checks
+            .iter()
+            .find(|c| c.name == "Boot block")
+            .unwrap();
+        assert_eq!(boot.summary, "Boots with standard AmigaDOS boot code");
+
+        adf.data[ROOT_BLOCK * ADF_SECTOR_SIZE + 100] ^= 0xff;
+        let report = adf.doctor();
+        assert_eq!(report.severity(), Severity::Error);
+        let checksums = report
+            .checks
+            .iter()
+            .find(|c| c.name == "Checksums")
+            .unwrap();
+        assert_eq!(checksums.severity, Severity::Error);
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "TestDisk").unwrap();
+        adf.install_bootblock().unwrap();
+        adf.patch_sector(0, 100, &[0x2d, 0x48, 0x00, 0x2e], None)
+            .unwrap();
+        let report = adf.doctor();
+        let boot = report
+            .checks
+            .iter()
+            .find(|c| c.name == "Boot block")
+            .unwrap();
+        assert_eq!(boot.severity, Severity::Error);
+        assert!(boot.summary.contains("CoolCapture"));
+    }
+
+    #[test]
+    fn test_lookup_entry_follows_hash_chains() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "TestDisk").unwrap();
+        // Both names hash to slot 33, so the second hangs off the first's chain.
+        assert_eq!(hash_name(b"filean", false), hash_name(b"fileba", false));
+        adf.write_file("filean", b"first").unwrap();
+        adf.write_file("fileba", b"second").unwrap();
+
+        let root = adf.root_block();
+        let first = adf.lookup_entry(root, "filean").unwrap().unwrap();
+        let second = adf.lookup_entry(root, "FILEBA").unwrap().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(adf.read_file_contents(second).unwrap(), b"second");
+        assert_eq!(adf.lookup_entry(root, "filebb").unwrap(), None);
+        assert_eq!(
+            adf.extract_file("fileba").unwrap().as_string().unwrap(),
+            "second"
+        );
+        assert_eq!(adf.list_root_directory().unwrap().len(), 2);
+        assert!(adf.find_entry("FILEAN").is_ok());
+
+        // Latin-1 letters only fold on international volumes.
+        adf.write_file("caf\u{e9}", b"latin").unwrap();
+        assert_eq!(
+            adf.read_file("CAF\u{c9}").unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        let intl = FormatOptions {
+            intl: true,
+            ..FormatOptions::default()
+        };
+        adf.format_with_options(DiskType::FFS, "Intl", &intl)
+            .unwrap();
+        adf.write_file("caf\u{e9}", b"latin").unwrap();
+        assert_eq!(adf.read_file("CAF\u{c9}").unwrap(), b"latin");
+    }
+
+    #[test]
+    fn test_data_blocks_across_extension_blocks() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "TestDisk").unwrap();
+        let contents: Vec<u8> = (0..100_000).map(|i| (i % 253) as u8).collect();
+        adf.write_file("big", &contents).unwrap();
+
+        // 196 blocks need the header table and two extension blocks.
+        let blocks = adf.data_blocks("big").unwrap();
+        assert_eq!(blocks.len(), contents.len().div_ceil(ADF_SECTOR_SIZE));
+        let mut joined: Vec<u8> = blocks
+            .iter()
+            .flat_map(|&block| adf.read_sector(block).to_vec())
+            .collect();
+        joined.truncate(contents.len());
+        assert_eq!(joined, contents);
+        assert_eq!(adf.read_file("big").unwrap(), contents);
+        assert!(adf.data_blocks("").is_err());
+    }
+
+    #[test]
+    fn test_hdf_large_blocks() {
+        // A 64 block FFS volume with 1024 byte blocks: root 32, bitmap 33, one file at 34
+        // whose data is in 35.
+        let layout = Layout::new(1024, 64);
+        let mut data = vec![0u8; layout.block_size * layout.blocks];
+        data[..4].copy_from_slice(b"DOS\x01");
+        let contents = b"hello from a big block";
+        let block = |n: usize| n * layout.block_size..(n + 1) * layout.block_size;
+        let set = |data: &mut Vec<u8>, n: usize, offset: usize, value: u32| {
+            let start = n * layout.block_size + offset;
+            data[start..start + 4].copy_from_slice(&value.to_be_bytes());
+        };
+        let name = |data: &mut Vec<u8>, n: usize, name: &[u8]| {
+            let start = n * layout.block_size + layout.name();
+            data[start] = name.len() as u8;
+            data[start + 1..start + 1 + name.len()].copy_from_slice(name);
+        };
+
+        set(&mut data, 32, 0, 2);
+        set(&mut data, 32, 12, layout.hash_table_size() as u32);
+        set(
+            &mut data,
+            32,
+            24 + hash_name_for(b"Greeting", false, layout.hash_table_size()) * 4,
+            34,
+        );
+        set(&mut data, 32, layout.bm_flag(), u32::MAX);
+        set(&mut data, 32, layout.bm_pages(), 33);
+        set(&mut data, 32, layout.sec_type(), 1);
+        name(&mut data, 32, b"BigBlocks");
+
+        set(&mut data, 34, 0, 2);
+        set(&mut data, 34, 4, 34);
+        set(&mut data, 34, 8, 1);
+        set(&mut data, 34, 16, 35);
+        set(&mut data, 34, 24 + (layout.hash_table_size() - 1) * 4, 35);
+        set(&mut data, 34, layout.byte_size(), contents.len() as u32);
+        set(&mut data, 34, layout.parent(), 32);
+        set(&mut data, 34, layout.sec_type(), (-3i32) as u32);
+        name(&mut data, 34, b"Greeting");
+        let start = 35 * layout.block_size;
+        data[start..start + contents.len()].copy_from_slice(contents);
+
+        // Blocks 2 to 63 are mapped; all but 32 to 35 are free.
+        let bitmap = (((1u64 << 62) - 1) & !(0b1111 << 30)).to_be_bytes();
+        let start = 33 * layout.block_size;
+        data[start + 4..start + 8].copy_from_slice(&bitmap[4..]);
+        data[start + 8..start + 12].copy_from_slice(&bitmap[..4]);
+        for n in [32, 34] {
+            let range = block(n);
+            let checksum = normal_checksum(&data[range]);
+            set(&mut data, n, 20, checksum);
+        }
+        let range = block(33);
+        let checksum = bitmap_checksum(&data[range]);
+        set(&mut data, 33, 0, checksum);
+
+        let hdf = Hdf::from_bytes(data.clone()).unwrap();
+        assert_eq!(hdf.block_size(), 1024);
+        assert_eq!(hdf.root_block(), 32);
+        assert!(hdf.is_ffs());
+        assert_eq!(hdf.label().unwrap(), "BigBlocks");
+        let entries = hdf.list_directory("").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "Greeting");
+        assert_eq!(hdf.read_file("greeting").unwrap(), contents);
+        assert_eq!(hdf.free_blocks().unwrap(), 58);
+        assert!(hdf.into_adf().is_err());
+        assert!(Hdf::with_block_size(std::io::Cursor::new(data.clone()), 3000).is_err());
+
+        let path = std::env::temp_dir().join("adflib_hdf_big_blocks.hdf");
+        std::fs::write(&path, &data).unwrap();
+        let hdf = Hdf::from_file(&path).unwrap();
+        assert_eq!(hdf.block_size(), 1024);
+        assert_eq!(hdf.read_file("Greeting").unwrap(), contents);
+        drop(hdf);
+        std::fs::write(&path, &data[..data.len() / 2]).unwrap();
+        assert!(Hdf::from_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let mut adf = ADF::new(4096, ADF_SECTOR_SIZE);
+        adf.format(DiskType::FFS, "Work").unwrap();
+        let hdf = Hdf::from_bytes(adf.data.clone()).unwrap();
+        assert_eq!(hdf.block_size(), ADF_SECTOR_SIZE);
+        assert_eq!(
+            hdf.free_blocks().unwrap(),
+            adf.bitmap_counts().free_blocks as usize
+        );
+        assert!(hdf.into_adf().is_ok());
+        assert!(Hdf::from_bytes(vec![0; 8192]).is_err());
+    }
+
+    // Packs (value, bit count) fields most significant bit first, as the DMS LZ modes read.
+    fn pack_bits(fields: &[(u32, u32)]) -> Vec<u8> {
+        let mut bits = Vec::new();
+        for &(value, count) in fields {
+            bits.extend((0..count).rev().map(|bit| (value >> bit) & 1 == 1));
+        }
+        bits.chunks(8)
+            .map(|byte| {
+                byte.iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (i, &bit)| acc | (u8::from(bit) << (7 - i)))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dms_quick_and_medium() {
+        use crate::dms::{unpack_track, DmsTrackHeader};
+        let mut unpacker = TrackUnpacker::new();
+        // Literals A and B, then three bytes from two back.
+        let quick = pack_bits(&[
+            (1, 1),
+            (b'A'.into(), 8),
+            (1, 1),
+            (b'B'.into(), 8),
+            (0, 1),
+            (1, 2),
+            (1, 8),
+        ]);
+        assert_eq!(unpacker.unpack(2, 1, &quick, 5, 5).unwrap(), b"ABABA");
+        // The window is kept, and moved on by 5: ABABA now starts 10 bytes back.
+        let repeat = pack_bits(&[(0, 1), (3, 2), (9, 8)]);
+        assert_eq!(unpacker.unpack(2, 0, &repeat, 5, 5).unwrap(), b"ABABA");
+        assert_eq!(unpacker.unpack(2, 0, &repeat, 5, 5).unwrap(), [0; 5]);
+
+        // Offset and length come from the D_CODE table: code 0 means length 3, then code 0
+        // again for the high offset bits and 3 more bits for the low ones. The LZ stage ends
+        // with an RLE run of four Zs.
+        let medium = pack_bits(&[
+            (1, 1),
+            (b'X'.into(), 8),
+            (1, 1),
+            (b'Y'.into(), 8),
+            (0, 1),
+            (0, 8),
+            (0, 3),
+            (1, 3),
+            (1, 1),
+            (0x90, 8),
+            (1, 1),
+            (4, 8),
+            (1, 1),
+            (b'Z'.into(), 8),
+        ]);
+        assert_eq!(unpacker.unpack(3, 0, &medium, 8, 9).unwrap(), b"XYXYXZZZZ");
+
+        let header = DmsTrackHeader {
+            mode: 3,
+            raw_len: 8,
+            unpacked_len: 9,
+            ..DmsTrackHeader::default()
+        };
+        assert_eq!(unpack_track(&header, &medium).unwrap(), b"XYXYXZZZZ");
+        let header = DmsTrackHeader { mode: 9, ..header };
+        assert_eq!(
+            unpack_track(&header, &medium).unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    fn test_dms_deep() {
+        use crate::raw::DeepTree;
+        // Codes symbols with a copy of the decoder's tree: the path from the leaf up to the
+        // root, one bit per level telling whether a node is the second child.
+        fn encode(tree: &mut DeepTree, symbol: usize, fields: &mut Vec<(u32, u32)>) {
+            let nodes = tree.son.len();
+            let mut path = Vec::new();
+            let mut node = tree.prnt[symbol + nodes];
+            while node != nodes - 1 {
+                let parent = tree.prnt[node];
+                path.push((u32::from(node != tree.son[parent]), 1));
+                node = parent;
+            }
+            fields.extend(path.into_iter().rev());
+            tree.update(symbol);
+        }
+
+        let mut tree = DeepTree::new();
+        let mut fields = Vec::new();
+        for &byte in b"DEEP" {
+            encode(&mut tree, byte.into(), &mut fields);
+        }
+        // A match of four bytes, four back: offset 3, its high bits code 0 and 3 low bits.
+        encode(&mut tree, 4 + 253, &mut fields);
+        fields.extend([(0, 8), (3, 3)]);
+        let mut unpacker = TrackUnpacker::new();
+        let packed = pack_bits(&fields);
+        assert_eq!(unpacker.unpack(4, 0, &packed, 8, 8).unwrap(), b"DEEPDEEP");
+
+        // Long enough for the tree to be rebuilt with halved counts on the way; no byte is
+        // an RLE marker, so the RLE stage passes the data through.
+        let contents: Vec<u8> = (0..40_000u32).map(|i| (i * i % 127) as u8).collect();
+        let mut tree = DeepTree::new();
+        let mut fields = Vec::new();
+        for &byte in &contents {
+            encode(&mut tree, byte.into(), &mut fields);
+        }
+        let packed = pack_bits(&fields);
+        let mut unpacker = TrackUnpacker::new();
+        let unpacked = unpacker.unpack(4, 0, &packed, contents.len(), contents.len());
+        assert_eq!(unpacked.unwrap(), contents);
+    }
+
+    #[test]
+    fn test_dms_heavy() {
+        // H, V and Y as literals and a match of 4 (symbol 257) each get a 2-bit code, in
+        // symbol order: H 00, V 01, Y 10, 257 11. Offset bit lengths 0 and 1 get 0 and 1.
+        let mut fields = vec![(258, 9)];
+        fields.extend((0..258).map(|symbol| match symbol {
+            72 | 86 | 89 | 257 => (2, 5),
+            _ => (0, 5),
+        }));
+        fields.extend([(2, 5), (1, 4), (1, 4)]);
+        // H V, then four bytes from offset 1, which has bit length 1.
+        fields.extend([(0b00, 2), (0b01, 2), (0b11, 2), (1, 1)]);
+        let packed = pack_bits(&fields);
+        let mut unpacker = TrackUnpacker::new();
+        assert_eq!(unpacker.unpack(5, 2 | 1, &packed, 6, 6).unwrap(), b"HVHVHV");
+
+        // Without bit 1 the next track reuses the codes.
+        let packed = pack_bits(&[(0b10, 2), (0b00, 2)]);
+        assert_eq!(unpacker.unpack(5, 0, &packed, 2, 2).unwrap(), b"YH");
+        // A reset keeps them too, as DMS does.
+        assert_eq!(unpacker.unpack(6, 0, &packed, 2, 2).unwrap(), b"YH");
+
+        // Bit 2 runs the output through RLE: 0x90 4 Z, coded 0 10 11, is a run of four Zs.
+        // The offset code
+        // is a single symbol, sent as a zero count and the symbol.
+        let mut fields = vec![(145, 9)];
+        fields.extend((0..145).map(|symbol| match symbol {
+            144 => (1, 5),
+            4 | 90 => (2, 5),
+            _ => (0, 5),
+        }));
+        fields.extend([(0, 5), (0, 5), (0b0, 1), (0b10, 2), (0b11, 2)]);
+        let packed = pack_bits(&fields);
+        assert_eq!(unpacker.unpack(5, 2 | 4, &packed, 3, 4).unwrap(), b"ZZZZ");
+
+        // Codes longer than the 12-bit table continue in the tree: symbols 0 to 12 get
+        // lengths 1 to 13 and symbol 13 length 13 as well, so 12 is twelve ones and a zero.
+        let mut fields = vec![(14, 9)];
+        fields.extend((0..14).map(|symbol: u32| (symbol.min(12) + 1, 5)));
+        fields.extend([(0, 5), (0, 5), (0x1fff, 13), (0x1ffe, 13), (0, 1)]);
+        let packed = pack_bits(&fields);
+        assert_eq!(unpacker.unpack(5, 2, &packed, 3, 3).unwrap(), [13, 12, 0]);
+
+        // Lengths that leave part of the code unused are rejected.
+        let packed = pack_bits(&[(1, 9), (1, 5)]);
+        assert!(unpacker.unpack(5, 2, &packed, 3, 3).is_err());
+    }
+
+    #[test]
+    fn test_dms_salvage() {
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        adf.write_file("Readme", &[0x90; 1000]).unwrap();
+        let dms = adf.to_dms(DmsMode::Rle).unwrap();
+        let (image, reports) = salvage_dms(&dms).unwrap();
+        assert_eq!(image, adf.data);
+        assert!(reports.is_empty());
+
+        let mut corrupt = dms.clone();
+        corrupt[DMS_HEADER_SIZE + DMS_TRACK_HEADER_SIZE] ^= 0xff;
+        assert!(unpack_dms(&corrupt).is_err());
+        let (image, reports) = salvage_dms(&corrupt).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].number, 0);
+        assert_eq!(reports[0].status, TrackStatus::Corrupt);
+        assert!(image[..DMS_TRACK_SIZE].iter().all(|&b| b == 0));
+        assert_eq!(image[DMS_TRACK_SIZE..], adf.data[DMS_TRACK_SIZE..]);
+
+        assert!(salvage_dms(&adf.data).is_err());
+    }
+
+    #[test]
+    fn test_dms_track_placement() {
+        use crate::dms::{track_checksum, DmsTrackHeader};
+
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        adf.write_file("Readme", &[0x90; 30_000]).unwrap();
+        let dms = pack_dms(&adf.data, DmsMode::Rle, UNIX_EPOCH).unwrap();
+        let mut records = Vec::new();
+        let mut offset = DMS_HEADER_SIZE;
+        while offset < dms.len() {
+            let header = DmsTrackHeader::from_bytes(&dms[offset..]).unwrap();
+            let end = offset + DMS_TRACK_HEADER_SIZE + header.packed_len as usize;
+            records.push(dms[offset..end].to_vec());
+            offset = end;
+        }
+        let archive = |high: u16, tracks: &[&Vec<u8>]| {
+            let mut header = dms[..DMS_HEADER_SIZE].to_vec();
+            header[18..20].copy_from_slice(&high.to_be_bytes());
+            let size = (u32::from(high) + 1) * DMS_TRACK_SIZE as u32;
+            header[24..28].copy_from_slice(&size.to_be_bytes());
+            let crc = crc16(&header[4..54]).to_be_bytes();
+            header[54..56].copy_from_slice(&crc);
+            tracks.iter().fold(header, |mut dms, track| {
+                dms.extend_from_slice(track);
+                dms
+            })
+        };
+
+        let reversed: Vec<_> = records.iter().rev().collect();
+        assert_eq!(unpack_dms(&archive(79, &reversed)).unwrap(), adf.data);
+
+        let fake = vec![0x4e; 1024];
+        let mut fake_track = DmsTrackHeader {
+            number: 0,
+            packed_len: 1024,
+            raw_len: 1024,
+            unpacked_len: 1024,
+            flags: 0,
+            mode: 0,
+            checksum: track_checksum(&fake),
+            data_crc: crc16(&fake),
+            header_crc: 0,
+            computed_header_crc: 0,
+        }
+        .with_header_crc()
+        .to_bytes()
+        .to_vec();
+        fake_track.extend_from_slice(&fake);
+        let first_half: Vec<_> = std::iter::once(&fake_track).chain(&records[..40]).collect();
+        let image = unpack_dms(&archive(39, &first_half)).unwrap();
+        let half = 40 * DMS_TRACK_SIZE;
+        assert_eq!(image.len(), adf.data.len());
+        assert_eq!(image[..half], adf.data[..half]);
+        assert!(image[half..].iter().all(|&b| b == 0));
+
+        let all: Vec<_> = records.iter().collect();
+        assert!(unpack_dms(&archive(39, &all)).is_err());
+        let twice: Vec<_> = records.iter().chain(&records[..1]).collect();
+        assert!(unpack_dms(&archive(79, &twice)).is_err());
+        assert!(unpack_dms(&archive(80, &all)).is_err());
+        assert!(unpack_dms(&archive(79, &[&fake_track])).is_err());
+        let (image, reports) = salvage_dms(&archive(79, &twice)).unwrap();
+        assert_eq!(image, adf.data);
+        assert_eq!(reports.len(), 1);
+    }
+
+    // QUICK fields for a track of literals that differ per number, all below the RLE marker,
+    // which QUICK output is scanned for.
+    fn quick_literals(number: u16) -> Vec<(u32, u32)> {
+        (0..DMS_TRACK_SIZE as u32)
+            .flat_map(|i| [(1, 1), ((i * 7 + u32::from(number)) % 128, 8)])
+            .collect()
+    }
+
+    // QUICK fields for a track copied from 256 bytes back, so it cannot be unpacked without
+    // the window of the track before it.
+    fn quick_copies() -> Vec<(u32, u32)> {
+        let copies = (0..DMS_TRACK_SIZE / 5).flat_map(|_| [(0, 1), (3, 2), (255, 8)]);
+        copies.chain([(0, 1), (2, 2), (255, 8)]).collect()
+    }
+
+    // The number, flags and QUICK fields of a track.
+    type QuickTrack = (u16, u8, Vec<(u32, u32)>);
+
+    // The bytes QUICK fields unpack to: one per literal, two more than the code per copy.
+    fn quick_len(fields: &[(u32, u32)]) -> usize {
+        let literals = fields.iter().filter(|&&field| field == (1, 1)).count();
+        let copies: u32 = fields
+            .iter()
+            .filter(|&&(_, bits)| bits == 2)
+            .map(|&(code, _)| code + 2)
+            .sum();
+        literals + copies as usize
+    }
+
+    // A QUICK archive of (number, flags, fields) tracks with the given info flags. The
+    // checksums come from unpacking the disk tracks in order as the archive says; the others
+    // unpack on their own.
+    fn quick_archive(info_flags: u32, tracks: &[QuickTrack]) -> Vec<u8> {
+        use crate::dms::{track_checksum, DmsTrackHeader};
+        let mut archive = pack_dms(
+            &[0; ADF_NUM_SECTORS * ADF_SECTOR_SIZE],
+            DmsMode::None,
+            UNIX_EPOCH,
+        )
+        .unwrap()[..DMS_HEADER_SIZE]
+            .to_vec();
+        archive[8..12].copy_from_slice(&info_flags.to_be_bytes());
+        let crc = crc16(&archive[4..54]);
+        archive[54..56].copy_from_slice(&crc.to_be_bytes());
+        let mut unpacker = crate::raw::TrackUnpacker::new();
+        for (number, flags, fields) in tracks {
+            let packed = pack_bits(fields);
+            let len = quick_len(fields);
+            let mut own = crate::raw::TrackUnpacker::new();
+            let track = match *number < 80 && len > 2048 {
+                true => &mut unpacker,
+                false => &mut own,
+            }
+            .unpack(2, *flags, &packed, len, len)
+            .unwrap();
+            let header = DmsTrackHeader {
+                number: *number,
+                packed_len: packed.len() as u16,
+                raw_len: len as u16,
+                unpacked_len: len as u16,
+                flags: *flags,
+                mode: 2,
+                checksum: track_checksum(&track),
+                data_crc: crc16(&packed),
+                ..DmsTrackHeader::default()
+            };
+            archive.extend_from_slice(&header.with_header_crc().to_bytes());
+            archive.extend_from_slice(&packed);
+        }
+        archive
+    }
+
+    #[test]
+    fn test_dms_reader() {
+        use crate::dms::DmsReader;
+        use std::io::Cursor;
+
+        // Even tracks are literals keeping the window, odd ones copy from it.
+        let tracks: Vec<_> = (0..DMS_CYLINDERS as u16)
+            .filter(|&number| number != 5)
+            .map(|number| match number % 2 {
+                0 => (number, 1, quick_literals(number)),
+                _ => (number, 0, quick_copies()),
+            })
+            .collect();
+        let archive = quick_archive(0, &tracks);
+        let image = unpack_dms(&archive).unwrap();
+
+        let mut reader = DmsReader::new(Cursor::new(&archive)).unwrap();
+        assert_eq!(reader.num_sectors(), ADF_NUM_SECTORS);
+        let sectors_per_track = DMS_TRACK_SIZE / ADF_SECTOR_SIZE;
+        let order = [3 * sectors_per_track + 7, 0, 5 * sectors_per_track, 1500]
+            .into_iter()
+            .chain((0..ADF_NUM_SECTORS).rev());
+        for sector in order {
+            let offset = sector * ADF_SECTOR_SIZE;
+            assert_eq!(
+                reader.read_sector(sector).unwrap(),
+                &image[offset..offset + ADF_SECTOR_SIZE],
+                "sector {}",
+                sector
+            );
+        }
+        assert!(reader.read_track(5).unwrap().iter().all(|&b| b == 0));
+        assert!(reader.read_sector(ADF_NUM_SECTORS).is_err());
+        assert_eq!(reader.header().high_track, 79);
+    }
+
+    #[test]
+    fn test_comment_latin1() {
+        use crate::disk::MAX_COMMENT_LEN;
+        let comment = |adf: &ADF| {
+            adf.list_root_directory()
+                .unwrap()
+                .into_iter()
+                .find(|info| info.name == "a")
+                .unwrap()
+                .comment
+        };
+        let mut adf = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        adf.format(DiskType::OFS, "TestDisk").unwrap();
+        adf.write_file("a", b"x").unwrap();
+        adf.set_comment("a", "Café crème").unwrap();
+        assert_eq!(comment(&adf), "Café crème");
+
+        let mut copy = ADF::new(ADF_NUM_SECTORS, ADF_SECTOR_SIZE);
+        copy.format(DiskType::OFS, "Copy").unwrap();
+        copy.copy_from(&adf, "a", "a").unwrap();
+        assert_eq!(comment(&copy), "Café crème");
+
+        let longest = "é".repeat(MAX_COMMENT_LEN);
+        adf.set_comment("a", &longest).unwrap();
+        assert_eq!(comment(&adf), longest);
+        assert!(adf
+            .set_comment("a", &"é".repeat(MAX_COMMENT_LEN + 1))
+            .is_err());
+        assert!(adf.set_comment("a", "a → b").is_err());
+        assert!(!adf.validate().has_problems());
+    }
+
+    #[test]
+    fn test_device_malformed_partitions() {
+        use crate::device::VolumeInfo;
+        let path = std::env::temp_dir().join(format!("adflib-bad-rdb-{}.hdf", std::process::id()));
+        let write_rdb = |cylinders: u32, low_cyl: u32, high_cyl: u32| {
+            let rigid_disk = RigidDisk {
+                block_bytes: ADF_SECTOR_SIZE as u32,
+                partition_list: 1,
+                cylinders,
+                sectors: 32,
+                heads: 1,
+            };
+            let partition = Partition {
+                next: RDB_END,
+                drive_name: "DH0".to_string(),
+                size_block: 128,
+                surfaces: 1,
+                blocks_per_track: 32,
+                reserved: 2,
+                low_cyl,
+                high_cyl,
+                dos_type: 0x444f5301,
+            };
+            let mut file = std::fs::File::create(&path).unwrap();
+            file.write_all(&rigid_disk.to_bytes()).unwrap();
+            file.write_all(&partition.to_bytes()).unwrap();
+            file.set_len(u64::from(cylinders) * 32 * ADF_SECTOR_SIZE as u64)
+                .unwrap();
+        };
+
+        // A partition ending before it starts has no blocks.
+        write_rdb(81, 40, 39);
+        let error = Device::open(&path).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let empty = VolumeInfo {
+            device_name: None,
+            first_block: 0,
+            blocks: 0,
+            dos_type: 0,
+            label: String::new(),
+        };
+        assert_eq!(empty.last_block(), None);
+
+        let cylinders = (MAX_VOLUME_BLOCKS / 32 + 2) as u32;
+        write_rdb(cylinders, 1, cylinders - 1);
+        let device = Device::open(&path).unwrap();
+        assert!(device.volumes()[0].blocks > MAX_VOLUME_BLOCKS);
+        let error = device.mount(0).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+        assert!(error.to_string().contains("too large"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_block_roundtrip_1024() {
+        use crate::layout::T_HEADER;
+        let layout = Layout::new(1024, 4000);
+        let table_size = layout.hash_table_size();
+        assert_eq!(table_size, 200);
+
+        let mut hash_table = vec![BlockPtr::NULL; table_size];
+        hash_table[table_size - 1] = BlockPtr::new(2100);
+        let root = RootBlock {
+            block_type: T_HEADER,
+            hash_table_size: table_size as u32,
+            hash_table,
+            bm_flag: 0xffff_ffff,
+            bm_pages: vec![BlockPtr::new(2001); layout.root_bitmap_pages()],
+            bm_ext: BlockPtr::new(2002),
+            name: "Large".to_string(),
+            extension: BlockPtr::new(7),
+            sec_type: SecType::Root,
+            ..RootBlock::default()
+        };
+        let bytes = root.to_bytes(&layout);
+        assert_eq!(bytes.len(), 1024);
+        assert_eq!(bytes[layout.name()], 5);
+        assert_eq!(&bytes[layout.name() + 1..layout.name() + 6], b"Large");
+        assert_eq!(read_u32_be(&bytes, layout.sec_type()), 1);
+        assert_eq!(read_u32_be(&bytes, layout.bm_ext()), 2002);
+        let parsed = RootBlock::from_bytes(&bytes, &layout).unwrap();
+        assert_eq!(parsed.hash_table, root.hash_table);
+        assert_eq!(
+            (parsed.name.as_str(), parsed.bm_ext),
+            ("Large", root.bm_ext)
+        );
+        assert_eq!(parsed.to_bytes(&layout), bytes);
+        assert!(RootBlock::from_bytes(&bytes[..512], &layout).is_err());
+
+        let blocks: Vec<usize> = (3000..3000 + table_size).collect();
+        let mut header = HeaderBlock {
+            block_type: T_HEADER,
+            header_key: BlockPtr::new(2100),
+            byte_size: 200_000,
+            comment: "Café".to_string(),
+            name: "Data".to_string(),
+            parent: BlockPtr::new(2000),
+            sec_type: SecType::File,
+            ..HeaderBlock::default()
+        };
+        header.set_data_blocks(&layout, &blocks);
+        let bytes = header.to_bytes(&layout);
+        assert_eq!(read_u32_be(&bytes, layout.byte_size()), 200_000);
+        assert_eq!(read_u32_be(&bytes, layout.parent()), 2000);
+        let parsed = HeaderBlock::from_bytes(&bytes, &layout).unwrap();
+        assert_eq!(
+            (parsed.name.as_str(), parsed.comment.as_str()),
+            ("Data", "Café")
+        );
+        assert_eq!(parsed.data_blocks()[0], BlockPtr::new(3000));
+        assert_eq!(parsed.data_blocks().len(), table_size);
+        assert_eq!(parsed.to_bytes(&layout), bytes);
+
+        let extension = FileExtBlock::new(&layout, 2200, 2100, &blocks[..3]);
+        let parsed = FileExtBlock::from_bytes(&extension.to_bytes(&layout), &layout).unwrap();
+        assert_eq!(parsed.data_blocks(), extension.data_blocks());
+        assert_eq!(parsed.parent, BlockPtr::new(2100));
+
+        let link = LinkBlock {
+            target: "Work:Data".to_string(),
+            name: "Link".to_string(),
+            sec_type: SecType::SoftLink,
+            ..LinkBlock::default()
+        };
+        let parsed = LinkBlock::from_bytes(&link.to_bytes(&layout), &layout).unwrap();
+        assert_eq!(
+            (parsed.target.as_str(), parsed.name.as_str()),
+            ("Work:Data", "Link")
+        );
+
+        let data = DataBlock {
+            data_size: 1000,
+            data: vec![0x42; 1000],
+            ..DataBlock::default()
+        };
+        let parsed = DataBlock::from_bytes(&data.to_bytes(&layout), &layout).unwrap();
+        assert_eq!(parsed.payload(), &[0x42; 1000][..]);
+
+        let mut page = BitmapBlock::default();
+        page.set_free(8000, true);
+        let parsed = BitmapBlock::from_bytes(&page.to_bytes(&layout), &layout).unwrap();
+        assert_eq!(parsed.map.len(), 255);
+        assert!(parsed.is_free(8000) && !parsed.is_free(7999));
+    }
+
+    #[test]
+    fn test_bitmap_extension_blocks() {
+        // 110000 blocks need 28 bitmap pages: 3 to 27 from the root, 28 to 30 from the
+        // extension block at 2.
+        let layout = Layout::new(ADF_SECTOR_SIZE, 110000);
+        assert_eq!(layout.bitmap_pages(), 28);
+        let mut data = vec![0u8; layout.block_size * layout.blocks];
+        data[..4].copy_from_slice(b"DOS\x01");
+        let root = layout.root_block();
+        let set = |data: &mut Vec<u8>, n: usize, offset: usize, value: u32| {
+            let start = n * layout.block_size + offset;
+            data[start..start + 4].copy_from_slice(&value.to_be_bytes());
+        };
+        set(&mut data, root, 0, 2);
+        set(&mut data, root, 12, layout.hash_table_size() as u32);
+        set(&mut data, root, layout.bm_flag(), u32::MAX);
+        set(&mut data, root, layout.sec_type(), 1);
+        for (index, page) in (3..28).enumerate() {
+            set(&mut data, root, layout.bm_pages() + index * 4, page);
+        }
+        set(&mut data, root, layout.bm_ext(), 2);
+        for (index, page) in (28..31).enumerate() {
+            set(&mut data, 2, index * 4, page);
+        }
+        for page in 3..31 {
+            let start = page * layout.block_size;
+            data[start + 4..start + layout.block_size].fill(0xff);
+        }
+        // Five blocks in use on the last page, which only the extension block lists.
+        set(&mut data, 30, 4, !0b11111);
+        let checksum =
+            normal_checksum(&data[root * layout.block_size..(root + 1) * layout.block_size]);
+        set(&mut data, root, 20, checksum);
+
+        let image = Image::new(&data).unwrap();
+        assert_eq!(image.bitmap_pages().unwrap(), (3..31).collect::<Vec<_>>());
+        assert_eq!(image.free_blocks().unwrap(), 110000 - 2 - 5);
+        let hdf = Hdf::from_bytes(data.clone()).unwrap();
+        assert_eq!(hdf.free_blocks().unwrap(), 110000 - 2 - 5);
+        assert!(hdf.into_adf().is_err());
+
+        // An extension chain that loops back on itself is corrupt rather than endless.
+        for index in 0..ADF_SECTOR_SIZE / 4 - 1 {
+            set(&mut data, 2, index * 4, 30);
+        }
+        set(&mut data, 2, ADF_SECTOR_SIZE - 4, 2);
+        let image = Image::new(&data).unwrap();
+        assert_eq!(image.free_blocks(), Err(RawError::Corrupt(2)));
+    }
+
+    // Archives in tests/data: every track must match the checksum in its header, and the
+    // tracks the SHA-256 that the reference decoder gives for them.
+    #[test]
+    fn test_dms_fixtures() {
+        use crate::dms::{read_dms_extras, track_checksum};
+        use crate::manifest::HashAlgo;
+        let fixtures: [(&[u8], &[u16], &str); 8] = [
+            (
+                include_bytes!("../tests/data/rle_small.dms"),
+                &[0xf734, 0xf71c],
+                "d032deb4a5008b234b68968699ab2a897250f0f32eeae0aea4b23fa99049f98c",
+            ),
+            (
+                include_bytes!("../tests/data/quick.dms"),
+                &[0x7262, 0x3b2a],
+                "2e58773530d5d9379d45da3cbce5b214e3395b07faab1ffb555bfa943291bb56",
+            ),
+            (
+                include_bytes!("../tests/data/medium.dms"),
+                &[0xf042, 0x2e9c],
+                "11a04094d4f649d5a2d67435c23cc2ca52e12ce02e02c258ed986de4cb5d4029",
+            ),
+            (
+                include_bytes!("../tests/data/deep.dms"),
+                &[0x49f8, 0xfec4],
+                "ead3271a8af179d0a61789487556ea7e86a67edce7407474c66deb5279013e17",
+            ),
+            (
+                include_bytes!("../tests/data/heavy1.dms"),
+                &[0x1c51, 0x22a3],
+                "636c848d085a1363ee16bfbe8f7fbe0596b2050036d97095fcdb30a3a785e5ac",
+            ),
+            (
+                include_bytes!("../tests/data/heavy2.dms"),
+                &[0x2995, 0xcce9],
+                "97dc85aef716d69fe39ddc80126fe7ac38bdb2923f94e5f48b71215bd90187ba",
+            ),
+            (
+                include_bytes!("../tests/data/heavy1_reset.dms"),
+                &[0xa9a8, 0x4335, 0x52df],
+                "33e9d9588745c6290869da0d5f4ea95f460b2e0261cc3c30dbb820ede51d6bff",
+            ),
+            (
+                include_bytes!("../tests/data/heavy2_reset.dms"),
+                &[0x0338, 0x5cbc, 0x1370],
+                "76599a5177aeb4a0227e29b7d331d71761b5aeca37293389c29ee5cceb2b9fb8",
+            ),
+        ];
+        for (dms, checksums, sha256) in fixtures {
+            let reports = verify_dms(dms).unwrap();
+            assert!(reports
+                .iter()
+                .all(|report| report.status == TrackStatus::Ok));
+            let image = unpack_dms(dms).unwrap();
+            let tracks = &image[..checksums.len() * DMS_TRACK_SIZE];
+            for (track, &checksum) in tracks.chunks(DMS_TRACK_SIZE).zip(checksums) {
+                assert_eq!(track_checksum(track), checksum);
+            }
+            assert_eq!(HashAlgo::Sha256.digest(tracks), sha256);
+            assert!(image[tracks.len()..].iter().all(|&b| b == 0));
+        }
+
+        let extras = read_dms_extras(fixtures[0].0).unwrap();
+        assert_eq!(extras.banner_text().as_deref(), Some("Made with adf2dms"));
+        assert_eq!(
+            extras.file_id_diz_text().as_deref(),
+            Some("Small RLE test disk.\nTwo cylinders.")
+        );
+    }
+
+    #[test]
+    fn test_dms_no_zero() {
+        use crate::dms::DmsReader;
+        use std::io::Cursor;
+
+        // NOZERO leaves the window to the track flags: track 0 clears it, so track 1 copies
+        // from an empty window whether the flag is set or not.
+        let tracks = [(0, 0, quick_literals(0)), (1, 0, quick_copies())];
+        let plain = quick_archive(0, &tracks);
+        let no_zero = quick_archive(0x01, &tracks);
+        assert_eq!(parse_header(&no_zero).unwrap().flag_names(), ["NOZERO"]);
+        assert_eq!(plain[DMS_HEADER_SIZE..], no_zero[DMS_HEADER_SIZE..]);
+
+        for archive in [&plain, &no_zero] {
+            let image = unpack_dms(archive).unwrap();
+            assert!(image[DMS_TRACK_SIZE..2 * DMS_TRACK_SIZE]
+                .iter()
+                .all(|&b| b == 0));
+            let reports = verify_dms(archive).unwrap();
+            assert!(reports
+                .iter()
+                .all(|report| report.status == TrackStatus::Ok));
+            let mut reader = DmsReader::new(Cursor::new(archive)).unwrap();
+            assert_eq!(
+                reader.read_track(1).unwrap(),
+                &image[DMS_TRACK_SIZE..2 * DMS_TRACK_SIZE]
+            );
+        }
+    }
+
+    #[test]
+    fn test_dms_salvage_lost_window() {
+        // Tracks 1 and 2 go on from the window of the track before; track 3 starts afresh.
+        let tracks = [
+            (0, 1, quick_literals(0)),
+            (1, 1, quick_copies()),
+            (2, 0, quick_copies()),
+            (3, 0, quick_literals(3)),
+        ];
+        let dms = quick_archive(0, &tracks);
+        let clean = unpack_dms(&dms).unwrap();
+        let mut corrupt = dms.clone();
+        corrupt[DMS_HEADER_SIZE + DMS_TRACK_HEADER_SIZE] ^= 0x40;
+
+        let (image, reports) = salvage_dms(&corrupt).unwrap();
+        let damaged: Vec<_> = reports.iter().map(|report| report.number).collect();
+        assert_eq!(damaged, [0, 1, 2]);
+        assert!(reports
+            .iter()
+            .all(|report| report.status == TrackStatus::Corrupt));
+        assert_eq!(
+            reports[1].detail,
+            "DMS track 1 goes on from damaged track 0"
+        );
+        assert_eq!(
+            reports[2].detail,
+            "DMS track 2 goes on from damaged track 1"
+        );
+        assert!(image[..3 * DMS_TRACK_SIZE].iter().all(|&b| b == 0));
+        assert_eq!(
+            image[3 * DMS_TRACK_SIZE..4 * DMS_TRACK_SIZE],
+            clean[3 * DMS_TRACK_SIZE..4 * DMS_TRACK_SIZE]
+        );
+
+        let reports = verify_dms(&corrupt).unwrap();
+        let statuses: Vec<_> = reports.iter().map(|report| &report.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                &TrackStatus::Corrupt,
+                &TrackStatus::Corrupt,
+                &TrackStatus::Corrupt,
+                &TrackStatus::Ok
+            ]
+        );
+        assert_eq!(reports[0].detail, "packed data CRC mismatch");
+        assert_eq!(
+            reports[1].detail,
+            "DMS track 1 goes on from damaged track 0"
+        );
+    }
+
+    #[test]
+    fn test_dms_text_tracks_keep_window() {
+        use crate::dms::{read_dms_extras, DmsReader};
+        use std::io::Cursor;
+
+        // A banner that keeps its window comes before a track that copies from the window it
+        // starts with, which is empty: the banner must not leave its bytes there.
+        let tracks = [
+            (0xffff, 1, quick_literals(9)),
+            (0, 1, quick_copies()),
+            (1, 0, quick_literals(1)),
+            (80, 1, quick_literals(80)),
+        ];
+        let dms = quick_archive(0x108, &tracks);
+        let image = unpack_dms(&dms).unwrap();
+        assert!(image[..DMS_TRACK_SIZE].iter().all(|&b| b == 0));
+        assert!(verify_dms(&dms)
+            .unwrap()
+            .iter()
+            .all(|report| report.status == TrackStatus::Ok));
+
+        let extras = read_dms_extras(&dms).unwrap();
+        let banner = extras.banner_text().unwrap();
+        assert_eq!(banner.as_bytes()[..3], [9, 16, 23]);
+        let mut reader = DmsReader::new(Cursor::new(&dms)).unwrap();
+        assert_eq!(reader.banner().unwrap(), Some(banner));
+        assert_eq!(reader.file_id_diz().unwrap(), extras.file_id_diz_text());
+        assert!(reader.file_id_diz().unwrap().is_some());
+
+        let dms = quick_archive(0, &tracks[1..3]);
+        let mut reader = DmsReader::new(Cursor::new(&dms)).unwrap();
+        assert_eq!(reader.banner().unwrap(), None);
+        assert_eq!(reader.file_id_diz().unwrap(), None);
+    }
+
+    #[test]
+    fn test_dms_fake_tracks_keep_window() {
+        // A fake track 0 of 1024 bytes that keeps its window comes before the real one, which
+        // copies from the empty window it starts with.
+        let fake = quick_literals(5)[..2 * 1024].to_vec();
+        let tracks = [
+            (0, 1, fake),
+            (0, 1, quick_copies()),
+            (90, 1, quick_literals(90)),
+            (1, 1, quick_copies()),
+        ];
+        let dms = quick_archive(0, &tracks);
+        let image = unpack_dms(&dms).unwrap();
+        assert!(image[..2 * DMS_TRACK_SIZE].iter().all(|&b| b == 0));
+        let (salvaged, reports) = salvage_dms(&dms).unwrap();
+        assert_eq!(salvaged, image);
+        assert!(reports.is_empty());
+        let reports = verify_dms(&dms).unwrap();
+        assert_eq!(reports.len(), 4);
+        assert!(reports
+            .iter()
+            .all(|report| report.status == TrackStatus::Ok));
+    }
+
+    #[test]
+    fn test_dms_reader_skips_extra_tracks() {
+        use crate::dms::DmsReader;
+        use std::io::Cursor;
+
+        // Track 1 goes on from track 0 across a banner and a fake track that clear their
+        // window, and track 2 copies from the empty window after a banner that keeps its own.
+        let tracks = [
+            (0, 1, quick_literals(0)),
+            (0xffff, 0, quick_literals(9)),
+            (0, 0, quick_literals(5)[..2 * 1024].to_vec()),
+            (1, 0, quick_copies()),
+            (0xffff, 1, quick_literals(9)),
+            (2, 0, quick_copies()),
+        ];
+        let dms = quick_archive(0x08, &tracks);
+        let image = unpack_dms(&dms).unwrap();
+        let second = &image[DMS_TRACK_SIZE..2 * DMS_TRACK_SIZE];
+        assert!(second.iter().any(|&b| b != 0));
+        assert!(image[2 * DMS_TRACK_SIZE..3 * DMS_TRACK_SIZE]
+            .iter()
+            .all(|&b| b == 0));
+
+        for order in [[1, 2, 0], [2, 0, 1], [0, 1, 2]] {
+            let mut reader = DmsReader::new(Cursor::new(&dms)).unwrap();
+            for cylinder in order {
+                assert_eq!(
+                    reader.read_track(cylinder).unwrap(),
+                    &image[cylinder * DMS_TRACK_SIZE..(cylinder + 1) * DMS_TRACK_SIZE]
+                );
+            }
+        }
+    }
 }
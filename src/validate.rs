@@ -0,0 +1,555 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+use crate::block::{bitmap_checksum, bootblock_checksum, normal_checksum};
+use crate::cancel::CancelToken;
+use crate::disk::{
+    read_u32_be, write_u32_be, ADF, ADF_SECTOR_SIZE, BLOCK_BM_FLAG, BLOCK_BYTE_SIZE,
+    BLOCK_CHECKSUM, BLOCK_HEADER_KEY, BLOCK_PARENT, BLOCK_SEC_TYPE, BLOCK_TABLE_SIZE, BLOCK_TYPE,
+    OFS_DATA_LEN, OFS_DATA_SIZE, OFS_SEQ_NUM, ST_FILE, ST_ROOT, ST_USERDIR, T_DATA, T_HEADER,
+    T_LIST,
+};
+use serde::Serialize;
+use std::fmt;
+use std::io::Result;
+
+const MAX_LISTED_BLOCKS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "INFO"),
+            Severity::Warning => write!(f, "WARNING"),
+            Severity::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub block: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.block {
+            Some(block) => write!(f, "[{}] block {}: {}", self.severity, block, self.message),
+            None => write!(f, "[{}] {}", self.severity, self.message),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationReport {
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    pub fn has_problems(&self) -> bool {
+        self.findings
+            .iter()
+            .any(|f| f.severity >= Severity::Warning)
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == severity)
+            .count()
+    }
+
+    fn push(&mut self, severity: Severity, block: Option<usize>, message: impl Into<String>) {
+        self.findings.push(Finding {
+            severity,
+            block,
+            message: message.into(),
+        });
+    }
+}
+
+struct Checker<'a> {
+    adf: &'a ADF,
+    progress: &'a mut dyn FnMut(usize, usize),
+    cancel: CancelToken,
+    expected: usize,
+    marked: usize,
+    report: ValidationReport,
+    used: Vec<bool>,
+    checksummed: Vec<usize>,
+    ffs: bool,
+    bootable: bool,
+    walked: bool,
+}
+
+impl<'a> Checker<'a> {
+    fn new(adf: &'a ADF, progress: &'a mut dyn FnMut(usize, usize)) -> Self {
+        let expected = adf
+            .read_allocation_map()
+            .iter()
+            .filter(|&&free| !free)
+            .count();
+        Checker {
+            adf,
+            progress,
+            cancel: CancelToken::default(),
+            expected,
+            marked: 0,
+            report: ValidationReport::default(),
+            used: vec![false; adf.num_blocks()],
+            checksummed: Vec::new(),
+            ffs: false,
+            bootable: false,
+            walked: false,
+        }
+    }
+
+    fn run(mut self) -> Self {
+        if !self.check_boot_block() {
+            return self;
+        }
+        self.used[0] = true;
+        self.used[1] = true;
+        self.used[self.adf.root_block()] = true;
+        if self.check_root_block() {
+            self.walk_directory(self.adf.root_block());
+            self.walked = true;
+            self.check_block_maps();
+        }
+        if self.cancel.is_cancelled() {
+            return self;
+        }
+        self.check_bitmap();
+        let total = self.expected.max(self.marked);
+        (self.progress)(total, total);
+        self
+    }
+
+    fn mark(&mut self, block: usize) -> bool {
+        if self.used[block] {
+            self.report.push(
+                Severity::Error,
+                Some(block),
+                "Block is referenced more than once (cross-linked)",
+            );
+            return false;
+        }
+        self.used[block] = true;
+        self.marked += 1;
+        (self.progress)(self.marked, self.expected.max(self.marked));
+        true
+    }
+
+    // Blocks in the bad block and reserved block maps count as allocated, so the bitmap
+    // check and repair keep them out of allocation.
+    fn check_block_maps(&mut self) {
+        let bad = self.adf.bad_blocks();
+        self.check_block_map(bad, "Bad block map", "marked bad");
+        let reserved = self.adf.reserved_blocks();
+        self.check_block_map(reserved, "Reserved block map", "reserved");
+    }
+
+    fn check_block_map(&mut self, blocks: Result<Vec<usize>>, map: &str, what: &str) {
+        let blocks = match blocks {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                self.report.push(
+                    Severity::Warning,
+                    None,
+                    format!("{} is unreadable: {}", map, e),
+                );
+                return;
+            }
+        };
+        if blocks.is_empty() {
+            return;
+        }
+        self.report.push(
+            Severity::Info,
+            None,
+            format!(
+                "{} blocks are {} ({})",
+                blocks.len(),
+                what,
+                list_blocks(&blocks)
+            ),
+        );
+        for block in blocks {
+            if block >= self.used.len() {
+                self.report.push(
+                    Severity::Warning,
+                    Some(block),
+                    format!("{} lists a block past the end of the volume", map),
+                );
+            } else if self.used[block] {
+                self.report.push(
+                    Severity::Warning,
+                    Some(block),
+                    format!("Block is {} but holds file system data", what),
+                );
+            } else {
+                self.used[block] = true;
+            }
+        }
+    }
+
+    fn check_checksum(&mut self, block: usize, what: &str) {
+        let block_data = self.adf.read_sector(block);
+        if read_u32_be(block_data, BLOCK_CHECKSUM) != normal_checksum(block_data) {
+            self.report.push(
+                Severity::Error,
+                Some(block),
+                format!("{} checksum mismatch", what),
+            );
+        }
+        self.checksummed.push(block);
+    }
+
+    fn check_boot_block(&mut self) -> bool {
+        let boot_block = self.adf.read_boot_block();
+        if &boot_block[0..3] != b"DOS" {
+            self.report.push(
+                Severity::Error,
+                Some(0),
+                "Boot block has no DOS signature, not an AmigaDOS disk",
+            );
+            return false;
+        }
+        if boot_block[3] > 7 {
+            self.report.push(
+                Severity::Warning,
+                Some(0),
+                format!("Unknown DOS type DOS\\{}", boot_block[3]),
+            );
+        }
+        self.ffs = boot_block[3] & 1 == 1;
+        self.bootable = boot_block[12..].iter().any(|&b| b != 0);
+
+        if !self.bootable {
+            self.report
+                .push(Severity::Info, Some(0), "Boot block contains no boot code");
+        } else if read_u32_be(boot_block, 4) != bootblock_checksum(boot_block) {
+            self.report.push(
+                Severity::Warning,
+                Some(0),
+                "Boot block checksum mismatch, the disk will not boot",
+            );
+        }
+        true
+    }
+
+    fn check_root_block(&mut self) -> bool {
+        let root_block = self.adf.read_sector(self.adf.root_block());
+        if read_u32_be(root_block, BLOCK_TYPE) != T_HEADER
+            || read_u32_be(root_block, BLOCK_SEC_TYPE) != ST_ROOT
+        {
+            self.report.push(
+                Severity::Error,
+                Some(self.adf.root_block()),
+                "Root block has an invalid block type",
+            );
+            return false;
+        }
+        if read_u32_be(root_block, BLOCK_TABLE_SIZE) as usize != self.adf.layout().hash_table_size()
+        {
+            self.report.push(
+                Severity::Warning,
+                Some(self.adf.root_block()),
+                format!(
+                    "Unexpected hash table size {}",
+                    read_u32_be(root_block, BLOCK_TABLE_SIZE)
+                ),
+            );
+        }
+        self.check_checksum(self.adf.root_block(), "Root block");
+        true
+    }
+
+    fn walk_directory(&mut self, dir_block: usize) {
+        for entry in self.adf.directory_entries(dir_block) {
+            if self.cancel.is_cancelled() {
+                return;
+            }
+            if !self.mark(entry) {
+                continue;
+            }
+
+            let block_data = self.adf.read_sector(entry);
+            if read_u32_be(block_data, BLOCK_TYPE) != T_HEADER {
+                self.report.push(
+                    Severity::Error,
+                    Some(entry),
+                    "Directory entry is not a header block",
+                );
+                continue;
+            }
+            if read_u32_be(block_data, BLOCK_HEADER_KEY) as usize != entry {
+                self.report.push(
+                    Severity::Error,
+                    Some(entry),
+                    "Header key does not match block",
+                );
+            }
+            if read_u32_be(block_data, BLOCK_PARENT) as usize != dir_block {
+                self.report.push(
+                    Severity::Error,
+                    Some(entry),
+                    format!("Parent pointer does not reference block {}", dir_block),
+                );
+            }
+            self.check_checksum(entry, "Header block");
+
+            match read_u32_be(block_data, BLOCK_SEC_TYPE) {
+                ST_USERDIR => self.walk_directory(entry),
+                ST_FILE => self.check_file(entry),
+                sec_type => self.report.push(
+                    Severity::Error,
+                    Some(entry),
+                    format!("Unknown secondary type {}", sec_type as i32),
+                ),
+            }
+        }
+    }
+
+    fn check_file(&mut self, header: usize) {
+        let (data_blocks, extension_blocks) = match self.adf.file_blocks(header) {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                self.report
+                    .push(Severity::Error, Some(header), e.to_string());
+                return;
+            }
+        };
+
+        for block in extension_blocks {
+            if !self.mark(block) {
+                continue;
+            }
+            let block_data = self.adf.read_sector(block);
+            if read_u32_be(block_data, BLOCK_TYPE) != T_LIST
+                || read_u32_be(block_data, BLOCK_SEC_TYPE) != ST_FILE
+            {
+                self.report.push(
+                    Severity::Error,
+                    Some(block),
+                    "Extension block has an invalid block type",
+                );
+                continue;
+            }
+            if read_u32_be(block_data, BLOCK_PARENT) as usize != header {
+                self.report.push(
+                    Severity::Error,
+                    Some(block),
+                    format!("Extension block does not reference header {}", header),
+                );
+            }
+            self.check_checksum(block, "Extension block");
+        }
+
+        for (i, &block) in data_blocks.iter().enumerate() {
+            if !self.mark(block) || self.ffs {
+                continue;
+            }
+            let block_data = self.adf.read_sector(block);
+            if read_u32_be(block_data, BLOCK_TYPE) != T_DATA
+                || read_u32_be(block_data, BLOCK_HEADER_KEY) as usize != header
+            {
+                self.report.push(
+                    Severity::Error,
+                    Some(block),
+                    format!("Data block does not belong to file header {}", header),
+                );
+                continue;
+            }
+            if read_u32_be(block_data, OFS_SEQ_NUM) as usize != i + 1 {
+                self.report.push(
+                    Severity::Error,
+                    Some(block),
+                    format!(
+                        "Data block has sequence number {}, expected {}",
+                        read_u32_be(block_data, OFS_SEQ_NUM),
+                        i + 1
+                    ),
+                );
+            }
+            if read_u32_be(block_data, OFS_DATA_LEN) as usize > OFS_DATA_SIZE {
+                self.report
+                    .push(Severity::Error, Some(block), "Data block size is too large");
+            }
+            self.check_checksum(block, "Data block");
+        }
+
+        let byte_size = read_u32_be(self.adf.read_sector(header), BLOCK_BYTE_SIZE) as usize;
+        let block_size = if self.ffs {
+            ADF_SECTOR_SIZE
+        } else {
+            OFS_DATA_SIZE
+        };
+        if byte_size.div_ceil(block_size) != data_blocks.len() {
+            self.report.push(
+                Severity::Warning,
+                Some(header),
+                format!(
+                    "File size of {} bytes does not match {} data blocks",
+                    byte_size,
+                    data_blocks.len()
+                ),
+            );
+        }
+    }
+
+    fn check_bitmap(&mut self) {
+        let root_block = self.adf.read_sector(self.adf.root_block());
+        if read_u32_be(root_block, BLOCK_BM_FLAG) != u32::MAX {
+            self.report.push(
+                Severity::Warning,
+                Some(self.adf.root_block()),
+                "Bitmap is flagged as invalid",
+            );
+        }
+
+        let pages = self.adf.bitmap_pages();
+        if pages.is_empty() {
+            self.report.push(
+                Severity::Error,
+                Some(self.adf.root_block()),
+                "No bitmap blocks found",
+            );
+            return;
+        }
+        for page in pages {
+            self.mark(page);
+            let block_data = self.adf.read_sector(page);
+            if read_u32_be(block_data, 0) != bitmap_checksum(block_data) {
+                self.report.push(
+                    Severity::Error,
+                    Some(page),
+                    "Bitmap block checksum mismatch",
+                );
+            }
+        }
+
+        let free = self.adf.read_allocation_map();
+        let marked_free: Vec<usize> = (2..self.adf.num_blocks())
+            .filter(|&b| self.used[b] && free[b])
+            .collect();
+        let unreferenced: Vec<usize> = (2..self.adf.num_blocks())
+            .filter(|&b| !self.used[b] && !free[b])
+            .collect();
+
+        if !marked_free.is_empty() {
+            self.report.push(
+                Severity::Error,
+                None,
+                format!(
+                    "{} blocks in use are marked free in the bitmap ({})",
+                    marked_free.len(),
+                    list_blocks(&marked_free)
+                ),
+            );
+        }
+        if !unreferenced.is_empty() {
+            self.report.push(
+                Severity::Warning,
+                None,
+                format!(
+                    "{} blocks are marked used but not referenced ({})",
+                    unreferenced.len(),
+                    list_blocks(&unreferenced)
+                ),
+            );
+        }
+    }
+}
+
+fn list_blocks(blocks: &[usize]) -> String {
+    let mut listed: Vec<String> = blocks
+        .iter()
+        .take(MAX_LISTED_BLOCKS)
+        .map(|b| b.to_string())
+        .collect();
+    if blocks.len() > MAX_LISTED_BLOCKS {
+        listed.push("...".to_string());
+    }
+    listed.join(", ")
+}
+
+impl ADF {
+    pub fn validate(&self) -> ValidationReport {
+        self.validate_with_progress(&mut |_, _| {})
+    }
+
+    pub fn validate_with_progress(
+        &self,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> ValidationReport {
+        Checker::new(self, progress).run().report
+    }
+
+    // Stops at the next directory entry once cancel is cancelled, returning Interrupted.
+    pub fn validate_cancellable(
+        &self,
+        progress: &mut dyn FnMut(usize, usize),
+        cancel: &CancelToken,
+    ) -> Result<ValidationReport> {
+        let mut checker = Checker::new(self, progress);
+        checker.cancel = cancel.clone();
+        let report = checker.run().report;
+        cancel.check()?;
+        Ok(report)
+    }
+
+    // Blocks the file system references, bad blocks included; None when the directory tree
+    // could not be walked.
+    pub(crate) fn referenced_blocks(&self) -> Option<Vec<bool>> {
+        let mut no_progress = |_, _| {};
+        let checker = Checker::new(self, &mut no_progress).run();
+        checker.walked.then_some(checker.used)
+    }
+
+    pub fn repair(&mut self) -> Result<ValidationReport> {
+        let mut no_progress = |_, _| {};
+        let checker = Checker::new(self, &mut no_progress).run();
+        let checksummed = checker.checksummed;
+        let used = checker.used;
+        let bootable = checker.bootable;
+        let rebuild_bitmap = checker.walked && !self.bitmap_pages().is_empty();
+
+        let mut action = format!("Repaired {} checksums", checksummed.len());
+        for block in checksummed {
+            self.update_block_checksum(block);
+        }
+
+        if bootable {
+            let checksum = bootblock_checksum(self.read_boot_block());
+            write_u32_be(self.data_mut(0..8), 4, checksum);
+        }
+
+        if rebuild_bitmap {
+            self.bitmap = used.iter().map(|&u| !u).collect();
+            let root = self.root_block();
+            write_u32_be(
+                self.data_mut(root * ADF_SECTOR_SIZE..(root + 1) * ADF_SECTOR_SIZE),
+                BLOCK_BM_FLAG,
+                u32::MAX,
+            );
+            self.update_bitmap_blocks()?;
+            self.update_block_checksum(self.root_block());
+            action.push_str(", rebuilt bitmap");
+        }
+        self.note_change(action);
+
+        Ok(self.validate())
+    }
+}
@@ -0,0 +1,137 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023
+// - Volker Schwaberow <volker@schwaberow.de>
+
+// Compares a volume with a reference directory on the host, the check that follows pack or
+// precedes mastering. Names match case-insensitively, as on AmigaDOS; files of the same size
+// are compared by SHA1.
+
+use crate::dat::to_hex;
+use crate::disk::{join_path, ADF};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum MismatchKind {
+    OnlyOnImage,
+    OnlyOnHost,
+    // A file on one side and a directory on the other.
+    Type,
+    Size,
+    Content,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Mismatch {
+    pub path: String,
+    pub kind: MismatchKind,
+    pub image_size: Option<u64>,
+    pub host_size: Option<u64>,
+    pub image_sha1: Option<String>,
+    pub host_sha1: Option<String>,
+}
+
+impl Mismatch {
+    fn new(path: String, kind: MismatchKind) -> Mismatch {
+        Mismatch {
+            path,
+            kind,
+            image_size: None,
+            host_size: None,
+            image_sha1: None,
+            host_sha1: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerifyReport {
+    // Files found identical on both sides.
+    pub matched: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl VerifyReport {
+    pub fn is_match(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+pub fn against_dir<P: AsRef<Path>>(adf: &ADF, host_dir: P) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    compare_dir(adf, "", host_dir.as_ref(), &mut report)?;
+    Ok(report)
+}
+
+fn sha1(data: &[u8]) -> String {
+    to_hex(&Sha1::digest(data))
+}
+
+fn compare_dir(adf: &ADF, dir: &str, host_dir: &Path, report: &mut VerifyReport) -> Result<()> {
+    let mut image: HashMap<String, (usize, String)> = adf
+        .directory_entries(adf.find_directory(dir)?)
+        .into_iter()
+        .map(|block| {
+            let name = adf.entry_name(block);
+            (name.to_lowercase(), (block, name))
+        })
+        .collect();
+
+    let mut entries = fs::read_dir(host_dir)?.collect::<Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let metadata = entry.metadata()?;
+        if !metadata.is_dir() && !metadata.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some((block, image_name)) = image.remove(&name.to_lowercase()) else {
+            let mut mismatch = Mismatch::new(join_path(dir, &name), MismatchKind::OnlyOnHost);
+            mismatch.host_size = metadata.is_file().then_some(metadata.len());
+            report.mismatches.push(mismatch);
+            continue;
+        };
+        let path = join_path(dir, &image_name);
+        let info = adf.read_file_header(block)?;
+        match (info.is_dir, metadata.is_dir()) {
+            (true, true) => compare_dir(adf, &path, &entry.path(), report)?,
+            (false, false) => {
+                let mut mismatch = Mismatch::new(path, MismatchKind::Size);
+                mismatch.image_size = Some(info.size as u64);
+                mismatch.host_size = Some(metadata.len());
+                if info.size as u64 != metadata.len() {
+                    report.mismatches.push(mismatch);
+                    continue;
+                }
+                let image_sha1 = sha1(&adf.read_file_contents(block)?);
+                let host_sha1 = sha1(&fs::read(entry.path())?);
+                if image_sha1 == host_sha1 {
+                    report.matched += 1;
+                    continue;
+                }
+                mismatch.kind = MismatchKind::Content;
+                mismatch.image_sha1 = Some(image_sha1);
+                mismatch.host_sha1 = Some(host_sha1);
+                report.mismatches.push(mismatch);
+            }
+            _ => report
+                .mismatches
+                .push(Mismatch::new(path, MismatchKind::Type)),
+        }
+    }
+
+    let mut only_on_image: Vec<(usize, String)> = image.into_values().collect();
+    only_on_image.sort_by(|a, b| a.1.cmp(&b.1));
+    for (block, name) in only_on_image {
+        let mut mismatch = Mismatch::new(join_path(dir, &name), MismatchKind::OnlyOnImage);
+        let info = adf.read_file_header(block)?;
+        mismatch.image_size = (!info.is_dir).then_some(info.size as u64);
+        report.mismatches.push(mismatch);
+    }
+    Ok(())
+}
@@ -0,0 +1,491 @@
+// Generates the QUICK, MEDIUM, DEEP and HEAVY fixtures in this directory; see README.md.
+// Built as an example of xdms 0.2.0 with its crc, header, decompress and decompress::tables
+// modules made public and Decompressor::encode_deep_symbol added (LZHUF EncodeChar on the
+// decoder's own tree, followed by the same update).
+use std::collections::HashMap;
+use xdms::crc::{checksum16, crc16};
+use xdms::decompress::tables::{D_CODE, D_LEN};
+use xdms::decompress::Decompressor;
+
+const TRACK: usize = 11264;
+
+struct Rng(u64);
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 16) as u32
+    }
+    fn below(&mut self, n: u32) -> u32 {
+        self.next() % n
+    }
+}
+
+#[derive(Default)]
+struct Bits {
+    out: Vec<u8>,
+    acc: u64,
+    n: u32,
+}
+impl Bits {
+    fn put(&mut self, value: u32, n: u32) {
+        if n == 0 {
+            return;
+        }
+        self.acc = (self.acc << n) | (value as u64 & ((1 << n) - 1));
+        self.n += n;
+        while self.n >= 8 {
+            self.n -= 8;
+            self.out.push((self.acc >> self.n) as u8);
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.n > 0 {
+            self.out.push((self.acc << (8 - self.n)) as u8);
+        }
+        self.out
+    }
+}
+
+// Disk-like contents: text, a table of longwords, zero runs, 0x90 bytes and a copy of
+// part of the previous track.
+fn track_data(rng: &mut Rng, previous: Option<&[u8]>) -> Vec<u8> {
+    let words = [
+        "Amiga", "disk", "track", "sector", "volume", "the", "of", "and", "Workbench", "CLI",
+        "startup", "sequence", "library", "device", "handler", "window", "screen", "copper",
+        "blitter", "sprite", "\n", "\n", ", ", ". ",
+    ];
+    let mut data = Vec::with_capacity(TRACK);
+    while data.len() < TRACK {
+        match rng.below(7) {
+            0 | 1 | 2 => {
+                for _ in 0..20 + rng.below(60) {
+                    data.extend_from_slice(words[rng.below(words.len() as u32) as usize].as_bytes());
+                    data.push(b' ');
+                }
+            }
+            3 => {
+                let base = rng.next();
+                for i in 0..16 + rng.below(64) {
+                    data.extend_from_slice(&(base.wrapping_add(i * 4)).to_be_bytes());
+                }
+            }
+            4 => {
+                let byte = [0, 0, 0xff, 0x90, 0x4e][rng.below(5) as usize];
+                let run = 1 + rng.below(400) as usize;
+                data.resize(data.len() + run, byte);
+            }
+            5 => {
+                for _ in 0..32 + rng.below(200) {
+                    data.push(rng.next() as u8);
+                }
+            }
+            _ => {
+                if let Some(previous) = previous {
+                    let start = rng.below(previous.len() as u32 - 600) as usize;
+                    data.extend_from_slice(&previous[start..start + 100 + rng.below(500) as usize]);
+                }
+            }
+        }
+    }
+    data.truncate(TRACK);
+    data
+}
+
+fn rle_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let byte = data[i];
+        let run = data[i..].iter().take_while(|&&b| b == byte).count();
+        if run >= 4 {
+            if run < 255 {
+                out.extend_from_slice(&[0x90, run as u8, byte]);
+            } else {
+                out.extend_from_slice(&[0x90, 0xff, byte, (run >> 8) as u8, run as u8]);
+            }
+            i += run;
+        } else {
+            if byte == 0x90 {
+                out.extend_from_slice(&[0x90, 0]);
+            } else {
+                out.push(byte);
+            }
+            i += 1;
+        }
+    }
+    out
+}
+
+// The decoder's window, followed while encoding so matches may reach into earlier tracks.
+struct Window {
+    buf: Vec<u8>,
+    pos: u16,
+    mask: u16,
+}
+impl Window {
+    fn reset(&mut self, pos: u16) {
+        self.buf[..0x3fc8].fill(0);
+        self.pos = pos;
+    }
+    // Length of the match at distance d for target, copying like the decoder.
+    fn match_len(&self, target: &[u8], d: u16, max: usize) -> usize {
+        let src = self.pos.wrapping_sub(d).wrapping_sub(1);
+        let mut len = 0;
+        while len < max.min(target.len()) {
+            let byte = if len > d as usize {
+                target[len - d as usize - 1]
+            } else {
+                self.buf[(src.wrapping_add(len as u16) & self.mask) as usize]
+            };
+            if byte != target[len] {
+                break;
+            }
+            len += 1;
+        }
+        len
+    }
+    fn push(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.buf[(self.pos & self.mask) as usize] = b;
+            self.pos = self.pos.wrapping_add(1);
+        }
+    }
+}
+
+enum Token {
+    Literal(u8),
+    Match(u16, usize),
+}
+
+fn parse(
+    window: &mut Window,
+    data: &[u8],
+    distances: &dyn Fn(u16) -> bool,
+    max_distance: u16,
+    lengths: (usize, usize),
+) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        for d in 0..max_distance {
+            if !distances(d) {
+                continue;
+            }
+            let len = window.match_len(&data[i..], d, lengths.1);
+            if len > best.1 {
+                best = (d, len);
+            }
+        }
+        if best.1 >= lengths.0 {
+            tokens.push(Token::Match(best.0, best.1));
+            window.push(&data[i..i + best.1]);
+            i += best.1;
+        } else {
+            tokens.push(Token::Literal(data[i]));
+            window.push(&data[i..i + 1]);
+            i += 1;
+        }
+    }
+    tokens
+}
+
+// The shortest bits for every (length, distance) MEDIUM can express.
+fn medium_codes() -> HashMap<(usize, u16), Vec<(u32, u32)>> {
+    let mut codes: HashMap<(usize, u16), Vec<(u32, u32)>> = HashMap::new();
+    for prefix in 0..256usize {
+        let length = D_CODE[prefix] as usize + 3;
+        let x1 = D_LEN[prefix] as u32;
+        for e1 in 0..(1u32 << x1) {
+            let mid = ((((prefix as u32) << x1) | e1) & 0xff) as usize;
+            let x2 = D_LEN[mid] as u32;
+            for e2 in 0..(1u32 << x2) {
+                let low = (((mid as u32) << x2) | e2) & 0xff;
+                let distance = ((D_CODE[mid] as u32) << 8 | low) as u16;
+                let bits = vec![(prefix as u32, 8), (e1, x1), (e2, x2)];
+                let cost: u32 = bits.iter().map(|b| b.1).sum();
+                let entry = codes.entry((length, distance)).or_insert(bits.clone());
+                if cost < entry.iter().map(|b| b.1).sum() {
+                    *entry = bits;
+                }
+            }
+        }
+    }
+    codes
+}
+
+fn deep_distance_codes() -> HashMap<u16, (u32, u32, u32)> {
+    let mut codes = HashMap::new();
+    for prefix in 0..256u32 {
+        let x = D_LEN[prefix as usize] as u32;
+        for e in 0..(1u32 << x) {
+            let low = ((prefix << x) | e) & 0xff;
+            let distance = ((D_CODE[prefix as usize] as u32) << 8 | low) as u16;
+            codes.entry(distance).or_insert((prefix, e, x));
+        }
+    }
+    codes
+}
+
+// Huffman code lengths, every symbol present, flattened until none is longer than max.
+fn code_lengths(freq: &[u32], max: u8) -> Vec<u8> {
+    let mut freq: Vec<u64> = freq.iter().map(|&f| f as u64 + 1).collect();
+    loop {
+        let n = freq.len();
+        let mut nodes: Vec<(u64, Vec<usize>)> = (0..n).map(|i| (freq[i], vec![i])).collect();
+        let mut lengths = vec![0u8; n];
+        while nodes.len() > 1 {
+            nodes.sort_by(|a, b| b.0.cmp(&a.0));
+            let a = nodes.pop().unwrap();
+            let b = nodes.pop().unwrap();
+            for &s in a.1.iter().chain(b.1.iter()) {
+                lengths[s] += 1;
+            }
+            nodes.push((a.0 + b.0, [a.1, b.1].concat()));
+        }
+        if lengths.iter().all(|&l| l <= max) {
+            return lengths;
+        }
+        freq = freq.iter().map(|&f| f / 2 + 1).collect();
+    }
+}
+
+// Canonical codes in the order make_table assigns them.
+fn canonical(lengths: &[u8]) -> Vec<(u32, u32)> {
+    let mut codes = vec![(0, 0); lengths.len()];
+    let mut code = 0u32;
+    for len in 1..=16u8 {
+        for (symbol, &l) in lengths.iter().enumerate() {
+            if l == len {
+                codes[symbol] = (code, len as u32);
+                code += 1;
+            }
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+struct Track {
+    flags: u8,
+    packed: Vec<u8>,
+    raw_len: usize,
+    data: Vec<u8>,
+}
+
+fn main() {
+    let medium = medium_codes();
+    let deep_codes = deep_distance_codes();
+    // The first track of the LZ archives keeps the window for the second; HEAVY's second
+    // track also reuses the tables and skips the RLE pass. The reset archives clear the
+    // window after every track and, from the second track on, reuse the tables.
+    let archives: [(&str, u8, &[u8]); 7] = [
+        ("quick", 2, &[1, 0]),
+        ("medium", 3, &[1, 0]),
+        ("deep", 4, &[1, 0]),
+        ("heavy1", 5, &[1 | 2 | 4, 0]),
+        ("heavy2", 6, &[1 | 2 | 4, 0]),
+        ("heavy1_reset", 5, &[2 | 4, 4, 0]),
+        ("heavy2_reset", 6, &[2 | 4, 4, 0]),
+    ];
+    for (name, mode_id, flags) in archives {
+        let resets = flags.iter().any(|&f| f & 1 == 0 && f != 0);
+        let seed = 0x2545F4914F6CDD1D ^ (mode_id as u64) << 40 ^ (resets as u64) << 20;
+        let mut rng = Rng(seed);
+        let (init, mask) = match mode_id {
+            2 => (251, 0xff),
+            3 => (0x3fbe, 0x3fff),
+            4 => (0x3fc4, 0x3fff),
+            5 => (0, 0x0fff),
+            _ => (0, 0x1fff),
+        };
+        let mut window = Window { buf: vec![0; 0x4000], pos: init, mask };
+        let mut deep_model = Decompressor::new();
+        let mut heavy_codes: Option<(Vec<(u32, u32)>, Vec<(u32, u32)>)> = None;
+        let mut last_distance = 0u16;
+        let mut tracks: Vec<Track> = Vec::new();
+        for &f in flags {
+            let mut data = track_data(&mut rng, tracks.last().map(|t| t.data.as_slice()));
+            if resets {
+                // A run first, so the first match of a track without RLE is one byte back.
+                data[..64].fill(0x4e);
+            }
+            let rle = mode_id < 5 || f & 4 != 0;
+            let raw = if rle { rle_encode(&data) } else { data.clone() };
+            let mut bits = Bits::default();
+            match mode_id {
+                2 => {
+                    for token in parse(&mut window, &raw, &|_| true, 256, (2, 5)) {
+                        match token {
+                            Token::Literal(b) => {
+                                bits.put(1, 1);
+                                bits.put(b as u32, 8);
+                            }
+                            Token::Match(d, l) => {
+                                bits.put(0, 1);
+                                bits.put(l as u32 - 2, 2);
+                                bits.put(d as u32, 8);
+                            }
+                        }
+                    }
+                    window.pos = window.pos.wrapping_add(5) & mask;
+                }
+                3 => {
+                    let tokens = parse(
+                        &mut window,
+                        &raw,
+                        &|d| (3..=66).any(|l| medium.contains_key(&(l, d))),
+                        4096,
+                        (3, 66),
+                    );
+                    for token in tokens {
+                        match token {
+                            Token::Literal(b) => {
+                                bits.put(1, 1);
+                                bits.put(b as u32, 8);
+                            }
+                            Token::Match(d, l) => {
+                                let code = medium.get(&(l, d)).expect("codable match");
+                                bits.put(0, 1);
+                                for &(v, n) in code {
+                                    bits.put(v, n);
+                                }
+                            }
+                        }
+                    }
+                    window.pos = window.pos.wrapping_add(66) & mask;
+                }
+                4 => {
+                    let tokens = parse(&mut window, &raw, &|d| deep_codes.contains_key(&d), 4096, (3, 60));
+                    for token in tokens {
+                        let symbol = match token {
+                            Token::Literal(b) => b as u16,
+                            Token::Match(_, l) => l as u16 + 253,
+                        };
+                        for b in deep_model.encode_deep_symbol(symbol) {
+                            bits.put(b, 1);
+                        }
+                        if let Token::Match(d, _) = token {
+                            let (prefix, e, x) = deep_codes[&d];
+                            bits.put(prefix, 8);
+                            bits.put(e, x);
+                        }
+                    }
+                    window.pos = window.pos.wrapping_add(60) & mask;
+                }
+                _ => {
+                    let np: usize = if mode_id == 5 { 14 } else { 15 };
+                    let max_distance = 1u16 << (np - 2);
+                    let tokens = parse(&mut window, &raw, &|_| true, max_distance.min(4096), (3, 256));
+                    let node = |d: u16| if d == 0 { 0 } else { 16 - d.leading_zeros() as usize };
+                    if f & 2 != 0 {
+                        let mut c_freq = vec![0u32; 510];
+                        let mut p_freq = vec![0u32; np];
+                        for token in &tokens {
+                            match *token {
+                                Token::Literal(b) => c_freq[b as usize] += 1,
+                                Token::Match(d, l) => {
+                                    c_freq[l + 253] += 1;
+                                    p_freq[node(d)] += 1;
+                                }
+                            }
+                        }
+                        let c_len = code_lengths(&c_freq, 16);
+                        let p_len = code_lengths(&p_freq, 15);
+                        bits.put(510, 9);
+                        for &l in &c_len {
+                            bits.put(l as u32, 5);
+                        }
+                        bits.put(np as u32, 5);
+                        for &l in &p_len {
+                            bits.put(l as u32, 4);
+                        }
+                        heavy_codes = Some((canonical(&c_len), canonical(&p_len)));
+                    }
+                    let (c_codes, p_codes) = heavy_codes.as_ref().unwrap();
+                    for token in tokens {
+                        match token {
+                            Token::Literal(b) => {
+                                let (code, n) = c_codes[b as usize];
+                                bits.put(code, n);
+                            }
+                            Token::Match(d, l) => {
+                                let (code, n) = c_codes[l + 253];
+                                bits.put(code, n);
+                                // After a reset the last offset is 0, so the reset archives
+                                // may repeat it for a match one byte back.
+                                if d == last_distance && (d != 0 || resets) {
+                                    let (code, n) = p_codes[np - 1];
+                                    bits.put(code, n);
+                                } else {
+                                    let node = node(d);
+                                    let (code, n) = p_codes[node];
+                                    bits.put(code, n);
+                                    if node > 1 {
+                                        bits.put(d as u32 & ((1 << (node - 1)) - 1), node as u32 - 1);
+                                    }
+                                    last_distance = d;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            if f & 1 == 0 {
+                window.reset(init);
+                deep_model.reset();
+                last_distance = 0;
+            }
+            let packed = bits.finish();
+            tracks.push(Track { flags: f, packed, raw_len: raw.len(), data });
+        }
+
+        let mut body = Vec::new();
+        for (number, t) in tracks.iter().enumerate() {
+            let mut h = [0u8; 20];
+            h[0..2].copy_from_slice(b"TR");
+            h[2..4].copy_from_slice(&(number as u16).to_be_bytes());
+            h[6..8].copy_from_slice(&(t.packed.len() as u16).to_be_bytes());
+            h[8..10].copy_from_slice(&(t.raw_len as u16).to_be_bytes());
+            h[10..12].copy_from_slice(&(TRACK as u16).to_be_bytes());
+            h[12] = t.flags;
+            h[13] = mode_id;
+            h[14..16].copy_from_slice(&checksum16(&t.data).to_be_bytes());
+            h[16..18].copy_from_slice(&crc16(&t.packed).to_be_bytes());
+            let c = crc16(&h[..18]);
+            h[18..20].copy_from_slice(&c.to_be_bytes());
+            body.extend_from_slice(&h);
+            body.extend_from_slice(&t.packed);
+        }
+        let mut head = [0u8; 56];
+        head[0..4].copy_from_slice(b"DMS!");
+        head[12..16].copy_from_slice(&0x2f000000u32.to_be_bytes());
+        head[18..20].copy_from_slice(&(tracks.len() as u16 - 1).to_be_bytes());
+        head[20..24].copy_from_slice(&(body.len() as u32).to_be_bytes());
+        head[24..28].copy_from_slice(&((tracks.len() * TRACK) as u32).to_be_bytes());
+        head[46..48].copy_from_slice(&111u16.to_be_bytes());
+        head[48..50].copy_from_slice(&111u16.to_be_bytes());
+        head[50..52].copy_from_slice(&2u16.to_be_bytes());
+        head[52..54].copy_from_slice(&(mode_id as u16).to_be_bytes());
+        let c = crc16(&head[4..54]);
+        head[54..56].copy_from_slice(&c.to_be_bytes());
+        let mut archive = head.to_vec();
+        archive.extend_from_slice(&body);
+
+        let adf = xdms::unpack_bytes(&archive).expect("reference decoder accepts the archive");
+        let source: Vec<u8> = tracks.iter().flat_map(|t| t.data.clone()).collect();
+        assert!(adf == source, "{name}: reference decode differs from the source");
+        for (number, t) in tracks.iter().enumerate() {
+            println!(
+                "{name} track {number} flags {} packed {} raw {} checksum 0x{:04x}",
+                t.flags,
+                t.packed.len(),
+                t.raw_len,
+                checksum16(&t.data)
+            );
+        }
+        std::fs::write(format!("{name}.dms"), &archive).unwrap();
+    }
+}